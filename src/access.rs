@@ -0,0 +1,141 @@
+//! Allow and deny rules for the addresses of visitors to a tunnel.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Block of IP addresses, written as `ADDR/PREFIX` or as a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Check whether an address is in this block.
+    ///
+    /// IPv4 addresses mapped into IPv6 are compared as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid IP address: {addr}"))?;
+        let max: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&prefix| prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length: {prefix}"))?,
+            None => max,
+        };
+        // Store IPv4-mapped IPv6 blocks as IPv4, so they match IPv4 visitors.
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() => Ok(Self {
+                addr: IpAddr::V4(v4),
+                prefix: prefix
+                    .checked_sub(96)
+                    .ok_or_else(|| format!("prefix too short for a mapped address: {s}"))?,
+            }),
+            addr => Ok(Self { addr, prefix }),
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Rules deciding which visitor addresses may connect to a tunnel.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRules {
+    /// If not empty, only addresses in these blocks are allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<Cidr>,
+
+    /// Addresses in these blocks are always refused, even if allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessRules {
+    /// Check whether there are no rules, so every address is allowed.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Check whether a visitor address may connect.
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessRules, Cidr};
+
+    #[test]
+    fn rules_match_address_blocks() {
+        let cidr = |s: &str| s.parse::<Cidr>().unwrap();
+        let rules = AccessRules {
+            allow: vec![cidr("203.0.113.0/24"), cidr("2001:db8::/32")],
+            deny: vec![cidr("203.0.113.7")],
+        };
+        assert!(rules.permits("203.0.113.1".parse().unwrap()));
+        assert!(rules.permits("::ffff:203.0.113.2".parse().unwrap()));
+        assert!(rules.permits("2001:db8::1".parse().unwrap()));
+        assert!(!rules.permits("203.0.113.7".parse().unwrap()));
+        assert!(!rules.permits("198.51.100.1".parse().unwrap()));
+        assert!(cidr("0.0.0.0/0").contains("10.1.2.3".parse().unwrap()));
+        assert_eq!(cidr("::ffff:10.0.0.0/104").to_string(), "10.0.0.0/8");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! HTTP API and dashboard for server operators to watch tunnels and close them.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::server::{
+    AuthFailure, AuthFailures, Health, HealthStatus, Reloader, Server, Suspender, TunnelInfo,
+    Tunnels,
+};
+use crate::web::login::{client_ip, too_many_attempts};
+use crate::web::Login;
+
+const DASHBOARD_HTML: &str = include_str!("admin/dashboard.html");
+
+/// Callback that reloads the server's configuration, called by `POST /api/reload`.
+pub type Reload = Arc<dyn Fn() -> Result<()> + Send + Sync>;
+
+/// Shared state of the admin API.
+#[derive(Clone)]
+struct AdminState {
+    tunnels: Tunnels,
+    auth_failures: AuthFailures,
+    reloader: Reloader,
+    suspender: Suspender,
+    health: Health,
+    login: Option<Arc<Login>>,
+    reload: Option<Reload>,
+}
+
+/// Builds the admin router for a server, with the dashboard at `/` and the API under `/api`.
+///
+/// The health checks `/healthz` and `/readyz` are public, for load balancers and Kubernetes
+/// probes. If `login` is set, API requests need an `Authorization: Bearer <token>` header. If `reload`
+/// is set, operators can reload the server's configuration through the API.
+pub fn router(server: &Server, login: Option<Login>, reload: Option<Reload>) -> Router {
+    let state = AdminState {
+        tunnels: server.tunnels(),
+        auth_failures: server.auth_failures(),
+        reloader: server.reloader(),
+        suspender: server.suspender(),
+        health: server.health(),
+        login: login.map(Arc::new),
+        reload,
+    };
+    Router::new()
+        .route("/api/auth-failures", get(list_auth_failures))
+        .route(
+            "/api/ports/:port/suspend",
+            post(suspend_port).delete(resume_port),
+        )
+        .route("/api/reload", post(reload_config))
+        .route("/api/secondary-secret", delete(retire_secondary_secret))
+        .route(
+            "/api/secondary-secret/promote",
+            post(promote_secondary_secret),
+        )
+        .route("/api/tunnels", get(list_tunnels))
+        .route(
+            "/api/tunnels/:id",
+            get(get_tunnel).delete(disconnect_tunnel),
+        )
+        .route(
+            "/api/tunnels/:id/connections/:connection",
+            delete(close_connection),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .route("/", get(dashboard))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+}
+
+/// Serves an admin router on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, router: Router) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("could not bind admin API on {addr}"))?;
+    info!(addr = %listener.local_addr()?, "admin API listening");
+    let router = router.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD_HTML)
+}
+
+/// Whether the server is alive, which holds while it is draining.
+async fn healthz(State(state): State<AdminState>) -> (StatusCode, Json<HealthStatus>) {
+    let status = state.health.status();
+    let code = if status.accepting {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(status))
+}
+
+/// Whether the server can take new tunnels.
+async fn readyz(State(state): State<AdminState>) -> (StatusCode, Json<HealthStatus>) {
+    let status = state.health.status();
+    let code = if status.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(status))
+}
+
+async fn require_token(State(state): State<AdminState>, request: Request, next: Next) -> Response {
+    let ip = client_ip(request.extensions());
+    match &state.login {
+        Some(login) if login.is_throttled(ip) => too_many_attempts(),
+        Some(login) if !login.is_authorized(request.headers(), ip) => {
+            error(StatusCode::UNAUTHORIZED, "admin token required")
+        }
+        _ => next.run(request).await,
+    }
+}
+
+async fn list_tunnels(State(state): State<AdminState>) -> Json<Vec<TunnelInfo>> {
+    Json(state.tunnels.list())
+}
+
+async fn list_auth_failures(State(state): State<AdminState>) -> Json<Vec<AuthFailure>> {
+    Json(state.auth_failures.recent())
+}
+
+async fn get_tunnel(State(state): State<AdminState>, Path(id): Path<Uuid>) -> Response {
+    match state.tunnels.get(id) {
+        Some(tunnel) => Json(tunnel).into_response(),
+        None => error(StatusCode::NOT_FOUND, "tunnel not found"),
+    }
+}
+
+async fn disconnect_tunnel(State(state): State<AdminState>, Path(id): Path<Uuid>) -> Response {
+    if state.tunnels.disconnect(id) {
+        info!(%id, "disconnecting tunnel from admin API");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        error(StatusCode::NOT_FOUND, "tunnel not found")
+    }
+}
+
+async fn close_connection(
+    State(state): State<AdminState>,
+    Path((id, connection)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if state.tunnels.close_connection(id, connection) {
+        info!(%id, %connection, "closing connection from admin API");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        error(StatusCode::NOT_FOUND, "connection not found")
+    }
+}
+
+/// Body of a request to suspend a port.
+#[derive(Deserialize)]
+struct SuspendRequest {
+    /// Why the port is suspended, sent to the client whose tunnel is closed.
+    reason: String,
+}
+
+async fn suspend_port(
+    State(state): State<AdminState>,
+    Path(port): Path<u16>,
+    Json(request): Json<SuspendRequest>,
+) -> Response {
+    info!(port, reason = %request.reason, "suspending port from admin API");
+    let closed = state.suspender.suspend(port, &request.reason);
+    Json(json!({ "ok": true, "closed": closed })).into_response()
+}
+
+async fn resume_port(State(state): State<AdminState>, Path(port): Path<u16>) -> Response {
+    if state.suspender.resume(port) {
+        info!(port, "resuming port from admin API");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        error(StatusCode::NOT_FOUND, "port is not suspended")
+    }
+}
+
+async fn reload_config(State(state): State<AdminState>) -> Response {
+    let Some(reload) = &state.reload else {
+        return error(StatusCode::NOT_FOUND, "reloading is not enabled");
+    };
+    match reload() {
+        Ok(()) => Json(json!({ "ok": true })).into_response(),
+        Err(err) => {
+            warn!(%err, "failed to reload configuration");
+            error(StatusCode::UNPROCESSABLE_ENTITY, &format!("{err:#}"))
+        }
+    }
+}
+
+async fn retire_secondary_secret(State(state): State<AdminState>) -> Response {
+    if state.reloader.set_secondary_secret(None) {
+        info!("retiring secondary secret from admin API");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        error(StatusCode::NOT_FOUND, "no secondary secret is set")
+    }
+}
+
+async fn promote_secondary_secret(State(state): State<AdminState>) -> Response {
+    if state.reloader.promote_secondary_secret() {
+        info!("promoting secondary secret from admin API");
+        Json(json!({ "ok": true })).into_response()
+    } else {
+        error(StatusCode::NOT_FOUND, "no secondary secret is set")
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,188 @@
+//! Append-only audit log of tunnel lifecycle events, for shipping to a SIEM.
+//!
+//! Each event is written as one JSON object per line, either appended to a file or sent as a
+//! UDP datagram to `udp:HOST:PORT`, such as a syslog server or log shipper.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::shared::PortRange;
+
+/// Where audit events are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditTarget {
+    /// Append events to a file.
+    File(PathBuf),
+
+    /// Send each event as a datagram to `HOST:PORT`.
+    Udp(String),
+}
+
+impl FromStr for AuditTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("udp:") {
+            Some(addr) if addr.rsplit_once(':').is_some() => Ok(Self::Udp(addr.to_string())),
+            Some(_) => Err("UDP audit log must be udp:HOST:PORT".into()),
+            None if s.is_empty() => Err("audit log path is empty".into()),
+            None => Ok(Self::File(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for AuditTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Udp(addr) => write!(f, "udp:{addr}"),
+        }
+    }
+}
+
+/// Open destination of audit events.
+enum Sink {
+    File(File),
+    Udp(UdpSocket),
+}
+
+/// Handle for recording audit events, shared by all tunnels of a server.
+#[derive(Clone)]
+pub struct AuditLog(Arc<Mutex<Sink>>);
+
+impl AuditLog {
+    /// Open an audit log, creating the file if needed.
+    pub fn open(target: &AuditTarget) -> Result<Self> {
+        let sink = match target {
+            AuditTarget::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("could not open audit log {}", path.display()))?;
+                Sink::File(file)
+            }
+            AuditTarget::Udp(addr) => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket
+                    .connect(addr)
+                    .with_context(|| format!("could not resolve audit log address {addr}"))?;
+                Sink::Udp(socket)
+            }
+        };
+        Ok(Self(Arc::new(Mutex::new(sink))))
+    }
+
+    /// Write an event with the current time, logging a warning if it cannot be written.
+    pub(crate) fn record(&self, event: AuditEvent<'_>) {
+        let entry = Entry {
+            time: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            event,
+        };
+        let mut line = serde_json::to_string(&entry).expect("audit events serialize to JSON");
+        line.push('\n');
+        let result = match &mut *self.0.lock().unwrap() {
+            Sink::File(file) => file.write_all(line.as_bytes()),
+            Sink::Udp(socket) => socket.send(line.as_bytes()).map(|_| ()),
+        };
+        if let Err(err) = result {
+            warn!(%err, "could not write audit event");
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// Something that happened to a tunnel, or a failed attempt to open one.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum AuditEvent<'a> {
+    /// A client opened a tunnel.
+    TunnelOpened {
+        tunnel: Uuid,
+        client: SocketAddr,
+        token: Option<&'a str>,
+        secondary_secret: bool,
+        requested_port: u16,
+        requested_ports: &'a [PortRange],
+        port: u16,
+        host: Option<&'a str>,
+    },
+
+    /// A tunnel closed, with the traffic of connections that closed before it.
+    TunnelClosed {
+        tunnel: Uuid,
+        client: SocketAddr,
+        token: Option<&'a str>,
+        port: u16,
+        host: Option<&'a str>,
+        duration_secs: u64,
+        bytes_in: u64,
+        bytes_out: u64,
+        connections: u64,
+    },
+
+    /// A client failed to authenticate.
+    AuthFailed { client: SocketAddr, error: String },
+
+    /// An operator suspended a public port, closing any tunnel on it.
+    TunnelSuspended { port: u16, reason: &'a str },
+
+    /// An operator allowed clients to listen on a suspended port again.
+    TunnelResumed { port: u16 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEvent, AuditLog, AuditTarget};
+
+    #[test]
+    fn events_are_appended_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("bore-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let target: AuditTarget = path.to_str().unwrap().parse().unwrap();
+        let log = AuditLog::open(&target).unwrap();
+        for _ in 0..2 {
+            log.record(AuditEvent::AuthFailed {
+                client: "203.0.113.7:4000".parse().unwrap(),
+                error: "invalid secret".into(),
+            });
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "auth_failed");
+        assert_eq!(lines[0]["client"], "203.0.113.7:4000");
+        assert!(lines[0]["time"].is_string());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            "udp:siem.example.com:514".parse::<AuditTarget>().unwrap(),
+            AuditTarget::Udp("siem.example.com:514".into())
+        );
+        assert!("udp:siem.example.com".parse::<AuditTarget>().is_err());
+    }
+}
@@ -9,6 +9,7 @@ use uuid::Uuid;
 use crate::shared::{ClientMessage, Delimited, ServerMessage};
 
 /// Wrapper around a MAC used for authenticating clients that have a secret.
+#[derive(Clone)]
 pub struct Authenticator(Hmac<Sha256>);
 
 impl Authenticator {
@@ -52,15 +53,9 @@ impl Authenticator {
         &self,
         stream: &mut Delimited<T>,
     ) -> Result<()> {
-        let challenge = Uuid::new_v4();
-        stream.send(ServerMessage::Challenge(challenge)).await?;
-        match stream.recv_timeout().await? {
-            Some(ClientMessage::Authenticate(tag)) => {
-                ensure!(self.validate(&challenge, &tag), "invalid secret");
-                Ok(())
-            }
-            _ => bail!("server requires secret, but no secret was provided"),
-        }
+        let (challenge, tag) = server_challenge(stream).await?;
+        ensure!(self.validate(&challenge, &tag), "invalid secret");
+        Ok(())
     }
 
     /// As the client, answer a challenge to attempt to authenticate with the server.
@@ -70,6 +65,7 @@ impl Authenticator {
     ) -> Result<()> {
         let challenge = match stream.recv_timeout().await? {
             Some(ServerMessage::Challenge(challenge)) => challenge,
+            Some(ServerMessage::Error(message)) => bail!("server error: {message}"),
             _ => bail!("expected authentication challenge, but no secret was required"),
         };
         let tag = self.answer(&challenge);
@@ -77,3 +73,17 @@ impl Authenticator {
         Ok(())
     }
 }
+
+/// As the server, send a challenge to the client and return it with their reply.
+///
+/// This lets a server check the reply against several secrets.
+pub async fn server_challenge<T: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut Delimited<T>,
+) -> Result<(Uuid, String)> {
+    let challenge = Uuid::new_v4();
+    stream.send(ServerMessage::Challenge(challenge)).await?;
+    match stream.recv_timeout().await? {
+        Some(ClientMessage::Authenticate(tag)) => Ok((challenge, tag)),
+        _ => bail!("server requires secret, but no secret was provided"),
+    }
+}
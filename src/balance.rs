@@ -0,0 +1,190 @@
+//! Load balancing of tunnel connections across local backends.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+use crate::shared::NETWORK_TIMEOUT;
+
+/// A local service that receives forwarded connections.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backend {
+    /// Host of the local service.
+    pub host: String,
+
+    /// Port of the local service.
+    pub port: u16,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    /// Parse a backend from `HOST:PORT`, with IPv6 hosts written as `[::1]:PORT`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, port) = s.rsplit_once(':').context("expected HOST:PORT")?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            bail!("backend host cannot be empty");
+        }
+        let port = port.parse().context("invalid backend port")?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Picks a backend for each new connection.
+#[derive(Debug)]
+pub(crate) struct Balancer {
+    /// Backends in the order they were configured.
+    backends: Vec<Backend>,
+
+    /// Whether each backend accepted the last connection made to it.
+    healthy: Vec<AtomicBool>,
+
+    /// Whether visitors from the same IP address stick to the same backend.
+    sticky: bool,
+
+    /// Counter used for round-robin selection.
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    /// Create a balancer over a non-empty list of backends.
+    pub(crate) fn new(backends: Vec<Backend>, sticky: bool) -> Self {
+        assert!(!backends.is_empty(), "must provide at least one backend");
+        Self {
+            healthy: backends.iter().map(|_| AtomicBool::new(true)).collect(),
+            backends,
+            sticky,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether there is more than one backend to choose from.
+    pub(crate) fn is_balanced(&self) -> bool {
+        self.backends.len() > 1
+    }
+
+    /// Whether this balancer would use visitor addresses when picking backends.
+    pub(crate) fn is_sticky(&self) -> bool {
+        self.sticky && self.is_balanced()
+    }
+
+    /// Backends to try for a connection, in order, hashing the visitor address in sticky mode.
+    ///
+    /// Falls back to round-robin when the visitor address is not known. Backends that are down
+    /// come last, so they are only tried once every other backend has failed.
+    pub(crate) fn candidates(&self, peer: Option<IpAddr>) -> Vec<&Backend> {
+        let index = match peer {
+            Some(ip) if self.sticky => {
+                // The default hasher uses fixed keys, so results are stable for the process.
+                let mut hasher = DefaultHasher::new();
+                ip.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+            _ => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+        let len = self.backends.len();
+        let (mut up, down): (Vec<_>, Vec<_>) = (0..len)
+            .map(|offset| (index + offset) % len)
+            .partition(|&i| self.healthy[i].load(Ordering::Relaxed));
+        up.extend(down);
+        up.into_iter().map(|i| &self.backends[i]).collect()
+    }
+
+    /// Record whether a backend accepted a connection, logging when it goes down or comes back.
+    pub(crate) fn set_healthy(&self, backend: &Backend, healthy: bool) {
+        let Some(index) = self.backends.iter().position(|b| b == backend) else {
+            return;
+        };
+        if self.healthy[index].swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                info!(%backend, "backend is up");
+            } else {
+                warn!(%backend, "backend is down");
+            }
+        }
+    }
+
+    /// Try connecting to every backend once, updating which ones are up.
+    pub(crate) async fn check_health(&self) {
+        for backend in &self.backends {
+            let connect = TcpStream::connect((backend.host.as_str(), backend.port));
+            let healthy = matches!(timeout(NETWORK_TIMEOUT, connect).await, Ok(Ok(_)));
+            self.set_healthy(backend, healthy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{Backend, Balancer};
+
+    fn backends() -> Vec<Backend> {
+        ["localhost:3000", "localhost:3001", "[::1]:3002"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_backend() {
+        let backend: Backend = "[::1]:3002".parse().unwrap();
+        assert_eq!(backend.host, "::1");
+        assert_eq!(backend.port, 3002);
+        assert_eq!(backend.to_string(), "[::1]:3002");
+        assert!("localhost".parse::<Backend>().is_err());
+        assert!(":3000".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn round_robin_cycles_through_backends() {
+        let balancer = Balancer::new(backends(), false);
+        let picked: Vec<_> = (0..4).map(|_| balancer.candidates(None)[0].port).collect();
+        assert_eq!(picked, [3000, 3001, 3002, 3000]);
+    }
+
+    #[test]
+    fn backends_that_are_down_are_tried_last() {
+        let backends = backends();
+        let balancer = Balancer::new(backends.clone(), false);
+        balancer.set_healthy(&backends[1], false);
+        let picked: Vec<_> = (0..3).map(|_| balancer.candidates(None)[0].port).collect();
+        assert_eq!(picked, [3000, 3002, 3002]);
+        let order: Vec<_> = balancer.candidates(None).iter().map(|b| b.port).collect();
+        assert_eq!(order, [3000, 3002, 3001]);
+    }
+
+    #[test]
+    fn sticky_keeps_visitor_on_same_backend() {
+        let balancer = Balancer::new(backends(), true);
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let first = balancer.candidates(Some(ip))[0].clone();
+        for _ in 0..10 {
+            assert_eq!(balancer.candidates(Some(ip))[0], &first);
+        }
+    }
+}
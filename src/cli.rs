@@ -1,20 +1,42 @@
 #![allow(missing_docs)]
 
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand};
+use futures_util::future::try_join_all;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
 use crate::{
-    client::{run_local, LocalArgs},
-    server::Server,
+    access::{AccessRules, Cidr},
+    admin,
+    audit::{AuditLog, AuditTarget},
+    client::{run_local, LocalArgs, OutputFormat, TunnelEvent},
+    config,
+    e2e::{self, E2eKey},
+    limit::{ByteRate, RateLimit},
+    logging,
+    metrics::{self, MetricsExporter},
+    server::{PortReservation, Reloader, Server, TokenUpdater},
+    shared::ReconnectHint,
+    systemd,
+    tls::ServerTls,
+    tokens,
     web::{
-        self, SessionInfo, SessionMode, SystemTunnelRole, SystemTunnelSpec, TunnelConfig, WebState,
+        self, update, LogFiles, Login, RestartPolicy, SessionInfo, SessionMode, Store,
+        SystemTunnelRole, SystemTunnelSpec, TunnelConfig, UpdateCheck, WebState,
     },
 };
 
+const RECONNECT_HINT_FLUSH: Duration = Duration::from_millis(500);
+
 const WEB_RISK_WARNING: &str =
-    "Warning: browser access is unauthenticated. Anyone who can reach remote web port can control local loopback tunnels on this machine.";
+    "Warning: anyone with the web console password can control local loopback tunnels on this machine.";
 
 /// Top-level CLI arguments.
 #[derive(Parser, Debug)]
@@ -35,9 +57,26 @@ pub struct Args {
     pub web: bool,
 
     /// Address for the local web console.
-    #[arg(long = "web-addr", default_value = "127.0.0.1:7836")]
+    #[arg(
+        long = "web-addr",
+        default_value = "127.0.0.1:7836",
+        env = "BORE_WEB_ADDR"
+    )]
     pub web_addr: SocketAddr,
 
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+
+    #[command(flatten)]
+    pub storage: StorageArgs,
+
+    #[command(flatten)]
+    pub tls: WebTlsArgs,
+
+    /// Export metrics to stdout, prometheus:ADDR, or statsd:HOST:PORT.
+    #[arg(long, global = true, value_name = "EXPORTER", env = "BORE_METRICS")]
+    pub metrics: Option<MetricsExporter>,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
@@ -48,6 +87,9 @@ pub enum Command {
     /// Starts a local proxy to the remote server.
     Local(LocalArgs),
 
+    /// Serves an end-to-end encrypted tunnel decrypted on a local address.
+    Connect(ConnectArgs),
+
     /// Starts web console. Prefer this form for `npx`.
     Web(WebArgs),
 
@@ -58,11 +100,36 @@ pub enum Command {
     Server(ServerArgs),
 }
 
+/// End-to-end encrypted tunnel CLI arguments.
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConnectArgs {
+    /// Address of the tunnel on the remote server, as HOST:PORT.
+    pub remote: String,
+
+    /// Key the tunnel is encrypted with, as given to `bore local --e2e-key`.
+    #[arg(long, value_name = "KEY", env = "BORE_E2E_KEY", hide_env_values = true)]
+    pub e2e_key: E2eKey,
+
+    /// Local address to serve the decrypted tunnel on; a free port is picked by default.
+    #[arg(
+        short,
+        long,
+        value_name = "ADDR",
+        default_value = "127.0.0.1:0",
+        env = "BORE_LISTEN_ADDR"
+    )]
+    pub listen: SocketAddr,
+}
+
 /// Web console CLI arguments.
 #[derive(clap::Args, Debug, Clone)]
 pub struct WebArgs {
     /// Address for local web console.
-    #[arg(long = "web-addr", default_value = "127.0.0.1:7836")]
+    #[arg(
+        long = "web-addr",
+        default_value = "127.0.0.1:7836",
+        env = "BORE_WEB_ADDR"
+    )]
     pub web_addr: SocketAddr,
 
     /// Expose web console through remote tunnel on target server.
@@ -80,6 +147,15 @@ pub struct WebArgs {
     /// Optional secret for tunnel authentication.
     #[arg(short, long, env = "BORE_SECRET", hide_env_values = true)]
     pub secret: Option<String>,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+
+    #[command(flatten)]
+    pub storage: StorageArgs,
+
+    #[command(flatten)]
+    pub tls: WebTlsArgs,
 }
 
 /// Home bundle CLI arguments.
@@ -94,7 +170,11 @@ pub struct HomeArgs {
     pub secret: Option<String>,
 
     /// Address for local web console.
-    #[arg(long = "web-addr", default_value = "127.0.0.1:7836")]
+    #[arg(
+        long = "web-addr",
+        default_value = "127.0.0.1:7836",
+        env = "BORE_WEB_ADDR"
+    )]
     pub web_addr: SocketAddr,
 
     /// Requested remote port for web console tunnel.
@@ -108,10 +188,154 @@ pub struct HomeArgs {
     /// Requested remote port for SSH tunnel.
     #[arg(long = "ssh-port", default_value_t = 2222)]
     pub ssh_port: u16,
+
+    #[command(flatten)]
+    pub console: ConsoleArgs,
+
+    #[command(flatten)]
+    pub storage: StorageArgs,
+
+    #[command(flatten)]
+    pub tls: WebTlsArgs,
+}
+
+/// Web console options for limiting tunnels and access to the API.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ConsoleArgs {
+    /// Maximum number of tunnels the web console may run at once.
+    #[arg(long, value_name = "COUNT", env = "BORE_MAX_TUNNELS")]
+    pub max_tunnels: Option<usize>,
+
+    /// Password for the web console API, generated when the console is reachable remotely.
+    #[arg(long, env = "BORE_WEB_PASSWORD", hide_env_values = true)]
+    pub web_password: Option<String>,
+
+    /// Other origin allowed to call the web console API, such as a reverse proxy's URL.
+    #[arg(
+        long = "web-allowed-origin",
+        value_name = "ORIGIN",
+        env = "BORE_WEB_ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub web_allowed_origins: Vec<String>,
+
+    /// Report in `/api/version` whether a newer release is on GitHub, checked at most hourly.
+    #[arg(long, env = "BORE_CHECK_UPDATES")]
+    pub check_updates: bool,
+}
+
+impl ConsoleArgs {
+    /// Apply the limits to the web console, requiring a password if it is `exposed`.
+    fn apply(&self, state: &mut WebState, exposed: bool) {
+        state.set_max_running(self.max_tunnels);
+        state.set_login(web_login(self.web_password.clone(), exposed));
+        state.set_allowed_origins(self.web_allowed_origins.clone());
+        if self.check_updates {
+            let check = UpdateCheck::new(update::UPSTREAM_URL).expect("upstream URL is valid");
+            state.set_update_check(Some(check));
+        }
+    }
+}
+
+/// Web console options for keeping tunnels and their logs on disk.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct StorageArgs {
+    /// JSON file where tunnels and relay profiles are saved and restored on startup.
+    #[arg(long, value_name = "FILE", env = "BORE_STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Directory where each tunnel's log is written to `<id>.log`.
+    #[arg(long, value_name = "DIR", env = "BORE_LOG_DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Rotate a tunnel's log file once it reaches this many bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = 1024 * 1024, requires = "log_dir")]
+    pub log_max_size: u64,
+
+    /// Number of rotated log files to keep per tunnel.
+    #[arg(long, value_name = "COUNT", default_value_t = 5, requires = "log_dir")]
+    pub log_keep: usize,
+}
+
+impl StorageArgs {
+    /// Set up log files and the state file, restoring saved tunnels.
+    async fn apply(&self, state: &mut WebState) -> Result<()> {
+        if let Some(dir) = &self.log_dir {
+            let log_files =
+                LogFiles::new(dir, self.log_max_size, self.log_keep).map_err(|err| {
+                    anyhow!("failed to create log directory {}: {err}", dir.display())
+                })?;
+            state.set_log_files(Some(log_files));
+        }
+        if let Some(path) = &self.state_file {
+            state.set_store(Some(Store::new(path)));
+            let restored = state.restore().await.map_err(|err| anyhow!(err.message))?;
+            info!(restored, path = %path.display(), "restored saved tunnels");
+        }
+        Ok(())
+    }
+}
+
+/// Web console options for serving over HTTPS.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct WebTlsArgs {
+    /// PEM file with the TLS certificate chain to serve the web console over HTTPS.
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "BORE_WEB_TLS_CERT",
+        requires = "tls_key",
+        conflicts_with = "tls_self_signed"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for --tls-cert.
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "BORE_WEB_TLS_KEY",
+        requires = "tls_cert"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve the web console over HTTPS with a self-signed certificate generated at startup.
+    #[arg(long, env = "BORE_WEB_TLS_SELF_SIGNED")]
+    pub tls_self_signed: bool,
+}
+
+impl WebTlsArgs {
+    /// TLS settings for the web console, if HTTPS is enabled.
+    ///
+    /// A self-signed certificate is valid for localhost and the given host names or addresses.
+    fn load(&self, hosts: &[String]) -> Result<Option<ServerTls>> {
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            return ServerTls::from_pem_files(cert, key).map(Some);
+        }
+        if !self.tls_self_signed {
+            return Ok(None);
+        }
+        let mut names = vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+            "::1".to_string(),
+        ];
+        for host in hosts {
+            if !names.contains(host) {
+                names.push(host.clone());
+            }
+        }
+        println!("Web console certificate is self-signed; browsers will ask you to trust it.");
+        ServerTls::self_signed(names).map(Some)
+    }
 }
 
 /// Server CLI arguments.
 #[derive(clap::Args, Debug, Clone)]
+#[command(group(
+    clap::ArgGroup::new("credentials")
+        .args(["secret", "reserve", "tokens"])
+        .multiple(true)
+))]
 pub struct ServerArgs {
     /// Minimum accepted TCP port number.
     #[arg(long, default_value_t = 1024, env = "BORE_MIN_PORT")]
@@ -125,6 +349,33 @@ pub struct ServerArgs {
     #[arg(short, long, env = "BORE_SECRET", hide_env_values = true)]
     pub secret: Option<String>,
 
+    /// Previous secret to keep accepting while clients move to the new one.
+    #[arg(
+        long,
+        env = "BORE_SECONDARY_SECRET",
+        hide_env_values = true,
+        requires = "secret"
+    )]
+    pub secondary_secret: Option<String>,
+
+    /// Reserve ports for clients with their own secret, as PORTS=SECRET, like `20000-20010=token`.
+    ///
+    /// Only clients using that secret can listen on the ports. All clients must authenticate.
+    #[arg(
+        long,
+        value_name = "PORTS=SECRET",
+        env = "BORE_RESERVE",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub reserve: Vec<PortReservation>,
+
+    /// TOML or YAML file of named client secrets, reloaded when it changes.
+    ///
+    /// Removing a token from the file revokes it and closes its tunnels.
+    #[arg(long, value_name = "FILE", env = "BORE_TOKENS")]
+    pub tokens: Option<PathBuf>,
+
     /// IP address to bind to, clients must reach this.
     #[arg(long, default_value = "0.0.0.0")]
     pub bind_addr: IpAddr,
@@ -132,6 +383,174 @@ pub struct ServerArgs {
     /// IP address where tunnels will listen on, defaults to --bind-addr.
     #[arg(long)]
     pub bind_tunnels: Option<IpAddr>,
+
+    /// Allow clients to reach other servers through this one with `--via`.
+    ///
+    /// Requires --secret, --reserve or --tokens, so that the server is not an open relay.
+    #[arg(long, requires = "credentials")]
+    pub allow_relay: bool,
+
+    /// On shutdown, ask clients to reconnect after this many seconds.
+    #[arg(long, value_name = "SECS", env = "BORE_RECONNECT_AFTER")]
+    pub reconnect_after: Option<u64>,
+
+    /// Replacement server that clients should reconnect to after shutdown.
+    #[arg(long, value_name = "HOST", requires = "reconnect_after")]
+    pub reconnect_to: Option<String>,
+
+    /// On shutdown, how many seconds to wait for open connections to finish.
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 30,
+        env = "BORE_DRAIN_TIMEOUT"
+    )]
+    pub drain_timeout: u64,
+
+    /// Limit tunnel handshakes and failed logins per source IP, as RATE[:BURST] per second.
+    #[arg(long, value_name = "RATE[:BURST]", env = "BORE_HANDSHAKE_LIMIT")]
+    pub handshake_limit: Option<RateLimit>,
+
+    /// Limit tunnel handshakes from all clients together, as RATE[:BURST] per second.
+    #[arg(long, value_name = "RATE[:BURST]", env = "BORE_GLOBAL_HANDSHAKE_LIMIT")]
+    pub global_handshake_limit: Option<RateLimit>,
+
+    /// Record tunnel lifecycle events as JSON lines in a file, or send them to udp:HOST:PORT.
+    #[arg(long, value_name = "PATH", env = "BORE_AUDIT_LOG")]
+    pub audit_log: Option<AuditTarget>,
+
+    /// Limit new public connections to tunnels per source IP, as RATE[:BURST] per second.
+    #[arg(long, value_name = "RATE[:BURST]", env = "BORE_VISITOR_LIMIT")]
+    pub visitor_limit: Option<RateLimit>,
+
+    /// Limit the throughput of each tunnel in each direction, like `5MBps` or `512KiB/s`.
+    #[arg(long, value_name = "RATE", env = "BORE_MAX_RATE")]
+    pub max_rate: Option<ByteRate>,
+
+    /// Refuse new visitors while this many connections to a tunnel are open.
+    ///
+    /// Clients can ask for a lower limit on their own tunnels with --max-connections.
+    #[arg(long, value_name = "N", env = "BORE_MAX_CONNECTIONS")]
+    pub max_connections: Option<u32>,
+
+    /// Close forwarded connections that transfer no data for this many seconds.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_IDLE_TIMEOUT",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub idle_timeout: Option<u64>,
+
+    /// Send a heartbeat to each client every this many seconds, instead of every half second.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_HEARTBEAT_INTERVAL",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub heartbeat_interval: Option<u64>,
+
+    /// Close tunnels whose clients send no pings for this many seconds.
+    ///
+    /// Only applies to clients that send pings, e.g., with --heartbeat-interval.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_HEARTBEAT_TIMEOUT",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub heartbeat_timeout: Option<u64>,
+
+    /// Route HTTP visitors to tunnels named by their subdomain of this domain.
+    ///
+    /// Clients started with `--name NAME` are reached at `http://NAME.DOMAIN`.
+    #[arg(long, value_name = "DOMAIN", env = "BORE_HTTP_DOMAIN")]
+    pub http_domain: Option<String>,
+
+    /// Port for HTTP visitors to named tunnels.
+    #[arg(
+        long,
+        value_name = "PORT",
+        default_value_t = 80,
+        env = "BORE_HTTP_PORT",
+        requires = "http_domain"
+    )]
+    pub http_port: u16,
+
+    /// Port for TLS visitors to named tunnels, routed by server name (SNI) without decrypting.
+    #[arg(
+        long,
+        value_name = "PORT",
+        env = "BORE_HTTPS_PORT",
+        requires = "http_domain"
+    )]
+    pub https_port: Option<u16>,
+
+    /// Require TLS on connections from clients, using --tls-cert and --tls-key.
+    #[arg(long, env = "BORE_TLS", requires_all = ["tls_cert", "tls_key"])]
+    pub tls: bool,
+
+    /// PEM file with the TLS certificate chain of the server.
+    #[arg(long, value_name = "FILE", env = "BORE_TLS_CERT", requires = "tls")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for --tls-cert.
+    #[arg(long, value_name = "FILE", env = "BORE_TLS_KEY", requires = "tls")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Only let visitors from these addresses or CIDR blocks connect to tunnels.
+    #[arg(long, value_name = "CIDR", env = "BORE_ALLOW", value_delimiter = ',')]
+    pub allow: Vec<Cidr>,
+
+    /// Refuse visitors from these addresses or CIDR blocks.
+    #[arg(long, value_name = "CIDR", env = "BORE_DENY", value_delimiter = ',')]
+    pub deny: Vec<Cidr>,
+
+    /// Serve Prometheus metrics at /metrics on this address, like `--metrics prometheus:ADDR`.
+    #[cfg(feature = "prometheus")]
+    #[arg(long, value_name = "ADDR", env = "BORE_METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Serve an HTTP API for listing and disconnecting tunnels on this address.
+    #[arg(long, value_name = "ADDR", env = "BORE_ADMIN_ADDR")]
+    pub admin_addr: Option<SocketAddr>,
+
+    /// Token that admin API requests must send as `Authorization: Bearer <TOKEN>`.
+    ///
+    /// Required unless --admin-addr is a loopback address.
+    #[arg(
+        long,
+        env = "BORE_ADMIN_TOKEN",
+        hide_env_values = true,
+        requires = "admin_addr"
+    )]
+    pub admin_token: Option<String>,
+
+    /// TOML or YAML file with default values for these options.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+impl Args {
+    /// Metrics exporter selected with `--metrics`, or with `--metrics-addr` on a subcommand.
+    pub fn metrics_exporter(&self) -> Option<MetricsExporter> {
+        #[cfg(feature = "prometheus")]
+        if let Some(addr) = self.metrics_addr() {
+            return Some(MetricsExporter::Prometheus(addr));
+        }
+        self.metrics.clone()
+    }
+
+    /// Address given with `--metrics-addr` on `bore local` or `bore server`.
+    #[cfg(feature = "prometheus")]
+    fn metrics_addr(&self) -> Option<SocketAddr> {
+        match &self.command {
+            Some(Command::Local(local_args)) => local_args.metrics_addr,
+            Some(Command::Server(server_args)) => server_args.metrics_addr,
+            _ => None,
+        }
+    }
 }
 
 /// Validates parsed CLI arguments.
@@ -151,15 +570,36 @@ pub fn validate_args(args: &Args) -> std::result::Result<(), clap::Error> {
                 "home mode requires --web-addr to bind to loopback",
             ))
         }
+        #[cfg(feature = "prometheus")]
+        Some(_) if args.metrics_addr().is_some() && args.metrics.is_some() => Err(Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--metrics-addr cannot be used together with --metrics",
+            )),
         _ => Ok(()),
     }
 }
 
+/// Whether stdout is reserved for machine-readable output, so logs go to stderr.
+pub fn json_output(args: &Args) -> bool {
+    matches!(&args.command, Some(Command::Local(local)) if local.output == OutputFormat::Json)
+}
+
 /// Runs parsed CLI command.
 pub async fn run(args: Args) -> Result<()> {
     if let Err(err) = validate_args(&args) {
         err.exit();
     }
+    #[cfg(unix)]
+    tokio::spawn(async {
+        if let Err(err) = logging::toggle_debug_on_sigusr1().await {
+            warn!(%err, "failed to handle SIGUSR1");
+        }
+    });
+    if let Some(exporter) = &args.metrics_exporter() {
+        metrics::install(exporter)?;
+        info!(%exporter, "metrics exporter installed");
+    }
 
     match args.command {
         Some(_) if args.web => {
@@ -171,7 +611,7 @@ pub async fn run(args: Args) -> Result<()> {
                 .exit();
         }
         None if args.web => {
-            return run_web_local(args.web_addr).await;
+            return run_web_local(args.web_addr, &args.console, &args.storage, &args.tls).await;
         }
         None => {
             Args::command()
@@ -182,20 +622,60 @@ pub async fn run(args: Args) -> Result<()> {
                 .exit();
         }
         Some(Command::Local(local_args)) => {
-            run_local(
-                local_args,
-                async {
-                    let _ = tokio::signal::ctrl_c().await;
-                },
-                None,
-            )
-            .await?;
+            let tunnels = local_args.split_tunnels();
+            let labeled = tunnels.len() > 1;
+            let mut printers = Vec::new();
+            let runs: Vec<_> = tunnels
+                .into_iter()
+                .map(|tunnel_args| {
+                    let label = labeled
+                        .then(|| format!("{}:{}", tunnel_args.local_host, tunnel_args.local_port));
+                    let event_tx = match tunnel_args.output {
+                        OutputFormat::Text if tunnel_args.check => {
+                            let (event_tx, event_rx) = mpsc::unbounded_channel();
+                            printers.push(tokio::spawn(print_check(event_rx, label)));
+                            Some(event_tx)
+                        }
+                        OutputFormat::Text => None,
+                        OutputFormat::Json => {
+                            let (event_tx, event_rx) = mpsc::unbounded_channel();
+                            printers.push(tokio::spawn(print_events(event_rx, label)));
+                            Some(event_tx)
+                        }
+                    };
+                    run_local(
+                        tunnel_args,
+                        async {
+                            let _ = tokio::signal::ctrl_c().await;
+                        },
+                        event_tx,
+                    )
+                })
+                .collect();
+            // Stop every tunnel as soon as one of them fails.
+            let result = try_join_all(runs).await;
+            for printer in printers {
+                printer.await?;
+            }
+            result?;
+        }
+        Some(Command::Connect(connect_args)) => {
+            let listener = TcpListener::bind(connect_args.listen)
+                .await
+                .with_context(|| format!("could not bind {}", connect_args.listen))?;
+            e2e::serve(listener, connect_args.remote, connect_args.e2e_key).await?;
         }
         Some(Command::Web(web_args)) => {
             if web_args.remote {
                 run_web_remote(web_args).await?;
             } else {
-                run_web_local(web_args.web_addr).await?;
+                run_web_local(
+                    web_args.web_addr,
+                    &web_args.console,
+                    &web_args.storage,
+                    &web_args.tls,
+                )
+                .await?;
             }
         }
         Some(Command::Home(home_args)) => {
@@ -211,18 +691,271 @@ pub async fn run(args: Args) -> Result<()> {
             let mut server = Server::new(port_range, server_args.secret.as_deref());
             server.set_bind_addr(server_args.bind_addr);
             server.set_bind_tunnels(server_args.bind_tunnels.unwrap_or(server_args.bind_addr));
-            server.listen().await?;
+            server.set_allow_relay(server_args.allow_relay);
+            if let Some(secret) = &server_args.secondary_secret {
+                server.set_secondary_secret(secret);
+            }
+            for reservation in &server_args.reserve {
+                server.reserve_ports(reservation.ports, &reservation.secret);
+            }
+            if let Some(path) = server_args.tokens.clone() {
+                let tokens = tokens::load(&path)?;
+                info!(count = tokens.len(), "loaded tokens");
+                server.set_tokens(tokens);
+                let updater = server.token_updater();
+                tokio::spawn(async move {
+                    tokens::watch(&path, |tokens| updater.update(tokens)).await;
+                });
+            }
+            if let Some(limit) = server_args.handshake_limit {
+                server.set_handshake_limit(limit);
+            }
+            if let Some(limit) = server_args.global_handshake_limit {
+                server.set_global_handshake_limit(limit);
+            }
+            if let Some(limit) = server_args.visitor_limit {
+                server.set_visitor_limit(limit);
+            }
+            if let Some(target) = &server_args.audit_log {
+                server.set_audit_log(AuditLog::open(target)?);
+                info!(%target, "writing audit log");
+            }
+            if let Some(max_rate) = server_args.max_rate {
+                server.set_max_rate(max_rate);
+            }
+            if let Some(max_connections) = server_args.max_connections {
+                server.set_max_connections(max_connections);
+            }
+            if let Some(secs) = server_args.idle_timeout {
+                server.set_idle_timeout(Duration::from_secs(secs));
+            }
+            if let Some(secs) = server_args.heartbeat_interval {
+                server.set_heartbeat_interval(Duration::from_secs(secs));
+            }
+            if let Some(secs) = server_args.heartbeat_timeout {
+                server.set_heartbeat_timeout(Duration::from_secs(secs));
+            }
+            if let Some(domain) = &server_args.http_domain {
+                server.set_http_routing(domain, server_args.http_port);
+                if let Some(port) = server_args.https_port {
+                    server.set_tls_routing(port);
+                }
+            }
+            server.set_access_rules(AccessRules {
+                allow: server_args.allow,
+                deny: server_args.deny,
+            });
+            if let (Some(cert), Some(key)) = (&server_args.tls_cert, &server_args.tls_key) {
+                server.set_tls(ServerTls::from_pem_files(cert, key)?);
+            }
+            let reload: admin::Reload = {
+                let reloader = server.reloader();
+                let updater = server.token_updater();
+                Arc::new(move || reload_server(&reloader, &updater))
+            };
+            #[cfg(unix)]
+            {
+                let reload = Arc::clone(&reload);
+                tokio::spawn(async move {
+                    use tokio::signal::unix::{signal, SignalKind};
+                    let mut hangup = match signal(SignalKind::hangup()) {
+                        Ok(hangup) => hangup,
+                        Err(err) => return warn!(%err, "could not listen for SIGHUP"),
+                    };
+                    while hangup.recv().await.is_some() {
+                        if let Err(err) = reload() {
+                            warn!(%err, "failed to reload configuration");
+                        }
+                    }
+                });
+            }
+            if let Some(addr) = server_args.admin_addr {
+                if !addr.ip().is_loopback() && server_args.admin_token.is_none() {
+                    Args::command()
+                        .error(
+                            ErrorKind::MissingRequiredArgument,
+                            "--admin-token is required when --admin-addr is not a loopback address",
+                        )
+                        .exit();
+                }
+                let login = server_args.admin_token.as_deref().map(Login::new);
+                let router = admin::router(&server, login, Some(Arc::clone(&reload)));
+                tokio::spawn(async move {
+                    if let Err(err) = admin::serve(addr, router).await {
+                        error!(%err, "admin API stopped");
+                    }
+                });
+            }
+            let notifier = server.restart_notifier();
+            let drainer = server.drainer();
+            let ready = server.ready();
+            tokio::spawn(async move {
+                if ready.await.is_err() {
+                    return;
+                }
+                systemd::notify("READY=1");
+                if let Some(period) = systemd::watchdog_interval() {
+                    // The pings stop if the runtime hangs, so systemd can restart the server.
+                    let mut interval = tokio::time::interval(period);
+                    loop {
+                        interval.tick().await;
+                        systemd::notify("WATCHDOG=1");
+                    }
+                }
+            });
+            let mut listen = tokio::spawn(server.listen());
+            tokio::select! {
+                result = &mut listen => result??,
+                _ = shutdown_signal() => {
+                    systemd::notify("STOPPING=1");
+                    if let Some(delay) = server_args.reconnect_after {
+                        let hint = ReconnectHint {
+                            delay,
+                            to: server_args.reconnect_to,
+                        };
+                        let clients = notifier.notify(hint);
+                        info!(clients, "sent reconnect hints");
+                        // Give tunnel tasks a moment to deliver the hint.
+                        tokio::time::sleep(RECONNECT_HINT_FLUSH).await;
+                    }
+                    let period = Duration::from_secs(server_args.drain_timeout);
+                    info!(?period, "draining connections");
+                    if drainer.drain(period).await {
+                        info!("all connections finished");
+                    } else {
+                        warn!("drain period ended with connections still open");
+                    }
+                    listen.abort();
+                }
+            }
         }
     }
 
     Ok(())
 }
 
-pub async fn run_web_local(web_addr: SocketAddr) -> Result<()> {
-    web::serve(web::ServeConfig {
-        addr: web_addr,
-        session: SessionInfo::local(),
-    })
+/// Read the server's arguments and configuration file again, and apply the settings that can
+/// change while it runs: the port range, access rules, primary and secondary secrets, and tokens
+/// file.
+fn reload_server(reloader: &Reloader, updater: &TokenUpdater) -> Result<()> {
+    let args = Args::try_parse_from(config::with_config_file(std::env::args_os())?)?;
+    let Some(Command::Server(server_args)) = args.command else {
+        bail!("not running as a server");
+    };
+    let port_range = server_args.min_port..=server_args.max_port;
+    ensure!(!port_range.is_empty(), "port range is empty");
+    // Load everything before applying anything, so a bad file changes nothing.
+    let tokens = server_args
+        .tokens
+        .as_deref()
+        .map(tokens::load)
+        .transpose()?;
+    info!(?port_range, "reloading configuration");
+    reloader.set_port_range(port_range);
+    reloader.set_access_rules(AccessRules {
+        allow: server_args.allow,
+        deny: server_args.deny,
+    });
+    reloader.set_secret(server_args.secret.as_deref());
+    reloader.set_secondary_secret(server_args.secondary_secret.as_deref());
+    if let Some(tokens) = tokens {
+        info!(count = tokens.len(), "reloaded tokens");
+        updater.update(tokens);
+    }
+    Ok(())
+}
+
+/// Wait for Ctrl-C, or for SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(err) => warn!(%err, "could not listen for SIGTERM"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Print tunnel events as JSON lines, tagged with the tunnel they belong to if given.
+async fn print_events(mut event_rx: mpsc::UnboundedReceiver<TunnelEvent>, tunnel: Option<String>) {
+    while let Some(event) = event_rx.recv().await {
+        let line = match &tunnel {
+            None => serde_json::to_string(&event),
+            Some(tunnel) => serde_json::to_value(&event).map(|mut value| {
+                if let Some(object) = value.as_object_mut() {
+                    object.insert("tunnel".into(), tunnel.clone().into());
+                }
+                value.to_string()
+            }),
+        };
+        match line {
+            Ok(line) => println!("{line}"),
+            Err(err) => warn!(%err, "failed to encode tunnel event"),
+        }
+    }
+}
+
+/// Print whether a `--check` run passed, tagged with the tunnel it belongs to if given.
+async fn print_check(mut event_rx: mpsc::UnboundedReceiver<TunnelEvent>, tunnel: Option<String>) {
+    let prefix = tunnel
+        .map(|tunnel| format!("{tunnel}: "))
+        .unwrap_or_default();
+    while let Some(event) = event_rx.recv().await {
+        match event {
+            TunnelEvent::Started {
+                remote_port: Some(port),
+            } => println!("{prefix}check passed: server accepted remote port {port}"),
+            TunnelEvent::Failed(err) => println!("{prefix}check failed: {err}"),
+            _ => {}
+        }
+    }
+}
+
+/// Require a web console password if one is given, or generate one if the console is exposed.
+fn web_login(password: Option<String>, exposed: bool) -> Option<Login> {
+    match password {
+        Some(password) => Some(Login::new(&password)),
+        None if exposed => {
+            let password = Login::generate_password();
+            println!("Web console password: {password}");
+            Some(Login::new(&password))
+        }
+        None => None,
+    }
+}
+
+pub async fn run_web_local(
+    web_addr: SocketAddr,
+    console: &ConsoleArgs,
+    storage: &StorageArgs,
+    tls: &WebTlsArgs,
+) -> Result<()> {
+    let hosts: Vec<_> = Some(web_addr.ip())
+        .filter(|ip| !ip.is_unspecified())
+        .map(|ip| ip.to_string())
+        .into_iter()
+        .collect();
+    let tls = tls.load(&hosts)?;
+    let mut state = WebState::new(SessionInfo::local());
+    console.apply(&mut state, !web_addr.ip().is_loopback());
+    storage.apply(&mut state).await?;
+    web::serve_with_state(
+        web::ServeConfig {
+            addr: web_addr,
+            session: SessionInfo::local(),
+            tls,
+        },
+        state,
+        tokio::signal::ctrl_c(),
+    )
     .await
 }
 
@@ -231,20 +964,25 @@ pub async fn run_web_remote(args: WebArgs) -> Result<()> {
         .to
         .clone()
         .ok_or_else(|| anyhow!("--to is required when --remote is used"))?;
+    let tls = args.tls.load(std::slice::from_ref(&server))?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
     let session = SessionInfo {
         mode: SessionMode::RemoteWeb,
         warnings: vec![WEB_RISK_WARNING.to_string()],
         loopback_only: true,
-        web_remote_url: Some(format!("http://{}:{}", server, args.port)),
+        web_remote_url: Some(format!("{scheme}://{}:{}", server, args.port)),
         ssh_remote_endpoint: None,
     };
-    let state = WebState::new(session);
+    let mut state = WebState::new(session);
+    args.console.apply(&mut state, true);
+    args.storage.apply(&mut state).await?;
     println!("{WEB_RISK_WARNING}");
-    let display_url = format!("http://{}:{}", server, args.port);
+    let display_url = format!("{scheme}://{}:{}", server, args.port);
     web::run_managed(
         web::ServeConfig {
             addr: args.web_addr,
             session: state.session().await,
+            tls,
         },
         state,
         vec![SystemTunnelSpec {
@@ -256,6 +994,9 @@ pub async fn run_web_remote(args: WebArgs) -> Result<()> {
                 port: Some(args.port),
                 local_host: "127.0.0.1".to_string(),
                 secret: args.secret,
+                depends_on: Vec::new(),
+                profile: None,
+                restart: RestartPolicy::default(),
             },
             display_url: Some(display_url),
         }],
@@ -268,20 +1009,25 @@ pub async fn run_web_remote(args: WebArgs) -> Result<()> {
 
 pub async fn run_home(args: HomeArgs) -> Result<()> {
     let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+    let tls = args.tls.load(std::slice::from_ref(&args.to))?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
     let session = SessionInfo {
         mode: SessionMode::Home,
         warnings: vec![WEB_RISK_WARNING.to_string()],
         loopback_only: true,
-        web_remote_url: Some(format!("http://{}:{}", args.to, args.web_port)),
+        web_remote_url: Some(format!("{scheme}://{}:{}", args.to, args.web_port)),
         ssh_remote_endpoint: Some(format!("{}:{}", args.to, args.ssh_port)),
     };
-    let state = WebState::new(session);
+    let mut state = WebState::new(session);
+    args.console.apply(&mut state, true);
+    args.storage.apply(&mut state).await?;
     println!("{WEB_RISK_WARNING}");
     println!("SSH access: ssh {user}@{} -p {}", args.to, args.ssh_port);
     web::run_managed(
         web::ServeConfig {
             addr: args.web_addr,
             session: state.session().await,
+            tls,
         },
         state,
         vec![
@@ -294,8 +1040,11 @@ pub async fn run_home(args: HomeArgs) -> Result<()> {
                     port: Some(args.web_port),
                     local_host: "127.0.0.1".to_string(),
                     secret: args.secret.clone(),
+                    depends_on: Vec::new(),
+                    profile: None,
+                    restart: RestartPolicy::default(),
                 },
-                display_url: Some(format!("http://{}:{}", args.to, args.web_port)),
+                display_url: Some(format!("{scheme}://{}:{}", args.to, args.web_port)),
             },
             SystemTunnelSpec {
                 role: SystemTunnelRole::Ssh,
@@ -306,6 +1055,9 @@ pub async fn run_home(args: HomeArgs) -> Result<()> {
                     port: Some(args.ssh_port),
                     local_host: "127.0.0.1".to_string(),
                     secret: args.secret,
+                    depends_on: Vec::new(),
+                    profile: None,
+                    restart: RestartPolicy::default(),
                 },
                 display_url: Some(format!("{}:{}", args.to, args.ssh_port)),
             },
@@ -321,7 +1073,7 @@ pub async fn run_home(args: HomeArgs) -> Result<()> {
 mod tests {
     use clap::{error::ErrorKind, CommandFactory, Parser};
 
-    use super::{validate_args, Args, Command};
+    use super::{validate_args, Args, Command, MetricsExporter, OutputFormat};
 
     #[test]
     fn parse_web_long_flag() {
@@ -352,6 +1104,104 @@ mod tests {
         assert!(args.command.is_some());
     }
 
+    #[test]
+    fn parse_local_backends() {
+        let args = Args::try_parse_from([
+            "bore",
+            "local",
+            "8000",
+            "--to",
+            "bore.pub",
+            "--backend",
+            "localhost:8001",
+            "--backend",
+            "[::1]:8002",
+            "--sticky",
+        ])
+        .expect("parse should succeed");
+        let Some(Command::Local(local)) = args.command else {
+            panic!("expected local command");
+        };
+        assert_eq!(local.backends.len(), 2);
+        assert_eq!(local.backends[1].host, "::1");
+        assert!(local.sticky);
+    }
+
+    #[test]
+    fn parse_local_extra_tunnels() {
+        let args = Args::try_parse_from([
+            "bore",
+            "local",
+            "3000",
+            "--to",
+            "bore.pub",
+            "--port-range",
+            "8000-8100",
+            "--tunnel",
+            "5432",
+            "--tunnel",
+            "db.lan:8080:9000",
+        ])
+        .expect("parse should succeed");
+        let Some(Command::Local(local)) = args.command else {
+            panic!("expected local command");
+        };
+        let tunnels = local.split_tunnels();
+        assert_eq!(tunnels.len(), 3);
+        assert!(tunnels[0].port_range.is_some() && tunnels[0].tunnels.is_empty());
+        assert_eq!(tunnels[1].local_host, "localhost");
+        assert_eq!((tunnels[1].local_port, tunnels[1].port), (5432, 0));
+        assert!(tunnels[1].port_range.is_none());
+        assert_eq!(tunnels[2].local_host, "db.lan");
+        assert_eq!((tunnels[2].local_port, tunnels[2].port), (8080, 9000));
+        assert_eq!(tunnels[2].to, "bore.pub");
+    }
+
+    #[test]
+    fn parse_local_json_output() {
+        let args = Args::try_parse_from([
+            "bore", "local", "8000", "--to", "bore.pub", "--output", "json",
+        ])
+        .expect("parse should succeed");
+        let Some(Command::Local(local)) = args.command else {
+            panic!("expected local command");
+        };
+        assert_eq!(local.output, OutputFormat::Json);
+    }
+
+    #[test]
+    fn parse_global_metrics_flag() {
+        let args = Args::try_parse_from(["bore", "server", "--metrics", "stdout"])
+            .expect("parse should succeed");
+        assert_eq!(args.metrics, Some(MetricsExporter::Stdout));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn parse_server_metrics_addr() {
+        let args = Args::try_parse_from(["bore", "server", "--metrics-addr", "127.0.0.1:9100"])
+            .expect("parse should succeed");
+        assert_eq!(
+            args.metrics_exporter(),
+            Some(MetricsExporter::Prometheus(
+                "127.0.0.1:9100".parse().unwrap()
+            ))
+        );
+        assert!(validate_args(&args).is_ok());
+
+        let args = Args::try_parse_from([
+            "bore",
+            "server",
+            "--metrics-addr",
+            "127.0.0.1:9100",
+            "--metrics",
+            "stdout",
+        ])
+        .expect("parse should succeed");
+        let err = validate_args(&args).expect_err("conflicting exporters should be rejected");
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
     #[test]
     fn parse_web_subcommand() {
         let args = Args::try_parse_from(["bore", "web", "--web-addr", "127.0.0.1:9000"])
@@ -360,6 +1210,78 @@ mod tests {
         assert!(matches!(args.command, Some(Command::Web(_))));
     }
 
+    #[test]
+    fn parse_web_log_files() {
+        let args =
+            Args::try_parse_from(["bore", "web", "--log-dir", "/tmp/bore", "--log-keep", "2"])
+                .expect("parse should succeed");
+        let Some(Command::Web(web_args)) = args.command else {
+            panic!("expected web command");
+        };
+        assert_eq!(web_args.storage.log_dir, Some("/tmp/bore".into()));
+        assert_eq!(web_args.storage.log_keep, 2);
+        assert_eq!(web_args.storage.log_max_size, 1024 * 1024);
+
+        assert!(Args::try_parse_from(["bore", "web", "--log-keep", "2"]).is_err());
+    }
+
+    #[test]
+    fn parse_console_options() {
+        let args = Args::try_parse_from(["bore", "-w", "--max-tunnels", "3"])
+            .expect("parse should succeed");
+        assert_eq!(args.console.max_tunnels, Some(3));
+        let args = Args::try_parse_from([
+            "bore",
+            "home",
+            "--to",
+            "host",
+            "--web-allowed-origin",
+            "https://a.example,https://b.example",
+        ])
+        .expect("parse should succeed");
+        let Some(Command::Home(home_args)) = args.command else {
+            panic!("expected home command");
+        };
+        assert_eq!(
+            home_args.console.web_allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn parse_web_tls() {
+        let args = Args::try_parse_from(["bore", "-w", "--tls-self-signed"])
+            .expect("parse should succeed");
+        assert!(args.tls.tls_self_signed);
+        let args = Args::try_parse_from([
+            "bore",
+            "web",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ])
+        .expect("parse should succeed");
+        let Some(Command::Web(web_args)) = args.command else {
+            panic!("expected web command");
+        };
+        assert_eq!(web_args.tls.tls_cert, Some("cert.pem".into()));
+
+        assert!(Args::try_parse_from(["bore", "web", "--tls-cert", "cert.pem"]).is_err());
+        assert!(Args::try_parse_from([
+            "bore",
+            "home",
+            "--to",
+            "host",
+            "--tls-self-signed",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ])
+        .is_err());
+    }
+
     #[test]
     fn parse_remote_web_subcommand() {
         let args = Args::try_parse_from(["bore", "web", "--remote", "--to", "host"])
@@ -413,6 +1335,14 @@ mod tests {
         assert!(args.command.is_some());
     }
 
+    #[test]
+    fn allow_relay_requires_credentials() {
+        assert!(Args::try_parse_from(["bore", "server", "--allow-relay"]).is_err());
+        let args = Args::try_parse_from(["bore", "server", "--allow-relay", "--secret", "abc"])
+            .expect("parse should succeed");
+        assert!(args.command.is_some());
+    }
+
     #[test]
     fn parse_version_short_flag() {
         let err = Args::try_parse_from(["bore", "-v"]).expect_err("version should exit");
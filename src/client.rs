@@ -1,28 +1,118 @@
 //! Client implementation for the `bore` service.
 
-use std::{future::Future, sync::Arc};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use std::{future::Future, net::SocketAddr, str::FromStr, sync::Arc};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use dashmap::DashMap;
+use futures_util::Stream;
+use hickory_resolver::Resolver;
+use metrics::{counter, gauge};
 use serde::{Deserialize, Serialize};
-use tokio::{io::AsyncWriteExt, net::TcpStream, sync::mpsc, time::timeout};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, watch, Notify},
+    time::{interval, sleep, timeout, MissedTickBehavior},
+};
+use tokio_util::task::AbortOnDropHandle;
 use tracing::{error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
+use crate::access::{AccessRules, Cidr};
 use crate::auth::Authenticator;
-use crate::shared::{ClientMessage, Delimited, ServerMessage, CONTROL_PORT, NETWORK_TIMEOUT};
+use crate::balance::{Backend, Balancer};
+use crate::e2e::{self, E2eKey};
+use crate::egress::EgressProxy;
+use crate::idle;
+use crate::mux::{MuxStream, Session};
+use crate::proxy_protocol::ProxyProtocol;
+use crate::shared::{
+    ClientMessage, Delimited, Features, HelloRequest, PortRange, ReconnectHint, ServerMessage,
+    WhenDown, CONTROL_PORT, MAX_FRAME_LENGTH, MAX_PORT_RANGES, NETWORK_TIMEOUT,
+};
+use crate::socks::{Socks, SocksAuth};
+use crate::stats::{format_bytes, ConnectionBytes, Counted, TrafficSnapshot, TrafficStats};
+use crate::tls::{ClientTls, Io, Transport};
+use crate::transport::{Dialer, TcpDialer};
+use crate::vhost;
+
+/// Number of attempts made to reconnect after the server sends a reconnect hint.
+const RECONNECT_ATTEMPTS: u32 = 10;
+
+/// Delay between attempts to reconnect after a reconnect hint.
+const RECONNECT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Default delay before the first attempt to reconnect with `--reconnect`.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Default longest delay between attempts to reconnect with `--reconnect`.
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Default fraction of each reconnect delay that is randomized with `--reconnect`.
+const RECONNECT_JITTER: f64 = 0.2;
+
+/// How often the `--progress` display is refreshed.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size of the pipes between a tunnel connection and services run in this process.
+const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Default time between pings, sent to measure the round-trip time if the server supports it.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
 
 /// CLI arguments for the local client tunnel.
-#[derive(clap::Args, Debug, Clone, Serialize, Deserialize)]
+#[derive(clap::Args, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct LocalArgs {
     /// The local port to expose.
-    #[arg(env = "BORE_LOCAL_PORT")]
+    #[arg(
+        env = "BORE_LOCAL_PORT",
+        required_unless_present_any = ["local_socket", "socks5"],
+        default_value_t = 0
+    )]
     pub local_port: u16,
 
     /// The local host to expose.
     #[arg(short, long, value_name = "HOST", default_value = "localhost")]
     pub local_host: String,
 
+    /// Unix domain socket of the local service to expose, instead of a host and port.
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "BORE_LOCAL_SOCKET",
+        conflicts_with_all = ["local_port", "local_host", "backends", "proxy_protocol"]
+    )]
+    #[serde(default)]
+    pub local_socket: Option<PathBuf>,
+
+    /// Run a built-in SOCKS5 proxy as the local service, so visitors can reach this network.
+    #[arg(
+        long,
+        conflicts_with_all = ["local_port", "local_host", "local_socket", "backends", "proxy_protocol"]
+    )]
+    #[serde(default)]
+    pub socks5: bool,
+
+    /// Username and password that SOCKS5 clients must send, as USER:PASSWORD.
+    #[arg(
+        long,
+        value_name = "USER:PASSWORD",
+        env = "BORE_SOCKS_AUTH",
+        hide_env_values = true,
+        requires = "socks5",
+        value_parser = parse_socks_auth
+    )]
+    #[serde(default)]
+    pub socks_auth: Option<String>,
+
     /// Address of the remote server to expose local ports to.
+    ///
+    /// Names like `_bore._tcp.example.com` are resolved through DNS SRV records.
     #[arg(short, long, env = "BORE_SERVER")]
     pub to: String,
 
@@ -30,13 +120,433 @@ pub struct LocalArgs {
     #[arg(short, long, default_value_t = 0)]
     pub port: u16,
 
+    /// Preferred port on the remote server, tried in order before --port-range; can be repeated.
+    #[arg(long = "preferred-port", value_name = "PORT", conflicts_with = "port")]
+    #[serde(default)]
+    pub preferred_ports: Vec<u16>,
+
+    /// Accept any free port on the remote server in this range, as START-END.
+    #[arg(long, value_name = "START-END", conflicts_with = "port")]
+    #[serde(default)]
+    pub port_range: Option<PortRange>,
+
+    /// Name to reach the tunnel at over HTTP, as a subdomain of the server's routing domain.
+    ///
+    /// The tunnel gets no port of its own, so the server must have HTTP routing enabled.
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = "BORE_NAME",
+        value_parser = parse_name,
+        conflicts_with_all = ["port", "preferred_ports", "port_range"]
+    )]
+    #[serde(default)]
+    pub name: Option<String>,
+
     /// Optional secret for authentication.
     #[arg(short, long, env = "BORE_SECRET", hide_env_values = true)]
     pub secret: Option<String>,
+
+    /// Address of an intermediate server used to reach the remote server.
+    #[arg(long, value_name = "HOST", env = "BORE_VIA")]
+    pub via: Option<String>,
+
+    /// Optional secret for authentication with the intermediate server.
+    #[arg(
+        long,
+        env = "BORE_VIA_SECRET",
+        hide_env_values = true,
+        requires = "via"
+    )]
+    pub via_secret: Option<String>,
+
+    /// Encrypt tunnel traffic with this key, so only `bore connect` with the same key can read it.
+    #[arg(
+        long,
+        value_name = "KEY",
+        env = "BORE_E2E_KEY",
+        hide_env_values = true,
+        value_parser = parse_e2e_key
+    )]
+    #[serde(default)]
+    pub e2e_key: Option<String>,
+
+    /// Reach the server through a proxy, as http:// or socks5://[USER:PASSWORD@]HOST:PORT.
+    #[arg(
+        long,
+        value_name = "URL",
+        env = "BORE_PROXY",
+        hide_env_values = true,
+        value_parser = parse_proxy
+    )]
+    #[serde(default)]
+    pub proxy: Option<String>,
+
+    /// Encrypt connections to the server, and to the intermediate server, with TLS.
+    #[arg(long, env = "BORE_TLS")]
+    #[serde(default)]
+    pub tls: bool,
+
+    /// PEM file of certificate authorities to trust for TLS, instead of the built-in roots.
+    #[arg(long, value_name = "FILE", env = "BORE_TLS_CA", requires = "tls")]
+    #[serde(default)]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Only let visitors from this address or CIDR block connect; can be repeated.
+    #[arg(long, value_name = "CIDR")]
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+
+    /// Refuse visitors from this address or CIDR block; can be repeated.
+    #[arg(long, value_name = "CIDR")]
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+
+    /// Refuse new visitors while this many connections to the tunnel are open.
+    #[arg(long, value_name = "N", env = "BORE_MAX_CONNECTIONS")]
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+
+    /// Close forwarded connections that transfer no data for this many seconds.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_IDLE_TIMEOUT",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+
+    /// Send a ping to the server every this many seconds, so it can detect a dead tunnel.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_HEARTBEAT_INTERVAL",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    #[serde(default)]
+    pub heartbeat_interval: Option<u64>,
+
+    /// Give up on the tunnel if the server sends nothing for this many seconds.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_HEARTBEAT_TIMEOUT",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    #[serde(default)]
+    pub heartbeat_timeout: Option<u64>,
+
+    /// Reconnect with exponential backoff when the connection to the server is lost.
+    #[arg(long, env = "BORE_RECONNECT")]
+    #[serde(default)]
+    pub reconnect: bool,
+
+    /// Give up after this many failed attempts to reconnect in a row; unlimited by default.
+    #[arg(long, value_name = "N", requires = "reconnect")]
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+
+    /// Seconds to wait before the first attempt to reconnect, doubled for each further attempt.
+    #[arg(long, value_name = "SECS", requires = "reconnect")]
+    #[serde(default)]
+    pub reconnect_delay: Option<u64>,
+
+    /// Longest wait between attempts to reconnect, in seconds; 60 by default.
+    #[arg(long, value_name = "SECS", requires = "reconnect")]
+    #[serde(default)]
+    pub reconnect_max_delay: Option<u64>,
+
+    /// Fraction of each wait to randomize, from 0 to 1, so clients do not reconnect in lockstep.
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        requires = "reconnect",
+        value_parser = parse_jitter
+    )]
+    #[serde(default)]
+    pub reconnect_jitter: Option<f64>,
+
+    /// Additional local service to balance connections across, as HOST:PORT.
+    #[arg(long = "backend", value_name = "HOST:PORT")]
+    #[serde(default)]
+    pub backends: Vec<Backend>,
+
+    /// Send each visitor IP address to the same backend, instead of round-robin.
+    #[arg(long, requires = "backends")]
+    #[serde(default)]
+    pub sticky: bool,
+
+    /// Check every this many seconds which backends accept connections, so traffic avoids them.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_HEALTH_CHECK_INTERVAL",
+        requires = "backends",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    #[serde(default)]
+    pub health_check_interval: Option<u64>,
+
+    /// Check every this many seconds whether the local service is up, and tell the server.
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "BORE_SERVICE_CHECK_INTERVAL",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    #[serde(default)]
+    pub service_check_interval: Option<u64>,
+
+    /// Check the local service with an HTTP GET of this path, instead of a TCP connection.
+    #[arg(long, value_name = "PATH", requires = "service_check_interval")]
+    #[serde(default)]
+    pub service_check_path: Option<String>,
+
+    /// What the server does with visitors while the local service is down.
+    #[arg(long, value_enum, requires = "service_check_interval")]
+    #[serde(default)]
+    pub when_down: Option<WhenDown>,
+
+    /// Send a PROXY protocol header with the visitor address to the local service.
+    #[arg(long, value_enum, value_name = "VERSION", env = "BORE_PROXY_PROTOCOL")]
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocol>,
+
+    /// Show a live display of throughput and connections in the terminal.
+    ///
+    /// If logs are written to the same terminal, progress is logged every second instead.
+    #[arg(long)]
+    #[serde(default)]
+    pub progress: bool,
+
+    /// Carry all visitor connections over one data connection to the server, if it supports it.
+    #[arg(long, env = "BORE_MULTIPLEX")]
+    #[serde(default)]
+    pub multiplex: bool,
+
+    /// Format of tunnel events printed to stdout.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    #[serde(default)]
+    pub output: OutputFormat,
+
+    /// Check that the server accepts the secret and port, then exit without keeping the tunnel.
+    #[arg(long, conflicts_with = "progress")]
+    #[serde(default)]
+    pub check: bool,
+
+    /// Another local service to expose through the same server, as [HOST:]PORT[:REMOTE_PORT].
+    ///
+    /// Can be repeated. With `--output json`, events carry a `tunnel` field naming the service.
+    #[arg(
+        long = "tunnel",
+        value_name = "[HOST:]PORT[:REMOTE_PORT]",
+        conflicts_with = "progress"
+    )]
+    #[serde(default)]
+    pub tunnels: Vec<TunnelSpec>,
+
+    /// Serve client metrics in the Prometheus format at /metrics on this address.
+    ///
+    /// Also measures the round-trip time to the server, if the server supports it.
+    #[cfg(feature = "prometheus")]
+    #[arg(long, value_name = "ADDR", env = "BORE_METRICS_ADDR")]
+    #[serde(skip)]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// TOML or YAML file with default values for these options.
+    #[arg(long, value_name = "FILE")]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+}
+
+impl LocalArgs {
+    /// Split into one set of arguments per tunnel, starting with the main one.
+    ///
+    /// Extra tunnels share the server settings, but not the port preferences or backends.
+    pub fn split_tunnels(mut self) -> Vec<LocalArgs> {
+        let specs = std::mem::take(&mut self.tunnels);
+        let mut tunnels = Vec::with_capacity(specs.len() + 1);
+        for spec in specs {
+            tunnels.push(LocalArgs {
+                local_port: spec.local_port,
+                local_host: spec.local_host.unwrap_or_else(|| self.local_host.clone()),
+                local_socket: None,
+                socks5: false,
+                port: spec.port,
+                preferred_ports: Vec::new(),
+                port_range: None,
+                name: None,
+                backends: Vec::new(),
+                sticky: false,
+                health_check_interval: None,
+                ..self.clone()
+            });
+        }
+        tunnels.insert(0, self);
+        tunnels
+    }
+
+    /// Policy for reconnecting after the connection to the server is lost, if enabled.
+    pub fn reconnect_policy(&self) -> Option<ReconnectPolicy> {
+        self.reconnect.then(|| ReconnectPolicy {
+            initial_delay: self
+                .reconnect_delay
+                .map_or(RECONNECT_DELAY, Duration::from_secs),
+            max_delay: self
+                .reconnect_max_delay
+                .map_or(RECONNECT_MAX_DELAY, Duration::from_secs),
+            jitter: self.reconnect_jitter.unwrap_or(RECONNECT_JITTER),
+            max_retries: self.max_retries,
+        })
+    }
+
+    /// Optional protocol capabilities to request for these options.
+    fn features(&self) -> Features {
+        #[cfg(feature = "prometheus")]
+        if self.metrics_addr.is_some() {
+            return Features::PING;
+        }
+        Features::NONE
+    }
+}
+
+/// Parse a tunnel name for HTTP routing.
+fn parse_name(s: &str) -> Result<String, String> {
+    vhost::validate_name(s)?;
+    Ok(s.to_string())
+}
+
+/// Check that SOCKS5 credentials have the form USER:PASSWORD.
+fn parse_socks_auth(s: &str) -> Result<String, String> {
+    s.parse::<SocksAuth>()?;
+    Ok(s.to_string())
+}
+
+/// Check that an end-to-end key is not empty.
+fn parse_e2e_key(s: &str) -> Result<String, String> {
+    s.parse::<E2eKey>()?;
+    Ok(s.to_string())
+}
+
+/// Check that a proxy URL can be used to reach the server.
+fn parse_proxy(s: &str) -> Result<String, String> {
+    s.parse::<EgressProxy>()?;
+    Ok(s.to_string())
+}
+
+/// Parse a jitter fraction between 0 and 1.
+fn parse_jitter(s: &str) -> Result<f64, String> {
+    s.parse()
+        .ok()
+        .filter(|jitter| (0.0..=1.0).contains(jitter))
+        .ok_or_else(|| format!("jitter must be a number from 0 to 1: {s}"))
+}
+
+/// How a client retries connecting to the server after losing its tunnel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt.
+    pub initial_delay: Duration,
+
+    /// Longest delay between attempts, however many have failed.
+    pub max_delay: Duration,
+
+    /// Fraction of each delay that is randomly taken off, from 0 to 1.
+    pub jitter: f64,
+
+    /// Failed attempts in a row before giving up, or unlimited if not set.
+    pub max_retries: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    /// Policy used after a reconnect hint from the server when `--reconnect` is not set.
+    const HINTED: Self = Self {
+        initial_delay: RECONNECT_RETRY_DELAY,
+        max_delay: RECONNECT_RETRY_DELAY,
+        jitter: 0.0,
+        max_retries: Some(RECONNECT_ATTEMPTS),
+    };
+
+    /// Delay before an attempt, counting from 1, doubling each time up to the maximum.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay);
+        delay.mul_f64(1.0 - self.jitter * fastrand::f64())
+    }
+
+    /// Check whether another attempt may follow this many failed ones.
+    fn allows(&self, failed: u32) -> bool {
+        self.max_retries.is_none_or(|max| failed < max)
+    }
+}
+
+/// Additional tunnel run by the same `bore local` process.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelSpec {
+    /// Local host to expose, or `--local-host` if not set.
+    pub local_host: Option<String>,
+
+    /// Local port to expose.
+    pub local_port: u16,
+
+    /// Port on the remote server to select, or 0 for any port.
+    pub port: u16,
+}
+
+impl FromStr for TunnelSpec {
+    type Err = anyhow::Error;
+
+    /// Parse `[HOST:]PORT[:REMOTE_PORT]`, with IPv6 hosts written as `[::1]:PORT`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (host, ports) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (host, ports) = rest.split_once("]:").context("expected [HOST]:PORT")?;
+                (Some(host), ports)
+            }
+            None => match s.split(':').collect::<Vec<_>>()[..] {
+                [_] => (None, s),
+                [first, _] if first.parse::<u16>().is_ok() => (None, s),
+                [host, _] | [host, _, _] => (Some(host), &s[host.len() + 1..]),
+                _ => bail!("expected [HOST:]PORT[:REMOTE_PORT]"),
+            },
+        };
+        if host.is_some_and(str::is_empty) {
+            bail!("tunnel host cannot be empty");
+        }
+        let (local_port, port) = match ports.split_once(':') {
+            Some((local_port, port)) => (local_port, port.parse().context("invalid remote port")?),
+            None => (ports, 0),
+        };
+        Ok(Self {
+            local_host: host.map(str::to_string),
+            local_port: local_port.parse().context("invalid local port")?,
+            port,
+        })
+    }
+}
+
+/// Output format for the local client.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human-readable logs only.
+    #[default]
+    Text,
+
+    /// One JSON-encoded [`TunnelEvent`] per line, for scripts and frontends.
+    Json,
 }
 
 /// Events emitted while a local tunnel is running.
+///
+/// With `--output json`, each event is printed as an object such as
+/// `{"event":"started","data":{"remote_port":4000}}` or `{"event":"stopped"}`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
 pub enum TunnelEvent {
     /// A log line for the tunnel.
     Log(String),
@@ -47,6 +557,38 @@ pub enum TunnelEvent {
         remote_port: Option<u16>,
     },
 
+    /// A visitor connected to the public port and is being forwarded.
+    ConnectionOpened {
+        /// Connection ID assigned by the server.
+        id: Uuid,
+        /// Address of the visitor, if the server reports it.
+        peer: Option<SocketAddr>,
+    },
+
+    /// A forwarded connection ended.
+    ConnectionClosed {
+        /// Connection ID assigned by the server.
+        id: Uuid,
+        /// Why the connection failed, if it did not end normally.
+        error: Option<String>,
+        /// Bytes received from the visitor, or 0 if the connection failed or went idle.
+        #[serde(default)]
+        bytes_in: u64,
+        /// Bytes sent to the visitor, or 0 if the connection failed or went idle.
+        #[serde(default)]
+        bytes_out: u64,
+    },
+
+    /// Connection to the server was lost, and the client will try to reconnect.
+    Reconnecting {
+        /// Number of this attempt, starting from 1.
+        attempt: u32,
+        /// Milliseconds to wait before the attempt.
+        delay_ms: u64,
+        /// Why the previous connection or attempt ended.
+        error: String,
+    },
+
     /// Tunnel stopped cleanly.
     Stopped,
 
@@ -54,26 +596,319 @@ pub enum TunnelEvent {
     Failed(String),
 }
 
+/// Stream of events from running tunnels, for building on typed events instead of logs.
+///
+/// Pass the sender from [`TunnelEvents::channel`] to [`Client::with_options`] or
+/// [`run_local`]. The stream ends once every sender is dropped.
+pub struct TunnelEvents(mpsc::UnboundedReceiver<TunnelEvent>);
+
+impl TunnelEvents {
+    /// Create a sender for tunnel events, and the stream that receives them.
+    pub fn channel() -> (mpsc::UnboundedSender<TunnelEvent>, Self) {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        (event_tx, Self(event_rx))
+    }
+}
+
+impl Stream for TunnelEvents {
+    type Item = TunnelEvent;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<TunnelEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+/// Visitor connections that a client is forwarding, which can be inspected and closed.
+#[derive(Debug, Clone, Default)]
+pub struct Connections(Arc<DashMap<Uuid, OpenConnection>>);
+
+impl Connections {
+    /// List the open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<_> = self
+            .0
+            .iter()
+            .map(|conn| {
+                let (bytes_in, bytes_out) = conn.bytes.get();
+                ConnectionInfo {
+                    id: *conn.key(),
+                    peer: conn.peer,
+                    open_secs: conn.opened.elapsed().as_secs(),
+                    bytes_in,
+                    bytes_out,
+                }
+            })
+            .collect();
+        connections.sort_by_key(|conn| std::cmp::Reverse(conn.open_secs));
+        connections
+    }
+
+    /// Close a connection, returning whether it was open.
+    pub fn close(&self, id: Uuid) -> bool {
+        let conn = self.0.get(&id);
+        if let Some(conn) = &conn {
+            conn.close.notify_one();
+        }
+        conn.is_some()
+    }
+
+    /// Count a connection as open until the returned slot is dropped.
+    fn open(&self, id: Uuid, peer: Option<SocketAddr>) -> ConnectionSlot {
+        let bytes = Arc::new(ConnectionBytes::default());
+        let close = Arc::new(Notify::new());
+        self.0.insert(
+            id,
+            OpenConnection {
+                peer,
+                opened: Instant::now(),
+                bytes: Arc::clone(&bytes),
+                close: Arc::clone(&close),
+            },
+        );
+        ConnectionSlot {
+            connections: self.clone(),
+            id,
+            peer,
+            bytes,
+            close,
+        }
+    }
+}
+
+/// Visitor connection of a client, from when it arrives until it closes.
+#[derive(Debug)]
+struct OpenConnection {
+    peer: Option<SocketAddr>,
+    opened: Instant,
+    bytes: Arc<ConnectionBytes>,
+    close: Arc<Notify>,
+}
+
+/// Keeps a connection listed in [`Connections`] until dropped.
+struct ConnectionSlot {
+    connections: Connections,
+    id: Uuid,
+    peer: Option<SocketAddr>,
+    bytes: Arc<ConnectionBytes>,
+    close: Arc<Notify>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.connections.0.remove(&self.id);
+    }
+}
+
+/// Point-in-time description of an open visitor connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    /// Connection ID assigned by the server, used to close it.
+    pub id: Uuid,
+
+    /// Address of the visitor, if the server reports it.
+    pub peer: Option<SocketAddr>,
+
+    /// Seconds since the connection arrived.
+    pub open_secs: u64,
+
+    /// Bytes received from the visitor so far.
+    pub bytes_in: u64,
+
+    /// Bytes sent to the visitor so far.
+    pub bytes_out: u64,
+}
+
+/// Periodic check of whether the local service is up, reported to the server.
+#[derive(Debug, Clone)]
+pub struct ServiceCheck {
+    /// Time between checks.
+    pub interval: Duration,
+
+    /// Path to request with HTTP GET, or `None` to only open a connection.
+    pub path: Option<String>,
+
+    /// What the server does with visitors while the service is down.
+    pub when_down: WhenDown,
+}
+
+/// Optional settings for a client, beyond the required tunnel addresses.
+#[derive(Debug, Default, Clone)]
+pub struct ClientOptions {
+    /// Optional protocol capabilities to request from the server.
+    pub features: Features,
+
+    /// Acceptable remote ports in order of preference, used when no fixed port is requested.
+    pub ports: Vec<PortRange>,
+
+    /// Name to reach the tunnel by through the server's shared HTTP port, instead of a port.
+    pub name: Option<String>,
+
+    /// Visitor addresses that may connect, enforced by the server.
+    pub access: AccessRules,
+
+    /// Most visitor connections open at once, enforced by the server.
+    pub max_connections: Option<u32>,
+
+    /// Close proxied connections that transfer no data for this long.
+    pub idle_timeout: Option<Duration>,
+
+    /// Time between pings sent to the server, if it supports them.
+    pub heartbeat_interval: Option<Duration>,
+
+    /// How long the server may send nothing before the tunnel fails.
+    pub heartbeat_timeout: Option<Duration>,
+
+    /// Address of an intermediate server that relays connections to the server.
+    pub via: Option<String>,
+
+    /// Optional secret for authentication with the intermediate server.
+    pub via_secret: Option<String>,
+
+    /// Proxy that connections to the server, or the intermediate server, are made through.
+    pub proxy: Option<EgressProxy>,
+
+    /// Transport that connections to the server are opened with, instead of TCP or `proxy`.
+    pub dialer: Option<Arc<dyn Dialer>>,
+
+    /// Key that tunnel traffic is encrypted with, so the server cannot read it.
+    pub e2e_key: Option<E2eKey>,
+
+    /// Unix domain socket of the local service, used instead of the local host and port.
+    pub local_socket: Option<PathBuf>,
+
+    /// Built-in SOCKS5 proxy to serve as the local service, instead of connecting anywhere.
+    pub socks: Option<Socks>,
+
+    /// Carry visitor connections as streams over one data connection, if the server supports it.
+    pub multiplex: bool,
+
+    /// Additional local services to balance connections across.
+    pub backends: Vec<Backend>,
+
+    /// Whether visitors from the same IP address stick to the same backend.
+    pub sticky: bool,
+
+    /// Time between checks of which backends accept connections, if set.
+    pub health_check_interval: Option<Duration>,
+
+    /// Check of the local service, reported to the server so it can turn visitors away.
+    pub service_check: Option<ServiceCheck>,
+
+    /// PROXY protocol header to send to local services, with the visitor address.
+    pub proxy_protocol: Option<ProxyProtocol>,
+
+    /// Traffic counters to update, shared across reconnects if provided.
+    pub stats: Option<Arc<TrafficStats>>,
+
+    /// Registry of open visitor connections to update, shared across reconnects if provided.
+    pub connections: Option<Connections>,
+
+    /// TLS settings for connections to the server and the intermediate server.
+    pub tls: Option<ClientTls>,
+}
+
+/// Intermediate server that relays control and data connections.
+struct Hop {
+    /// Address of the intermediate server.
+    to: String,
+
+    /// Optional secret used to authenticate with the intermediate server.
+    auth: Option<Authenticator>,
+}
+
+/// Reason why a client stopped listening for connections.
+enum ListenExit {
+    /// The shutdown future resolved.
+    Shutdown,
+
+    /// The server closed the control connection.
+    Closed,
+
+    /// The server asked the client to reconnect later.
+    Reconnect(ReconnectHint),
+}
+
+/// Address of a server's control port, possibly discovered through DNS SRV records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    host: String,
+    port: u16,
+}
+
 /// State structure for the client.
 pub struct Client {
     /// Control connection to the server.
-    conn: Option<Delimited<TcpStream>>,
+    conn: Option<Delimited<Transport>>,
 
-    /// Destination address of the server.
-    to: String,
+    /// Resolved control endpoint of the server, used for all data connections.
+    server: Endpoint,
+
+    /// Local services that connections are forwarded to.
+    backends: Balancer,
+
+    /// Time between checks of which backends accept connections, if set.
+    health_check_interval: Option<Duration>,
 
-    // Local host that is forwarded.
-    local_host: String,
+    /// Check of the local service, if the server takes reports of its health.
+    service_check: Option<ServiceCheck>,
 
-    /// Local port that is forwarded.
-    local_port: u16,
+    /// Unix domain socket that connections are forwarded to instead of the backends, if set.
+    local_socket: Option<PathBuf>,
+
+    /// Built-in SOCKS5 proxy that serves connections instead of a local service, if set.
+    socks: Option<Socks>,
+
+    /// Key that tunnel traffic is encrypted with end to end, if set.
+    e2e_key: Option<E2eKey>,
+
+    /// Data connection that visitor connections are multiplexed over, if negotiated.
+    session: Option<Session>,
+
+    /// PROXY protocol header to send to local services, if any.
+    proxy_protocol: Option<ProxyProtocol>,
 
     /// Port that is publicly available on the remote.
     remote_port: u16,
 
+    /// Public host name of a named tunnel, reached through the server's HTTP port.
+    public_host: Option<String>,
+
+    /// Shared port where TLS visitors reach a named tunnel, if the server routes TLS.
+    tls_port: Option<u16>,
+
     /// Optional secret used to authenticate clients.
     auth: Option<Authenticator>,
 
+    /// Capabilities agreed with the server for this tunnel.
+    features: Features,
+
+    /// Optional intermediate server used to reach the server.
+    via: Option<Hop>,
+
+    /// Opens connections to the server, or the intermediate server.
+    dialer: Arc<dyn Dialer>,
+
+    /// TLS settings, if connections to the server are encrypted.
+    tls: Option<ClientTls>,
+
+    /// How long proxied connections may go without traffic before they are closed.
+    idle_timeout: Option<Duration>,
+
+    /// Time between pings sent to the server.
+    heartbeat_interval: Duration,
+
+    /// How long the server may send nothing before the tunnel fails.
+    heartbeat_timeout: Option<Duration>,
+
+    /// Traffic counters for connections through this tunnel.
+    stats: Arc<TrafficStats>,
+
+    /// Visitor connections that are being forwarded.
+    connections: Connections,
+
     /// Optional event sink for web tunnel management.
     event_tx: Option<mpsc::UnboundedSender<TunnelEvent>>,
 }
@@ -99,36 +934,225 @@ impl Client {
         secret: Option<&str>,
         event_tx: Option<mpsc::UnboundedSender<TunnelEvent>>,
     ) -> Result<Self> {
-        let mut stream = Delimited::new(connect_with_timeout(to, CONTROL_PORT).await?);
+        let options = ClientOptions::default();
+        Self::with_options(local_host, local_port, to, port, secret, options, event_tx).await
+    }
+
+    /// Create a new client with optional settings, emitting tunnel events.
+    pub async fn with_options(
+        local_host: &str,
+        local_port: u16,
+        to: &str,
+        port: u16,
+        secret: Option<&str>,
+        options: ClientOptions,
+        event_tx: Option<mpsc::UnboundedSender<TunnelEvent>>,
+    ) -> Result<Self> {
+        let via = options.via.map(|via| Hop {
+            to: via,
+            auth: options.via_secret.as_deref().map(Authenticator::new),
+        });
+        let endpoints = resolve_server(to).await?;
+        let tls = options.tls;
+        let dialer: Arc<dyn Dialer> = match (options.dialer, options.proxy) {
+            (Some(dialer), _) => dialer,
+            (None, Some(proxy)) => Arc::new(proxy),
+            (None, None) => Arc::new(TcpDialer),
+        };
+        let (mut stream, server) =
+            connect_any(&endpoints, via.as_ref(), &*dialer, tls.as_ref()).await?;
         let auth = secret.map(Authenticator::new);
         if let Some(auth) = &auth {
             auth.client_handshake(&mut stream).await?;
         }
 
-        stream.send(ClientMessage::Hello(port)).await?;
-        let remote_port = match stream.recv_timeout().await? {
-            Some(ServerMessage::Hello(remote_port)) => remote_port,
-            Some(ServerMessage::Error(message)) => bail!("server error: {message}"),
-            Some(ServerMessage::Challenge(_)) => {
-                bail!("server requires authentication, but no client secret was provided");
+        let mut backends = vec![Backend {
+            host: local_host.to_string(),
+            port: local_port,
+        }];
+        backends.extend(options.backends);
+        let backends = Balancer::new(backends, options.sticky);
+        let mut requested = options.features;
+        // Visitors are only listed by address if the registry of connections is watched.
+        if backends.is_sticky() || options.proxy_protocol.is_some() || options.connections.is_some()
+        {
+            requested = requested | Features::PEER_ADDR;
+        }
+        if !options.access.is_empty() {
+            requested = requested | Features::ACCESS_RULES;
+        }
+        if options.max_connections.is_some() {
+            requested = requested | Features::CONNECTION_LIMIT;
+        }
+        if options.heartbeat_interval.is_some() {
+            requested = requested | Features::PING;
+        }
+        if options.name.is_some() {
+            requested = requested | Features::HTTP_ROUTING;
+        }
+        if options.multiplex {
+            requested = requested | Features::MULTIPLEX;
+        }
+        if options.service_check.is_some() {
+            requested = requested | Features::SERVICE_HEALTH;
+        }
+
+        let ports = if port == 0 && options.name.is_none() {
+            options.ports
+        } else {
+            Vec::new()
+        };
+        if ports.len() > MAX_PORT_RANGES {
+            bail!("at most {MAX_PORT_RANGES} preferred ports or ranges can be requested");
+        }
+
+        // Only use the extended hello when needed, so older servers keep working.
+        if requested.is_empty() && ports.is_empty() {
+            stream.send(ClientMessage::Hello(port)).await?;
+        } else {
+            let request = HelloRequest {
+                port,
+                ports: ports.clone(),
+                access: options.access.clone(),
+                name: options.name.clone(),
+                max_connections: options.max_connections,
+                when_down: options.service_check.as_ref().map(|check| check.when_down),
+                features: requested,
+            };
+            let message = ClientMessage::ExtendedHello(request);
+            if serde_json::to_string(&message)?.len() > MAX_FRAME_LENGTH {
+                bail!("too many preferred ports or access rules to send to the server");
             }
-            Some(_) => bail!("unexpected initial non-hello message"),
-            None => bail!("unexpected EOF"),
+            stream.send(message).await?;
+        }
+        let (remote_port, public_host, tls_port, session, features) =
+            match stream.recv_timeout().await? {
+                Some(ServerMessage::Hello(remote_port)) => {
+                    (remote_port, None, None, None, Features::NONE)
+                }
+                Some(ServerMessage::ExtendedHello(response)) => (
+                    response.port,
+                    response.host,
+                    response.tls_port,
+                    response.session,
+                    response.features,
+                ),
+                Some(ServerMessage::Error(message)) => bail!("server error: {message}"),
+                Some(ServerMessage::Challenge(_)) => {
+                    bail!("server requires authentication, but no client secret was provided");
+                }
+                Some(_) => bail!("unexpected initial non-hello message"),
+                None => bail!("unexpected EOF"),
+            };
+        if !ports.is_empty() && !ports.iter().any(|range| range.contains(remote_port)) {
+            bail!("server assigned port {remote_port}, which is not a preferred port");
+        }
+        if !options.access.is_empty() && !features.contains(Features::ACCESS_RULES) {
+            bail!("server does not support access rules for visitors");
+        }
+        let public_host = match (&options.name, public_host) {
+            (None, _) => None,
+            (Some(_), Some(host)) if features.contains(Features::HTTP_ROUTING) => Some(host),
+            (Some(_), _) => bail!("server does not support named tunnels"),
         };
         info!(remote_port, "connected to server");
-        info!("listening at {to}:{remote_port}");
+        if let Some(idle_timeout) = options.idle_timeout {
+            info!(?idle_timeout, "closing idle connections");
+            gauge!("bore_client_idle_timeout_seconds").set(idle_timeout.as_secs_f64());
+        }
+        let session = match session.filter(|_| features.contains(Features::MULTIPLEX)) {
+            Some(id) => {
+                let mut conn =
+                    connect_server(&server, via.as_ref(), &*dialer, tls.as_ref()).await?;
+                if let Some(auth) = &auth {
+                    auth.client_handshake(&mut conn).await?;
+                }
+                conn.send(ClientMessage::Multiplex(id)).await?;
+                info!("multiplexing visitor connections");
+                Some(Session::new(conn.into_parts().io))
+            }
+            None if options.multiplex => {
+                warn!("server does not support multiplexing, opening a connection per visitor");
+                None
+            }
+            None => None,
+        };
+        let tls_port = tls_port.filter(|_| public_host.is_some());
+        let mut public_urls = vec![match &public_host {
+            Some(host) if remote_port == 80 => format!("http://{host}"),
+            Some(host) => format!("http://{host}:{remote_port}"),
+            None => format!("{}:{remote_port}", server.host),
+        }];
+        if let (Some(host), Some(tls_port)) = (&public_host, tls_port) {
+            public_urls.push(match tls_port {
+                443 => format!("https://{host}"),
+                _ => format!("https://{host}:{tls_port}"),
+            });
+        }
+        for url in &public_urls {
+            info!("listening at {url}");
+        }
 
         let client = Client {
             conn: Some(stream),
-            to: to.to_string(),
-            local_host: local_host.to_string(),
-            local_port,
+            server,
+            backends,
+            health_check_interval: options.health_check_interval,
+            service_check: options
+                .service_check
+                .clone()
+                .filter(|_| features.contains(Features::SERVICE_HEALTH)),
+            local_socket: options.local_socket,
+            socks: options.socks,
+            e2e_key: options.e2e_key,
+            session,
+            proxy_protocol: options.proxy_protocol,
             remote_port,
+            public_host,
+            tls_port,
             auth,
+            features,
+            via,
+            dialer,
+            tls,
+            idle_timeout: options.idle_timeout,
+            heartbeat_interval: options.heartbeat_interval.unwrap_or(PING_INTERVAL),
+            heartbeat_timeout: options.heartbeat_timeout,
+            stats: options.stats.unwrap_or_default(),
+            connections: options.connections.unwrap_or_default(),
             event_tx,
         };
-        client.emit_log(format!("connected to {to}:{CONTROL_PORT}"));
-        client.emit_log(format!("listening at {to}:{remote_port}"));
+        if client.backends.is_sticky() && !features.contains(Features::PEER_ADDR) {
+            warn!("server does not send visitor addresses, sticky sessions are disabled");
+            client.emit_log("sticky sessions are not supported by the server".to_string());
+        }
+        if client.proxy_protocol.is_some() && !features.contains(Features::PEER_ADDR) {
+            warn!("server does not send visitor addresses, PROXY headers will not include them");
+            client.emit_log("visitor addresses are not supported by the server".to_string());
+        }
+        if options.max_connections.is_some() && !features.contains(Features::CONNECTION_LIMIT) {
+            warn!("server does not limit connections, --max-connections is ignored");
+            client.emit_log("connection limits are not supported by the server".to_string());
+        }
+        if options.heartbeat_interval.is_some() && !features.contains(Features::PING) {
+            warn!("server does not answer pings, --heartbeat-interval is ignored");
+            client.emit_log("pings are not supported by the server".to_string());
+        }
+        if options.service_check.is_some() && client.service_check.is_none() {
+            warn!("server does not take service health, --service-check-interval is ignored");
+            client.emit_log("service health checks are not supported by the server".to_string());
+        }
+        let Endpoint { host, port } = &client.server;
+        match &client.via {
+            Some(via) => client.emit_log(format!(
+                "connected to {host}:{port} via {}:{CONTROL_PORT}",
+                via.to
+            )),
+            None => client.emit_log(format!("connected to {host}:{port}")),
+        }
+        for url in public_urls {
+            client.emit_log(format!("listening at {url}"));
+        }
 
         Ok(client)
     }
@@ -138,6 +1162,31 @@ impl Client {
         self.remote_port
     }
 
+    /// Returns the public host name of a named tunnel, reached over HTTP.
+    pub fn public_host(&self) -> Option<&str> {
+        self.public_host.as_deref()
+    }
+
+    /// Returns the shared port where TLS visitors reach a named tunnel, routed by server name.
+    pub fn tls_port(&self) -> Option<u16> {
+        self.tls_port
+    }
+
+    /// Returns the traffic counters for this tunnel.
+    pub fn stats(&self) -> Arc<TrafficStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Returns a handle to the visitor connections this tunnel is forwarding.
+    pub fn connections(&self) -> Connections {
+        self.connections.clone()
+    }
+
+    /// Returns the optional capabilities agreed with the server.
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
     /// Start the client, listening for new connections.
     pub async fn listen(self) -> Result<()> {
         self.listen_with_shutdown(std::future::pending::<()>())
@@ -145,66 +1194,302 @@ impl Client {
     }
 
     /// Start the client, listening for new connections until shutdown resolves.
-    pub async fn listen_with_shutdown<S>(mut self, shutdown: S) -> Result<()>
+    pub async fn listen_with_shutdown<S>(self, shutdown: S) -> Result<()>
+    where
+        S: Future<Output = ()>,
+    {
+        self.run(shutdown).await.map(|_| ())
+    }
+
+    async fn run<S>(mut self, shutdown: S) -> Result<ListenExit>
     where
         S: Future<Output = ()>,
     {
         let mut conn = self.conn.take().expect("control connection should exist");
+        let mut session = self.session.take();
         let this = Arc::new(self);
         tokio::pin!(shutdown);
+        let pings = this.features.contains(Features::PING);
+        let mut ping_ticker = interval(this.heartbeat_interval);
+        ping_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut pending_ping: Option<(u64, Instant)> = None;
+        let heartbeat_timeout = this.heartbeat_timeout;
+        let mut last_seen = Instant::now();
+        let _health_checks = this
+            .health_check_interval
+            .filter(|_| this.backends.is_balanced())
+            .map(|period| {
+                let this = Arc::clone(&this);
+                AbortOnDropHandle::new(tokio::spawn(async move {
+                    let mut ticker = interval(period);
+                    loop {
+                        ticker.tick().await;
+                        this.backends.check_health().await;
+                    }
+                }))
+            });
+        let (service_tx, mut service_rx) = watch::channel(true);
+        let checking_service = this.service_check.is_some();
+        let _service_checks = this.service_check.clone().map(|check| {
+            let this = Arc::clone(&this);
+            AbortOnDropHandle::new(tokio::spawn(async move {
+                let mut ticker = interval(check.interval);
+                loop {
+                    ticker.tick().await;
+                    let up = this.probe_service(check.path.as_deref()).await;
+                    service_tx.send_if_modified(|was_up| std::mem::replace(was_up, up) != up);
+                }
+            }))
+        });
 
         loop {
+            let silence =
+                heartbeat_timeout.map(|timeout| timeout.saturating_sub(last_seen.elapsed()));
             tokio::select! {
                 _ = &mut shutdown => {
                     this.emit_log("shutdown requested".to_string());
-                    return Ok(());
+                    return Ok(ListenExit::Shutdown);
+                }
+                _ = ping_ticker.tick(), if pings => {
+                    let nonce = fastrand::u64(..);
+                    pending_ping = Some((nonce, Instant::now()));
+                    conn.send(ClientMessage::Ping(nonce)).await?;
+                }
+                _ = sleep(silence.unwrap_or_default()), if silence.is_some() => {
+                    warn!(?heartbeat_timeout, "server stopped responding");
+                    counter!("bore_client_heartbeat_timeouts_total").increment(1);
+                    bail!("no heartbeat from server in {heartbeat_timeout:?}");
+                }
+                Ok(()) = service_rx.changed(), if checking_service => {
+                    let up = *service_rx.borrow_and_update();
+                    if up {
+                        info!("local service is up");
+                        this.emit_log("local service is up".to_string());
+                    } else {
+                        warn!("local service is down");
+                        this.emit_log("local service is down".to_string());
+                    }
+                    gauge!("bore_client_service_up").set(if up { 1.0 } else { 0.0 });
+                    conn.send(ClientMessage::ServiceHealth(up)).await?;
+                }
+                accepted = accept_stream(&mut session), if session.is_some() => {
+                    match accepted {
+                        Some((stream, metadata)) => {
+                            let peer = std::str::from_utf8(&metadata)
+                                .ok()
+                                .and_then(|addr| addr.parse().ok());
+                            this.spawn_connection(Uuid::new_v4(), peer, Some(stream));
+                        }
+                        None => {
+                            warn!("multiplexed connection closed");
+                            session = None;
+                        }
+                    }
                 }
                 message = conn.recv() => {
+                    last_seen = Instant::now();
                     match message? {
-                        Some(ServerMessage::Hello(_)) => warn!("unexpected hello"),
+                        Some(ServerMessage::Hello(_) | ServerMessage::ExtendedHello(_)) => {
+                            warn!("unexpected hello")
+                        }
                         Some(ServerMessage::Challenge(_)) => warn!("unexpected challenge"),
                         Some(ServerMessage::Heartbeat) => (),
-                        Some(ServerMessage::Connection(id)) => {
-                            let this = Arc::clone(&this);
-                            tokio::spawn(
-                                async move {
-                                    info!("new connection");
-                                    this.emit_log(format!("accepted remote connection {id}"));
-                                    match this.handle_connection(id).await {
-                                        Ok(_) => info!("connection exited"),
-                                        Err(err) => {
-                                            this.emit_log(format!("connection {id} exited with error: {err}"));
-                                            warn!(%err, "connection exited with error");
-                                        }
-                                    }
-                                }
-                                .instrument(info_span!("proxy", %id)),
-                            );
+                        Some(ServerMessage::Connection(id)) => this.spawn_connection(id, None, None),
+                        Some(ServerMessage::ConnectionFrom(id, addr)) => {
+                            this.spawn_connection(id, Some(addr), None)
                         }
                         Some(ServerMessage::Error(err)) => {
                             this.emit_log(format!("server error: {err}"));
                             error!(%err, "server error");
                         }
-                        None => return Ok(()),
+                        Some(ServerMessage::Reconnect(hint)) => {
+                            info!(?hint, "server asked to reconnect");
+                            return Ok(ListenExit::Reconnect(hint));
+                        }
+                        Some(ServerMessage::Pong(nonce)) => {
+                            if let Some((_, sent_at)) =
+                                pending_ping.take_if(|(sent, _)| *sent == nonce)
+                            {
+                                let rtt = sent_at.elapsed().as_secs_f64();
+                                gauge!("bore_client_heartbeat_rtt_seconds").set(rtt);
+                            }
+                        }
+                        None => return Ok(ListenExit::Closed),
                     }
                 }
             }
         }
     }
 
-    async fn handle_connection(&self, id: Uuid) -> Result<()> {
-        let mut remote_conn =
-            Delimited::new(connect_with_timeout(&self.to[..], CONTROL_PORT).await?);
+    /// Forward a visitor connection, arriving on `stream` if it is multiplexed.
+    fn spawn_connection(
+        self: &Arc<Self>,
+        id: Uuid,
+        peer: Option<SocketAddr>,
+        stream: Option<MuxStream>,
+    ) {
+        let this = Arc::clone(self);
+        tokio::spawn(
+            async move {
+                info!("new connection");
+                emit_event(&this.event_tx, TunnelEvent::ConnectionOpened { id, peer });
+                let slot = this.connections.open(id, peer);
+                let forward = async {
+                    match stream {
+                        Some(stream) => this.forward(Box::new(stream), &[], &slot).await,
+                        None => this.handle_connection(id, &slot).await,
+                    }
+                };
+                let result = tokio::select! {
+                    result = forward => result,
+                    _ = slot.close.notified() => {
+                        info!("closed connection on request");
+                        Ok(slot.bytes.get())
+                    }
+                };
+                let (error, (bytes_in, bytes_out)) = match result {
+                    Ok(bytes) => {
+                        info!("connection exited");
+                        (None, bytes)
+                    }
+                    Err(err) => {
+                        warn!(%err, "connection exited with error");
+                        (Some(err.to_string()), (0, 0))
+                    }
+                };
+                let event = TunnelEvent::ConnectionClosed {
+                    id,
+                    error,
+                    bytes_in,
+                    bytes_out,
+                };
+                emit_event(&this.event_tx, event);
+            }
+            .instrument(info_span!("proxy", %id)),
+        );
+    }
+
+    async fn handle_connection(&self, id: Uuid, slot: &ConnectionSlot) -> Result<(u64, u64)> {
+        let mut remote_conn = connect_server(
+            &self.server,
+            self.via.as_ref(),
+            &*self.dialer,
+            self.tls.as_ref(),
+        )
+        .await?;
         if let Some(auth) = &self.auth {
             auth.client_handshake(&mut remote_conn).await?;
         }
         remote_conn.send(ClientMessage::Accept(id)).await?;
-        let mut local_conn = connect_with_timeout(&self.local_host, self.local_port).await?;
-        let mut parts = remote_conn.into_parts();
+        let parts = remote_conn.into_parts();
         debug_assert!(parts.write_buf.is_empty(), "framed write buffer not empty");
-        local_conn.write_all(&parts.read_buf).await?;
-        tokio::io::copy_bidirectional(&mut local_conn, &mut parts.io).await?;
-        Ok(())
+        self.forward(parts.io, &parts.read_buf, slot).await
+    }
+
+    /// Proxy a data connection from the server to the local service, until either side closes.
+    ///
+    /// `prefix` holds bytes from the server that were already read from the connection.
+    /// Returns the bytes received from and sent to the visitor.
+    async fn forward(
+        &self,
+        remote_conn: Transport,
+        prefix: &[u8],
+        slot: &ConnectionSlot,
+    ) -> Result<(u64, u64)> {
+        let peer = slot.peer;
+        let mut local_conn: Transport = match (&self.socks, &self.local_socket) {
+            (Some(socks), _) => {
+                // Serve SOCKS in this process, at the other end of a pipe standing in for
+                // the connection to a local service.
+                let (local_conn, service) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+                let socks = socks.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(err) = socks.serve(service).await {
+                            info!(%err, "SOCKS request failed");
+                        }
+                    }
+                    .in_current_span(),
+                );
+                Box::new(local_conn)
+            }
+            (None, Some(path)) => connect_socket(path).await?,
+            (None, None) => {
+                let mut local_conn = self.connect_backend(peer).await?;
+                if let Some(version) = self.proxy_protocol {
+                    let header = version.header(peer, local_conn.peer_addr()?);
+                    local_conn.write_all(&header).await?;
+                }
+                Box::new(local_conn)
+            }
+        };
+        if let Some(key) = &self.e2e_key {
+            // Encrypt in this process, so the server only sees the other end of the pipe.
+            let (encrypted, pipe) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+            let key = key.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(err) = e2e::relay(local_conn, pipe, &key).await {
+                        info!(%err, "end-to-end encryption failed");
+                    }
+                }
+                .in_current_span(),
+            );
+            local_conn = Box::new(encrypted);
+        }
+        let _guard = self.stats.open_connection();
+        local_conn.write_all(prefix).await?;
+        let mut remote_conn = Counted::new(remote_conn, Arc::clone(&self.stats))
+            .with_connection(Arc::clone(&slot.bytes));
+        let copied =
+            idle::copy_bidirectional(&mut local_conn, &mut remote_conn, self.idle_timeout).await?;
+        let Some((bytes_out, bytes_in)) = copied else {
+            info!("closed idle connection");
+            counter!("bore_client_idle_connections_closed_total").increment(1);
+            return Ok((0, 0));
+        };
+        Ok((bytes_in + prefix.len() as u64, bytes_out))
+    }
+
+    /// Connect to a backend for a visitor, failing over to the others if it is down.
+    async fn connect_backend(&self, peer: Option<SocketAddr>) -> Result<TcpStream> {
+        let mut last_err = None;
+        for backend in self.backends.candidates(peer.map(|addr| addr.ip())) {
+            match connect_with_timeout(&backend.host, backend.port).await {
+                Ok(conn) => {
+                    self.backends.set_healthy(backend, true);
+                    return Ok(conn);
+                }
+                Err(err) => {
+                    self.backends.set_healthy(backend, false);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.expect("balancer has at least one backend"))
+    }
+
+    /// Check whether the local service accepts connections, and answers `path` if it is set.
+    async fn probe_service(&self, path: Option<&str>) -> bool {
+        if self.socks.is_some() {
+            return true;
+        }
+        let conn = match &self.local_socket {
+            Some(socket) => connect_socket(socket).await,
+            None => self
+                .connect_backend(None)
+                .await
+                .map(|conn| Box::new(conn) as Transport),
+        };
+        match (conn, path) {
+            (Err(_), _) => false,
+            (Ok(_), None) => true,
+            (Ok(conn), Some(path)) => {
+                let host = &self.backends.candidates(None)[0].host;
+                let probe = http_probe(conn, host, path);
+                matches!(timeout(NETWORK_TIMEOUT, probe).await, Ok(Ok(true)))
+            }
+        }
     }
 
     fn emit_log(&self, message: String) {
@@ -218,15 +1503,34 @@ pub async fn run_local<S>(
     shutdown: S,
     event_tx: Option<mpsc::UnboundedSender<TunnelEvent>>,
 ) -> Result<()>
+where
+    S: Future<Output = ()>,
+{
+    let stats = Arc::new(TrafficStats::default());
+    run_local_with_stats(args, stats, None, shutdown, event_tx).await
+}
+
+/// Runs a local tunnel like [`run_local`], counting its traffic in `stats` and listing its
+/// open visitor connections in `connections`, if set.
+pub async fn run_local_with_stats<S>(
+    args: LocalArgs,
+    stats: Arc<TrafficStats>,
+    connections: Option<Connections>,
+    shutdown: S,
+    event_tx: Option<mpsc::UnboundedSender<TunnelEvent>>,
+) -> Result<()>
 where
     S: Future<Output = ()>,
 {
     emit_event(
         &event_tx,
         TunnelEvent::Log(format!(
-            "starting tunnel {}:{} -> {}:{}",
-            args.local_host,
-            args.local_port,
+            "starting tunnel {} -> {}:{}",
+            match &args.local_socket {
+                _ if args.socks5 => "SOCKS5 proxy".to_string(),
+                Some(path) => path.display().to_string(),
+                None => format!("{}:{}", args.local_host, args.local_port),
+            },
             args.to,
             if args.port == 0 {
                 "auto".to_string()
@@ -236,37 +1540,239 @@ where
         )),
     );
 
-    let client = match Client::new_with_events(
+    // With JSON output, logs are written to stderr, see `cli::json_output`.
+    let logs_on_stderr = args.output == OutputFormat::Json;
+    let _progress = args.progress.then(|| {
+        let progress = show_progress(Arc::clone(&stats), logs_on_stderr);
+        AbortOnDropHandle::new(tokio::spawn(progress))
+    });
+
+    let mut client =
+        match connect_local(&args, &args.to, args.port, &stats, &connections, &event_tx).await {
+            Ok(client) => client,
+            Err(err) => {
+                emit_event(&event_tx, TunnelEvent::Failed(err.to_string()));
+                return Err(err);
+            }
+        };
+    if args.check {
+        let remote_port = client.remote_port();
+        info!(remote_port, "preflight check passed");
+        emit_event(
+            &event_tx,
+            TunnelEvent::Started {
+                remote_port: Some(remote_port),
+            },
+        );
+        drop(client);
+        emit_event(&event_tx, TunnelEvent::Stopped);
+        return Ok(());
+    }
+    tokio::pin!(shutdown);
+
+    let policy = args.reconnect_policy();
+    let mut to = args.to.clone();
+    loop {
+        emit_event(
+            &event_tx,
+            TunnelEvent::Started {
+                remote_port: Some(client.remote_port()),
+            },
+        );
+
+        // Keep the same public port, so that the tunnel address stays stable.
+        let remote_port = client.remote_port();
+        let (retry, mut delay, mut error) = match client.run(&mut shutdown).await {
+            Ok(ListenExit::Reconnect(hint)) => {
+                to = hint.to.unwrap_or_else(|| args.to.clone());
+                emit_event(
+                    &event_tx,
+                    TunnelEvent::Log(format!(
+                        "server is restarting, reconnecting to {to} in {}s",
+                        hint.delay
+                    )),
+                );
+                let retry = policy.unwrap_or(ReconnectPolicy::HINTED);
+                let delay = Duration::from_secs(hint.delay);
+                (retry, delay, "server is restarting".to_string())
+            }
+            Ok(ListenExit::Shutdown) => break,
+            Ok(ListenExit::Closed) => match policy {
+                Some(policy) => {
+                    warn!("server closed the connection");
+                    let error = "server closed the connection".to_string();
+                    (policy, policy.delay(1), error)
+                }
+                None => break,
+            },
+            Err(err) => match policy {
+                Some(policy) => {
+                    warn!(%err, "lost connection to server");
+                    (policy, policy.delay(1), err.to_string())
+                }
+                None => {
+                    emit_event(&event_tx, TunnelEvent::Failed(err.to_string()));
+                    return Err(err);
+                }
+            },
+        };
+
+        let mut attempt = 1;
+        client = loop {
+            emit_event(
+                &event_tx,
+                TunnelEvent::Reconnecting {
+                    attempt,
+                    delay_ms: delay.as_millis() as u64,
+                    error: error.clone(),
+                },
+            );
+            let reconnect = async {
+                sleep(delay).await;
+                connect_local(&args, &to, remote_port, &stats, &connections, &event_tx).await
+            };
+            let result = tokio::select! {
+                _ = &mut shutdown => {
+                    emit_event(&event_tx, TunnelEvent::Stopped);
+                    return Ok(());
+                }
+                result = reconnect => result,
+            };
+            match result {
+                Ok(client) => break client,
+                Err(err) if retry.allows(attempt) => {
+                    warn!(%err, attempt, "failed to reconnect");
+                    error = err.to_string();
+                    attempt += 1;
+                    delay = retry.delay(attempt);
+                }
+                Err(err) => {
+                    emit_event(&event_tx, TunnelEvent::Failed(err.to_string()));
+                    return Err(err);
+                }
+            }
+        };
+        counter!("bore_client_reconnects_total").increment(1);
+    }
+
+    emit_event(&event_tx, TunnelEvent::Stopped);
+    Ok(())
+}
+
+async fn connect_local(
+    args: &LocalArgs,
+    to: &str,
+    port: u16,
+    stats: &Arc<TrafficStats>,
+    connections: &Option<Connections>,
+    event_tx: &Option<mpsc::UnboundedSender<TunnelEvent>>,
+) -> Result<Client> {
+    let ports = args
+        .preferred_ports
+        .iter()
+        .map(|&port| PortRange::single(port))
+        .chain(args.port_range)
+        .collect();
+    let socks_auth = args
+        .socks_auth
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(|err: String| anyhow!(err))?;
+    let options = ClientOptions {
+        features: args.features(),
+        ports,
+        name: args.name.clone(),
+        access: AccessRules {
+            allow: args.allow.clone(),
+            deny: args.deny.clone(),
+        },
+        max_connections: args.max_connections,
+        idle_timeout: args.idle_timeout.map(Duration::from_secs),
+        heartbeat_interval: args.heartbeat_interval.map(Duration::from_secs),
+        heartbeat_timeout: args.heartbeat_timeout.map(Duration::from_secs),
+        via: args.via.clone(),
+        via_secret: args.via_secret.clone(),
+        e2e_key: args
+            .e2e_key
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(|err: String| anyhow!(err))?,
+        proxy: args
+            .proxy
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(|err: String| anyhow!(err))?,
+        dialer: None,
+        local_socket: args.local_socket.clone(),
+        socks: args.socks5.then(|| Socks { auth: socks_auth }),
+        multiplex: args.multiplex,
+        backends: args.backends.clone(),
+        sticky: args.sticky,
+        health_check_interval: args.health_check_interval.map(Duration::from_secs),
+        service_check: args.service_check_interval.map(|secs| ServiceCheck {
+            interval: Duration::from_secs(secs),
+            path: args.service_check_path.clone(),
+            when_down: args.when_down.unwrap_or_default(),
+        }),
+        proxy_protocol: args.proxy_protocol,
+        stats: Some(Arc::clone(stats)),
+        connections: connections.clone(),
+        tls: args
+            .tls
+            .then(|| ClientTls::new(args.tls_ca.as_deref()))
+            .transpose()?,
+    };
+    Client::with_options(
         &args.local_host,
         args.local_port,
-        &args.to,
-        args.port,
+        to,
+        port,
         args.secret.as_deref(),
+        options,
         event_tx.clone(),
     )
     .await
-    {
-        Ok(client) => client,
-        Err(err) => {
-            emit_event(&event_tx, TunnelEvent::Failed(err.to_string()));
-            return Err(err);
-        }
-    };
-
-    emit_event(
-        &event_tx,
-        TunnelEvent::Started {
-            remote_port: Some(client.remote_port()),
-        },
-    );
+}
 
-    if let Err(err) = client.listen_with_shutdown(shutdown).await {
-        emit_event(&event_tx, TunnelEvent::Failed(err.to_string()));
-        return Err(err);
+/// Show throughput every second until aborted.
+///
+/// The display is redrawn in place on stderr only if nothing else writes to that terminal, that
+/// is when logs go to stdout and stdout is redirected. Otherwise the redrawn line would be torn
+/// up by log lines, so progress is logged instead.
+async fn show_progress(stats: Arc<TrafficStats>, logs_on_stderr: bool) {
+    let redraw =
+        std::io::stderr().is_terminal() && !logs_on_stderr && !std::io::stdout().is_terminal();
+    let mut ticker = interval(PROGRESS_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last = TrafficSnapshot::default();
+    loop {
+        ticker.tick().await;
+        let now = stats.snapshot();
+        let seconds = PROGRESS_INTERVAL.as_secs_f64();
+        let rate_in = ((now.bytes_in - last.bytes_in) as f64 / seconds) as u64;
+        let rate_out = ((now.bytes_out - last.bytes_out) as f64 / seconds) as u64;
+        let line = format!(
+            "in {}/s, out {}/s | {} in, {} out | {} active, {} total connections",
+            format_bytes(rate_in),
+            format_bytes(rate_out),
+            format_bytes(now.bytes_in),
+            format_bytes(now.bytes_out),
+            now.active_connections,
+            now.total_connections,
+        );
+        if redraw {
+            // Rewrite the same line, clearing leftovers from a longer previous line.
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r{line}\x1b[K");
+            let _ = stderr.flush();
+        } else {
+            info!("{line}");
+        }
+        last = now;
     }
-
-    emit_event(&event_tx, TunnelEvent::Stopped);
-    Ok(())
 }
 
 fn emit_event(event_tx: &Option<mpsc::UnboundedSender<TunnelEvent>>, event: TunnelEvent) {
@@ -275,6 +1781,144 @@ fn emit_event(event_tx: &Option<mpsc::UnboundedSender<TunnelEvent>>, event: Tunn
     }
 }
 
+/// Resolve the control endpoints of a server, in the order they should be tried.
+///
+/// Names starting with an underscore, like `_bore._tcp.example.com`, are looked up
+/// as DNS SRV records. Other names are used as-is with the default control port.
+async fn resolve_server(to: &str) -> Result<Vec<Endpoint>> {
+    if !to.starts_with('_') {
+        return Ok(vec![Endpoint {
+            host: to.to_string(),
+            port: CONTROL_PORT,
+        }]);
+    }
+
+    let resolver = Resolver::builder_tokio()
+        .context("failed to read system DNS configuration")?
+        .build();
+    let lookup = timeout(NETWORK_TIMEOUT, resolver.srv_lookup(to))
+        .await
+        .with_context(|| format!("timed out looking up SRV records for {to}"))?
+        .with_context(|| format!("could not look up SRV records for {to}"))?;
+    let records = lookup
+        .iter()
+        .map(|srv| {
+            let host = srv.target().to_utf8();
+            let endpoint = Endpoint {
+                host: host.trim_end_matches('.').to_string(),
+                port: srv.port(),
+            };
+            (srv.priority(), srv.weight(), endpoint)
+        })
+        .collect();
+    let endpoints = order_srv_records(records);
+    if endpoints.is_empty() {
+        bail!("no SRV records found for {to}");
+    }
+    info!(?endpoints, "resolved SRV records for {to}");
+    Ok(endpoints)
+}
+
+/// Order SRV records by priority, then randomly by weight within each priority (RFC 2782).
+fn order_srv_records(mut records: Vec<(u16, u16, Endpoint)>) -> Vec<Endpoint> {
+    records.sort_by_key(|(priority, _, _)| *priority);
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].0;
+        let group_len = records.iter().take_while(|r| r.0 == priority).count();
+        let mut group: Vec<_> = records.drain(..group_len).collect();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|(_, weight, _)| u32::from(*weight)).sum();
+            let index = if total == 0 {
+                fastrand::usize(..group.len())
+            } else {
+                let pick = fastrand::u32(1..=total);
+                let mut running = 0;
+                group
+                    .iter()
+                    .position(|(_, weight, _)| {
+                        running += u32::from(*weight);
+                        running >= pick
+                    })
+                    .expect("pick is within total weight")
+            };
+            ordered.push(group.remove(index).2);
+        }
+    }
+    ordered
+}
+
+/// Connect to the first reachable endpoint, returning the endpoint that was used.
+async fn connect_any(
+    endpoints: &[Endpoint],
+    via: Option<&Hop>,
+    dialer: &dyn Dialer,
+    tls: Option<&ClientTls>,
+) -> Result<(Delimited<Transport>, Endpoint)> {
+    let mut last_err = None;
+    for endpoint in endpoints {
+        match connect_server(endpoint, via, dialer, tls).await {
+            Ok(stream) => return Ok((stream, endpoint.clone())),
+            Err(err) => {
+                warn!(%err, "failed to connect to {}:{}", endpoint.host, endpoint.port);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| anyhow!("no server addresses to connect to")))
+}
+
+/// Open a connection to the server's control port, optionally through an intermediate server.
+///
+/// Intermediate servers only relay to the default control port of the target host. With TLS,
+/// the session with the target is nested inside the one with the intermediate server, so the
+/// intermediate server cannot read the relayed traffic. The first hop is opened with `dialer`,
+/// which may go through a proxy.
+async fn connect_server(
+    endpoint: &Endpoint,
+    via: Option<&Hop>,
+    dialer: &dyn Dialer,
+    tls: Option<&ClientTls>,
+) -> Result<Delimited<Transport>> {
+    let Some(via) = via else {
+        let stream = dialer.dial(&endpoint.host, endpoint.port).await?;
+        return Ok(Delimited::new(secure(stream, &endpoint.host, tls).await?));
+    };
+    // Relaying to any other port would make intermediate servers open TCP proxies.
+    ensure!(
+        endpoint.port == CONTROL_PORT,
+        "cannot reach {}:{} with --via, since intermediate servers only relay to port {CONTROL_PORT}",
+        endpoint.host,
+        endpoint.port
+    );
+    let stream = dialer.dial(&via.to, CONTROL_PORT).await?;
+    let mut stream = Delimited::new(secure(stream, &via.to, tls).await?);
+    if let Some(auth) = &via.auth {
+        auth.client_handshake(&mut stream).await?;
+    }
+    stream
+        .send(ClientMessage::Relay(endpoint.host.clone()))
+        .await?;
+    if tls.is_none() {
+        return Ok(stream);
+    }
+    let parts = stream.into_parts();
+    debug_assert!(parts.read_buf.is_empty(), "framed read buffer not empty");
+    Ok(Delimited::new(secure(parts.io, &endpoint.host, tls).await?))
+}
+
+/// Start a TLS session over a connection if TLS is enabled.
+async fn secure(
+    stream: impl Io + 'static,
+    host: &str,
+    tls: Option<&ClientTls>,
+) -> Result<Transport> {
+    match tls {
+        Some(tls) => tls.connect(host, stream).await,
+        None => Ok(Box::new(stream)),
+    }
+}
+
 async fn connect_with_timeout(to: &str, port: u16) -> Result<TcpStream> {
     match timeout(NETWORK_TIMEOUT, TcpStream::connect((to, port))).await {
         Ok(res) => res,
@@ -282,3 +1926,199 @@ async fn connect_with_timeout(to: &str, port: u16) -> Result<TcpStream> {
     }
     .with_context(|| format!("could not connect to {to}:{port}"))
 }
+
+/// Request `path` from an HTTP service, returning whether it answered with a 2xx or 3xx status.
+async fn http_probe<S>(mut conn: S, host: &str, path: &str) -> Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!("GET {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    conn.write_all(request.as_bytes()).await?;
+    // The status line is all that is needed, like `HTTP/1.1 200 OK`.
+    let mut status_line = Vec::new();
+    while !status_line.ends_with(b"\r\n") && status_line.len() < 256 {
+        status_line.push(conn.read_u8().await?);
+    }
+    let status_line = String::from_utf8_lossy(&status_line);
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    Ok(status.starts_with('2') || status.starts_with('3'))
+}
+
+/// Wait for the server to open a stream on a multiplexed connection, if there is one.
+async fn accept_stream(session: &mut Option<Session>) -> Option<(MuxStream, Vec<u8>)> {
+    match session {
+        Some(session) => session.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Connect to a local service listening on a Unix domain socket.
+#[cfg(unix)]
+async fn connect_socket(path: &Path) -> Result<Transport> {
+    match timeout(NETWORK_TIMEOUT, tokio::net::UnixStream::connect(path)).await {
+        Ok(res) => res.map(|stream| Box::new(stream) as Transport),
+        Err(err) => Err(err.into()),
+    }
+    .with_context(|| format!("could not connect to {}", path.display()))
+}
+
+/// Connect to a local service listening on a Unix domain socket.
+#[cfg(not(unix))]
+async fn connect_socket(path: &Path) -> Result<Transport> {
+    bail!(
+        "could not connect to {}: Unix sockets are not supported on this platform",
+        path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use std::time::Duration;
+
+    use super::{
+        connect_server, http_probe, order_srv_records, Endpoint, Hop, ReconnectPolicy, TunnelEvent,
+        TunnelSpec,
+    };
+    use crate::transport::TcpDialer;
+
+    fn endpoint(host: &str) -> Endpoint {
+        Endpoint {
+            host: host.to_string(),
+            port: 7835,
+        }
+    }
+
+    #[test]
+    fn reconnect_delays_back_off_with_jitter() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.0,
+            max_retries: Some(3),
+        };
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| policy.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10]);
+        assert_eq!(policy.delay(100), Duration::from_secs(10));
+        assert!(policy.allows(2));
+        assert!(!policy.allows(3));
+
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..20 {
+            let delay = policy.delay(2);
+            assert!(delay > Duration::from_millis(999) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn srv_records_are_ordered_by_priority() {
+        let records = vec![
+            (20, 0, endpoint("backup")),
+            (10, 5, endpoint("primary-a")),
+            (10, 5, endpoint("primary-b")),
+        ];
+        let ordered = order_srv_records(records);
+        assert_eq!(ordered.len(), 3);
+        assert!(ordered[..2].contains(&endpoint("primary-a")));
+        assert!(ordered[..2].contains(&endpoint("primary-b")));
+        assert_eq!(ordered[2], endpoint("backup"));
+    }
+
+    #[test]
+    fn srv_records_skip_zero_weights_when_possible() {
+        for _ in 0..20 {
+            let records = vec![(10, 0, endpoint("idle")), (10, 1, endpoint("busy"))];
+            assert_eq!(order_srv_records(records)[0], endpoint("busy"));
+        }
+    }
+
+    #[test]
+    fn tunnel_specs_are_parsed() {
+        let spec = |s: &str| s.parse::<TunnelSpec>().unwrap();
+        let tuple = |spec: TunnelSpec| (spec.local_host, spec.local_port, spec.port);
+        assert_eq!(tuple(spec("5432")), (None, 5432, 0));
+        assert_eq!(tuple(spec("5432:15432")), (None, 5432, 15432));
+        assert_eq!(tuple(spec("db:5432")), (Some("db".into()), 5432, 0));
+        assert_eq!(
+            tuple(spec("10.0.0.2:80:8080")),
+            (Some("10.0.0.2".into()), 80, 8080)
+        );
+        assert_eq!(tuple(spec("[::1]:80")), (Some("::1".into()), 80, 0));
+        assert!("db:".parse::<TunnelSpec>().is_err());
+        assert!(":80".parse::<TunnelSpec>().is_err());
+    }
+
+    #[test]
+    fn tunnel_events_have_stable_json_format() {
+        let event = TunnelEvent::Started {
+            remote_port: Some(4000),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({"event": "started", "data": {"remote_port": 4000}})
+        );
+        assert_eq!(
+            serde_json::to_value(TunnelEvent::Log("hi".into())).unwrap(),
+            json!({"event": "log", "data": "hi"})
+        );
+        assert_eq!(
+            serde_json::to_value(TunnelEvent::Stopped).unwrap(),
+            json!({"event": "stopped"})
+        );
+        let event = TunnelEvent::ConnectionOpened {
+            id: Uuid::nil(),
+            peer: Some("203.0.113.7:5000".parse().unwrap()),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "event": "connection_opened",
+                "data": {"id": Uuid::nil(), "peer": "203.0.113.7:5000"}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn http_probe_checks_the_status() {
+        use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+        for (response, healthy) in [
+            (&b"HTTP/1.1 204 No Content\r\n\r\n"[..], true),
+            (b"HTTP/1.1 500 Internal Server Error\r\n\r\n", false),
+        ] {
+            let (conn, mut service) = duplex(1024);
+            tokio::spawn(async move {
+                let mut request = [0u8; 16];
+                service.read_exact(&mut request).await.unwrap();
+                assert_eq!(&request, b"GET /health HTTP");
+                service.write_all(response).await.unwrap();
+            });
+            let result = http_probe(conn, "localhost", "/health").await.unwrap();
+            assert_eq!(result, healthy);
+        }
+    }
+
+    #[tokio::test]
+    async fn relays_only_reach_the_default_control_port() {
+        let via = Hop {
+            to: "relay.invalid".to_string(),
+            auth: None,
+        };
+        let srv = Endpoint {
+            port: 7000,
+            ..endpoint("bore.example.com")
+        };
+        let err = connect_server(&srv, Some(&via), &TcpDialer, None)
+            .await
+            .err()
+            .expect("non-default port should be refused");
+        assert!(err.to_string().contains("only relay to port"));
+    }
+}
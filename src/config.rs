@@ -0,0 +1,167 @@
+//! Config files with default arguments for `bore local` and `bore server`.
+//!
+//! A config file is a TOML or YAML table keyed by option name, like
+//! `to = "bore.pub"` or `min-port = 9000`. Its values are added to the
+//! command line, unless the same option is already given as a flag or
+//! through an environment variable.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{parser::ValueSource, Arg, CommandFactory};
+use serde_json::{Map, Value};
+
+use crate::cli::Args;
+
+/// Add the options from a subcommand's `--config` file to the command line arguments.
+///
+/// Arguments without a config file are returned unchanged.
+pub fn with_config_file<I, T>(args: I) -> Result<Vec<OsString>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+
+    // Parse leniently first, since required options may only be in the config file.
+    let mut command = Args::command().ignore_errors(true);
+    let Ok(matches) = command.try_get_matches_from_mut(&args) else {
+        return Ok(args);
+    };
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(args);
+    };
+    let Some(path) = sub_matches.try_get_one::<PathBuf>("config").ok().flatten() else {
+        return Ok(args);
+    };
+    let subcommand = command
+        .find_subcommand(name)
+        .expect("matched subcommand should exist");
+
+    let mut added = Vec::new();
+    for (key, value) in load(path)? {
+        let arg = subcommand
+            .get_arguments()
+            .find(|arg| matches_key(arg, &key))
+            .filter(|arg| arg.get_id() != "config")
+            .with_context(|| format!("unknown option {key} in {}", path.display()))?;
+        let source = sub_matches.value_source(arg.get_id().as_str());
+        if matches!(
+            source,
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        push_values(&mut added, arg, value)
+            .with_context(|| format!("invalid option {key} in {}", path.display()))?;
+    }
+
+    // Put the options right after the subcommand name, so that they apply to it.
+    let position = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg.as_os_str() == name)
+        .expect("matched subcommand should be in the arguments")
+        + 2;
+    args.splice(position..position, added);
+    Ok(args)
+}
+
+/// Read a config file as a table of option names and values.
+fn load(path: &Path) -> Result<Map<String, Value>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("could not read config file {}", path.display()))?;
+    let yaml = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("yaml" | "yml")
+    );
+    let value: Value = if yaml {
+        serde_yaml::from_str(&text)
+            .with_context(|| format!("invalid YAML in {}", path.display()))?
+    } else {
+        toml::from_str(&text).with_context(|| format!("invalid TOML in {}", path.display()))?
+    };
+    match value {
+        Value::Object(table) => Ok(table),
+        Value::Null => Ok(Map::new()),
+        _ => bail!("config file {} must contain a table", path.display()),
+    }
+}
+
+/// Check whether a config key names an option, by field name or long flag.
+fn matches_key(arg: &Arg, key: &str) -> bool {
+    arg.get_id() == key.replace('-', "_").as_str()
+        || arg.get_long() == Some(key.replace('_', "-").as_str())
+}
+
+/// Convert a config value into command line arguments for an option.
+fn push_values(args: &mut Vec<OsString>, arg: &Arg, value: Value) -> Result<()> {
+    let flag = arg.get_long().map(|long| format!("--{long}"));
+    match value {
+        Value::Null => {}
+        Value::Array(values) => {
+            for value in values {
+                if value.is_array() {
+                    bail!("nested lists are not supported");
+                }
+                push_values(args, arg, value)?;
+            }
+        }
+        Value::Object(_) => bail!("tables are not supported"),
+        Value::Bool(enabled) if !arg.get_action().takes_values() => {
+            if enabled {
+                args.push(flag.context("switch must have a flag")?.into());
+            }
+        }
+        value => {
+            let value = match value {
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+            match flag {
+                Some(flag) => args.push(format!("{flag}={value}").into()),
+                None => args.push(value.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::with_config_file;
+    use crate::cli::{Args, Command};
+
+    #[test]
+    fn config_file_fills_in_missing_options() {
+        let dir = std::env::temp_dir().join(format!("bore-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tunnel.toml");
+        fs::write(
+            &path,
+            "local_port = 3000\nto = \"bore.pub\"\nport = 9000\n\
+             tunnel = [\"5432\", \"8080:9001\"]\nsticky = false\n",
+        )
+        .unwrap();
+        let config = path.to_str().unwrap();
+
+        let args = with_config_file(["bore", "local", "--config", config, "--port", "9100"]);
+        let Some(Command::Local(local)) = Args::try_parse_from(args.unwrap()).unwrap().command
+        else {
+            panic!("expected local command");
+        };
+        assert_eq!((local.local_port, local.port), (3000, 9100));
+        assert_eq!(local.to, "bore.pub");
+        assert_eq!(local.tunnels.len(), 2);
+
+        fs::write(&path, "bind_address = \"0.0.0.0\"\n").unwrap();
+        assert!(with_config_file(["bore", "server", "--config", config]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! End-to-end encryption of tunnel connections, with a key that the server never sees.
+//!
+//! The shared key is a passphrase, stretched once with PBKDF2-HMAC-SHA256 so that it is slow to
+//! guess. Both ends send a random salt, then derive a key for each direction from the stretched
+//! key and both salts with HKDF-SHA256. Data is sent in frames of a two-byte length followed by a
+//! ChaCha20-Poly1305 ciphertext, with a counter as the nonce. An empty frame marks the end of
+//! the stream, so a relay cannot cut it short unnoticed.
+
+use std::fmt;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hkdf, pbkdf2};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::shared::NETWORK_TIMEOUT;
+
+/// Length of the random salt each end sends before any data.
+const SALT_LEN: usize = 32;
+
+/// Most plaintext bytes sent in one frame.
+const MAX_PLAINTEXT: usize = 16 * 1024;
+
+/// Context string for key derivation, changed if the format ever changes.
+const KEY_INFO: &[u8] = b"bore e2e v1";
+
+/// Salt for stretching the passphrase. Both ends must derive the same key, so it is fixed.
+const PASSPHRASE_SALT: &[u8] = b"bore e2e passphrase v1";
+
+/// PBKDF2 iterations for stretching the passphrase, following OWASP's advice for HMAC-SHA256.
+const PASSPHRASE_ITERATIONS: NonZeroU32 = NonZeroU32::new(600_000).unwrap();
+
+/// Key shared by the two ends of an encrypted tunnel.
+#[derive(Clone)]
+pub struct E2eKey {
+    secret: [u8; 32],
+}
+
+impl FromStr for E2eKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("end-to-end key cannot be empty".into());
+        }
+        let mut secret = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            PASSPHRASE_ITERATIONS,
+            PASSPHRASE_SALT,
+            s.as_bytes(),
+            &mut secret,
+        );
+        Ok(Self { secret })
+    }
+}
+
+impl fmt::Debug for E2eKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("E2eKey").finish_non_exhaustive()
+    }
+}
+
+/// Key and nonce counter for one direction of a connection.
+struct Direction {
+    key: LessSafeKey,
+    counter: u64,
+}
+
+impl Direction {
+    /// Derive the key for data sent by the end with `from` to the end with `to`.
+    fn new(key: &E2eKey, from: &[u8; SALT_LEN], to: &[u8; SALT_LEN]) -> Self {
+        let salt = [from.as_slice(), to.as_slice()].concat();
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &salt).extract(&key.secret);
+        let okm = prk
+            .expand(&[KEY_INFO], &aead::CHACHA20_POLY1305)
+            .expect("key length is valid for HKDF");
+        Self {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            counter: 0,
+        }
+    }
+
+    /// Nonce for the next frame. Each key is used for one connection, so a counter is unique.
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[NONCE_LEN - 8..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    /// Encrypt a frame in place, appending its tag.
+    fn seal(&mut self, frame: &mut Vec<u8>) -> Result<()> {
+        let nonce = self.next_nonce();
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::empty(), frame)
+            .map_err(|_| anyhow!("could not encrypt data"))
+    }
+
+    /// Decrypt a frame in place, returning the plaintext.
+    fn open<'a>(&mut self, frame: &'a mut [u8]) -> Result<&'a mut [u8]> {
+        let nonce = self.next_nonce();
+        self.key
+            .open_in_place(nonce, Aad::empty(), frame)
+            .map_err(|_| anyhow!("could not decrypt data, check that the keys match"))
+    }
+}
+
+/// Relay a connection, encrypting what `plain` sends onto `encrypted`, and decrypting back.
+///
+/// The other end of `encrypted` must be another relay with the same key.
+pub async fn relay<P, E>(plain: P, encrypted: E, key: &E2eKey) -> Result<()>
+where
+    P: AsyncRead + AsyncWrite + Unpin,
+    E: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut plain_rd, mut plain_wr) = io::split(plain);
+    let (mut encrypted_rd, mut encrypted_wr) = io::split(encrypted);
+
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| anyhow!("could not generate a random salt"))?;
+    encrypted_wr.write_all(&salt).await?;
+    let mut peer_salt = [0u8; SALT_LEN];
+    encrypted_rd.read_exact(&mut peer_salt).await?;
+    if peer_salt == salt {
+        // Our own data echoed back would otherwise decrypt, and could be replayed to us.
+        bail!("peer sent back our own salt");
+    }
+    let mut sealer = Direction::new(key, &salt, &peer_salt);
+    let mut opener = Direction::new(key, &peer_salt, &salt);
+
+    let send = async {
+        let mut buf = vec![0u8; MAX_PLAINTEXT];
+        loop {
+            let n = plain_rd.read(&mut buf).await?;
+            let mut frame = buf[..n].to_vec();
+            sealer.seal(&mut frame)?;
+            encrypted_wr.write_u16(frame.len() as u16).await?;
+            encrypted_wr.write_all(&frame).await?;
+            if n == 0 {
+                encrypted_wr.shutdown().await?;
+                return anyhow::Ok(());
+            }
+        }
+    };
+    let recv = async {
+        loop {
+            let len = match encrypted_rd.read_u16().await {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    bail!("encrypted stream ended without closing")
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let mut frame = vec![0u8; len as usize];
+            encrypted_rd.read_exact(&mut frame).await?;
+            let data = opener.open(&mut frame)?;
+            if data.is_empty() {
+                plain_wr.shutdown().await?;
+                return Ok(());
+            }
+            plain_wr.write_all(data).await?;
+        }
+    };
+    tokio::try_join!(send, recv)?;
+    Ok(())
+}
+
+/// Accept connections on `listener`, relaying each to an encrypted tunnel at `remote`.
+pub async fn serve(listener: TcpListener, remote: String, key: E2eKey) -> Result<()> {
+    info!(addr = %listener.local_addr()?, %remote, "serving decrypted tunnel");
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let remote = remote.clone();
+        let key = key.clone();
+        tokio::spawn(
+            async move {
+                if let Err(err) = connect(stream, &remote, &key).await {
+                    warn!(%err, "connection exited with error");
+                }
+            }
+            .instrument(info_span!("e2e", %addr)),
+        );
+    }
+}
+
+/// Relay one local connection to an encrypted tunnel.
+async fn connect(stream: TcpStream, remote: &str, key: &E2eKey) -> Result<()> {
+    let tunnel = match timeout(NETWORK_TIMEOUT, TcpStream::connect(remote)).await {
+        Ok(res) => res,
+        Err(err) => Err(err.into()),
+    }
+    .with_context(|| format!("could not connect to {remote}"))?;
+    info!("new connection");
+    relay(stream, tunnel, key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{relay, E2eKey};
+
+    #[tokio::test]
+    async fn relays_with_the_same_key_exchange_data() {
+        let key: E2eKey = "correct horse".parse().unwrap();
+        let (mut client, client_plain) = duplex(1024);
+        let (mut service, service_plain) = duplex(1024);
+        let (left, right) = duplex(1024);
+        let left_key = key.clone();
+        tokio::spawn(async move { relay(client_plain, left, &left_key).await });
+        tokio::spawn(async move { relay(service_plain, right, &key).await });
+
+        let mut buf = [0u8; 5];
+        client.write_all(b"hello").await.unwrap();
+        service.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        service.write_all(b"world").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world");
+        client.shutdown().await.unwrap();
+        assert_eq!(service.read(&mut buf).await.unwrap(), 0);
+
+        let (mut client, client_plain) = duplex(1024);
+        let (_service, service_plain) = duplex(1024);
+        let (left, right) = duplex(1024);
+        let key: E2eKey = "correct horse".parse().unwrap();
+        let wrong: E2eKey = "battery staple".parse().unwrap();
+        tokio::spawn(async move { relay(client_plain, left, &key).await });
+        let other = tokio::spawn(async move { relay(service_plain, right, &wrong).await });
+        client.write_all(b"hello").await.unwrap();
+        assert!(other.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn relays_reject_their_own_salt() {
+        let key: E2eKey = "correct horse".parse().unwrap();
+        let (_client, client_plain) = duplex(1024);
+        let (encrypted, echo) = duplex(1024);
+        tokio::spawn(async move {
+            let (mut rd, mut wr) = tokio::io::split(echo);
+            tokio::io::copy(&mut rd, &mut wr).await
+        });
+        assert!(relay(client_plain, encrypted, &key).await.is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Closing of proxied connections that stop transferring data.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep_until, Instant};
+
+/// Copy data in both directions until either side closes, or until no data moves for `timeout`.
+///
+/// Returns the bytes copied from `a` to `b` and from `b` to `a`, or `None` if the
+/// connection was closed for being idle.
+pub async fn copy_bidirectional<A, B>(
+    a: &mut A,
+    b: &mut B,
+    timeout: Option<Duration>,
+) -> io::Result<Option<(u64, u64)>>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let Some(timeout) = timeout else {
+        return tokio::io::copy_bidirectional(a, b).await.map(Some);
+    };
+    let start = Instant::now();
+    let last_active = AtomicU64::new(0);
+    let mut b = Tracked {
+        inner: b,
+        start,
+        last_active: &last_active,
+    };
+    let copy = tokio::io::copy_bidirectional(a, &mut b);
+    tokio::pin!(copy);
+    loop {
+        let active_at = last_active.load(Ordering::Relaxed);
+        let deadline = start + Duration::from_millis(active_at) + timeout;
+        tokio::select! {
+            result = &mut copy => return result.map(Some),
+            _ = sleep_until(deadline) => {
+                if last_active.load(Ordering::Relaxed) == active_at {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Stream wrapper that records when data last moved through it.
+struct Tracked<'a, S: ?Sized> {
+    inner: &'a mut S,
+    start: Instant,
+    last_active: &'a AtomicU64,
+}
+
+impl<S: ?Sized> Tracked<'_, S> {
+    fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_active.store(elapsed, Ordering::Relaxed);
+    }
+}
+
+impl<S: AsyncRead + Unpin + ?Sized> AsyncRead for Tracked<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            self.touch();
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin + ?Sized> AsyncWrite for Tracked<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut *self.inner).poll_write(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(written)) if written > 0) {
+            self.touch();
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::copy_bidirectional;
+
+    #[tokio::test]
+    async fn idle_connections_are_closed() {
+        let (mut a, mut a_peer) = duplex(64);
+        let (mut b, mut b_peer) = duplex(64);
+        let timeout = Some(Duration::from_millis(300));
+        let copy = tokio::spawn(async move { copy_bidirectional(&mut a, &mut b, timeout).await });
+
+        // Traffic keeps the connection open past the timeout.
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            a_peer.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            b_peer.read_exact(&mut buf).await.unwrap();
+        }
+        assert!(!copy.is_finished());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(copy.await.unwrap().unwrap(), None);
+    }
+}
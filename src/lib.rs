@@ -11,15 +11,95 @@
 //! There are two components to the crate, offering implementations of the
 //! server network daemon and client local forwarding proxy. Both are public
 //! members and can be run programmatically with a Tokio 1.0 runtime.
+//!
+//! # Embedding
+//!
+//! [`Server`] is configured with setters before it starts listening, and gives
+//! out handles for changing it while it runs, such as [`Server::drainer`]:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use bore_cli::Server;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut server = Server::new(1024..=65535, Some("secret"));
+//! server.set_max_connections(100);
+//! let drainer = server.drainer();
+//! let listen = tokio::spawn(server.listen());
+//!
+//! tokio::signal::ctrl_c().await?;
+//! drainer.drain(Duration::from_secs(30)).await;
+//! listen.abort();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Client`] connects when it is created, so the public port is known before
+//! any visitor arrives. Optional settings go in [`ClientOptions`], and typed
+//! tunnel events arrive on a [`TunnelEvents`](client::TunnelEvents) stream:
+//!
+//! ```no_run
+//! use bore_cli::client::TunnelEvents;
+//! use bore_cli::{Client, ClientOptions};
+//! use futures_util::StreamExt;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let options = ClientOptions {
+//!     max_connections: Some(10),
+//!     ..Default::default()
+//! };
+//! let (event_tx, mut events) = TunnelEvents::channel();
+//! let client =
+//!     Client::with_options("localhost", 3000, "bore.pub", 0, None, options, Some(event_tx))
+//!         .await?;
+//! println!("listening at bore.pub:{}", client.remote_port());
+//!
+//! tokio::spawn(async move {
+//!     while let Some(event) = events.next().await {
+//!         println!("{event:?}");
+//!     }
+//! });
+//! client
+//!     .listen_with_shutdown(async {
+//!         let _ = tokio::signal::ctrl_c().await;
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
 
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
 
+pub mod access;
+pub mod admin;
+pub mod audit;
 pub mod auth;
+pub mod balance;
 /// CLI argument parsing and command dispatch.
 pub mod cli;
 pub mod client;
+pub mod config;
+pub mod e2e;
+pub mod egress;
+pub mod idle;
+pub mod limit;
+pub mod logging;
+pub mod metrics;
+pub mod mux;
+pub mod proxy_protocol;
 pub mod server;
 pub mod shared;
+pub mod socks;
+pub mod stats;
+pub mod systemd;
+pub mod tls;
+pub mod tokens;
+pub mod transport;
+pub mod vhost;
 /// Local web console for managing client tunnels.
 pub mod web;
+
+pub use client::{Client, ClientOptions};
+pub use server::Server;
@@ -0,0 +1,331 @@
+//! Token-bucket rate limiting, keyed by source address or shared globally.
+
+use std::future::Future;
+use std::hash::Hash;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// Sustained rate and burst size of a token bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    pub rate: f64,
+
+    /// Maximum number of tokens that can accumulate.
+    pub burst: f64,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parse `RATE` or `RATE:BURST`, where the burst defaults to the rate.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rate, burst) = match s.split_once(':') {
+            Some((rate, burst)) => (rate, Some(burst)),
+            None => (s, None),
+        };
+        let rate: f64 = rate.parse().map_err(|_| format!("invalid rate: {rate}"))?;
+        let burst: f64 = match burst {
+            Some(burst) => burst
+                .parse()
+                .map_err(|_| format!("invalid burst: {burst}"))?,
+            None => rate.max(1.0),
+        };
+        if !(rate > 0.0 && rate.is_finite()) {
+            return Err("rate must be a positive number".into());
+        }
+        if !(burst >= 1.0 && burst.is_finite()) {
+            return Err("burst must be at least 1".into());
+        }
+        Ok(Self { rate, burst })
+    }
+}
+
+/// Error returned when a rate limit is exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttled {
+    /// Whether this is the first rejection since the key was last allowed.
+    pub newly: bool,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    throttled: bool,
+}
+
+/// Token buckets for a set of keys, created on first use.
+#[derive(Debug)]
+pub struct RateLimiter<K: Eq + Hash> {
+    limit: RateLimit,
+    buckets: DashMap<K, Bucket>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Create a limiter where each key gets its own bucket.
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Take one token for a key, failing if its bucket is empty.
+    pub fn try_acquire(&self, key: K) -> Result<(), Throttled> {
+        let now = Instant::now();
+        let mut bucket = self.buckets.entry(key).or_insert_with(|| Bucket {
+            tokens: self.limit.burst,
+            updated: now,
+            throttled: false,
+        });
+        self.refill(&mut bucket, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.throttled = false;
+            Ok(())
+        } else {
+            let newly = !bucket.throttled;
+            bucket.throttled = true;
+            Err(Throttled { newly })
+        }
+    }
+
+    /// Check whether a key has no tokens left, without taking one.
+    pub fn is_exhausted(&self, key: &K) -> bool {
+        match self.buckets.get_mut(key) {
+            Some(mut bucket) => {
+                self.refill(&mut bucket, Instant::now());
+                bucket.tokens < 1.0
+            }
+            None => false,
+        }
+    }
+
+    /// Forget keys whose buckets have refilled, to bound memory use.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let full = Duration::from_secs_f64(self.limit.burst / self.limit.rate);
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.updated) < full);
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        bucket.updated = now;
+    }
+}
+
+/// Throughput limit in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRate(pub u64);
+
+impl FromStr for ByteRate {
+    type Err = String;
+
+    /// Parse a byte count per second, like `5MBps`, `512KiB/s`, or `1000000`.
+    ///
+    /// Units `K`, `M`, and `G` are powers of 1000, and `Ki`, `Mi`, and `Gi` powers of 1024.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid byte rate: {s}");
+        let unit = s
+            .strip_suffix("ps")
+            .or_else(|| s.strip_suffix("/s"))
+            .unwrap_or(s);
+        let unit = unit.strip_suffix(['B', 'b']).unwrap_or(unit);
+        let (number, multiplier) = [
+            ("Ki", 1 << 10),
+            ("Mi", 1 << 20),
+            ("Gi", 1 << 30),
+            ("K", 1_000),
+            ("k", 1_000),
+            ("M", 1_000_000),
+            ("G", 1_000_000_000),
+        ]
+        .into_iter()
+        .find_map(|(suffix, multiplier)| Some((unit.strip_suffix(suffix)?, multiplier)))
+        .unwrap_or((unit, 1));
+        let number: f64 = number.trim().parse().map_err(|_| invalid())?;
+        let rate = number * multiplier as f64;
+        if !(rate >= 1.0 && rate < u64::MAX as f64) {
+            return Err("byte rate must be at least 1 byte per second".into());
+        }
+        Ok(Self(rate as u64))
+    }
+}
+
+#[derive(Debug)]
+struct ByteBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Shared token buckets that cap the throughput of a tunnel in each direction.
+///
+/// Transfers may overdraw a bucket, and later transfers then wait until it refills,
+/// so the average rate stays at the limit even with large reads and writes.
+#[derive(Debug)]
+pub struct Shaper {
+    rate: f64,
+    upload: Mutex<ByteBucket>,
+    download: Mutex<ByteBucket>,
+}
+
+impl Shaper {
+    /// Create a shaper allowing up to one second of traffic in a burst.
+    pub fn new(rate: ByteRate) -> Self {
+        let bucket = || {
+            Mutex::new(ByteBucket {
+                tokens: rate.0 as f64,
+                updated: Instant::now(),
+            })
+        };
+        Self {
+            rate: rate.0 as f64,
+            upload: bucket(),
+            download: bucket(),
+        }
+    }
+
+    /// Take tokens for transferred bytes, returning how long to wait if the bucket is overdrawn.
+    fn take(&self, bucket: &Mutex<ByteBucket>, bytes: usize) -> Option<Duration> {
+        let mut bucket = bucket.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate) - bytes as f64;
+        bucket.updated = now;
+        (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / self.rate))
+    }
+}
+
+/// Stream wrapper that limits its throughput with a [`Shaper`].
+///
+/// Reads take from the upload bucket and writes from the download bucket, so this
+/// should wrap the side of a proxied connection that faces the visitor.
+#[derive(Debug)]
+pub struct Shaped<S> {
+    inner: S,
+    shaper: Arc<Shaper>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Shaped<S> {
+    /// Wrap a stream, sharing the limits of `shaper` with other streams.
+    pub fn new(inner: S, shaper: Arc<Shaper>) -> Self {
+        Self {
+            inner,
+            shaper,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+/// Wait for a pending delay to finish, clearing it afterwards.
+fn poll_delay(delay: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(sleep) = delay {
+        ready!(sleep.as_mut().poll(cx));
+        *delay = None;
+    }
+    Poll::Ready(())
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Shaped<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(poll_delay(&mut this.read_delay, cx));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - before;
+        if let Some(wait) = this.shaper.take(&this.shaper.upload, read) {
+            this.read_delay = Some(Box::pin(sleep(wait)));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Shaped<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(poll_delay(&mut this.write_delay, cx));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(wait) = this.shaper.take(&this.shaper.download, written) {
+            this.write_delay = Some(Box::pin(sleep(wait)));
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteRate, RateLimit, RateLimiter, Throttled};
+
+    #[test]
+    fn parse_rate_limits() {
+        let limit: RateLimit = "5:20".parse().unwrap();
+        assert_eq!(
+            limit,
+            RateLimit {
+                rate: 5.0,
+                burst: 20.0
+            }
+        );
+        let limit: RateLimit = "0.5".parse().unwrap();
+        assert_eq!(limit.burst, 1.0);
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("5:0".parse::<RateLimit>().is_err());
+        assert!("fast".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn buckets_allow_burst_then_throttle_per_key() {
+        let limiter = RateLimiter::new(RateLimit {
+            rate: 0.001,
+            burst: 2.0,
+        });
+        assert_eq!(limiter.try_acquire("a"), Ok(()));
+        assert_eq!(limiter.try_acquire("a"), Ok(()));
+        assert!(limiter.is_exhausted(&"a"));
+        assert_eq!(limiter.try_acquire("a"), Err(Throttled { newly: true }));
+        assert_eq!(limiter.try_acquire("a"), Err(Throttled { newly: false }));
+        assert_eq!(limiter.try_acquire("b"), Ok(()));
+        assert!(!limiter.is_exhausted(&"c"));
+    }
+
+    #[test]
+    fn parse_byte_rates() {
+        let rate = |s: &str| s.parse::<ByteRate>().map(|rate| rate.0);
+        assert_eq!(rate("5MBps"), Ok(5_000_000));
+        assert_eq!(rate("512KiB/s"), Ok(524_288));
+        assert_eq!(rate("1.5M"), Ok(1_500_000));
+        assert_eq!(rate("1000"), Ok(1000));
+        assert!(rate("0").is_err());
+        assert!(rate("fast").is_err());
+    }
+}
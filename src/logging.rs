@@ -0,0 +1,118 @@
+//! Logging setup with a filter that can be changed while running.
+
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{filter::LevelFilter, reload, EnvFilter, Registry};
+
+/// Filter used when `RUST_LOG` is not set.
+const DEFAULT_FILTER: &str = "info";
+
+/// Filter switched to by `SIGUSR1`.
+const DEBUG_FILTER: &str = "debug";
+
+static LOG_FILTER: OnceLock<LogFilter> = OnceLock::new();
+
+/// Handle to the tracing filter of the running process.
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    initial: String,
+}
+
+impl LogFilter {
+    /// Get the filter installed by [`init`], if any.
+    pub fn global() -> Option<&'static LogFilter> {
+        LOG_FILTER.get()
+    }
+
+    /// Current filter directives, in `RUST_LOG` syntax.
+    pub fn current(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Replace the filter with new directives, in `RUST_LOG` syntax.
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .parse(directives)?;
+        self.handle.reload(filter)?;
+        info!(filter = directives, "log filter changed");
+        Ok(())
+    }
+
+    /// Switch between the initial filter and debug logging.
+    pub fn toggle_debug(&self) -> Result<()> {
+        if self.current() == self.initial {
+            self.set(DEBUG_FILTER)
+        } else {
+            self.set(&self.initial)
+        }
+    }
+}
+
+/// Install the global tracing subscriber, filtered by `RUST_LOG`.
+///
+/// Logs are written to stderr instead of stdout when `stderr` is set.
+pub fn init(stderr: bool) -> Result<()> {
+    let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse_lossy(&directives);
+    let initial = filter.to_string();
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let writer = if stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .try_init()?;
+
+    LOG_FILTER
+        .set(LogFilter { handle, initial })
+        .map_err(|_| anyhow!("logging is already initialized"))
+}
+
+/// Toggle debug logging whenever the process receives `SIGUSR1`.
+#[cfg(unix)]
+pub async fn toggle_debug_on_sigusr1() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let Some(log_filter) = LogFilter::global() else {
+        return Ok(());
+    };
+    let mut signals = signal(SignalKind::user_defined1())?;
+    while signals.recv().await.is_some() {
+        log_filter.toggle_debug()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init, LogFilter};
+
+    #[test]
+    fn log_filter_can_be_changed_at_runtime() {
+        init(true).expect("logging should initialize");
+        let log_filter = LogFilter::global().expect("filter should be installed");
+        let initial = log_filter.current();
+
+        log_filter.toggle_debug().unwrap();
+        assert_eq!(log_filter.current(), "debug");
+        log_filter.toggle_debug().unwrap();
+        assert_eq!(log_filter.current(), initial);
+
+        log_filter.set("warn,bore_cli::server=trace").unwrap();
+        assert!(log_filter.current().contains("bore_cli::server=trace"));
+        assert!(log_filter.set("not a [filter").is_err());
+    }
+}
@@ -1,10 +1,11 @@
 use anyhow::Result;
-use bore_cli::cli::{run, Args};
+use bore_cli::cli::{json_output, run, Args};
+use bore_cli::{config, logging};
 use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let args = Args::parse();
+    let args = Args::parse_from(config::with_config_file(std::env::args_os())?);
+    logging::init(json_output(&args))?;
     run(args).await
 }
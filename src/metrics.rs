@@ -0,0 +1,236 @@
+//! Metrics exporters for counters recorded through the [`metrics`] facade.
+//!
+//! The server, client, and web console record metrics with the `metrics`
+//! macros regardless of where they end up. One exporter can be installed per
+//! process, selected with `--metrics`:
+//!
+//! - `stdout`: print all metrics every few seconds.
+//! - `prometheus:ADDR`: serve `/metrics` on `ADDR` (requires the `prometheus` feature).
+//! - `statsd:HOST:PORT`: push metrics over UDP (requires the `statsd` feature).
+
+use std::fmt::{self, Display};
+use std::io::Write;
+#[cfg(feature = "prometheus")]
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+use metrics_util::registry::{AtomicStorage, Registry};
+
+/// How often the stdout exporter prints metrics.
+const STDOUT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Prometheus histogram buckets for durations, in seconds.
+#[cfg(feature = "prometheus")]
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Destination for metrics recorded by this process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsExporter {
+    /// Print metrics to stdout periodically.
+    Stdout,
+
+    /// Serve metrics in the Prometheus text format on this address.
+    #[cfg(feature = "prometheus")]
+    Prometheus(SocketAddr),
+
+    /// Push metrics to a statsd server.
+    #[cfg(feature = "statsd")]
+    Statsd {
+        /// Hostname of the statsd server.
+        host: String,
+        /// UDP port of the statsd server.
+        port: u16,
+    },
+}
+
+impl FromStr for MetricsExporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, target) = match s.split_once(':') {
+            Some((kind, target)) => (kind, Some(target)),
+            None => (s, None),
+        };
+        match (kind, target) {
+            ("stdout", None) => Ok(Self::Stdout),
+            #[cfg(feature = "prometheus")]
+            ("prometheus", Some(addr)) => addr
+                .parse()
+                .map(Self::Prometheus)
+                .map_err(|err| format!("invalid prometheus address: {err}")),
+            #[cfg(feature = "statsd")]
+            ("statsd", Some(target)) => {
+                let (host, port) = target
+                    .rsplit_once(':')
+                    .ok_or("statsd exporter must be statsd:HOST:PORT")?;
+                let port = port
+                    .parse()
+                    .map_err(|err| format!("invalid statsd port: {err}"))?;
+                Ok(Self::Statsd {
+                    host: host.to_string(),
+                    port,
+                })
+            }
+            #[cfg(not(feature = "prometheus"))]
+            ("prometheus", Some(_)) => Err("bore was built without the prometheus feature".into()),
+            #[cfg(not(feature = "statsd"))]
+            ("statsd", Some(_)) => Err("bore was built without the statsd feature".into()),
+            _ => Err("expected stdout, prometheus:ADDR, or statsd:HOST:PORT".to_string()),
+        }
+    }
+}
+
+impl Display for MetricsExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => f.write_str("stdout"),
+            #[cfg(feature = "prometheus")]
+            Self::Prometheus(addr) => write!(f, "prometheus:{addr}"),
+            #[cfg(feature = "statsd")]
+            Self::Statsd { host, port } => write!(f, "statsd:{host}:{port}"),
+        }
+    }
+}
+
+/// Install the global metrics recorder for an exporter.
+///
+/// Must be called from within a Tokio runtime, and at most once per process.
+pub fn install(exporter: &MetricsExporter) -> Result<()> {
+    match exporter {
+        MetricsExporter::Stdout => {
+            let recorder = StdoutRecorder::default();
+            let registry = Arc::clone(&recorder.registry);
+            metrics::set_global_recorder(recorder)
+                .map_err(|_| anyhow!("a metrics recorder is already installed"))?;
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(STDOUT_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    print_metrics(&registry);
+                }
+            });
+        }
+        #[cfg(feature = "prometheus")]
+        MetricsExporter::Prometheus(addr) => {
+            use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+            PrometheusBuilder::new()
+                .with_http_listener(*addr)
+                .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), DURATION_BUCKETS)?
+                .install()?;
+        }
+        #[cfg(feature = "statsd")]
+        MetricsExporter::Statsd { host, port } => {
+            let recorder = metrics_exporter_statsd::StatsdBuilder::from(host.as_str(), *port)
+                .build(Some("bore"))?;
+            metrics::set_global_recorder(recorder)
+                .map_err(|_| anyhow!("a metrics recorder is already installed"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Recorder that keeps metrics in memory for [`print_metrics`].
+struct StdoutRecorder {
+    registry: Arc<Registry<Key, AtomicStorage>>,
+}
+
+impl Default for StdoutRecorder {
+    fn default() -> Self {
+        Self {
+            registry: Arc::new(Registry::atomic()),
+        }
+    }
+}
+
+impl Recorder for StdoutRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        self.registry
+            .get_or_create_counter(key, |counter| Counter::from_arc(Arc::clone(counter)))
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        self.registry
+            .get_or_create_gauge(key, |gauge| Gauge::from_arc(Arc::clone(gauge)))
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        self.registry
+            .get_or_create_histogram(key, |histogram| Histogram::from_arc(Arc::clone(histogram)))
+    }
+}
+
+/// Print every metric on its own line, with histograms as count and sum since the last print.
+fn print_metrics(registry: &Registry<Key, AtomicStorage>) {
+    let mut lines = Vec::new();
+    registry.visit_counters(|key, counter| {
+        lines.push(format!(
+            "{} {}",
+            format_key(key),
+            counter.load(Ordering::Relaxed)
+        ));
+    });
+    registry.visit_gauges(|key, gauge| {
+        let value = f64::from_bits(gauge.load(Ordering::Relaxed));
+        lines.push(format!("{} {value}", format_key(key)));
+    });
+    registry.visit_histograms(|key, histogram| {
+        let (mut count, mut sum) = (0, 0.0);
+        histogram.clear_with(|values| {
+            count += values.len();
+            sum += values.iter().sum::<f64>();
+        });
+        lines.push(format!("{}_count {count}", format_key(key)));
+        lines.push(format!("{}_sum {sum}", format_key(key)));
+    });
+    lines.sort();
+
+    let mut stdout = std::io::stdout().lock();
+    for line in lines {
+        let _ = writeln!(stdout, "{line}");
+    }
+}
+
+/// Format a metric key like `name{label="value"}`.
+fn format_key(key: &Key) -> String {
+    let labels: Vec<_> = key
+        .labels()
+        .map(|label| format!("{}=\"{}\"", label.key(), label.value()))
+        .collect();
+    if labels.is_empty() {
+        key.name().to_string()
+    } else {
+        format!("{}{{{}}}", key.name(), labels.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetricsExporter;
+
+    #[test]
+    fn parse_metrics_exporters() {
+        assert_eq!("stdout".parse(), Ok(MetricsExporter::Stdout));
+        assert!("stdout:extra".parse::<MetricsExporter>().is_err());
+        assert!("influx:localhost".parse::<MetricsExporter>().is_err());
+        #[cfg(feature = "prometheus")]
+        assert_eq!(
+            "prometheus:127.0.0.1:9100".parse(),
+            Ok(MetricsExporter::Prometheus(
+                "127.0.0.1:9100".parse().unwrap()
+            ))
+        );
+    }
+}
@@ -0,0 +1,495 @@
+//! Multiplexing of many streams over one connection, so that the connections of a tunnel share
+//! a single data connection between client and server instead of opening one each.
+//!
+//! Every frame starts with a 7-byte header: a kind, a stream ID, and a payload length. Streams
+//! are opened by the server, with the visitor address as the payload of the open frame. Each
+//! stream has its own credit-based flow control, so a slow reader does not stall the others.
+//!
+//! The queues inside a session are bounded even where the channels are not: data frames are
+//! limited by each stream's window in both directions, and streams the peer opens but nobody
+//! has accepted yet are limited to `MAX_PENDING_OPENS`, beyond which they are closed at once.
+
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::PollSemaphore;
+use tracing::{debug, warn};
+
+use crate::tls::Transport;
+
+/// Length of the header that starts every frame.
+const HEADER_LENGTH: usize = 7;
+
+/// Largest payload of a single data frame.
+const MAX_PAYLOAD: usize = 16 * 1024;
+
+/// Bytes that may be sent on a stream before the receiver has read them.
+const WINDOW: u32 = 256 * 1024;
+
+/// Streams opened by the peer that may wait to be accepted.
+const MAX_PENDING_OPENS: usize = 256;
+
+/// Kind of a frame, which is its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Opens a new stream, with metadata as the payload.
+    Open = 0,
+
+    /// Data on a stream.
+    Data = 1,
+
+    /// The sender will write no more data on the stream.
+    Close = 2,
+
+    /// The receiver read some data, given as a 4-byte count, and can take that much more.
+    Window = 3,
+}
+
+impl Kind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Open),
+            1 => Some(Self::Data),
+            2 => Some(Self::Close),
+            3 => Some(Self::Window),
+            _ => None,
+        }
+    }
+}
+
+/// A frame waiting to be written to the connection.
+struct Frame {
+    kind: Kind,
+    id: u32,
+    payload: Vec<u8>,
+}
+
+/// Receiving end of a stream, kept by the session to deliver frames from the peer.
+struct StreamEntry {
+    /// Data sent by the peer, dropped when the peer closes its side.
+    data: Option<mpsc::UnboundedSender<Vec<u8>>>,
+
+    /// Bytes received that the peer has not been given credit for again.
+    unread: Arc<AtomicU32>,
+
+    /// Credit for sending data to the peer.
+    credit: Arc<Semaphore>,
+}
+
+/// State shared by a session, its streams, and its reader task.
+struct Shared {
+    streams: Mutex<HashMap<u32, StreamEntry>>,
+    next_id: AtomicU32,
+}
+
+impl Shared {
+    /// Register a new stream, returning its half of the connection.
+    fn add_stream(self: &Arc<Self>, id: u32, frames: mpsc::UnboundedSender<Frame>) -> MuxStream {
+        let (data_tx, data_rx) = mpsc::unbounded_channel();
+        let unread = Arc::new(AtomicU32::new(0));
+        let credit = Arc::new(Semaphore::new(WINDOW as usize));
+        let entry = StreamEntry {
+            data: Some(data_tx),
+            unread: Arc::clone(&unread),
+            credit: Arc::clone(&credit),
+        };
+        self.streams.lock().unwrap().insert(id, entry);
+        MuxStream {
+            id,
+            shared: Arc::clone(self),
+            frames,
+            data: data_rx,
+            buffer: Vec::new(),
+            position: 0,
+            unread,
+            unacknowledged: 0,
+            credit: PollSemaphore::new(credit),
+            closed: false,
+        }
+    }
+}
+
+/// Streams multiplexed over one connection.
+///
+/// The connection is closed once the session and all of its streams are dropped.
+pub struct Session {
+    shared: Arc<Shared>,
+    frames: mpsc::UnboundedSender<Frame>,
+    incoming: mpsc::Receiver<(MuxStream, Vec<u8>)>,
+}
+
+impl Session {
+    /// Start multiplexing streams over a connection.
+    pub fn new(conn: Transport) -> Self {
+        let (reader, writer) = tokio::io::split(conn);
+        let shared = Arc::new(Shared {
+            streams: Mutex::new(HashMap::new()),
+            next_id: AtomicU32::new(1),
+        });
+        let (frames_tx, frames_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming_rx) = mpsc::channel(MAX_PENDING_OPENS);
+        tokio::spawn(write_frames(writer, frames_rx));
+        let frames = frames_tx.downgrade();
+        let reader_shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            if let Err(err) = read_frames(reader, &reader_shared, frames, incoming_tx).await {
+                warn!(%err, "multiplexed connection failed");
+            }
+            // Streams see the end of their data, and writers waiting for credit give up.
+            for (_, entry) in reader_shared.streams.lock().unwrap().drain() {
+                entry.credit.close();
+            }
+        });
+        Self {
+            shared,
+            frames: frames_tx,
+            incoming: incoming_rx,
+        }
+    }
+
+    /// Open a stream to the peer, sending it `metadata` with the stream.
+    pub fn open(&self, metadata: &[u8]) -> io::Result<MuxStream> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let stream = self.shared.add_stream(id, self.frames.clone());
+        send(&self.frames, Kind::Open, id, metadata.to_vec())?;
+        Ok(stream)
+    }
+
+    /// Wait for the peer to open a stream, returning it with its metadata.
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn accept(&mut self) -> Option<(MuxStream, Vec<u8>)> {
+        self.incoming.recv().await
+    }
+
+    /// Whether the connection has failed or been closed by the peer.
+    pub fn is_closed(&self) -> bool {
+        self.frames.is_closed() || self.incoming.is_closed()
+    }
+}
+
+/// Queue a frame to be written to the connection.
+fn send(
+    frames: &mpsc::UnboundedSender<Frame>,
+    kind: Kind,
+    id: u32,
+    payload: Vec<u8>,
+) -> io::Result<()> {
+    frames
+        .send(Frame { kind, id, payload })
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "multiplexed connection closed"))
+}
+
+/// Write queued frames to the connection until every sender is dropped.
+async fn write_frames(
+    writer: impl AsyncWrite + Unpin,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    while let Some(frame) = frames.recv().await {
+        let mut header = [0u8; HEADER_LENGTH];
+        header[0] = frame.kind as u8;
+        header[1..5].copy_from_slice(&frame.id.to_be_bytes());
+        header[5..].copy_from_slice(&(frame.payload.len() as u16).to_be_bytes());
+        writer.write_all(&header).await?;
+        writer.write_all(&frame.payload).await?;
+        if frames.is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.shutdown().await
+}
+
+/// Deliver frames from the connection to their streams until it closes.
+async fn read_frames(
+    mut reader: impl AsyncRead + Unpin,
+    shared: &Arc<Shared>,
+    frames: mpsc::WeakUnboundedSender<Frame>,
+    incoming: mpsc::Sender<(MuxStream, Vec<u8>)>,
+) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; HEADER_LENGTH];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        let id = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let mut payload = vec![0u8; u16::from_be_bytes([header[5], header[6]]) as usize];
+        reader.read_exact(&mut payload).await?;
+        let kind = Kind::from_byte(header[0]).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "unknown multiplexed frame")
+        })?;
+        let mut streams = shared.streams.lock().unwrap();
+        match kind {
+            Kind::Open => {
+                if streams.contains_key(&id) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream opened twice",
+                    ));
+                }
+                drop(streams);
+                // The session and all streams are gone, so nobody can take the stream.
+                let Some(frames) = frames.upgrade() else {
+                    return Ok(());
+                };
+                let stream = shared.add_stream(id, frames);
+                if let Err(mpsc::error::TrySendError::Full((stream, _))) =
+                    incoming.try_send((stream, payload))
+                {
+                    // Dropping the stream closes it, so the peer sees it end right away.
+                    debug!(id, "too many streams waiting to be accepted, closing one");
+                    drop(stream);
+                }
+            }
+            Kind::Data => {
+                // Data for a stream that was already dropped is discarded.
+                let Some(entry) = streams.get(&id) else {
+                    continue;
+                };
+                let length = payload.len() as u32;
+                if entry.unread.fetch_add(length, Ordering::Relaxed) + length > WINDOW {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream data exceeds its window",
+                    ));
+                }
+                if let Some(data) = &entry.data {
+                    let _ = data.send(payload);
+                }
+            }
+            Kind::Close => {
+                if let Some(entry) = streams.get_mut(&id) {
+                    entry.data = None;
+                }
+            }
+            Kind::Window => {
+                let bytes: [u8; 4] = payload.as_slice().try_into().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid window update")
+                })?;
+                if let Some(entry) = streams.get(&id) {
+                    // Credit never exceeds the window, which also keeps the semaphore in range.
+                    let update = u32::from_be_bytes(bytes) as usize;
+                    if entry.credit.available_permits() + update > WINDOW as usize {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "window update exceeds the window",
+                        ));
+                    }
+                    entry.credit.add_permits(update);
+                }
+            }
+        }
+    }
+}
+
+/// One stream of a multiplexed connection.
+pub struct MuxStream {
+    id: u32,
+    shared: Arc<Shared>,
+    frames: mpsc::UnboundedSender<Frame>,
+    data: mpsc::UnboundedReceiver<Vec<u8>>,
+
+    /// Data received but not yet read, starting at `position`.
+    buffer: Vec<u8>,
+    position: usize,
+
+    /// Bytes received that the peer has not been given credit for again, shared with the reader.
+    unread: Arc<AtomicU32>,
+
+    /// Bytes read since the peer was last given more credit.
+    unacknowledged: u32,
+
+    credit: PollSemaphore,
+
+    /// Whether this side has closed the stream for writing.
+    closed: bool,
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.position == self.buffer.len() {
+            match ready!(self.data.poll_recv(cx)) {
+                Some(data) => {
+                    self.buffer = data;
+                    self.position = 0;
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = buf.remaining().min(self.buffer.len() - self.position);
+        buf.put_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        self.unacknowledged += n as u32;
+        if self.unacknowledged >= WINDOW / 2 {
+            let update = self.unacknowledged.to_be_bytes().to_vec();
+            self.unread
+                .fetch_sub(self.unacknowledged, Ordering::Relaxed);
+            self.unacknowledged = 0;
+            // If the connection is gone, the read still succeeds with data already received.
+            let _ = send(&self.frames, Kind::Window, self.id, update);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = buf.len().min(MAX_PAYLOAD);
+        match ready!(self.credit.poll_acquire_many(cx, n as u32)) {
+            Some(permit) => permit.forget(),
+            None => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+        send(&self.frames, Kind::Data, self.id, buf[..n].to_vec())?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.closed {
+            self.closed = true;
+            send(&self.frames, Kind::Close, self.id, Vec::new())?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = send(&self.frames, Kind::Close, self.id, Vec::new());
+        }
+        self.shared.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{Kind, Session, HEADER_LENGTH, MAX_PAYLOAD, MAX_PENDING_OPENS, WINDOW};
+
+    /// Encode a frame the way the writer task does.
+    fn frame(kind: Kind, id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_LENGTH + payload.len());
+        frame.push(kind as u8);
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn streams_share_one_connection() {
+        let (a, b) = duplex(4096);
+        let server = Session::new(Box::new(a));
+        let mut client = Session::new(Box::new(b));
+
+        let mut first = server.open(b"first").unwrap();
+        let mut second = server.open(b"second").unwrap();
+        let (mut first_peer, metadata) = client.accept().await.unwrap();
+        assert_eq!(metadata, b"first");
+        let (mut second_peer, metadata) = client.accept().await.unwrap();
+        assert_eq!(metadata, b"second");
+
+        // More than a window of data, which needs the reader to give credit back.
+        let data = vec![7u8; WINDOW as usize * 3];
+        let expected = data.clone();
+        let writer = tokio::spawn(async move {
+            first.write_all(&data).await.unwrap();
+            first.shutdown().await.unwrap();
+            first
+        });
+        // The other stream is not blocked by the unread data on the first one.
+        second_peer.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        second.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let mut received = Vec::new();
+        first_peer.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, expected);
+        drop(writer.await.unwrap());
+
+        drop((second, server));
+        drop((first_peer, second_peer));
+        assert!(client.accept().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sending_past_the_window_fails_the_session() {
+        let (a, mut peer) = duplex(4096);
+        let mut session = Session::new(Box::new(a));
+
+        peer.write_all(&frame(Kind::Open, 1, b"")).await.unwrap();
+        let (_stream, _) = session.accept().await.unwrap();
+        let payload = vec![0u8; MAX_PAYLOAD];
+        for _ in 0..=WINDOW as usize / MAX_PAYLOAD {
+            peer.write_all(&frame(Kind::Data, 1, &payload))
+                .await
+                .unwrap();
+        }
+        assert!(session.accept().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn credit_past_the_window_fails_the_session() {
+        let (a, mut peer) = duplex(4096);
+        let mut session = Session::new(Box::new(a));
+
+        let _stream = session.open(b"").unwrap();
+        let update = WINDOW.to_be_bytes();
+        peer.write_all(&frame(Kind::Window, 1, &update))
+            .await
+            .unwrap();
+        assert!(session.accept().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn streams_past_the_pending_limit_are_closed() {
+        let (a, mut peer) = duplex(64 * 1024);
+        let mut session = Session::new(Box::new(a));
+
+        let last = MAX_PENDING_OPENS as u32 + 1;
+        for id in 1..=last {
+            peer.write_all(&frame(Kind::Open, id, b"")).await.unwrap();
+        }
+        let mut header = [0u8; HEADER_LENGTH];
+        peer.read_exact(&mut header).await.unwrap();
+        assert_eq!(header.as_slice(), frame(Kind::Close, last, b""));
+
+        // The streams that fit are still delivered, and the session keeps working.
+        for _ in 0..MAX_PENDING_OPENS {
+            session.accept().await.unwrap();
+        }
+        peer.write_all(&frame(Kind::Open, last + 1, b"later"))
+            .await
+            .unwrap();
+        let (_stream, metadata) = session.accept().await.unwrap();
+        assert_eq!(metadata, b"later");
+    }
+}
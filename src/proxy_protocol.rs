@@ -0,0 +1,126 @@
+//! Headers of the PROXY protocol, which tell local services the real visitor address.
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use std::net::{IpAddr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+/// Signature that starts every version 2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Version of the PROXY protocol sent to local services.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    /// Human-readable header, like `PROXY TCP4 203.0.113.7 127.0.0.1 51234 8000`.
+    V1,
+
+    /// Binary header.
+    V2,
+}
+
+impl ProxyProtocol {
+    /// Encode a header for a connection from `source` to `destination`.
+    ///
+    /// If the source is not known, the header says so, and the receiver uses
+    /// the address of the connection itself.
+    pub fn header(self, source: Option<SocketAddr>, destination: SocketAddr) -> Vec<u8> {
+        let addrs = source.map(|source| same_family(source, destination));
+        match self {
+            Self::V1 => match addrs {
+                Some((source, destination)) => {
+                    let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+                    format!(
+                        "PROXY {family} {} {} {} {}\r\n",
+                        source.ip(),
+                        destination.ip(),
+                        source.port(),
+                        destination.port()
+                    )
+                    .into_bytes()
+                }
+                None => b"PROXY UNKNOWN\r\n".to_vec(),
+            },
+            Self::V2 => {
+                let mut header = V2_SIGNATURE.to_vec();
+                let mut body = Vec::new();
+                let family = match addrs {
+                    Some((source, destination)) => {
+                        let family = match (source.ip(), destination.ip()) {
+                            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                                body.extend(src.octets());
+                                body.extend(dst.octets());
+                                0x11
+                            }
+                            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                                body.extend(src.octets());
+                                body.extend(dst.octets());
+                                0x21
+                            }
+                            _ => unreachable!("addresses have the same family"),
+                        };
+                        body.extend(source.port().to_be_bytes());
+                        body.extend(destination.port().to_be_bytes());
+                        family
+                    }
+                    None => 0x00,
+                };
+                // Version 2, with the PROXY command if the source is known, or LOCAL if not.
+                header.push(if addrs.is_some() { 0x21 } else { 0x20 });
+                header.push(family);
+                header.extend((body.len() as u16).to_be_bytes());
+                header.extend(body);
+                header
+            }
+        }
+    }
+}
+
+/// Convert two addresses to the same family, using IPv4-mapped IPv6 addresses if needed.
+fn same_family(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let (a_ip, b_ip) = (a.ip().to_canonical(), b.ip().to_canonical());
+    let (a_ip, b_ip) = match (a_ip, b_ip) {
+        (IpAddr::V4(a_ip), IpAddr::V6(b_ip)) => (IpAddr::V6(a_ip.to_ipv6_mapped()), b_ip.into()),
+        (IpAddr::V6(a_ip), IpAddr::V4(b_ip)) => (a_ip.into(), IpAddr::V6(b_ip.to_ipv6_mapped())),
+        ips => ips,
+    };
+    (
+        SocketAddr::new(a_ip, a.port()),
+        SocketAddr::new(b_ip, b.port()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyProtocol;
+
+    #[test]
+    fn headers_are_encoded() {
+        let source = "203.0.113.7:51234".parse().unwrap();
+        let destination = "127.0.0.1:8000".parse().unwrap();
+        assert_eq!(
+            ProxyProtocol::V1.header(Some(source), destination),
+            b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 8000\r\n"
+        );
+        assert_eq!(
+            ProxyProtocol::V1.header(Some(source), "[::1]:8000".parse().unwrap()),
+            b"PROXY TCP6 ::ffff:203.0.113.7 ::1 51234 8000\r\n"
+        );
+        assert_eq!(
+            ProxyProtocol::V1.header(None, destination),
+            b"PROXY UNKNOWN\r\n"
+        );
+
+        let header = ProxyProtocol::V2.header(Some(source), destination);
+        assert_eq!(&header[..12], b"\r\n\r\n\0\r\nQUIT\n");
+        assert_eq!(
+            &header[12..],
+            [0x21, 0x11, 0, 12, 203, 0, 113, 7, 127, 0, 0, 1, 0xc8, 0x22, 0x1f, 0x40]
+        );
+        assert_eq!(
+            &ProxyProtocol::V2.header(None, destination)[12..],
+            [0x20, 0x00, 0, 0]
+        );
+    }
+}
@@ -1,35 +1,615 @@
 //! Server implementation for the `bore` service.
 
-use std::net::{IpAddr, Ipv4Addr};
-use std::{io, ops::RangeInclusive, sync::Arc, time::Duration};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::{io, ops::RangeInclusive, sync::Arc, sync::Mutex};
 
-use anyhow::Result;
-use dashmap::DashMap;
+use anyhow::{ensure, Context, Result};
+use dashmap::{mapref::entry::Entry, DashMap, DashSet};
+use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Notify};
 use tokio::time::{sleep, timeout};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing::{info, info_span, warn, Instrument};
 use uuid::Uuid;
 
-use crate::auth::Authenticator;
-use crate::shared::{ClientMessage, Delimited, ServerMessage, CONTROL_PORT};
+use crate::access::AccessRules;
+use crate::audit::{AuditEvent, AuditLog};
+use crate::auth::{self, Authenticator};
+use crate::idle;
+use crate::limit::{ByteRate, RateLimit, RateLimiter, Shaped, Shaper};
+use crate::mux::Session;
+use crate::shared::{
+    ClientMessage, Delimited, Features, HelloRequest, HelloResponse, MalformedFrame, PortRange,
+    ReconnectHint, ServerMessage, WhenDown, CONTROL_PORT, MAX_PORT_RANGES, NETWORK_TIMEOUT,
+};
+use crate::tls::{ServerTls, Transport};
+use crate::tokens::Token;
+use crate::transport::Acceptor;
+use crate::vhost::{self, Protocol};
+
+/// Error sent to clients whose handshakes are rate limited.
+const THROTTLED: &str = "too many handshakes, try again later";
+
+/// Error sent to clients once the server starts shutting down.
+const SHUTTING_DOWN: &str = "server is shutting down";
+
+/// Response sent to visitors of a tunnel whose local service is down, if the client asks for it.
+const SERVICE_UNAVAILABLE_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Content-Type: text/plain\r\nContent-Length: 26\r\nConnection: close\r\n\r\n\
+    local service unavailable\n";
+
+/// Number of leading bytes of unrecognized traffic that are logged.
+const PROBE_PREFIX_LENGTH: usize = 48;
+
+/// Optional protocol capabilities implemented by this server.
+const SUPPORTED_FEATURES: Features = Features::PEER_ADDR
+    .union(Features::PING)
+    .union(Features::ACCESS_RULES)
+    .union(Features::CONNECTION_LIMIT)
+    .union(Features::MULTIPLEX)
+    .union(Features::SERVICE_HEALTH);
+
+/// Maximum number of preferred ports tried for one client, to bound the work per handshake.
+const MAX_PREFERRED_PORTS: usize = 1024;
+
+/// Default time between heartbeats sent to clients.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of routed HTTP connections that can wait for a named tunnel to take them.
+const ROUTED_BACKLOG: usize = 64;
+
+/// Number of recent authentication failures kept for the admin dashboard.
+const RECENT_AUTH_FAILURES: usize = 100;
+
+/// How often idle rate limiter buckets are forgotten.
+const LIMITER_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// State structure for the server.
 pub struct Server {
-    /// Range of TCP ports that can be forwarded.
-    port_range: RangeInclusive<u16>,
+    /// Range of TCP ports that can be forwarded, which can be changed while running.
+    port_range: Arc<watch::Sender<RangeInclusive<u16>>>,
+
+    /// Optional secret used to authenticate clients, which can be changed while running.
+    auth: Arc<watch::Sender<Option<Authenticator>>>,
 
-    /// Optional secret used to authenticate clients.
-    auth: Option<Authenticator>,
+    /// Previous secret still accepted while clients move to the new one, which can be retired.
+    secondary_auth: Arc<watch::Sender<Option<Authenticator>>>,
+
+    /// Ports that only clients with a particular secret may listen on.
+    reservations: Vec<Reservation>,
+
+    /// Named secrets that clients may authenticate with, if a tokens file is used.
+    tokens: Arc<watch::Sender<Option<Arc<[Token]>>>>,
 
     /// Concurrent map of IDs to incoming connections.
-    conns: Arc<DashMap<Uuid, TcpStream>>,
+    conns: Arc<DashMap<Uuid, PendingConnection>>,
 
     /// IP address where the control server will bind to.
     bind_addr: IpAddr,
 
     /// IP address where tunnels will listen on.
     bind_tunnels: IpAddr,
+
+    /// Notified once the server is accepting connections.
+    ready: Option<oneshot::Sender<()>>,
+
+    /// Whether the control listener is accepting connections.
+    accepting: Arc<AtomicBool>,
+
+    /// Broadcast channel for reconnect hints sent to all connected clients.
+    restart: broadcast::Sender<ReconnectHint>,
+
+    /// Whether clients may use this server as a hop to reach another server.
+    allow_relay: bool,
+
+    /// Tunnels that can be suspended, keyed by public port.
+    suspendable: Arc<DashMap<u16, oneshot::Sender<String>>>,
+
+    /// Public ports that clients are not allowed to listen on.
+    suspended: Arc<DashSet<u16>>,
+
+    /// Limit on handshakes and failed authentications from each source IP.
+    handshake_limit: Option<RateLimiter<IpAddr>>,
+
+    /// Limit on handshakes from all clients together.
+    global_handshake_limit: Option<RateLimiter<()>>,
+
+    /// Limit on new public connections from each source IP, across all tunnels.
+    visitor_limit: Option<RateLimiter<IpAddr>>,
+
+    /// Optional TLS settings, required of all control connections when set.
+    tls: Option<ServerTls>,
+
+    /// Visitor addresses that may connect to any tunnel, which can be changed while running.
+    access: Arc<watch::Sender<AccessRules>>,
+
+    /// Limit on the throughput of each tunnel, in each direction.
+    max_rate: Option<ByteRate>,
+
+    /// Limit on concurrent visitor connections to each tunnel.
+    max_connections: Option<u32>,
+
+    /// How long proxied connections may go without traffic before they are closed.
+    idle_timeout: Option<Duration>,
+
+    /// Time between heartbeats sent to clients.
+    heartbeat_interval: Duration,
+
+    /// How long a pinging client may stay silent before its tunnel is closed.
+    heartbeat_timeout: Option<Duration>,
+
+    /// Shared port where HTTP requests are routed to named tunnels, if enabled.
+    http: Option<HttpRouting>,
+
+    /// Named tunnels, keyed by the subdomain that routes to them.
+    routes: DashMap<String, mpsc::Sender<RoutedConnection>>,
+
+    /// Open tunnels, for operators to inspect and close.
+    tunnels: Tunnels,
+
+    /// Tunnels waiting for their multiplexed data connection, keyed by session ID.
+    sessions: DashMap<Uuid, oneshot::Sender<Transport>>,
+
+    /// Recent failed logins, for operators to inspect.
+    auth_failures: AuthFailures,
+
+    /// Where tunnel lifecycle events are recorded, if anywhere.
+    audit: Option<AuditLog>,
+
+    /// Set once the server starts shutting down, to stop taking new tunnels and visitors.
+    draining: CancellationToken,
+
+    /// Tasks serving control and data connections, waited on while draining.
+    tasks: TaskTracker,
+}
+
+/// What a client proved about itself by authenticating.
+#[derive(Debug, Default)]
+struct Identity {
+    /// Name of the token the client used, if any.
+    token: Option<String>,
+
+    /// Fingerprint of that token's secret, to notice when the token is given a new one.
+    token_fingerprint: Option<String>,
+
+    /// Ports reserved for the client.
+    ports: Vec<PortRange>,
+
+    /// Whether the client used the secondary secret, which is due to be retired.
+    secondary_secret: bool,
+}
+
+/// Ports set aside for clients that authenticate with a particular secret.
+struct Reservation {
+    ports: PortRange,
+    auth: Authenticator,
+}
+
+/// Ports reserved for a secret, written as `PORTS=SECRET`, like `20000-20010=token`.
+#[derive(Clone)]
+pub struct PortReservation {
+    /// Reserved ports.
+    pub ports: PortRange,
+
+    /// Secret that clients use to claim the ports.
+    pub secret: String,
+}
+
+impl FromStr for PortReservation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ports, secret) = s
+            .split_once('=')
+            .ok_or("expected a reservation like PORTS=SECRET")?;
+        if secret.is_empty() {
+            return Err("reservation secret is empty".into());
+        }
+        Ok(Self {
+            ports: ports.parse()?,
+            secret: secret.to_string(),
+        })
+    }
+}
+
+impl fmt::Debug for PortReservation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortReservation")
+            .field("ports", &self.ports)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settings for routing HTTP requests to tunnels by host name.
+#[derive(Debug, Clone)]
+struct HttpRouting {
+    /// Base domain, where tunnels are reached at `NAME.DOMAIN`.
+    domain: String,
+
+    /// Shared port for HTTP visitors.
+    port: u16,
+
+    /// Shared port for TLS visitors, routed by server name.
+    tls_port: Option<u16>,
+}
+
+/// HTTP visitor routed to a named tunnel, with the request head already read.
+type RoutedConnection = (TcpStream, SocketAddr, Vec<u8>);
+
+/// Visitor connection waiting for its client to accept it.
+struct PendingConnection {
+    /// Public port the connection arrived on.
+    port: u16,
+
+    /// Stream from the visitor.
+    stream: TcpStream,
+
+    /// Throughput limit shared with the other connections of the tunnel.
+    shaper: Option<Arc<Shaper>>,
+
+    /// Bytes already read from the visitor, to be forwarded first.
+    head: Vec<u8>,
+
+    /// Place in the tunnel's count of open connections, held until the connection closes.
+    slot: ConnectionSlot,
+}
+
+/// Counts a connection as open for its tunnel until dropped.
+struct ConnectionSlot {
+    tunnel: Arc<ActiveTunnel>,
+    id: Uuid,
+    close: Arc<Notify>,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.tunnel.connections.remove(&self.id);
+    }
+}
+
+/// Live tunnels of a server, which operators can inspect and close.
+#[derive(Clone, Default)]
+pub struct Tunnels(Arc<DashMap<Uuid, Arc<ActiveTunnel>>>);
+
+impl Tunnels {
+    /// List the open tunnels, oldest first.
+    pub fn list(&self) -> Vec<TunnelInfo> {
+        let mut tunnels: Vec<_> = self
+            .0
+            .iter()
+            .map(|entry| entry.info(*entry.key(), false))
+            .collect();
+        tunnels.sort_by_key(|tunnel| std::cmp::Reverse(tunnel.uptime_secs));
+        tunnels
+    }
+
+    /// Describe an open tunnel, including its public connections.
+    pub fn get(&self, id: Uuid) -> Option<TunnelInfo> {
+        self.0.get(&id).map(|entry| entry.info(id, true))
+    }
+
+    /// Disconnect a tunnel's client, returning whether the tunnel was open.
+    pub fn disconnect(&self, id: Uuid) -> bool {
+        match self.0.get(&id) {
+            Some(tunnel) => {
+                tunnel.disconnect.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close one public connection of a tunnel, returning whether it was open.
+    pub fn close_connection(&self, id: Uuid, connection: Uuid) -> bool {
+        let Some(tunnel) = self.0.get(&id) else {
+            return false;
+        };
+        let conn = tunnel.connections.get(&connection);
+        if let Some(conn) = &conn {
+            conn.close.notify_one();
+        }
+        conn.is_some()
+    }
+}
+
+/// Recent failed logins to a server, newest last.
+#[derive(Clone, Default)]
+pub struct AuthFailures(Arc<Mutex<VecDeque<AuthFailure>>>);
+
+impl AuthFailures {
+    /// List the recent failures, oldest first.
+    pub fn recent(&self) -> Vec<AuthFailure> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record(&self, addr: SocketAddr, err: &anyhow::Error) {
+        let mut failures = self.0.lock().unwrap();
+        failures.push_back(AuthFailure {
+            at: format_rfc3339(OffsetDateTime::now_utc()),
+            addr,
+            error: err.to_string(),
+        });
+        while failures.len() > RECENT_AUTH_FAILURES {
+            failures.pop_front();
+        }
+    }
+}
+
+/// A client that failed to authenticate.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthFailure {
+    /// When the failure happened, in RFC 3339 format.
+    pub at: String,
+
+    /// Address of the client.
+    pub addr: SocketAddr,
+
+    /// Why authentication failed.
+    pub error: String,
+}
+
+/// Bookkeeping for a tunnel while its client is connected.
+struct ActiveTunnel {
+    client: SocketAddr,
+    port: u16,
+    host: Option<String>,
+    token: Option<String>,
+    secondary_secret: bool,
+    service_up: AtomicBool,
+    started_at: OffsetDateTime,
+    started: Instant,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    total_connections: AtomicU64,
+    connections: DashMap<Uuid, OpenConnection>,
+    disconnect: Notify,
+}
+
+/// Public connection of a tunnel, from when it arrives until it closes.
+struct OpenConnection {
+    visitor: SocketAddr,
+    opened: Instant,
+    close: Arc<Notify>,
+}
+
+impl ActiveTunnel {
+    /// Count a new public connection as open until the returned slot is dropped.
+    fn open_connection(self: &Arc<Self>, id: Uuid, visitor: SocketAddr) -> ConnectionSlot {
+        let close = Arc::new(Notify::new());
+        self.connections.insert(
+            id,
+            OpenConnection {
+                visitor,
+                opened: Instant::now(),
+                close: Arc::clone(&close),
+            },
+        );
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionSlot {
+            tunnel: Arc::clone(self),
+            id,
+            close,
+        }
+    }
+
+    fn info(&self, id: Uuid, with_connections: bool) -> TunnelInfo {
+        let connections = with_connections.then(|| {
+            self.connections
+                .iter()
+                .map(|conn| ConnectionInfo {
+                    id: *conn.key(),
+                    visitor: conn.visitor,
+                    open_secs: conn.opened.elapsed().as_secs(),
+                })
+                .collect()
+        });
+        TunnelInfo {
+            id,
+            client: self.client,
+            port: self.port,
+            host: self.host.clone(),
+            token: self.token.clone(),
+            secondary_secret: self.secondary_secret,
+            service_up: self.service_up.load(Ordering::Relaxed),
+            started_at: format_rfc3339(self.started_at),
+            uptime_secs: self.started.elapsed().as_secs(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            active_connections: self.connections.len(),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+            connections,
+        }
+    }
+}
+
+/// Point-in-time description of an open tunnel.
+///
+/// Bytes are added when each connection closes, like the server's metrics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelInfo {
+    /// ID used to refer to the tunnel in the admin API.
+    pub id: Uuid,
+
+    /// Address of the client's control connection.
+    pub client: SocketAddr,
+
+    /// Public port, or the shared HTTP port for named tunnels.
+    pub port: u16,
+
+    /// Public host name of a named tunnel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// Name of the token the client authenticated with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Whether the client authenticated with the secondary secret, which is due to be retired.
+    pub secondary_secret: bool,
+
+    /// Whether the client last reported its local service as up, or does not check it.
+    pub service_up: bool,
+
+    /// When the tunnel was opened, in RFC 3339 format.
+    pub started_at: String,
+
+    /// Seconds since the tunnel was opened.
+    pub uptime_secs: u64,
+
+    /// Bytes received from visitors.
+    pub bytes_in: u64,
+
+    /// Bytes sent to visitors.
+    pub bytes_out: u64,
+
+    /// Public connections that are currently open.
+    pub active_connections: usize,
+
+    /// Public connections accepted since the tunnel was opened.
+    pub total_connections: u64,
+
+    /// Open public connections, when describing a single tunnel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connections: Option<Vec<ConnectionInfo>>,
+}
+
+/// Point-in-time description of an open public connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    /// ID used to close the connection in the admin API.
+    pub id: Uuid,
+
+    /// Address of the visitor.
+    pub visitor: SocketAddr,
+
+    /// Seconds since the connection arrived.
+    pub open_secs: u64,
+}
+
+/// Handle used to ask connected clients to reconnect, e.g., before a restart.
+#[derive(Clone)]
+pub struct RestartNotifier(broadcast::Sender<ReconnectHint>);
+
+impl RestartNotifier {
+    /// Send a reconnect hint to all connected clients, returning how many were notified.
+    pub fn notify(&self, hint: ReconnectHint) -> usize {
+        self.0.send(hint).unwrap_or(0)
+    }
+}
+
+/// Handle used to shut down a server gracefully.
+#[derive(Clone)]
+pub struct Drainer {
+    draining: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl Drainer {
+    /// Close all tunnels and refuse new ones, then wait up to `period` for forwarded
+    /// connections to finish.
+    ///
+    /// Clients are told that the server is shutting down. Visitors who already reached a
+    /// client keep their connections until they close. Returns whether all of them did in time.
+    pub async fn drain(&self, period: Duration) -> bool {
+        self.draining.cancel();
+        self.tasks.close();
+        timeout(period, self.tasks.wait()).await.is_ok()
+    }
+}
+
+/// Handle for probing whether a server is up and can take new tunnels.
+#[derive(Clone)]
+pub struct Health {
+    accepting: Arc<AtomicBool>,
+    draining: CancellationToken,
+    tunnels: Tunnels,
+    port_range: Arc<watch::Sender<RangeInclusive<u16>>>,
+}
+
+impl Health {
+    /// Check the server's current state.
+    pub fn status(&self) -> HealthStatus {
+        let port_range = self.port_range.borrow().clone();
+        let ports_in_use: HashSet<u16> = self
+            .tunnels
+            .0
+            .iter()
+            .map(|tunnel| tunnel.port)
+            .filter(|port| port_range.contains(port))
+            .collect();
+        let range_len = usize::from(port_range.end() - port_range.start()) + 1;
+        HealthStatus {
+            accepting: self.accepting.load(Ordering::Relaxed),
+            draining: self.draining.is_cancelled(),
+            tunnels: self.tunnels.0.len(),
+            ports_exhausted: ports_in_use.len() >= range_len,
+        }
+    }
+}
+
+/// State of a server, as reported by its health checks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthStatus {
+    /// Whether the control listener is accepting connections.
+    pub accepting: bool,
+
+    /// Whether the server is shutting down and refusing new tunnels.
+    pub draining: bool,
+
+    /// Number of open tunnels.
+    pub tunnels: usize,
+
+    /// Whether every port in the range is taken by a tunnel.
+    pub ports_exhausted: bool,
+}
+
+impl HealthStatus {
+    /// Whether the server can take new tunnels.
+    pub fn is_ready(&self) -> bool {
+        self.accepting && !self.draining && !self.ports_exhausted
+    }
+}
+
+/// Handle used to suspend tunnels, e.g., after an abuse report.
+#[derive(Clone)]
+pub struct Suspender {
+    suspendable: Arc<DashMap<u16, oneshot::Sender<String>>>,
+    suspended: Arc<DashSet<u16>>,
+    audit: Option<AuditLog>,
+}
+
+impl Suspender {
+    /// Close the tunnel on a public port and block the port until it is resumed.
+    ///
+    /// The owning client is sent the reason. Returns whether a tunnel was open on the port.
+    pub fn suspend(&self, port: u16, reason: &str) -> bool {
+        self.suspended.insert(port);
+        if let Some(audit) = &self.audit {
+            audit.record(AuditEvent::TunnelSuspended { port, reason });
+        }
+        match self.suspendable.remove(&port) {
+            Some((_, tx)) => tx.send(reason.to_string()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Allow clients to listen on a suspended port again.
+    pub fn resume(&self, port: u16) -> bool {
+        let resumed = self.suspended.remove(&port).is_some();
+        if let (true, Some(audit)) = (resumed, &self.audit) {
+            audit.record(AuditEvent::TunnelResumed { port });
+        }
+        resumed
+    }
 }
 
 impl Server {
@@ -37,11 +617,215 @@ impl Server {
     pub fn new(port_range: RangeInclusive<u16>, secret: Option<&str>) -> Self {
         assert!(!port_range.is_empty(), "must provide at least one port");
         Server {
-            port_range,
+            port_range: Arc::new(watch::channel(port_range).0),
             conns: Arc::new(DashMap::new()),
-            auth: secret.map(Authenticator::new),
+            auth: Arc::new(watch::channel(secret.map(Authenticator::new)).0),
+            secondary_auth: Arc::new(watch::channel(None).0),
+            reservations: Vec::new(),
+            tokens: Arc::new(watch::channel(None).0),
             bind_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             bind_tunnels: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            ready: None,
+            accepting: Arc::new(AtomicBool::new(false)),
+            restart: broadcast::channel(1).0,
+            allow_relay: false,
+            suspendable: Arc::new(DashMap::new()),
+            suspended: Arc::new(DashSet::new()),
+            handshake_limit: None,
+            global_handshake_limit: None,
+            visitor_limit: None,
+            tls: None,
+            access: Arc::new(watch::channel(AccessRules::default()).0),
+            max_rate: None,
+            max_connections: None,
+            idle_timeout: None,
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            heartbeat_timeout: None,
+            http: None,
+            routes: DashMap::new(),
+            tunnels: Tunnels::default(),
+            sessions: DashMap::new(),
+            auth_failures: AuthFailures::default(),
+            audit: None,
+            draining: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// Get a handle for listing and closing open tunnels.
+    pub fn tunnels(&self) -> Tunnels {
+        self.tunnels.clone()
+    }
+
+    /// Get a handle for checking the server's health.
+    pub fn health(&self) -> Health {
+        Health {
+            accepting: Arc::clone(&self.accepting),
+            draining: self.draining.clone(),
+            tunnels: self.tunnels.clone(),
+            port_range: Arc::clone(&self.port_range),
+        }
+    }
+
+    /// Get a handle for reading recent authentication failures.
+    pub fn auth_failures(&self) -> AuthFailures {
+        self.auth_failures.clone()
+    }
+
+    /// Get a handle for suspending tunnels by their public port.
+    pub fn suspender(&self) -> Suspender {
+        Suspender {
+            suspendable: Arc::clone(&self.suspendable),
+            suspended: Arc::clone(&self.suspended),
+            audit: self.audit.clone(),
+        }
+    }
+
+    /// Set whether clients may relay control connections through this server.
+    pub fn set_allow_relay(&mut self, allow_relay: bool) {
+        self.allow_relay = allow_relay;
+    }
+
+    /// Reserve ports for clients that authenticate with `secret`, so no other client can take them.
+    ///
+    /// The secret is accepted alongside the server's own secret, and clients that use it get
+    /// their reserved ports when they do not ask for one. Once any ports are reserved, all
+    /// clients must authenticate.
+    pub fn reserve_ports(&mut self, ports: PortRange, secret: &str) {
+        self.reservations.push(Reservation {
+            ports,
+            auth: Authenticator::new(secret),
+        });
+    }
+
+    /// Accept clients that authenticate with any of these named tokens.
+    ///
+    /// Tokens are accepted alongside the server's own secret. Once tokens are set, all clients
+    /// must authenticate.
+    pub fn set_tokens(&mut self, tokens: Vec<Token>) {
+        self.tokens.send_replace(Some(tokens.into()));
+    }
+
+    /// Get a handle for replacing the tokens of the running server.
+    pub fn token_updater(&self) -> TokenUpdater {
+        TokenUpdater {
+            tokens: Arc::clone(&self.tokens),
+        }
+    }
+
+    /// Also accept a previous secret, so clients can move to a new one without downtime.
+    ///
+    /// Tunnels stay open when the secondary secret is retired, but their clients must have
+    /// moved to the primary secret to open new data connections.
+    pub fn set_secondary_secret(&mut self, secret: &str) {
+        self.secondary_auth
+            .send_replace(Some(Authenticator::new(secret)));
+    }
+
+    /// Get a handle for changing the port range, access rules, and secrets of the running server.
+    pub fn reloader(&self) -> Reloader {
+        Reloader {
+            port_range: Arc::clone(&self.port_range),
+            access: Arc::clone(&self.access),
+            auth: Arc::clone(&self.auth),
+            secondary_auth: Arc::clone(&self.secondary_auth),
+        }
+    }
+
+    /// Limit how often each source IP can open tunnels or fail to authenticate.
+    pub fn set_handshake_limit(&mut self, limit: RateLimit) {
+        self.handshake_limit = Some(RateLimiter::new(limit));
+    }
+
+    /// Limit how often tunnels can be opened across all clients.
+    pub fn set_global_handshake_limit(&mut self, limit: RateLimit) {
+        self.global_handshake_limit = Some(RateLimiter::new(limit));
+    }
+
+    /// Limit how often each source IP can open public connections to tunnels.
+    pub fn set_visitor_limit(&mut self, limit: RateLimit) {
+        self.visitor_limit = Some(RateLimiter::new(limit));
+    }
+
+    /// Only let visitors from addresses matching these rules connect to tunnels.
+    ///
+    /// Clients can add their own rules for a tunnel, but cannot relax these.
+    pub fn set_access_rules(&mut self, access: AccessRules) {
+        self.access.send_replace(access);
+    }
+
+    /// Limit the throughput of each tunnel, shared by all of its connections.
+    pub fn set_max_rate(&mut self, max_rate: ByteRate) {
+        self.max_rate = Some(max_rate);
+    }
+
+    /// Refuse new visitors while this many connections to a tunnel are open.
+    ///
+    /// Clients can ask for a lower limit on their own tunnels, but not a higher one.
+    pub fn set_max_connections(&mut self, max_connections: u32) {
+        self.max_connections = Some(max_connections);
+    }
+
+    /// Close proxied connections that transfer no data for this long.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = Some(idle_timeout);
+    }
+
+    /// Set the time between heartbeats sent to clients, half a second by default.
+    pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Duration) {
+        self.heartbeat_interval = heartbeat_interval;
+    }
+
+    /// Close tunnels whose clients send nothing for this long.
+    ///
+    /// Only applies to clients that send pings, since older clients never send
+    /// anything on an open tunnel.
+    pub fn set_heartbeat_timeout(&mut self, heartbeat_timeout: Duration) {
+        self.heartbeat_timeout = Some(heartbeat_timeout);
+    }
+
+    /// Route HTTP requests on a shared port to tunnels named by the subdomain of `domain`.
+    ///
+    /// Clients that ask for a name get no port of their own, and are reached at `NAME.DOMAIN`.
+    pub fn set_http_routing(&mut self, domain: &str, port: u16) {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        self.http = Some(HttpRouting {
+            domain,
+            port,
+            tls_port: None,
+        });
+    }
+
+    /// Also route TLS visitors on `port` to named tunnels, by the server name in their ClientHello.
+    ///
+    /// TLS is not terminated, so tunnels keep end-to-end encryption to their local services.
+    /// Has no effect unless HTTP routing is enabled.
+    pub fn set_tls_routing(&mut self, port: u16) {
+        if let Some(http) = &mut self.http {
+            http.tls_port = Some(port);
+        }
+    }
+
+    /// Require TLS on control and data connections from clients.
+    pub fn set_tls(&mut self, tls: ServerTls) {
+        self.tls = Some(tls);
+    }
+
+    /// Record tunnels opening, closing, and being suspended, and failed logins, in an audit log.
+    pub fn set_audit_log(&mut self, audit: AuditLog) {
+        self.audit = Some(audit);
+    }
+
+    /// Get a handle for sending reconnect hints to connected clients.
+    pub fn restart_notifier(&self) -> RestartNotifier {
+        RestartNotifier(self.restart.clone())
+    }
+
+    /// Get a handle for shutting the server down without cutting off open connections.
+    pub fn drainer(&self) -> Drainer {
+        Drainer {
+            draining: self.draining.clone(),
+            tasks: self.tasks.clone(),
         }
     }
 
@@ -55,19 +839,88 @@ impl Server {
         self.bind_tunnels = bind_tunnels;
     }
 
+    /// Get a receiver that completes once the server is accepting connections.
+    pub fn ready(&mut self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.ready = Some(tx);
+        rx
+    }
+
     /// Start the server, listening for new connections.
     pub async fn listen(self) -> Result<()> {
+        let listener = TcpListener::bind((self.bind_addr, CONTROL_PORT)).await?;
+        info!(addr = ?self.bind_addr, "server listening");
+        self.listen_on(listener).await
+    }
+
+    /// Start the server, taking control connections from `acceptor` instead of binding the
+    /// control port. Visitors still connect to tunnels over TCP.
+    pub async fn listen_on(mut self, acceptor: impl Acceptor) -> Result<()> {
+        let ready = self.ready.take();
         let this = Arc::new(self);
-        let listener = TcpListener::bind((this.bind_addr, CONTROL_PORT)).await?;
-        info!(addr = ?this.bind_addr, "server listening");
+        if let Some(idle_timeout) = this.idle_timeout {
+            info!(?idle_timeout, "closing idle connections");
+            gauge!("bore_server_idle_timeout_seconds").set(idle_timeout.as_secs_f64());
+        }
 
+        if let Some(http) = &this.http {
+            let ports = [(http.port, Protocol::Http)]
+                .into_iter()
+                .chain(http.tls_port.map(|port| (port, Protocol::Tls)));
+            for (port, protocol) in ports {
+                let listener = TcpListener::bind((this.bind_tunnels, port))
+                    .await
+                    .with_context(|| {
+                        format!("could not listen for {} on port {port}", protocol.as_str())
+                    })?;
+                info!(port, domain = %http.domain, protocol = protocol.as_str(), "routing visitors by host");
+                let this = Arc::clone(&this);
+                tokio::spawn(async move {
+                    loop {
+                        let (stream, addr) = match listener.accept().await {
+                            Ok(accepted) => accepted,
+                            Err(err) => {
+                                warn!(%err, "failed to accept routed connection");
+                                continue;
+                            }
+                        };
+                        let this = Arc::clone(&this);
+                        tokio::spawn(async move {
+                            if let Err(err) = this.route_visitor(stream, addr, protocol).await {
+                                info!(%addr, %err, "could not route visitor");
+                            }
+                        });
+                    }
+                });
+            }
+        }
+
+        if this.handshake_limit.is_some() || this.visitor_limit.is_some() {
+            let this = Arc::clone(&this);
+            tokio::spawn(async move {
+                loop {
+                    sleep(LIMITER_PRUNE_INTERVAL).await;
+                    if let Some(limiter) = &this.handshake_limit {
+                        limiter.prune();
+                    }
+                    if let Some(limiter) = &this.visitor_limit {
+                        limiter.prune();
+                    }
+                }
+            });
+        }
+
+        this.accepting.store(true, Ordering::Relaxed);
+        if let Some(ready) = ready {
+            let _ = ready.send(());
+        }
         loop {
-            let (stream, addr) = listener.accept().await?;
+            let (stream, addr) = acceptor.accept().await?;
             let this = Arc::clone(&this);
-            tokio::spawn(
+            this.tasks.clone().spawn(
                 async move {
                     info!("incoming connection");
-                    if let Err(err) = this.handle_connection(stream).await {
+                    if let Err(err) = this.handle_connection(stream, addr).await {
                         warn!(%err, "connection exited with error");
                     } else {
                         info!("connection exited");
@@ -78,7 +931,15 @@ impl Server {
         }
     }
 
-    async fn create_listener(&self, port: u16) -> Result<TcpListener, &'static str> {
+    /// Bind a listener for a new tunnel, on a port that the client may use.
+    ///
+    /// `owned` are the ports reserved for the client, which it gets if it asks for no port.
+    async fn create_listener(
+        &self,
+        port: u16,
+        preferred: &[PortRange],
+        owned: &[PortRange],
+    ) -> Result<TcpListener, &'static str> {
         let try_bind = |port: u16| async move {
             TcpListener::bind((self.bind_tunnels, port))
                 .await
@@ -88,12 +949,47 @@ impl Server {
                     _ => "failed to bind to port",
                 })
         };
+        let port_range = self.port_range.borrow().clone();
+        let is_owned = |port: u16| owned.iter().any(|range| range.contains(port));
+        let allowed = |port: u16| {
+            is_owned(port)
+                || port_range.contains(&port)
+                    && !self.reservations.iter().any(|r| r.ports.contains(port))
+        };
+        let preferred = if port == 0 && preferred.is_empty() {
+            owned
+        } else {
+            preferred
+        };
         if port > 0 {
             // Client requests a specific port number.
-            if !self.port_range.contains(&port) {
+            if !allowed(port) {
+                if port_range.contains(&port) {
+                    counter!("bore_server_reserved_port_refusals_total").increment(1);
+                    return Err("port is reserved for another client");
+                }
                 return Err("client port number not in allowed range");
             }
+            if self.suspended.contains(&port) {
+                return Err("port is suspended");
+            }
             try_bind(port).await
+        } else if !preferred.is_empty() {
+            // Client accepts a set of ports, so take the first free one in order.
+            if preferred.len() > MAX_PORT_RANGES {
+                return Err("too many preferred port ranges");
+            }
+            let candidates = preferred
+                .iter()
+                .flat_map(|range| range.start..=range.end)
+                .filter(|&port| allowed(port) && !self.suspended.contains(&port))
+                .take(MAX_PREFERRED_PORTS);
+            for port in candidates {
+                if let Ok(listener) = try_bind(port).await {
+                    return Ok(listener);
+                }
+            }
+            Err("no preferred port is available")
         } else {
             // Client requests any available port in range.
             //
@@ -105,7 +1001,10 @@ impl Server {
             // Checking 150 times gives us 99.999% success at utilizing 85% of ports under these
             // conditions, when ε=0.15 and δ=0.00001.
             for _ in 0..150 {
-                let port = fastrand::u16(self.port_range.clone());
+                let port = fastrand::u16(port_range.clone());
+                if !allowed(port) || self.suspended.contains(&port) {
+                    continue;
+                }
                 match try_bind(port).await {
                     Ok(listener) => return Ok(listener),
                     Err(_) => continue,
@@ -115,73 +1014,875 @@ impl Server {
         }
     }
 
-    async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+    /// Optional capabilities of this server, depending on its settings.
+    fn supported_features(&self) -> Features {
+        if self.http.is_some() {
+            SUPPORTED_FEATURES | Features::HTTP_ROUTING
+        } else {
+            SUPPORTED_FEATURES
+        }
+    }
+
+    /// Register a named tunnel, returning where its routed visitors arrive.
+    fn add_route(&self, name: &str) -> Result<Incoming<'_>, String> {
+        let Some(http) = &self.http else {
+            return Err("HTTP routing is not enabled on this server".into());
+        };
+        vhost::validate_name(name)?;
+        match self.routes.entry(name.to_string()) {
+            Entry::Occupied(_) => Err(format!("tunnel name {name} is already in use")),
+            Entry::Vacant(entry) => {
+                let (tx, rx) = mpsc::channel(ROUTED_BACKLOG);
+                entry.insert(tx);
+                Ok(Incoming::Routed {
+                    _route: Route {
+                        routes: &self.routes,
+                        name: name.to_string(),
+                    },
+                    host: format!("{name}.{}", http.domain),
+                    rx,
+                })
+            }
+        }
+    }
+
+    /// Pass a visitor on a shared port to the tunnel named by its Host header or server name.
+    async fn route_visitor(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        protocol: Protocol,
+    ) -> Result<()> {
+        let http = self.http.as_ref().context("HTTP routing is not enabled")?;
+        let head = timeout(NETWORK_TIMEOUT, protocol.read_head(&mut stream))
+            .await
+            .context("timed out waiting for request head")??;
+        let route = protocol
+            .route(&head, &http.domain)
+            .and_then(|name| self.routes.get(&name).map(|tx| tx.clone()));
+        let Some(tx) = route else {
+            counter!("bore_server_unrouted_connections_total", "protocol" => protocol.as_str())
+                .increment(1);
+            stream.write_all(protocol.not_found()).await?;
+            return Ok(());
+        };
+        if let Err(err) = tx.try_send((stream, addr, head)) {
+            let (mut stream, _, _) = err.into_inner();
+            stream.write_all(protocol.unavailable()).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_connection(&self, stream: Transport, addr: SocketAddr) -> Result<()> {
+        let accepted_at = Instant::now();
+        counter!("bore_server_control_connections_total").increment(1);
+        let stream = match &self.tls {
+            Some(tls) => tls.accept(stream).await?,
+            None => stream,
+        };
         let mut stream = Delimited::new(stream);
-        if let Some(auth) = &self.auth {
-            if let Err(err) = auth.server_handshake(&mut stream).await {
+        if let Some(limiter) = &self.handshake_limit {
+            if limiter.is_exhausted(&addr.ip()) {
+                counter!("bore_server_handshakes_throttled_total", "scope" => "ip").increment(1);
+                stream.send(ServerMessage::Error(THROTTLED.into())).await?;
+                return Ok(());
+            }
+        }
+        let identity = match self.authenticate(&mut stream).await {
+            Ok(identity) => identity,
+            Err(err) => {
+                counter!("bore_server_auth_failures_total").increment(1);
+                self.auth_failures.record(addr, &err);
+                if let Some(audit) = &self.audit {
+                    audit.record(AuditEvent::AuthFailed {
+                        client: addr,
+                        error: err.to_string(),
+                    });
+                }
+                self.throttle_ip(addr.ip());
+                log_probe(addr, accepted_at, &err, &stream);
                 warn!(%err, "server handshake failed");
                 stream.send(ServerMessage::Error(err.to_string())).await?;
                 return Ok(());
             }
-        }
+        };
 
-        match stream.recv_timeout().await? {
+        let message = match stream.recv_timeout().await {
+            Ok(message) => message,
+            Err(err) => {
+                log_probe(addr, accepted_at, &err, &stream);
+                return Err(err);
+            }
+        };
+        if matches!(
+            message,
+            Some(
+                ClientMessage::Hello(_) | ClientMessage::ExtendedHello(_) | ClientMessage::Relay(_)
+            )
+        ) {
+            if self.draining.is_cancelled() {
+                stream
+                    .send(ServerMessage::Error(SHUTTING_DOWN.into()))
+                    .await?;
+                return Ok(());
+            }
+            if !self.acquire_handshake(addr.ip()) {
+                stream.send(ServerMessage::Error(THROTTLED.into())).await?;
+                return Ok(());
+            }
+        }
+        match message {
             Some(ClientMessage::Authenticate(_)) => {
                 warn!("unexpected authenticate");
                 Ok(())
             }
+            Some(ClientMessage::Ping(_)) => {
+                warn!("unexpected ping");
+                Ok(())
+            }
+            Some(ClientMessage::ServiceHealth(_)) => {
+                warn!("unexpected service health");
+                Ok(())
+            }
             Some(ClientMessage::Hello(port)) => {
-                let listener = match self.create_listener(port).await {
-                    Ok(listener) => listener,
-                    Err(err) => {
-                        stream.send(ServerMessage::Error(err.into())).await?;
+                let request = HelloRequest {
+                    port,
+                    ..Default::default()
+                };
+                self.handle_hello(stream, request, false, &identity, addr, accepted_at)
+                    .await
+            }
+            Some(ClientMessage::ExtendedHello(request)) => {
+                self.handle_hello(stream, request, true, &identity, addr, accepted_at)
+                    .await
+            }
+            Some(ClientMessage::Accept(id)) => {
+                info!(%id, "forwarding connection");
+                match self.conns.remove(&id) {
+                    Some((_, pending)) => {
+                        let parts = stream.into_parts();
+                        debug_assert!(parts.write_buf.is_empty(), "framed write buffer not empty");
+                        forward(id, pending, parts.io, &parts.read_buf, self.idle_timeout).await?;
+                    }
+                    None => warn!(%id, "missing connection"),
+                }
+                Ok(())
+            }
+            Some(ClientMessage::Multiplex(id)) => {
+                let parts = stream.into_parts();
+                match self.sessions.remove(&id) {
+                    Some((_, tx)) if parts.read_buf.is_empty() => {
+                        info!(%id, "multiplexing connections");
+                        let _ = tx.send(parts.io);
+                    }
+                    Some(_) => warn!(%id, "unexpected data after multiplex request"),
+                    None => warn!(%id, "missing tunnel for multiplexed connection"),
+                }
+                Ok(())
+            }
+            Some(ClientMessage::Relay(to)) => {
+                if !self.allow_relay {
+                    warn!(%to, "relaying is not enabled");
+                    let message = "relaying is not enabled on this server".to_string();
+                    stream.send(ServerMessage::Error(message)).await?;
+                    return Ok(());
+                }
+                info!(%to, "relaying connection");
+                let connect = TcpStream::connect((to.as_str(), CONTROL_PORT));
+                let mut upstream = match timeout(NETWORK_TIMEOUT, connect).await {
+                    Ok(Ok(upstream)) => upstream,
+                    Ok(Err(err)) => {
+                        warn!(%to, %err, "failed to connect to relay target");
+                        let message = format!("could not connect to {to}:{CONTROL_PORT}");
+                        stream.send(ServerMessage::Error(message)).await?;
                         return Ok(());
                     }
-                };
-                let host = listener.local_addr()?.ip();
-                let port = listener.local_addr()?.port();
-                info!(?host, ?port, "new client");
-                stream.send(ServerMessage::Hello(port)).await?;
-
-                loop {
-                    if stream.send(ServerMessage::Heartbeat).await.is_err() {
-                        // Assume that the TCP connection has been dropped.
+                    Err(_) => {
+                        warn!(%to, "timed out connecting to relay target");
+                        let message = format!("timed out connecting to {to}:{CONTROL_PORT}");
+                        stream.send(ServerMessage::Error(message)).await?;
                         return Ok(());
                     }
-                    const TIMEOUT: Duration = Duration::from_millis(500);
-                    if let Ok(result) = timeout(TIMEOUT, listener.accept()).await {
-                        let (stream2, addr) = result?;
-                        info!(?addr, ?port, "new connection");
+                };
+                let mut parts = stream.into_parts();
+                debug_assert!(parts.write_buf.is_empty(), "framed write buffer not empty");
+                upstream.write_all(&parts.read_buf).await?;
+                tokio::io::copy_bidirectional(&mut parts.io, &mut upstream).await?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
 
-                        let id = Uuid::new_v4();
-                        let conns = Arc::clone(&self.conns);
+    /// Check the client's secret, if the server requires one, returning who the client is.
+    async fn authenticate(&self, stream: &mut Delimited<Transport>) -> Result<Identity> {
+        let tokens = self.tokens.borrow().clone();
+        let primary = self.auth.borrow().clone();
+        let secondary = self.secondary_auth.borrow().clone();
+        if self.reservations.is_empty() && tokens.is_none() && secondary.is_none() {
+            if let Some(auth) = &primary {
+                auth.server_handshake(stream).await?;
+            }
+            return Ok(Identity::default());
+        }
+        let (challenge, tag) = auth::server_challenge(stream).await?;
+        let mut identity = Identity {
+            token: None,
+            token_fingerprint: None,
+            secondary_secret: false,
+            ports: self
+                .reservations
+                .iter()
+                .filter(|reservation| reservation.auth.validate(&challenge, &tag))
+                .map(|reservation| reservation.ports)
+                .collect(),
+        };
+        let token = tokens
+            .iter()
+            .flat_map(|tokens| tokens.iter())
+            .find(|token| token.validate(&challenge, &tag));
+        if let Some(token) = token {
+            identity.token = Some(token.name().to_string());
+            identity.token_fingerprint = Some(token.fingerprint());
+            identity.ports.extend_from_slice(token.ports());
+        }
+        let mut shared = primary.is_some_and(|auth| auth.validate(&challenge, &tag));
+        if !shared && secondary.is_some_and(|auth| auth.validate(&challenge, &tag)) {
+            counter!("bore_server_secondary_secret_logins_total").increment(1);
+            identity.secondary_secret = true;
+            shared = true;
+        }
+        ensure!(
+            shared || identity.token.is_some() || !identity.ports.is_empty(),
+            "invalid secret"
+        );
+        Ok(identity)
+    }
 
-                        conns.insert(id, stream2);
-                        tokio::spawn(async move {
-                            // Remove stale entries to avoid memory leaks.
-                            sleep(Duration::from_secs(10)).await;
-                            if conns.remove(&id).is_some() {
-                                warn!(%id, "removed stale connection");
+    /// Take a handshake token for a source IP, returning false if it is throttled.
+    ///
+    /// Only new tunnels and relays count, so a busy tunnel can still accept connections.
+    fn acquire_handshake(&self, ip: IpAddr) -> bool {
+        if !self.throttle_ip(ip) {
+            return false;
+        }
+        if let Some(limiter) = &self.global_handshake_limit {
+            if let Err(throttled) = limiter.try_acquire(()) {
+                counter!("bore_server_handshakes_throttled_total", "scope" => "global")
+                    .increment(1);
+                if throttled.newly {
+                    warn!("throttling handshakes from all clients");
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Take a token from the per-IP bucket, returning false if it is empty.
+    fn throttle_ip(&self, ip: IpAddr) -> bool {
+        let Some(limiter) = &self.handshake_limit else {
+            return true;
+        };
+        match limiter.try_acquire(ip) {
+            Ok(()) => true,
+            Err(throttled) => {
+                counter!("bore_server_handshakes_throttled_total", "scope" => "ip").increment(1);
+                if throttled.newly {
+                    warn!(%ip, "throttling handshakes from address");
+                }
+                false
+            }
+        }
+    }
+
+    /// Take a token from a visitor's per-IP bucket, returning false if it is empty.
+    fn throttle_visitor(&self, ip: IpAddr) -> bool {
+        let Some(limiter) = &self.visitor_limit else {
+            return true;
+        };
+        match limiter.try_acquire(ip) {
+            Ok(()) => true,
+            Err(throttled) => {
+                counter!("bore_server_visitor_connections_throttled_total").increment(1);
+                if throttled.newly {
+                    warn!(%ip, "throttling visitor connections from address");
+                }
+                false
+            }
+        }
+    }
+
+    async fn handle_hello(
+        &self,
+        mut stream: Delimited<Transport>,
+        request: HelloRequest,
+        extended: bool,
+        identity: &Identity,
+        client: SocketAddr,
+        accepted_at: Instant,
+    ) -> Result<()> {
+        let features = request.features & self.supported_features();
+        let incoming = match &request.name {
+            Some(name) => self.add_route(name),
+            None => self
+                .create_listener(request.port, &request.ports, &identity.ports)
+                .await
+                .map(Incoming::Listener)
+                .map_err(String::from),
+        };
+        let mut incoming = match incoming {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                stream.send(ServerMessage::Error(err)).await?;
+                return Ok(());
+            }
+        };
+        let (host, port, public_host) = match &incoming {
+            Incoming::Listener(listener) => {
+                let addr = listener.local_addr()?;
+                (addr.ip().to_string(), addr.port(), None)
+            }
+            Incoming::Routed { host, .. } => {
+                let port = self.http.as_ref().map_or(0, |http| http.port);
+                (host.clone(), port, Some(host.clone()))
+            }
+        };
+        info!(
+            %host,
+            ?port,
+            token = identity.token.as_deref(),
+            secondary_secret = identity.secondary_secret,
+            "new client"
+        );
+        let access = if features.contains(Features::ACCESS_RULES) {
+            request.access
+        } else {
+            AccessRules::default()
+        };
+        let requested_max = request
+            .max_connections
+            .filter(|_| features.contains(Features::CONNECTION_LIMIT));
+        let max_connections = self.max_connections.into_iter().chain(requested_max).min();
+        if let Some(max_connections) = max_connections {
+            info!(?port, max_connections, "limiting concurrent connections");
+        }
+        let when_down = request
+            .when_down
+            .filter(|_| features.contains(Features::SERVICE_HEALTH))
+            .unwrap_or_default();
+        let session_id = features.contains(Features::MULTIPLEX).then(Uuid::new_v4);
+        let (session_tx, mut session_rx) = oneshot::channel();
+        if let Some(id) = session_id {
+            self.sessions.insert(id, session_tx);
+        }
+        if extended {
+            info!(features = features.bits(), "negotiated features");
+            let response = HelloResponse {
+                session: session_id,
+                port,
+                tls_port: public_host
+                    .as_ref()
+                    .and(self.http.as_ref())
+                    .and_then(|http| http.tls_port),
+                host: public_host,
+                features,
+            };
+            stream.send(ServerMessage::ExtendedHello(response)).await?;
+        } else {
+            stream.send(ServerMessage::Hello(port)).await?;
+        }
+        histogram!("bore_server_handshake_duration_seconds").record(accepted_at.elapsed());
+
+        let shaper = self.max_rate.map(|rate| Arc::new(Shaper::new(rate)));
+        let tunnel_id = Uuid::new_v4();
+        let tunnel = Arc::new(ActiveTunnel {
+            client,
+            port,
+            host: matches!(incoming, Incoming::Routed { .. }).then(|| host.clone()),
+            token: identity.token.clone(),
+            secondary_secret: identity.secondary_secret,
+            service_up: AtomicBool::new(true),
+            started_at: OffsetDateTime::now_utc(),
+            started: Instant::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            connections: DashMap::new(),
+            disconnect: Notify::new(),
+        });
+        self.tunnels.0.insert(tunnel_id, Arc::clone(&tunnel));
+        if let Some(audit) = &self.audit {
+            audit.record(AuditEvent::TunnelOpened {
+                tunnel: tunnel_id,
+                client,
+                token: tunnel.token.as_deref(),
+                secondary_secret: tunnel.secondary_secret,
+                requested_port: request.port,
+                requested_ports: &request.ports,
+                port,
+                host: tunnel.host.as_deref(),
+            });
+        }
+        let mut restart_rx = self.restart.subscribe();
+        // Only tunnels with their own port can be suspended, since the suspender works by port.
+        let suspendable = matches!(incoming, Incoming::Listener(_));
+        let (suspend_tx, mut suspend_rx) = oneshot::channel();
+        if suspendable {
+            self.suspendable.insert(port, suspend_tx);
+        }
+        gauge!("bore_server_tunnels_active").increment(1.0);
+        let _registration = Registration {
+            map: &self.suspendable,
+            port: suspendable.then_some(port),
+            tunnels: &self.tunnels,
+            id: tunnel_id,
+            sessions: &self.sessions,
+            session: session_id,
+            audit: self.audit.as_ref(),
+        };
+        let heartbeat_timeout = self
+            .heartbeat_timeout
+            .filter(|_| features.contains(Features::PING));
+        let mut last_seen = Instant::now();
+        let mut tokens_rx = self.tokens.subscribe();
+        let mut awaiting_session = session_id.is_some();
+        let mut session = None;
+        loop {
+            if stream.send(ServerMessage::Heartbeat).await.is_err() {
+                // Assume that the TCP connection has been dropped.
+                return Ok(());
+            }
+            if heartbeat_timeout.is_some_and(|timeout| last_seen.elapsed() > timeout) {
+                warn!(
+                    ?port,
+                    ?heartbeat_timeout,
+                    "client stopped responding, closing tunnel"
+                );
+                counter!("bore_server_heartbeat_timeouts_total").increment(1);
+                return Ok(());
+            }
+            let accepted = tokio::select! {
+                hint = restart_rx.recv() => {
+                    if let Ok(hint) = hint {
+                        info!(?hint, "asking client to reconnect");
+                        stream.send(ServerMessage::Reconnect(hint)).await?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+                reason = &mut suspend_rx, if suspendable => {
+                    let reason = reason.unwrap_or_default();
+                    warn!(?port, %reason, "suspending tunnel");
+                    let message = format!("tunnel suspended: {reason}");
+                    stream.send(ServerMessage::Error(message)).await?;
+                    return Ok(());
+                }
+                conn = &mut session_rx, if awaiting_session => {
+                    awaiting_session = false;
+                    if let Ok(conn) = conn {
+                        info!(?port, "visitor connections are multiplexed");
+                        session = Some(Session::new(conn));
+                    }
+                    continue;
+                }
+                _ = self.draining.cancelled() => {
+                    info!(?port, "closing tunnel for shutdown");
+                    stream.send(ServerMessage::Error(SHUTTING_DOWN.into())).await?;
+                    return Ok(());
+                }
+                _ = tunnel.disconnect.notified() => {
+                    warn!(?port, "disconnecting tunnel at operator request");
+                    let message = "tunnel closed by the server operator".to_string();
+                    stream.send(ServerMessage::Error(message)).await?;
+                    return Ok(());
+                }
+                _ = tokens_rx.changed(), if identity.token.is_some() => {
+                    // A token whose secret changed is revoked too, even though its name is the same.
+                    let revoked = tokens_rx.borrow_and_update().as_ref().is_some_and(|tokens| {
+                        !tokens.iter().any(|token| {
+                            Some(token.name()) == identity.token.as_deref()
+                                && Some(token.fingerprint()) == identity.token_fingerprint
+                        })
+                    });
+                    if revoked {
+                        warn!(?port, token = identity.token.as_deref(), "closing tunnel of revoked token");
+                        counter!("bore_server_tunnels_revoked_total").increment(1);
+                        let message = "authentication token was revoked".to_string();
+                        stream.send(ServerMessage::Error(message)).await?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+                message = stream.recv::<ClientMessage>() => {
+                    if let Ok(Some(message)) = message {
+                        last_seen = Instant::now();
+                        match message {
+                            ClientMessage::Ping(nonce) => {
+                                stream.send(ServerMessage::Pong(nonce)).await?;
                             }
+                            ClientMessage::ServiceHealth(up)
+                                if features.contains(Features::SERVICE_HEALTH) =>
+                            {
+                                if up {
+                                    info!(?port, "client reports local service is up");
+                                } else {
+                                    warn!(?port, "client reports local service is down");
+                                }
+                                tunnel.service_up.store(up, Ordering::Relaxed);
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    // The client closed the control connection, so release the port now rather
+                    // than when the next heartbeat fails, or a client that exits and reconnects,
+                    // like after `bore local --check`, finds its port still taken.
+                    return Ok(());
+                }
+                accepted = timeout(self.heartbeat_interval, incoming.accept()) => accepted,
+            };
+            if let Ok(result) = accepted {
+                let (stream2, addr, head) = result?;
+                if !self.access.borrow().permits(addr.ip()) || !access.permits(addr.ip()) {
+                    info!(?addr, ?port, "refused connection from disallowed address");
+                    counter!("bore_server_visitor_connections_refused_total").increment(1);
+                    continue;
+                }
+                if !self.throttle_visitor(addr.ip()) {
+                    continue;
+                }
+                if max_connections.is_some_and(|max| tunnel.connections.len() >= max as usize) {
+                    info!(?addr, ?port, "refused connection over the connection limit");
+                    counter!("bore_server_visitor_connections_over_limit_total").increment(1);
+                    continue;
+                }
+                if !tunnel.service_up.load(Ordering::Relaxed) {
+                    info!(
+                        ?addr,
+                        ?port,
+                        "refused connection while local service is down"
+                    );
+                    counter!("bore_server_visitor_connections_service_down_total").increment(1);
+                    if when_down == WhenDown::Http503 {
+                        let mut stream2 = stream2;
+                        tokio::spawn(async move {
+                            let response = stream2.write_all(SERVICE_UNAVAILABLE_RESPONSE);
+                            let _ = timeout(NETWORK_TIMEOUT, response).await;
                         });
-                        stream.send(ServerMessage::Connection(id)).await?;
                     }
+                    continue;
                 }
-            }
-            Some(ClientMessage::Accept(id)) => {
-                info!(%id, "forwarding connection");
-                match self.conns.remove(&id) {
-                    Some((_, mut stream2)) => {
-                        let mut parts = stream.into_parts();
-                        debug_assert!(parts.write_buf.is_empty(), "framed write buffer not empty");
-                        stream2.write_all(&parts.read_buf).await?;
-                        tokio::io::copy_bidirectional(&mut parts.io, &mut stream2).await?;
+                info!(?addr, ?port, "new connection");
+                counter!("bore_server_visitor_connections_total").increment(1);
+
+                let id = Uuid::new_v4();
+                let conns = Arc::clone(&self.conns);
+
+                let pending = PendingConnection {
+                    port,
+                    stream: stream2,
+                    shaper: shaper.clone(),
+                    head,
+                    slot: tunnel.open_connection(id, addr),
+                };
+                // With a multiplexed data connection, the visitor becomes a new stream on it.
+                let metadata = match features.contains(Features::PEER_ADDR) {
+                    true => addr.to_string(),
+                    false => String::new(),
+                };
+                let mux_stream = session
+                    .as_ref()
+                    .filter(|session| !session.is_closed())
+                    .and_then(|session| session.open(metadata.as_bytes()).ok());
+                if let Some(mux_stream) = mux_stream {
+                    let idle_timeout = self.idle_timeout;
+                    self.tasks.spawn(
+                        async move {
+                            let conn = Box::new(mux_stream);
+                            if let Err(err) = forward(id, pending, conn, &[], idle_timeout).await {
+                                warn!(%err, "connection exited with error");
+                            }
+                        }
+                        .instrument(info_span!("proxy", %id)),
+                    );
+                    continue;
+                }
+                conns.insert(id, pending);
+                tokio::spawn(async move {
+                    // Remove stale entries to avoid memory leaks.
+                    sleep(Duration::from_secs(10)).await;
+                    if conns.remove(&id).is_some() {
+                        warn!(%id, "removed stale connection");
                     }
-                    None => warn!(%id, "missing connection"),
+                });
+                if features.contains(Features::PEER_ADDR) {
+                    stream.send(ServerMessage::ConnectionFrom(id, addr)).await?;
+                } else {
+                    stream.send(ServerMessage::Connection(id)).await?;
                 }
-                Ok(())
             }
-            None => Ok(()),
         }
     }
 }
+
+/// Proxy a visitor connection over a data connection from the client, until either side closes.
+///
+/// `prefix` holds bytes from the client that were read along with its request to accept.
+async fn forward(
+    id: Uuid,
+    pending: PendingConnection,
+    mut conn: Transport,
+    prefix: &[u8],
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    let mut stream2: Transport = match pending.shaper {
+        Some(shaper) => Box::new(Shaped::new(pending.stream, shaper)),
+        None => Box::new(pending.stream),
+    };
+    let slot = pending.slot;
+    let port = pending.port.to_string();
+    let active = gauge!("bore_server_tunnel_connections_active", "port" => port.clone());
+    active.increment(1.0);
+    let copy = async {
+        conn.write_all(&pending.head).await?;
+        stream2.write_all(prefix).await?;
+        idle::copy_bidirectional(&mut conn, &mut stream2, idle_timeout).await
+    };
+    let result = tokio::select! {
+        result = copy => result,
+        _ = slot.close.notified() => {
+            active.decrement(1.0);
+            info!(%id, ?port, "closed connection at operator request");
+            return Ok(());
+        }
+    };
+    active.decrement(1.0);
+    // Bytes are counted when the connection closes, from the visitor's side.
+    let Some((bytes_out, bytes_in)) = result? else {
+        info!(%id, ?port, "closed idle connection");
+        counter!("bore_server_idle_connections_closed_total").increment(1);
+        return Ok(());
+    };
+    let bytes_in = bytes_in + pending.head.len() as u64;
+    let bytes_out = bytes_out + prefix.len() as u64;
+    counter!("bore_server_tunnel_bytes_in_total", "port" => port.clone()).increment(bytes_in);
+    counter!("bore_server_tunnel_bytes_out_total", "port" => port).increment(bytes_out);
+    slot.tunnel.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+    slot.tunnel
+        .bytes_out
+        .fetch_add(bytes_out, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Handle for replacing the tokens accepted by a running server.
+#[derive(Clone)]
+pub struct TokenUpdater {
+    tokens: Arc<watch::Sender<Option<Arc<[Token]>>>>,
+}
+
+impl TokenUpdater {
+    /// Replace the accepted tokens, closing tunnels opened with tokens that were removed.
+    pub fn update(&self, tokens: Vec<Token>) {
+        self.tokens.send_replace(Some(tokens.into()));
+    }
+}
+
+/// Handle for changing the settings of a running server without closing its tunnels.
+#[derive(Clone)]
+pub struct Reloader {
+    port_range: Arc<watch::Sender<RangeInclusive<u16>>>,
+    access: Arc<watch::Sender<AccessRules>>,
+    auth: Arc<watch::Sender<Option<Authenticator>>>,
+    secondary_auth: Arc<watch::Sender<Option<Authenticator>>>,
+}
+
+impl Reloader {
+    /// Replace the range of ports that new tunnels may listen on.
+    ///
+    /// Tunnels already open outside the new range are left alone.
+    pub fn set_port_range(&self, port_range: RangeInclusive<u16>) {
+        assert!(!port_range.is_empty(), "must provide at least one port");
+        self.port_range.send_replace(port_range);
+    }
+
+    /// Replace the rules for which visitors may connect to any tunnel.
+    pub fn set_access_rules(&self, access: AccessRules) {
+        self.access.send_replace(access);
+    }
+
+    /// Replace the secondary secret, or retire it with `None`.
+    ///
+    /// Returns whether a secondary secret was accepted before.
+    pub fn set_secondary_secret(&self, secret: Option<&str>) -> bool {
+        self.secondary_auth
+            .send_replace(secret.map(Authenticator::new))
+            .is_some()
+    }
+
+    /// Replace the primary secret, or stop requiring one with `None`.
+    ///
+    /// Open tunnels stay up, but their clients need the new secret to open data connections.
+    pub fn set_secret(&self, secret: Option<&str>) {
+        self.auth.send_replace(secret.map(Authenticator::new));
+    }
+
+    /// Make the secondary secret the primary one, keeping the old primary as the secondary.
+    ///
+    /// Returns false, changing nothing, if no secondary secret is set.
+    pub fn promote_secondary_secret(&self) -> bool {
+        let Some(secondary) = self.secondary_auth.borrow().clone() else {
+            return false;
+        };
+        let previous = self.auth.send_replace(Some(secondary));
+        self.secondary_auth.send_replace(previous);
+        true
+    }
+}
+
+/// Unregisters an active tunnel when its control connection ends.
+struct Registration<'a> {
+    map: &'a DashMap<u16, oneshot::Sender<String>>,
+    port: Option<u16>,
+    tunnels: &'a Tunnels,
+    id: Uuid,
+    sessions: &'a DashMap<Uuid, oneshot::Sender<Transport>>,
+    session: Option<Uuid>,
+    audit: Option<&'a AuditLog>,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        if let Some(port) = self.port {
+            self.map.remove(&port);
+        }
+        if let Some(session) = self.session {
+            self.sessions.remove(&session);
+        }
+        let removed = self.tunnels.0.remove(&self.id);
+        if let (Some((id, tunnel)), Some(audit)) = (removed, self.audit) {
+            audit.record(AuditEvent::TunnelClosed {
+                tunnel: id,
+                client: tunnel.client,
+                token: tunnel.token.as_deref(),
+                port: tunnel.port,
+                host: tunnel.host.as_deref(),
+                duration_secs: tunnel.started.elapsed().as_secs(),
+                bytes_in: tunnel.bytes_in.load(Ordering::Relaxed),
+                bytes_out: tunnel.bytes_out.load(Ordering::Relaxed),
+                connections: tunnel.total_connections.load(Ordering::Relaxed),
+            });
+        }
+        gauge!("bore_server_tunnels_active").decrement(1.0);
+    }
+}
+
+/// Source of visitor connections for a tunnel.
+enum Incoming<'a> {
+    /// Visitors connect to the tunnel's own public port.
+    Listener(TcpListener),
+
+    /// Visitors are routed from the shared HTTP port by the name of the tunnel.
+    Routed {
+        /// Held so the route is removed when the tunnel closes.
+        _route: Route<'a>,
+        host: String,
+        rx: mpsc::Receiver<RoutedConnection>,
+    },
+}
+
+impl Incoming<'_> {
+    /// Wait for the next visitor, with any bytes that were already read from it.
+    async fn accept(&mut self) -> io::Result<RoutedConnection> {
+        match self {
+            Self::Listener(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((stream, addr, Vec::new()))
+            }
+            Self::Routed { rx, .. } => rx
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::other("route was removed")),
+        }
+    }
+}
+
+/// Removes a named tunnel from the HTTP routes when dropped.
+struct Route<'a> {
+    routes: &'a DashMap<String, mpsc::Sender<RoutedConnection>>,
+    name: String,
+}
+
+impl Drop for Route<'_> {
+    fn drop(&mut self) {
+        self.routes.remove(&self.name);
+    }
+}
+
+fn format_rfc3339(at: OffsetDateTime) -> String {
+    at.format(&Rfc3339)
+        .unwrap_or_else(|_| "1970-01-01T00:00:00Z".to_string())
+}
+
+/// Log traffic on the control port that is not the bore protocol, e.g., from scanners.
+///
+/// These events use the `bore::probe` target, so operators can filter them separately.
+fn log_probe(
+    addr: SocketAddr,
+    accepted_at: Instant,
+    err: &anyhow::Error,
+    stream: &Delimited<Transport>,
+) {
+    let bytes = match err.downcast_ref::<MalformedFrame>() {
+        Some(MalformedFrame(frame)) => frame.as_slice(),
+        None => stream.read_buffer(),
+    };
+    if bytes.is_empty() {
+        return;
+    }
+    let prefix = &bytes[..bytes.len().min(PROBE_PREFIX_LENGTH)];
+    let kind = probe_kind(bytes);
+    counter!("bore_server_probes_total", "kind" => kind).increment(1);
+    info!(
+        target: "bore::probe",
+        %addr,
+        kind,
+        first_bytes = %prefix.escape_ascii(),
+        elapsed_ms = accepted_at.elapsed().as_millis() as u64,
+        "unrecognized traffic on control port",
+    );
+}
+
+/// Guess what kind of client sent unrecognized traffic, from its first bytes.
+fn probe_kind(bytes: &[u8]) -> &'static str {
+    const HTTP_METHODS: [&[u8]; 6] = [
+        b"GET ",
+        b"POST ",
+        b"HEAD ",
+        b"PUT ",
+        b"OPTIONS ",
+        b"CONNECT ",
+    ];
+    if HTTP_METHODS.iter().any(|method| bytes.starts_with(method)) {
+        "http"
+    } else if bytes.starts_with(&[0x16, 0x03]) {
+        "tls"
+    } else if bytes.starts_with(b"SSH-") {
+        "ssh"
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::probe_kind;
+
+    #[test]
+    fn probe_kind_recognizes_common_scanners() {
+        assert_eq!(probe_kind(b"GET / HTTP/1.1\r\n"), "http");
+        assert_eq!(probe_kind(&[0x16, 0x03, 0x01, 0x02]), "tls");
+        assert_eq!(probe_kind(b"SSH-2.0-Go"), "ssh");
+        assert_eq!(probe_kind(b"\x00garbage"), "unknown");
+    }
+}
@@ -1,5 +1,8 @@
 //! Shared data structures, utilities, and protocol definitions.
 
+use std::net::SocketAddr;
+use std::ops::{BitAnd, BitOr};
+use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
@@ -11,15 +14,252 @@ use tokio_util::codec::{AnyDelimiterCodec, Framed, FramedParts};
 use tracing::trace;
 use uuid::Uuid;
 
+use crate::access::AccessRules;
+
 /// TCP port used for control connections with the server.
 pub const CONTROL_PORT: u16 = 7835;
 
 /// Maximum byte length for a JSON frame in the stream.
 pub const MAX_FRAME_LENGTH: usize = 256;
 
+/// Maximum number of preferred port ranges in a [`HelloRequest`], so it fits in one frame.
+pub const MAX_PORT_RANGES: usize = 8;
+
 /// Timeout for network connections and initial protocol messages.
 pub const NETWORK_TIMEOUT: Duration = Duration::from_secs(3);
 
+/// Bitmap of optional protocol capabilities, negotiated during the hello exchange.
+///
+/// Unknown bits are preserved when parsing, so peers can advertise capabilities
+/// that the other side does not know about yet. The effective set for a
+/// connection is the intersection of what both sides support.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Features(u32);
+
+impl Features {
+    /// No optional capabilities.
+    pub const NONE: Self = Self(0);
+
+    /// Compression of proxied data streams.
+    pub const COMPRESSION: Self = Self(1 << 0);
+
+    /// Multiplexing of data streams over one data connection per tunnel.
+    pub const MULTIPLEX: Self = Self(1 << 1);
+
+    /// Resuming a tunnel after the control connection drops.
+    pub const RESUMPTION: Self = Self(1 << 2);
+
+    /// Forwarding of UDP datagrams.
+    pub const UDP: Self = Self(1 << 3);
+
+    /// Visitor addresses included with new connections.
+    pub const PEER_ADDR: Self = Self(1 << 4);
+
+    /// Pings from the client, answered by the server to measure round-trip time.
+    pub const PING: Self = Self(1 << 5);
+
+    /// Visitor address rules requested by the client and enforced by the server.
+    pub const ACCESS_RULES: Self = Self(1 << 6);
+
+    /// Limit on concurrent visitor connections requested by the client and enforced by the server.
+    pub const CONNECTION_LIMIT: Self = Self(1 << 7);
+
+    /// Tunnels reached by name through the server's shared HTTP port, instead of their own port.
+    pub const HTTP_ROUTING: Self = Self(1 << 8);
+
+    /// Reports from the client of whether its local service is up.
+    pub const SERVICE_HEALTH: Self = Self(1 << 9);
+
+    /// Construct a feature set from raw bits.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Return the raw bits of this feature set.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Check whether no features are set.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Combine two feature sets, usable in constants.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Check whether all features in `other` are also set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// Inclusive range of ports, written as `START-END` or as a single port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "(u16, u16)", into = "(u16, u16)")]
+pub struct PortRange {
+    /// First port in the range.
+    pub start: u16,
+
+    /// Last port in the range.
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Range containing only one port.
+    pub const fn single(port: u16) -> Self {
+        Self {
+            start: port,
+            end: port,
+        }
+    }
+
+    /// Check whether a port is in this range.
+    pub const fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
+    }
+}
+
+impl From<(u16, u16)> for PortRange {
+    fn from((start, end): (u16, u16)) -> Self {
+        Self { start, end }
+    }
+}
+
+impl From<PortRange> for (u16, u16) {
+    fn from(range: PortRange) -> Self {
+        (range.start, range.end)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|&port| port > 0)
+                .ok_or_else(|| format!("invalid port: {port}"))
+        };
+        let range = match s.split_once('-') {
+            Some((start, end)) => Self {
+                start: parse(start)?,
+                end: parse(end)?,
+            },
+            None => Self::single(parse(s)?),
+        };
+        if range.start > range.end {
+            return Err(format!("port range {s} ends before it starts"));
+        }
+        Ok(range)
+    }
+}
+
+/// Extended initial client message, used when the client requests extensions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HelloRequest {
+    /// Port on the remote server to select, or 0 for any port.
+    pub port: u16,
+
+    /// Acceptable ports in order of preference, used when `port` is 0.
+    ///
+    /// The server takes the first free port, and fails if none are free.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortRange>,
+
+    /// Addresses that may connect to the tunnel, used with [`Features::ACCESS_RULES`].
+    #[serde(default, skip_serializing_if = "AccessRules::is_empty")]
+    pub access: AccessRules,
+
+    /// Name to route HTTP requests by, used with [`Features::HTTP_ROUTING`].
+    ///
+    /// Named tunnels do not get a port of their own, so `port` and `ports` are ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    /// Most visitor connections open at once, used with [`Features::CONNECTION_LIMIT`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+
+    /// What to do with visitors while the local service is down, used with
+    /// [`Features::SERVICE_HEALTH`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when_down: Option<WhenDown>,
+
+    /// Optional capabilities the client would like to use.
+    #[serde(default, skip_serializing_if = "Features::is_empty")]
+    pub features: Features,
+}
+
+/// What the server does with new visitors while a tunnel's local service is down.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenDown {
+    /// Close the connection right away.
+    #[default]
+    Close,
+
+    /// Answer with an HTTP `503 Service Unavailable` response, then close.
+    #[value(name = "http-503")]
+    #[serde(rename = "http_503")]
+    Http503,
+}
+
+/// Extended response to a [`HelloRequest`].
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HelloResponse {
+    /// Actual public port of the tunnel.
+    pub port: u16,
+
+    /// Public host name of a named tunnel, used with [`Features::HTTP_ROUTING`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// Shared port where TLS visitors reach a named tunnel, routed by server name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_port: Option<u16>,
+
+    /// ID that opens the multiplexed data connection, used with [`Features::MULTIPLEX`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<Uuid>,
+
+    /// Capabilities that both sides agreed to use on this connection.
+    #[serde(default, skip_serializing_if = "Features::is_empty")]
+    pub features: Features,
+}
+
+/// Instructions for clients to reconnect after a planned server restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconnectHint {
+    /// Seconds to wait before reconnecting.
+    pub delay: u64,
+
+    /// Address of a replacement server, or the same server if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
 /// A message from the client on the control connection.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
@@ -29,8 +269,32 @@ pub enum ClientMessage {
     /// Initial client message specifying a port to forward.
     Hello(u16),
 
+    /// Initial client message with a port and negotiated extensions.
+    ///
+    /// Clients only send this when they request extensions, so that servers
+    /// without negotiation support keep working with default clients.
+    ExtendedHello(HelloRequest),
+
     /// Accepts an incoming TCP connection, using this stream as a proxy.
     Accept(Uuid),
+
+    /// Asks the server to forward this connection to another server's control port.
+    Relay(String),
+
+    /// Asks the server to echo a nonce back, to measure round-trip time.
+    ///
+    /// Only sent when the [`Features::PING`] extension was negotiated.
+    Ping(u64),
+
+    /// Turns this stream into the multiplexed data connection of a tunnel.
+    ///
+    /// Only sent when the [`Features::MULTIPLEX`] extension was negotiated.
+    Multiplex(Uuid),
+
+    /// Reports whether the local service is up, when that changes.
+    ///
+    /// Only sent when the [`Features::SERVICE_HEALTH`] extension was negotiated.
+    ServiceHealth(bool),
 }
 
 /// A message from the server on the control connection.
@@ -42,14 +306,38 @@ pub enum ServerMessage {
     /// Response to a client's initial message, with actual public port.
     Hello(u16),
 
+    /// Response to an extended hello, with the agreed extensions.
+    ExtendedHello(HelloResponse),
+
     /// No-op used to test if the client is still reachable.
     Heartbeat,
 
     /// Asks the client to accept a forwarded TCP connection.
     Connection(Uuid),
 
+    /// Asks the client to accept a forwarded TCP connection from a visitor address.
+    ///
+    /// Only sent when the [`Features::PEER_ADDR`] extension was negotiated.
+    ConnectionFrom(Uuid, SocketAddr),
+
     /// Indicates a server error that terminates the connection.
     Error(String),
+
+    /// Asks the client to reconnect later, sent before a planned restart.
+    Reconnect(ReconnectHint),
+
+    /// Answer to a [`ClientMessage::Ping`], with the same nonce.
+    Pong(u64),
+}
+
+/// Error context holding the raw bytes of a frame that was not valid JSON.
+#[derive(Debug)]
+pub struct MalformedFrame(pub Vec<u8>);
+
+impl std::fmt::Display for MalformedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("unable to parse message")
+    }
 }
 
 /// Transport stream with JSON frames delimited by null characters.
@@ -67,8 +355,8 @@ impl<U: AsyncRead + AsyncWrite + Unpin> Delimited<U> {
         trace!("waiting to receive json message");
         if let Some(next_message) = self.0.next().await {
             let byte_message = next_message.context("frame error, invalid byte length")?;
-            let serialized_obj =
-                serde_json::from_slice(&byte_message).context("unable to parse message")?;
+            let serialized_obj = serde_json::from_slice(&byte_message)
+                .with_context(|| MalformedFrame(byte_message.to_vec()))?;
             Ok(serialized_obj)
         } else {
             Ok(None)
@@ -92,8 +380,29 @@ impl<U: AsyncRead + AsyncWrite + Unpin> Delimited<U> {
         Ok(())
     }
 
+    /// Bytes received but not yet decoded into a frame.
+    pub fn read_buffer(&self) -> &[u8] {
+        self.0.read_buffer()
+    }
+
     /// Consume this object, returning current buffers and the inner transport.
     pub fn into_parts(self) -> FramedParts<U, AnyDelimiterCodec> {
         self.0.into_parts()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PortRange;
+
+    #[test]
+    fn port_ranges_are_parsed() {
+        assert_eq!("8000".parse(), Ok(PortRange::single(8000)));
+        let range: PortRange = "8000-8100".parse().unwrap();
+        assert_eq!((range.start, range.end), (8000, 8100));
+        assert!(range.contains(8050) && !range.contains(8101));
+        assert!("8100-8000".parse::<PortRange>().is_err());
+        assert!("0-10".parse::<PortRange>().is_err());
+        assert_eq!(serde_json::to_string(&range).unwrap(), "[8000,8100]");
+    }
+}
@@ -0,0 +1,288 @@
+//! Built-in SOCKS5 server, which a client can expose as its local service.
+//!
+//! Only the CONNECT command is supported. See RFC 1928, and RFC 1929 for
+//! username and password authentication.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::auth::Authenticator;
+use crate::idle;
+use crate::shared::NETWORK_TIMEOUT;
+
+pub(crate) const VERSION: u8 = 0x05;
+pub(crate) const NO_AUTHENTICATION: u8 = 0x00;
+pub(crate) const USERNAME_PASSWORD: u8 = 0x02;
+pub(crate) const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+pub(crate) const CONNECT: u8 = 0x01;
+
+pub(crate) const ATYP_IPV4: u8 = 0x01;
+pub(crate) const ATYP_DOMAIN: u8 = 0x03;
+pub(crate) const ATYP_IPV6: u8 = 0x04;
+
+pub(crate) const SUCCEEDED: u8 = 0x00;
+const GENERAL_FAILURE: u8 = 0x01;
+const NETWORK_UNREACHABLE: u8 = 0x03;
+const HOST_UNREACHABLE: u8 = 0x04;
+const CONNECTION_REFUSED: u8 = 0x05;
+const COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Username and password that SOCKS clients must send.
+#[derive(Clone)]
+pub struct SocksAuth {
+    username: String,
+    password: Authenticator,
+}
+
+impl SocksAuth {
+    /// Check a username and password sent by a client.
+    fn validate(&self, username: &[u8], password: &[u8]) -> bool {
+        // Compare MACs rather than strings, so the check takes constant time.
+        let challenge = Uuid::nil();
+        let tag = Authenticator::new(&String::from_utf8_lossy(password)).answer(&challenge);
+        self.password.validate(&challenge, &tag) && username == self.username.as_bytes()
+    }
+}
+
+impl FromStr for SocksAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, password) = s
+            .split_once(':')
+            .ok_or("expected credentials like USER:PASSWORD")?;
+        // Each field has a one-byte length on the wire.
+        if username.is_empty() || username.len() > 255 {
+            return Err("SOCKS username must be 1 to 255 bytes".into());
+        }
+        if password.is_empty() || password.len() > 255 {
+            return Err("SOCKS password must be 1 to 255 bytes".into());
+        }
+        Ok(Self {
+            username: username.to_string(),
+            password: Authenticator::new(password),
+        })
+    }
+}
+
+impl fmt::Debug for SocksAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocksAuth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settings of the built-in SOCKS5 server.
+#[derive(Debug, Clone, Default)]
+pub struct Socks {
+    /// Credentials required of SOCKS clients, if any.
+    pub auth: Option<SocksAuth>,
+}
+
+/// Address that a SOCKS client asks to connect to.
+enum Target {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Addr(addr) => write!(f, "{addr}"),
+            Self::Domain(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+impl Socks {
+    /// Serve one SOCKS client, connecting it to the address it asks for.
+    pub async fn serve<S>(&self, mut stream: S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await?;
+        if header[0] != VERSION {
+            bail!("unsupported SOCKS version {}", header[0]);
+        }
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).await?;
+        let method = match self.auth {
+            Some(_) => USERNAME_PASSWORD,
+            None => NO_AUTHENTICATION,
+        };
+        if !methods.contains(&method) {
+            stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+            bail!("SOCKS client offered no acceptable authentication method");
+        }
+        stream.write_all(&[VERSION, method]).await?;
+        if let Some(auth) = &self.auth {
+            authenticate(&mut stream, auth).await?;
+        }
+
+        let mut request = [0u8; 4];
+        stream.read_exact(&mut request).await?;
+        if request[0] != VERSION {
+            bail!("unsupported SOCKS version {}", request[0]);
+        }
+        if request[1] != CONNECT {
+            reply(&mut stream, COMMAND_NOT_SUPPORTED, None).await?;
+            bail!("unsupported SOCKS command {}", request[1]);
+        }
+        let target = match request[3] {
+            ATYP_IPV4 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await?;
+                Target::Addr((Ipv4Addr::from(ip), stream.read_u16().await?).into())
+            }
+            ATYP_IPV6 => {
+                let mut ip = [0u8; 16];
+                stream.read_exact(&mut ip).await?;
+                Target::Addr((Ipv6Addr::from(ip), stream.read_u16().await?).into())
+            }
+            ATYP_DOMAIN => {
+                let mut host = vec![0u8; stream.read_u8().await? as usize];
+                stream.read_exact(&mut host).await?;
+                let Ok(host) = String::from_utf8(host) else {
+                    reply(&mut stream, GENERAL_FAILURE, None).await?;
+                    bail!("SOCKS domain name is not valid UTF-8");
+                };
+                Target::Domain(host, stream.read_u16().await?)
+            }
+            atyp => {
+                reply(&mut stream, ADDRESS_TYPE_NOT_SUPPORTED, None).await?;
+                bail!("unsupported SOCKS address type {atyp}");
+            }
+        };
+
+        info!(%target, "SOCKS connect");
+        let connect = async {
+            match &target {
+                Target::Addr(addr) => TcpStream::connect(addr).await,
+                Target::Domain(host, port) => TcpStream::connect((host.as_str(), *port)).await,
+            }
+        };
+        let mut upstream = match timeout(NETWORK_TIMEOUT, connect).await {
+            Ok(Ok(upstream)) => upstream,
+            Ok(Err(err)) => {
+                reply(&mut stream, error_code(&err), None).await?;
+                bail!("could not connect to {target}: {err}");
+            }
+            Err(_) => {
+                reply(&mut stream, HOST_UNREACHABLE, None).await?;
+                bail!("timed out connecting to {target}");
+            }
+        };
+        reply(&mut stream, SUCCEEDED, Some(upstream.local_addr()?)).await?;
+        idle::copy_bidirectional(&mut stream, &mut upstream, None).await?;
+        Ok(())
+    }
+}
+
+/// Check the username and password sent by a client.
+async fn authenticate<S>(stream: &mut S, auth: &SocksAuth) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // The subnegotiation has its own version number, which is always 1.
+    if stream.read_u8().await? != 0x01 {
+        bail!("unsupported SOCKS authentication version");
+    }
+    let mut username = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut username).await?;
+    let mut password = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut password).await?;
+    if auth.validate(&username, &password) {
+        stream.write_all(&[0x01, 0x00]).await?;
+        Ok(())
+    } else {
+        stream.write_all(&[0x01, 0x01]).await?;
+        bail!("invalid SOCKS username or password");
+    }
+}
+
+/// Send the reply to a request, with the address the connection is bound to.
+async fn reply<S>(stream: &mut S, code: u8, bound: Option<SocketAddr>) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let bound = bound.unwrap_or_else(|| (Ipv4Addr::UNSPECIFIED, 0).into());
+    let mut message = vec![VERSION, code, 0x00];
+    match bound.ip() {
+        IpAddr::V4(ip) => {
+            message.push(ATYP_IPV4);
+            message.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            message.push(ATYP_IPV6);
+            message.extend(ip.octets());
+        }
+    }
+    message.extend(bound.port().to_be_bytes());
+    stream.write_all(&message).await
+}
+
+/// Reply code for a failed connection to the target.
+fn error_code(err: &io::Error) -> u8 {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused => CONNECTION_REFUSED,
+        io::ErrorKind::NetworkUnreachable => NETWORK_UNREACHABLE,
+        io::ErrorKind::HostUnreachable => HOST_UNREACHABLE,
+        _ => GENERAL_FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{Socks, SocksAuth};
+
+    #[tokio::test]
+    async fn connect_request_is_proxied() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = target.local_addr().unwrap().port();
+        let socks = Socks {
+            auth: Some("alice:secret".parse().unwrap()),
+        };
+        let (mut client, server) = duplex(1024);
+        tokio::spawn(async move { socks.serve(server).await });
+
+        let mut reply = [0u8; 2];
+        client.write_all(&[5, 1, 2]).await.unwrap();
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [5, 2]);
+        client.write_all(b"\x01\x05alice\x06secret").await.unwrap();
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [1, 0]);
+
+        let mut request = vec![5, 1, 0, 3, 9];
+        request.extend(b"127.0.0.1");
+        request.extend(port.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+        let mut response = [0u8; 10];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response[..4], [5, 0, 0, 1]);
+
+        let (mut upstream, _) = target.accept().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        upstream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        assert!("alice".parse::<SocksAuth>().is_err());
+        assert!(":secret".parse::<SocksAuth>().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Traffic counters for proxied connections.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Live traffic counters, shared by all connections of a tunnel.
+#[derive(Debug, Default)]
+pub struct TrafficStats {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    active_connections: AtomicU64,
+    total_connections: AtomicU64,
+}
+
+/// Point-in-time copy of [`TrafficStats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficSnapshot {
+    /// Bytes received from visitors.
+    pub bytes_in: u64,
+
+    /// Bytes sent to visitors.
+    pub bytes_out: u64,
+
+    /// Connections that are currently open.
+    pub active_connections: u64,
+
+    /// Connections opened since the counters were created.
+    pub total_connections: u64,
+}
+
+impl TrafficStats {
+    /// Record a new connection, which stays active until the guard is dropped.
+    pub fn open_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        counter!("bore_tunnel_connections_total").increment(1);
+        gauge!("bore_tunnel_connections_active").increment(1.0);
+        ConnectionGuard(Arc::clone(self))
+    }
+
+    /// Take a snapshot of the current counter values.
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        TrafficSnapshot {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            total_connections: self.total_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bytes transferred on a single connection so far.
+#[derive(Debug, Default)]
+pub struct ConnectionBytes {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl ConnectionBytes {
+    /// Bytes received from and sent to the visitor.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Marks a connection as active for as long as it is held.
+#[derive(Debug)]
+pub struct ConnectionGuard(Arc<TrafficStats>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+        gauge!("bore_tunnel_connections_active").decrement(1.0);
+    }
+}
+
+/// Stream wrapper that counts bytes in [`TrafficStats`] as they are transferred.
+///
+/// Reads are counted as inbound traffic and writes as outbound traffic, so this
+/// should wrap the side of a proxied connection that faces the visitor.
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    stats: Arc<TrafficStats>,
+    connection: Option<Arc<ConnectionBytes>>,
+}
+
+impl<S> Counted<S> {
+    /// Wrap a stream, counting its traffic in `stats`.
+    pub fn new(inner: S, stats: Arc<TrafficStats>) -> Self {
+        Self {
+            inner,
+            stats,
+            connection: None,
+        }
+    }
+
+    /// Also count the traffic in `bytes`, for this connection alone.
+    pub fn with_connection(mut self, bytes: Arc<ConnectionBytes>) -> Self {
+        self.connection = Some(bytes);
+        self
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            self.stats.bytes_in.fetch_add(read, Ordering::Relaxed);
+            if let Some(connection) = &self.connection {
+                connection.bytes_in.fetch_add(read, Ordering::Relaxed);
+            }
+            counter!("bore_tunnel_bytes_in_total").increment(read);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.stats
+                .bytes_out
+                .fetch_add(written as u64, Ordering::Relaxed);
+            if let Some(connection) = &self.connection {
+                connection
+                    .bytes_out
+                    .fetch_add(written as u64, Ordering::Relaxed);
+            }
+            counter!("bore_tunnel_bytes_out_total").increment(written as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Format a byte count with a binary unit suffix, like `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{format_bytes, Counted, TrafficStats};
+
+    #[tokio::test]
+    async fn counted_stream_tracks_bytes_and_connections() {
+        let stats = Arc::new(TrafficStats::default());
+        let (client, mut server) = tokio::io::duplex(64);
+        let guard = stats.open_connection();
+        let mut client = Counted::new(client, Arc::clone(&stats));
+
+        client.write_all(b"hello").await.unwrap();
+        server.write_all(b"hi").await.unwrap();
+        let mut buf = [0u8; 2];
+        client.read_exact(&mut buf).await.unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_out, 5);
+        assert_eq!(snapshot.bytes_in, 2);
+        assert_eq!(snapshot.active_connections, 1);
+        drop(guard);
+        assert_eq!(stats.snapshot().active_connections, 0);
+        assert_eq!(stats.snapshot().total_connections, 1);
+    }
+
+    #[test]
+    fn format_bytes_uses_binary_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
@@ -0,0 +1,64 @@
+//! Integration with systemd: readiness and the watchdog.
+//!
+//! State changes are sent as datagrams to the socket named by `NOTIFY_SOCKET`. See
+//! `sd_notify(3)`. Outside of systemd, the variable is not set and every function here does
+//! nothing.
+
+use std::env;
+use std::time::Duration;
+
+/// Send a state change to systemd, like `READY=1`, logging a warning if it cannot be sent.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(err) = send(&path, state) {
+            tracing::warn!(%err, "could not notify systemd");
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    // A leading `@` names a socket in the abstract namespace.
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        return socket.send_to_addr(state.as_bytes(), &addr).map(|_| ());
+    }
+    socket.send_to(state.as_bytes(), path).map(|_| ())
+}
+
+/// How often to send `WATCHDOG=1`, if systemd expects it: half of `WATCHDOG_USEC`.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    #[test]
+    fn notifications_are_sent_as_datagrams() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("bore-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        super::send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 16];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
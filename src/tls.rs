@@ -0,0 +1,129 @@
+//! Optional TLS encryption for connections between clients and the server.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::timeout;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::shared::NETWORK_TIMEOUT;
+
+/// Byte stream that can carry the bore protocol.
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Io for T {}
+
+/// Connection between a client and the server, which may be encrypted.
+pub type Transport = Box<dyn Io>;
+
+/// TLS settings for accepting control connections on the server.
+#[derive(Clone)]
+pub struct ServerTls(TlsAcceptor);
+
+impl fmt::Debug for ServerTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerTls").finish_non_exhaustive()
+    }
+}
+
+impl ServerTls {
+    /// Load a PEM certificate chain and its private key.
+    pub fn from_pem_files(cert: &Path, key: &Path) -> Result<Self> {
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("could not read certificates from {}", cert.display()))?;
+        if certs.is_empty() {
+            bail!("no certificates found in {}", cert.display());
+        }
+        let key = PrivateKeyDer::from_pem_file(key)
+            .with_context(|| format!("could not read private key from {}", key.display()))?;
+        Self::from_der(certs, key)
+    }
+
+    /// Generate a self-signed certificate for the given host names and IP addresses.
+    ///
+    /// Clients do not trust it unless told to, so it only protects against passive eavesdropping
+    /// until the certificate is accepted.
+    pub fn self_signed(names: Vec<String>) -> Result<Self> {
+        let certified = rcgen::generate_simple_self_signed(names)
+            .context("could not generate a self-signed certificate")?;
+        let key = PrivateKeyDer::try_from(certified.signing_key.serialize_der())
+            .map_err(|err| anyhow!(err))?;
+        Self::from_der(vec![certified.cert.der().clone()], key)
+    }
+
+    fn from_der(certs: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Result<Self> {
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("invalid TLS certificate or key")?;
+        Ok(Self(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    /// Perform the server side of the TLS handshake.
+    pub async fn accept(&self, stream: impl Io + 'static) -> Result<Transport> {
+        let stream = timeout(NETWORK_TIMEOUT, self.0.accept(stream))
+            .await
+            .context("timed out waiting for TLS handshake")?
+            .context("TLS handshake failed")?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// TLS settings for connecting to servers from a client.
+#[derive(Clone)]
+pub struct ClientTls(TlsConnector);
+
+impl fmt::Debug for ClientTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTls").finish_non_exhaustive()
+    }
+}
+
+impl ClientTls {
+    /// Trust the certificate authorities in a PEM file, or the built-in web roots if not set.
+    pub fn new(ca: Option<&Path>) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        match ca {
+            Some(ca) => {
+                let certs = CertificateDer::pem_file_iter(ca)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .with_context(|| {
+                        format!("could not read certificates from {}", ca.display())
+                    })?;
+                let (added, _) = roots.add_parsable_certificates(certs);
+                if added == 0 {
+                    bail!("no usable certificates found in {}", ca.display());
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self(TlsConnector::from(Arc::new(config))))
+    }
+
+    /// Perform the client side of the TLS handshake, verifying the certificate for `host`.
+    pub async fn connect(&self, host: &str, stream: impl Io + 'static) -> Result<Transport> {
+        let name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("invalid TLS server name {host}"))?;
+        let stream = timeout(NETWORK_TIMEOUT, self.0.connect(name, stream))
+            .await
+            .context("timed out waiting for TLS handshake")?
+            .with_context(|| format!("TLS handshake with {host} failed"))?;
+        Ok(Box::new(stream))
+    }
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
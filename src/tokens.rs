@@ -0,0 +1,199 @@
+//! Named credentials for clients, read from a tokens file.
+//!
+//! A tokens file is a TOML or YAML table with a list of tokens, like
+//!
+//! ```toml
+//! [[tokens]]
+//! name = "alice"
+//! secret = "correct horse battery staple"
+//! ports = ["20000-20010"]
+//! ```
+//!
+//! Each token is a separate secret, so it can be revoked by removing it from the file.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::Authenticator;
+use crate::shared::PortRange;
+
+/// How often a tokens file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A named secret that a client can authenticate with.
+pub struct Token {
+    name: String,
+    auth: Authenticator,
+    ports: Vec<PortRange>,
+}
+
+impl Token {
+    /// Create a token, optionally with ports reserved for it.
+    pub fn new(name: &str, secret: &str, ports: Vec<PortRange>) -> Self {
+        Self {
+            name: name.to_string(),
+            auth: Authenticator::new(secret),
+            ports,
+        }
+    }
+
+    /// Name of the token, used in logs.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Ports reserved for clients using this token.
+    pub fn ports(&self) -> &[PortRange] {
+        &self.ports
+    }
+
+    /// Digest of the token's secret, which changes with the secret but does not reveal it.
+    pub fn fingerprint(&self) -> String {
+        self.auth.answer(&Uuid::nil())
+    }
+
+    /// Check whether a reply to an authentication challenge was made with this token.
+    pub fn validate(&self, challenge: &Uuid, tag: &str) -> bool {
+        self.auth.validate(challenge, tag)
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("name", &self.name)
+            .field("ports", &self.ports)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Contents of a tokens file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokensFile {
+    #[serde(default)]
+    tokens: Vec<TokenEntry>,
+}
+
+/// One token in a tokens file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenEntry {
+    name: String,
+    secret: String,
+    #[serde(default)]
+    ports: Vec<String>,
+}
+
+/// Read the tokens in a TOML or YAML (`.yaml`/`.yml`) file.
+pub fn load(path: &Path) -> Result<Vec<Token>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("could not read tokens file {}", path.display()))?;
+    let yaml = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("yaml" | "yml")
+    );
+    parse(&text, yaml).with_context(|| format!("invalid tokens file {}", path.display()))
+}
+
+/// Parse the tokens in the text of a tokens file.
+fn parse(text: &str, yaml: bool) -> Result<Vec<Token>> {
+    let file: TokensFile = if yaml {
+        serde_yaml::from_str(text)?
+    } else {
+        toml::from_str(text)?
+    };
+    let mut names = HashSet::new();
+    let mut tokens = Vec::with_capacity(file.tokens.len());
+    for entry in file.tokens {
+        ensure!(!entry.name.is_empty(), "token name is empty");
+        ensure!(
+            !entry.secret.is_empty(),
+            "token {} has no secret",
+            entry.name
+        );
+        if !names.insert(entry.name.clone()) {
+            bail!("token name {} is used more than once", entry.name);
+        }
+        let ports = entry
+            .ports
+            .iter()
+            .map(|ports| ports.parse())
+            .collect::<Result<_, String>>()
+            .map_err(|err| anyhow::anyhow!("token {}: {err}", entry.name))?;
+        tokens.push(Token::new(&entry.name, &entry.secret, ports));
+    }
+    Ok(tokens)
+}
+
+/// Reload a tokens file whenever it changes, passing the new tokens to `update`.
+///
+/// If the file becomes invalid, the previous tokens are kept until it is fixed.
+pub async fn watch(path: &Path, update: impl Fn(Vec<Token>)) {
+    let mut last_modified = modified(path);
+    loop {
+        sleep(RELOAD_INTERVAL).await;
+        let current = modified(path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match load(path) {
+            Ok(tokens) => {
+                info!(count = tokens.len(), "reloaded tokens");
+                update(tokens);
+            }
+            Err(err) => warn!(%err, "keeping previous tokens"),
+        }
+    }
+}
+
+/// Time a file was last modified, if it can be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::shared::PortRange;
+
+    #[test]
+    fn tokens_file_is_parsed() {
+        let text = r#"
+            [[tokens]]
+            name = "alice"
+            secret = "a"
+            ports = ["20000-20010", "30000"]
+
+            [[tokens]]
+            name = "bob"
+            secret = "b"
+        "#;
+        let tokens = parse(text, false).unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].name(), "alice");
+        assert_eq!(
+            tokens[0].ports(),
+            [PortRange::from((20000, 20010)), PortRange::single(30000)]
+        );
+        assert!(tokens[1].ports().is_empty());
+
+        let yaml = "tokens:\n  - name: alice\n    secret: a\n";
+        assert_eq!(parse(yaml, true).unwrap()[0].name(), "alice");
+
+        let duplicate = "tokens:\n  - {name: a, secret: x}\n  - {name: a, secret: y}\n";
+        assert!(parse(duplicate, true).is_err());
+        assert!(parse("tokens:\n  - {name: a, secret: ''}\n", true).is_err());
+        assert!(parse("tokens:\n  - {name: a, secret: x, ports: [0]}\n", true).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Pluggable transports that carry the bore protocol between clients and the server.
+//!
+//! Clients dial TCP and the server accepts TCP by default. Embedders can carry control and data
+//! connections some other way, such as over WebSocket or an in-memory pipe in tests, by giving
+//! a client a [`Dialer`] and the server an [`Acceptor`]. TLS, when enabled, runs on top of
+//! either. Visitors always reach tunnels over TCP.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::BoxFuture;
+use tokio::io::duplex;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::timeout;
+
+use crate::egress::EgressProxy;
+use crate::shared::NETWORK_TIMEOUT;
+use crate::tls::Transport;
+
+/// Size of the buffer in each direction of an in-memory connection.
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Opens connections from a client to a server.
+pub trait Dialer: fmt::Debug + Send + Sync {
+    /// Open a connection to the server at `host:port`.
+    fn dial<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, Result<Transport>>;
+}
+
+/// Accepts connections from clients on a server.
+pub trait Acceptor: Send + Sync {
+    /// Wait for the next connection, returning it with the address of the client.
+    fn accept(&self) -> BoxFuture<'_, Result<(Transport, SocketAddr)>>;
+}
+
+/// Dialer that opens plain TCP connections, used unless another is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpDialer;
+
+impl Dialer for TcpDialer {
+    fn dial<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, Result<Transport>> {
+        Box::pin(async move {
+            let stream = match timeout(NETWORK_TIMEOUT, TcpStream::connect((host, port))).await {
+                Ok(res) => res,
+                Err(err) => Err(err.into()),
+            }
+            .with_context(|| format!("could not connect to {host}:{port}"))?;
+            Ok(Box::new(stream) as Transport)
+        })
+    }
+}
+
+impl Dialer for EgressProxy {
+    fn dial<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, Result<Transport>> {
+        Box::pin(async move { Ok(Box::new(self.connect(host, port).await?) as Transport) })
+    }
+}
+
+impl Acceptor for TcpListener {
+    fn accept(&self) -> BoxFuture<'_, Result<(Transport, SocketAddr)>> {
+        Box::pin(async move {
+            let (stream, addr) = TcpListener::accept(self).await?;
+            Ok((Box::new(stream) as Transport, addr))
+        })
+    }
+}
+
+/// Create a dialer and an acceptor connected in memory, for running a client and a server in
+/// one process without the network.
+///
+/// The dialer ignores the host and port it is given, and every connection comes from
+/// `127.0.0.1:0`.
+pub fn memory() -> (MemoryDialer, MemoryAcceptor) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (MemoryDialer(tx), MemoryAcceptor(Mutex::new(rx)))
+}
+
+/// Dialing end of an in-memory transport, created by [`memory`].
+#[derive(Clone)]
+pub struct MemoryDialer(mpsc::UnboundedSender<Transport>);
+
+impl fmt::Debug for MemoryDialer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryDialer").finish_non_exhaustive()
+    }
+}
+
+impl Dialer for MemoryDialer {
+    fn dial<'a>(&'a self, _host: &'a str, _port: u16) -> BoxFuture<'a, Result<Transport>> {
+        Box::pin(async move {
+            let (client, server) = duplex(MEMORY_BUFFER_SIZE);
+            self.0
+                .send(Box::new(server))
+                .map_err(|_| anyhow!("in-memory server is closed"))?;
+            Ok(Box::new(client) as Transport)
+        })
+    }
+}
+
+/// Accepting end of an in-memory transport, created by [`memory`].
+pub struct MemoryAcceptor(Mutex<mpsc::UnboundedReceiver<Transport>>);
+
+impl Acceptor for MemoryAcceptor {
+    fn accept(&self) -> BoxFuture<'_, Result<(Transport, SocketAddr)>> {
+        Box::pin(async move {
+            let stream = self.0.lock().await.recv().await;
+            let stream = stream.context("all in-memory dialers were dropped")?;
+            Ok((stream, (Ipv4Addr::LOCALHOST, 0).into()))
+        })
+    }
+}
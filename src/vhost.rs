@@ -0,0 +1,320 @@
+//! Routing of visitors on a shared port to named tunnels.
+//!
+//! HTTP requests are routed by the subdomain in their Host header, and TLS connections by the
+//! server name (SNI) in their ClientHello, which is read without terminating TLS.
+
+use anyhow::{bail, Result};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// Longest request head that is read while looking for the Host header.
+pub const MAX_HEAD_LENGTH: usize = 8192;
+
+/// Response sent to visitors whose Host header does not match an open tunnel.
+pub const NOT_FOUND: &[u8] = b"HTTP/1.1 404 Not Found\r\n\
+    Content-Type: text/plain\r\n\
+    Content-Length: 24\r\n\
+    Connection: close\r\n\
+    \r\n\
+    no tunnel for this host\n";
+
+/// Response sent to visitors of a tunnel that is too busy to take the connection.
+pub const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Content-Type: text/plain\r\n\
+    Content-Length: 16\r\n\
+    Connection: close\r\n\
+    \r\n\
+    tunnel is busy.\n";
+
+/// TLS alert sent to visitors whose server name does not match an open tunnel.
+pub const UNRECOGNIZED_NAME: &[u8] = &[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x70];
+
+/// Protocol spoken by visitors on a shared port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Plain HTTP, routed by the Host header.
+    Http,
+
+    /// TLS, routed by the server name in the ClientHello.
+    Tls,
+}
+
+impl Protocol {
+    /// Label for logs and metrics.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Tls => "tls",
+        }
+    }
+
+    /// Read from a visitor until the tunnel name can be found, returning all bytes read.
+    pub async fn read_head(self, stream: &mut TcpStream) -> Result<Vec<u8>> {
+        match self {
+            Self::Http => read_head(stream).await,
+            Self::Tls => read_client_hello(stream).await,
+        }
+    }
+
+    /// Find the tunnel name addressed by the bytes from [`Protocol::read_head`].
+    pub fn route(self, head: &[u8], domain: &str) -> Option<String> {
+        match self {
+            Self::Http => route(head, domain),
+            Self::Tls => name_in(&server_name(head)?, domain),
+        }
+    }
+
+    /// Response for visitors addressing no open tunnel.
+    pub fn not_found(self) -> &'static [u8] {
+        match self {
+            Self::Http => NOT_FOUND,
+            Self::Tls => UNRECOGNIZED_NAME,
+        }
+    }
+
+    /// Response for visitors of a tunnel that is too busy, if the protocol has one.
+    pub fn unavailable(self) -> &'static [u8] {
+        match self {
+            Self::Http => UNAVAILABLE,
+            Self::Tls => &[],
+        }
+    }
+}
+
+/// Check that a tunnel name is a DNS label of lowercase letters, digits, and inner hyphens.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid tunnel name {name:?}, expected lowercase letters, digits, and hyphens"
+        ))
+    }
+}
+
+/// Read from a visitor until the end of the HTTP request head, returning all bytes read.
+pub async fn read_head(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(1024);
+    loop {
+        if head.len() >= MAX_HEAD_LENGTH {
+            bail!("request head is too long");
+        }
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            bail!("connection closed before the request head ended");
+        }
+        // Search from just before the new bytes, in case the terminator was split.
+        let from = head.len().saturating_sub(3);
+        head.extend_from_slice(&buf[..n]);
+        if head[from..].windows(4).any(|window| window == b"\r\n\r\n") {
+            return Ok(head);
+        }
+    }
+}
+
+/// Find the tunnel name addressed by a request head, as the subdomain of `domain`.
+pub fn route(head: &[u8], domain: &str) -> Option<String> {
+    let head = std::str::from_utf8(head).ok()?;
+    let host = head.split("\r\n").skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("host")
+            .then(|| value.trim())
+    })?;
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    };
+    name_in(host, domain)
+}
+
+/// Find the tunnel name in a host name, as the subdomain of `domain`.
+fn name_in(host: &str, domain: &str) -> Option<String> {
+    let host = host.to_ascii_lowercase();
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let name = host
+        .trim_end_matches('.')
+        .strip_suffix(&domain)?
+        .strip_suffix('.')?;
+    validate_name(name).ok()?;
+    Some(name.to_string())
+}
+
+/// Read the TLS records of a visitor's ClientHello, returning all bytes read.
+pub async fn read_client_hello(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut records = Vec::with_capacity(1024);
+    loop {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await?;
+        if header[0] != 0x16 {
+            bail!("connection did not start with a TLS handshake");
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if records.len() + header.len() + length > MAX_HEAD_LENGTH {
+            bail!("ClientHello is too long");
+        }
+        records.extend_from_slice(&header);
+        let start = records.len();
+        records.resize(start + length, 0);
+        stream.read_exact(&mut records[start..]).await?;
+        // The ClientHello may be split across records, so stop once it is complete.
+        let handshake = handshake_bytes(&records);
+        if handshake.len() >= 4 {
+            let hello_length = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            if handshake.len() >= 4 + hello_length as usize {
+                return Ok(records);
+            }
+        }
+    }
+}
+
+/// Join the payloads of consecutive TLS handshake records.
+fn handshake_bytes(mut records: &[u8]) -> Vec<u8> {
+    let mut handshake = Vec::new();
+    while records.len() >= 5 && records[0] == 0x16 {
+        let length = u16::from_be_bytes([records[3], records[4]]) as usize;
+        let end = (5 + length).min(records.len());
+        handshake.extend_from_slice(&records[5..end]);
+        records = &records[end..];
+    }
+    handshake
+}
+
+/// Find the server name (SNI) that a TLS ClientHello asks for.
+pub fn server_name(records: &[u8]) -> Option<String> {
+    let handshake = handshake_bytes(records);
+    let mut hello = Reader(&handshake);
+    if hello.take(1)? != [0x01] {
+        return None;
+    }
+    let mut hello = Reader(hello.vec(3)?);
+    hello.take(2 + 32)?; // legacy version and random
+    hello.vec(1)?; // session ID
+    hello.vec(2)?; // cipher suites
+    hello.vec(1)?; // compression methods
+    let mut extensions = Reader(hello.vec(2)?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.take(2)?;
+        let data = extensions.vec(2)?;
+        if kind != [0x00, 0x00] {
+            continue;
+        }
+        let mut names = Reader(Reader(data).vec(2)?);
+        while !names.0.is_empty() {
+            let name_type = names.take(1)?;
+            let name = names.vec(2)?;
+            if name_type == [0x00] {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Cursor over bytes in a TLS message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Take the next `n` bytes.
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    /// Take a vector of bytes prefixed by its length, in `n` big-endian bytes.
+    fn vec(&mut self, n: usize) -> Option<&'a [u8]> {
+        let length = self
+            .take(n)?
+            .iter()
+            .fold(0, |length, &b| length << 8 | b as usize);
+        self.take(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{route, server_name, validate_name, NOT_FOUND, UNAVAILABLE};
+
+    #[test]
+    fn requests_are_routed_by_subdomain() {
+        let head = |host: &str| format!("GET / HTTP/1.1\r\nHost: {host}\r\nAccept: */*\r\n\r\n");
+        let domain = "tunnel.example.com";
+        let name_for = |host: &str| route(head(host).as_bytes(), domain);
+        assert_eq!(name_for("myapp.tunnel.example.com"), Some("myapp".into()));
+        assert_eq!(
+            name_for("MyApp.Tunnel.Example.com:8080"),
+            Some("myapp".into())
+        );
+        assert_eq!(name_for("tunnel.example.com"), None);
+        assert_eq!(name_for("a.b.tunnel.example.com"), None);
+        assert_eq!(name_for("myapp.example.com"), None);
+        assert_eq!(route(b"GET / HTTP/1.1\r\n\r\n", domain), None);
+
+        assert!(validate_name("my-app2").is_ok());
+        assert!(validate_name("-app").is_err());
+        assert!(validate_name("My_App").is_err());
+        assert!(validate_name("").is_err());
+    }
+
+    #[test]
+    fn server_name_is_read_from_client_hello() {
+        let name = b"myapp.tunnel.example.com";
+        let mut sni = vec![0x00];
+        sni.extend((name.len() as u16).to_be_bytes());
+        sni.extend(name);
+        let mut extension = vec![0x00, 0x00];
+        extension.extend((sni.len() as u16 + 2).to_be_bytes());
+        extension.extend((sni.len() as u16).to_be_bytes());
+        extension.extend(sni);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend([0u8; 32]); // random
+        hello.extend([0x00]); // session ID
+        hello.extend([0x00, 0x02, 0x13, 0x01]); // cipher suites
+        hello.extend([0x01, 0x00]); // compression methods
+        let mut extensions = vec![0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]; // versions
+        extensions.extend(extension);
+        hello.extend((extensions.len() as u16).to_be_bytes());
+        hello.extend(extensions);
+
+        let mut handshake = vec![0x01, 0x00];
+        handshake.extend((hello.len() as u16).to_be_bytes());
+        handshake.extend(hello);
+
+        // Split the ClientHello across two records.
+        let (first, second) = handshake.split_at(20);
+        let mut records = Vec::new();
+        for part in [first, second] {
+            records.extend([0x16, 0x03, 0x01]);
+            records.extend((part.len() as u16).to_be_bytes());
+            records.extend(part);
+        }
+        assert_eq!(
+            server_name(&records).as_deref(),
+            Some("myapp.tunnel.example.com")
+        );
+        assert_eq!(server_name(&records[..30]), None);
+        assert_eq!(server_name(b"GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn responses_have_correct_lengths() {
+        for response in [NOT_FOUND, UNAVAILABLE] {
+            let text = std::str::from_utf8(response).unwrap();
+            let (head, body) = text.split_once("\r\n\r\n").unwrap();
+            assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        }
+    }
+}
@@ -1,28 +1,73 @@
 #![allow(missing_docs)]
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::{get, post, put},
+    extract::{Path, Query, State},
+    http::{header, Extensions, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post, put},
     Json, Router,
 };
-use serde::Serialize;
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
 
-use super::state::{SessionInfo, StateError, TunnelConfig, TunnelInfo, WebState};
+use crate::client::ConnectionInfo;
+
+use crate::logging::LogFilter;
+
+use super::login::{client_ip, session_cookie, too_many_attempts, SESSION_COOKIE};
+use super::state::{
+    ImportMode, LogEntry, LogQuery, ProfileBundle, ProfileCheck, ProfileConfig, ProfileImport,
+    RelayProfile, RemoteServer, RemoteServerConfig, RemoteServerStatus, SessionInfo, StateError,
+    Summary, TunnelConfig, TunnelInfo, TunnelShare, TunnelStats, WebState,
+};
+use super::update;
 
 pub fn router() -> Router<WebState> {
     Router::new()
         .route("/session", get(get_session))
+        .route("/summary", get(get_summary))
+        .route("/version", get(get_version))
+        .route("/log-level", get(get_log_level).put(set_log_level))
+        .route("/metrics/export", get(export_usage))
+        .route("/events", get(stream_events))
         .route("/tunnels", get(list_tunnels).post(create_tunnel))
         .route("/tunnels/:id", put(update_tunnel).delete(delete_tunnel))
         .route("/tunnels/:id/start", post(start_tunnel))
         .route("/tunnels/:id/stop", post(stop_tunnel))
         .route("/tunnels/:id/logs", get(get_logs))
+        .route("/tunnels/:id/logs/search", get(search_logs))
+        .route("/tunnels/:id/logs/download", get(download_logs))
+        .route("/tunnels/:id/stats", get(get_stats))
+        .route("/tunnels/:id/share", get(get_share))
+        .route("/tunnels/:id/connections", get(list_connections))
+        .route(
+            "/tunnels/:id/connections/:connection",
+            delete(close_connection),
+        )
+        .route("/profiles", get(list_profiles).post(create_profile))
+        .route("/profiles/export", get(export_profiles))
+        .route("/profiles/import", post(import_profiles))
+        .route("/profiles/:id", put(update_profile).delete(delete_profile))
+        .route("/profiles/:id/check", post(check_profile))
+        .route("/servers", get(list_servers).post(create_server))
+        .route("/servers/:id", delete(delete_server))
+        .route("/servers/:id/status", get(get_server_status))
+}
+
+/// Routes that stay reachable without a session.
+pub fn login_router() -> Router<WebState> {
+    Router::new()
+        .route("/login", post(login))
+        .route("/logout", post(logout))
 }
 
 #[derive(Debug, Serialize)]
-struct TunnelIdResponse {
+struct IdResponse {
     id: String,
 }
 
@@ -31,6 +76,75 @@ struct AckResponse {
     ok: bool,
 }
 
+#[derive(Debug, Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    /// Newest release upstream, if `--check-updates` is on and the lookup worked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latest_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_available: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevelBody {
+    filter: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginBody {
+    password: String,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DocumentFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileExportQuery {
+    #[serde(default)]
+    format: DocumentFormat,
+    #[serde(default)]
+    secrets: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileImportQuery {
+    #[serde(default)]
+    format: DocumentFormat,
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+/// Traffic totals of one tunnel since it was created.
+#[derive(Debug, Serialize)]
+struct UsageRecord {
+    id: String,
+    name: String,
+    created_at: String,
+    bytes_in: u64,
+    bytes_out: u64,
+    connections: u64,
+}
+
 #[derive(Debug, Serialize)]
 struct ErrorResponse {
     error: String,
@@ -41,10 +155,137 @@ struct LogsResponse {
     logs: Vec<String>,
 }
 
+async fn login(
+    State(state): State<WebState>,
+    extensions: Extensions,
+    Json(body): Json<LoginBody>,
+) -> Result<Response, ApiError> {
+    let Some(login) = state.login() else {
+        return Ok(Json(AckResponse { ok: true }).into_response());
+    };
+    let ip = client_ip(&extensions);
+    if login.is_throttled(ip) {
+        return Ok(too_many_attempts());
+    }
+    let session = login.login(&body.password, ip).ok_or_else(|| ApiError {
+        status: StatusCode::UNAUTHORIZED,
+        message: "invalid password".to_string(),
+    })?;
+    let cookie = format!("{SESSION_COOKIE}={session}; Path=/; HttpOnly; SameSite=Strict");
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(AckResponse { ok: true }),
+    )
+        .into_response())
+}
+
+async fn logout(State(state): State<WebState>, headers: HeaderMap) -> Response {
+    if let (Some(login), Some(session)) = (state.login(), session_cookie(&headers)) {
+        login.logout(session);
+    }
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0");
+    (
+        [(header::SET_COOKIE, cookie)],
+        Json(AckResponse { ok: true }),
+    )
+        .into_response()
+}
+
 async fn get_session(State(state): State<WebState>) -> Json<SessionInfo> {
     Json(state.session().await)
 }
 
+async fn get_summary(State(state): State<WebState>) -> Json<Summary> {
+    Json(state.summary().await)
+}
+
+async fn get_version(State(state): State<WebState>) -> Json<VersionResponse> {
+    let version = env!("CARGO_PKG_VERSION");
+    let latest_version = match state.update_check() {
+        Some(check) => check.latest().await,
+        None => None,
+    };
+    Json(VersionResponse {
+        version,
+        update_available: latest_version
+            .as_deref()
+            .map(|latest| update::is_newer(latest, version)),
+        latest_version,
+    })
+}
+
+async fn get_log_level() -> Result<Json<LogLevelBody>, ApiError> {
+    let filter = log_filter()?.current();
+    Ok(Json(LogLevelBody { filter }))
+}
+
+async fn set_log_level(Json(body): Json<LogLevelBody>) -> Result<Json<AckResponse>, ApiError> {
+    log_filter()?.set(&body.filter).map_err(|err| ApiError {
+        status: StatusCode::BAD_REQUEST,
+        message: format!("invalid log filter: {err}"),
+    })?;
+    Ok(Json(AckResponse { ok: true }))
+}
+
+fn log_filter() -> Result<&'static LogFilter, ApiError> {
+    LogFilter::global().ok_or_else(|| ApiError {
+        status: StatusCode::NOT_FOUND,
+        message: "logging is not managed by this process".to_string(),
+    })
+}
+
+async fn export_usage(State(state): State<WebState>, Query(query): Query<ExportQuery>) -> Response {
+    let records: Vec<_> = state
+        .list_tunnels()
+        .await
+        .into_iter()
+        .map(|tunnel| UsageRecord {
+            id: tunnel.id,
+            name: tunnel.config.name,
+            created_at: tunnel.created_at,
+            bytes_in: tunnel.traffic.totals.bytes_in,
+            bytes_out: tunnel.traffic.totals.bytes_out,
+            connections: tunnel.traffic.totals.total_connections,
+        })
+        .collect();
+    if query.format == ExportFormat::Json {
+        return Json(records).into_response();
+    }
+
+    let mut csv = String::from("id,name,created_at,bytes_in,bytes_out,connections\n");
+    for record in records {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            record.id,
+            csv_field(&record.name),
+            record.created_at,
+            record.bytes_in,
+            record.bytes_out,
+            record.connections
+        ));
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"bore-usage.csv\"",
+            ),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+/// Quote a CSV field if it contains a separator, quote, or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 async fn list_tunnels(State(state): State<WebState>) -> Json<Vec<TunnelInfo>> {
     Json(state.list_tunnels().await)
 }
@@ -54,7 +295,7 @@ async fn create_tunnel(
     Json(config): Json<TunnelConfig>,
 ) -> Result<impl IntoResponse, ApiError> {
     let id = state.create_tunnel(config).await?;
-    Ok((StatusCode::CREATED, Json(TunnelIdResponse { id })))
+    Ok((StatusCode::CREATED, Json(IdResponse { id })))
 }
 
 async fn start_tunnel(
@@ -98,6 +339,190 @@ async fn get_logs(
     Ok(Json(LogsResponse { logs }))
 }
 
+async fn search_logs(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+    Query(query): Query<LogQuery>,
+) -> Result<Json<Vec<LogEntry>>, ApiError> {
+    Ok(Json(state.search_logs(&id, &query).await?))
+}
+
+async fn get_stats(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<TunnelStats>, ApiError> {
+    Ok(Json(state.tunnel_stats(&id).await?))
+}
+
+async fn get_share(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<TunnelShare>, ApiError> {
+    Ok(Json(state.share(&id).await?))
+}
+
+async fn list_connections(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<ConnectionInfo>>, ApiError> {
+    Ok(Json(state.connections(&id).await?))
+}
+
+async fn close_connection(
+    State(state): State<WebState>,
+    Path((id, connection)): Path<(String, Uuid)>,
+) -> Result<Json<AckResponse>, ApiError> {
+    state.close_connection(&id, connection).await?;
+    Ok(Json(AckResponse { ok: true }))
+}
+
+/// Server-sent events for throughput samples, visitor connections and log lines of every
+/// tunnel, as they happen, named `stats`, `connection_opened`, `connection_closed` and `log`.
+async fn stream_events(
+    State(state): State<WebState>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let updates = stream::unfold(state.subscribe(), |mut updates| async move {
+        loop {
+            match updates.recv().await {
+                Ok(update) => {
+                    let event = Event::default().event(update.name()).json_data(&update);
+                    return Some((event, updates));
+                }
+                // A slow reader misses samples rather than holding them up.
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(updates).keep_alive(KeepAlive::default())
+}
+
+async fn download_logs(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Response, ApiError> {
+    let log = state.download_logs(&id).await?;
+    let disposition = format!("attachment; filename=\"bore-{id}.log\"");
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        log,
+    )
+        .into_response())
+}
+
+async fn list_profiles(State(state): State<WebState>) -> Json<Vec<RelayProfile>> {
+    Json(state.list_profiles().await)
+}
+
+async fn create_profile(
+    State(state): State<WebState>,
+    Json(profile): Json<ProfileConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = state.create_profile(profile).await?;
+    Ok((StatusCode::CREATED, Json(IdResponse { id })))
+}
+
+async fn update_profile(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+    Json(profile): Json<ProfileConfig>,
+) -> Result<Json<AckResponse>, ApiError> {
+    state.update_profile(&id, profile).await?;
+    Ok(Json(AckResponse { ok: true }))
+}
+
+async fn delete_profile(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<AckResponse>, ApiError> {
+    state.delete_profile(&id).await?;
+    Ok(Json(AckResponse { ok: true }))
+}
+
+async fn export_profiles(
+    State(state): State<WebState>,
+    Query(query): Query<ProfileExportQuery>,
+) -> Response {
+    let bundle = state.export_profiles(query.secrets).await;
+    let (body, content_type, extension) = match query.format {
+        DocumentFormat::Json => (
+            serde_json::to_string_pretty(&bundle).expect("profiles should serialize"),
+            "application/json",
+            "json",
+        ),
+        DocumentFormat::Yaml => (
+            serde_yaml::to_string(&bundle).expect("profiles should serialize"),
+            "application/yaml",
+            "yaml",
+        ),
+    };
+    let disposition = format!("attachment; filename=\"bore-profiles.{extension}\"");
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+async fn import_profiles(
+    State(state): State<WebState>,
+    Query(query): Query<ProfileImportQuery>,
+    body: String,
+) -> Result<Json<ProfileImport>, ApiError> {
+    let bundle: ProfileBundle = match query.format {
+        DocumentFormat::Json => serde_json::from_str(&body).map_err(|err| err.to_string()),
+        DocumentFormat::Yaml => serde_yaml::from_str(&body).map_err(|err| err.to_string()),
+    }
+    .map_err(|err| ApiError {
+        status: StatusCode::BAD_REQUEST,
+        message: format!("invalid profiles document: {err}"),
+    })?;
+    Ok(Json(state.import_profiles(bundle, query.mode).await?))
+}
+
+async fn check_profile(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<ProfileCheck>, ApiError> {
+    Ok(Json(state.check_profile(&id).await?))
+}
+
+async fn list_servers(State(state): State<WebState>) -> Json<Vec<RemoteServer>> {
+    Json(state.list_servers().await)
+}
+
+async fn create_server(
+    State(state): State<WebState>,
+    Json(server): Json<RemoteServerConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    let id = state.create_server(server).await?;
+    Ok((StatusCode::CREATED, Json(IdResponse { id })))
+}
+
+async fn delete_server(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<AckResponse>, ApiError> {
+    state.delete_server(&id).await?;
+    Ok(Json(AckResponse { ok: true }))
+}
+
+async fn get_server_status(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+) -> Result<Json<RemoteServerStatus>, ApiError> {
+    Ok(Json(state.server_status(&id).await?))
+}
+
 struct ApiError {
     status: StatusCode,
     message: String,
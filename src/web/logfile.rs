@@ -0,0 +1,110 @@
+//! Size-rotated log files for web console tunnels.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Settings for writing each tunnel's log to its own file.
+#[derive(Debug, Clone)]
+pub struct LogFiles {
+    dir: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl LogFiles {
+    /// Write logs to `dir`, rotating a tunnel's file once it exceeds `max_bytes`.
+    ///
+    /// At most `keep` rotated files are kept per tunnel, named `<id>.log.1` (newest) and up.
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            max_bytes,
+            keep,
+        })
+    }
+
+    /// Path of the current log file of a tunnel.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.log"))
+    }
+
+    /// Append one line to a tunnel's log file, rotating it first if it is full.
+    pub fn append(&self, id: &str, line: &str) -> io::Result<()> {
+        let path = self.path(id);
+        let len = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        if len > 0 && len + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate(&path)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{line}")
+    }
+
+    /// Read a tunnel's whole log, rotated files included, oldest line first.
+    pub fn read(&self, id: &str) -> io::Result<String> {
+        let path = self.path(id);
+        let mut log = String::new();
+        for index in (1..=self.keep).rev() {
+            match fs::read_to_string(format!("{}.{index}", path.display())) {
+                Ok(contents) => log.push_str(&contents),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        match fs::read_to_string(&path) {
+            Ok(contents) => log.push_str(&contents),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(log)
+    }
+
+    fn rotate(&self, path: &Path) -> io::Result<()> {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{index}", path.display()));
+        if self.keep == 0 {
+            return fs::remove_file(path);
+        }
+        match fs::remove_file(rotated(self.keep)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        for index in (1..self.keep).rev() {
+            match fs::rename(rotated(index), rotated(index + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        fs::rename(path, rotated(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogFiles;
+
+    #[test]
+    fn log_files_rotate_and_keep_limited_history() {
+        let dir = std::env::temp_dir().join(format!("bore-logs-{}", uuid::Uuid::new_v4()));
+        let logs = LogFiles::new(&dir, 16, 2).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            logs.append("tunnel", line).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("tunnel.log"), "fourth line\n");
+        assert_eq!(read("tunnel.log.1"), "third line\n");
+        assert_eq!(read("tunnel.log.2"), "second line\n");
+        assert!(!dir.join("tunnel.log.3").exists());
+        assert_eq!(
+            logs.read("tunnel").unwrap(),
+            "second line\nthird line\nfourth line\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Password login for the web console API.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, Extensions, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::WebState;
+use crate::auth::Authenticator;
+use crate::limit::{RateLimit, RateLimiter};
+
+/// Name of the cookie holding a browser's session ID.
+pub const SESSION_COOKIE: &str = "bore_session";
+
+/// Number of browser sessions kept before the oldest is logged out.
+const MAX_SESSIONS: usize = 32;
+
+/// Wrong passwords accepted from one address in a burst, and per second after that.
+const FAILURE_LIMIT: RateLimit = RateLimit {
+    rate: 0.1,
+    burst: 5.0,
+};
+
+/// Password required to use the API, and the sessions of browsers that entered it.
+pub struct Login {
+    password: Authenticator,
+    sessions: Mutex<VecDeque<String>>,
+    failures: RateLimiter<IpAddr>,
+}
+
+impl fmt::Debug for Login {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Login").finish_non_exhaustive()
+    }
+}
+
+impl Login {
+    /// Require this password for API requests.
+    pub fn new(password: &str) -> Self {
+        Self {
+            password: Authenticator::new(password),
+            sessions: Mutex::new(VecDeque::new()),
+            failures: RateLimiter::new(FAILURE_LIMIT),
+        }
+    }
+
+    /// Generate a random password, for consoles that are reachable from other machines.
+    pub fn generate_password() -> String {
+        Uuid::new_v4().simple().to_string()
+    }
+
+    /// Start a session if the password is correct, returning its ID.
+    ///
+    /// Wrong passwords count against `ip`, see [`Login::is_throttled`].
+    pub fn login(&self, password: &str, ip: IpAddr) -> Option<String> {
+        if !self.check_password(password, ip) {
+            return None;
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.push_back(id.clone());
+        while sessions.len() > MAX_SESSIONS {
+            sessions.pop_front();
+        }
+        Some(id)
+    }
+
+    /// End a session.
+    pub fn logout(&self, id: &str) {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|session| session != id);
+    }
+
+    /// Whether `ip` sent too many wrong passwords recently, and must wait before trying again.
+    pub fn is_throttled(&self, ip: IpAddr) -> bool {
+        self.failures.is_exhausted(&ip)
+    }
+
+    /// Check a request for a session cookie or an `Authorization: Bearer <password>` header.
+    ///
+    /// A wrong password counts against `ip`, like a failed login.
+    pub fn is_authorized(&self, headers: &HeaderMap, ip: IpAddr) -> bool {
+        if let Some(id) = session_cookie(headers) {
+            if self
+                .sessions
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|session| session == id)
+            {
+                return true;
+            }
+        }
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|password| self.check_password(password, ip))
+    }
+
+    fn check_password(&self, password: &str, ip: IpAddr) -> bool {
+        // Compare MACs rather than strings, so the check takes constant time.
+        let challenge = Uuid::nil();
+        let tag = Authenticator::new(password).answer(&challenge);
+        let valid = self.password.validate(&challenge, &tag);
+        if !valid {
+            self.failures.prune();
+            let _ = self.failures.try_acquire(ip);
+        }
+        valid
+    }
+}
+
+/// Address of the client that sent a request, or the unspecified address if it is not known.
+pub fn client_ip(extensions: &Extensions) -> IpAddr {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |info| info.0.ip())
+}
+
+/// Response to a client that must wait before trying another password.
+pub fn too_many_attempts() -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({ "error": "too many failed login attempts, try again later" })),
+    )
+        .into_response()
+}
+
+/// Get the session ID from the cookies of a request.
+pub fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Reject API requests without a valid session when a password is set.
+pub async fn require_login(
+    State(state): State<WebState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = client_ip(request.extensions());
+    match state.login() {
+        Some(login) if login.is_throttled(ip) => too_many_attempts(),
+        Some(login) if !login.is_authorized(request.headers(), ip) => (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "login required" })),
+        )
+            .into_response(),
+        _ => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::Login;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn sessions_and_bearer_passwords_are_authorized() {
+        let login = Login::new("hunter2");
+        assert!(login.login("wrong", IP).is_none());
+        let session = login
+            .login("hunter2", IP)
+            .expect("password should be accepted");
+
+        let mut headers = HeaderMap::new();
+        assert!(!login.is_authorized(&headers, IP));
+        let cookie = format!("theme=dark; bore_session={session}");
+        headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+        assert!(login.is_authorized(&headers, IP));
+
+        login.logout(&session);
+        assert!(!login.is_authorized(&headers, IP));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer hunter2"),
+        );
+        assert!(login.is_authorized(&headers, IP));
+    }
+
+    #[test]
+    fn wrong_passwords_throttle_the_address() {
+        let login = Login::new("hunter2");
+        for _ in 0..5 {
+            assert!(!login.is_throttled(IP));
+            assert!(login.login("wrong", IP).is_none());
+        }
+        assert!(login.is_throttled(IP));
+        assert!(!login.is_throttled("203.0.113.7".parse().unwrap()));
+    }
+}
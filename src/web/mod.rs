@@ -4,22 +4,42 @@ use std::{future::Future, net::SocketAddr};
 
 use anyhow::{anyhow, Result};
 use axum::{
+    extract::ConnectInfo,
+    middleware,
     response::{Html, IntoResponse},
     routing::get,
-    Router,
+    Extension, Router,
 };
+use hyper::server::conn::http1;
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
 use tokio::{net::TcpListener, sync::oneshot};
+use tracing::{debug, warn};
+
+use crate::tls::ServerTls;
 
 /// HTTP API routes and handlers.
 pub mod api;
+pub mod logfile;
+pub mod login;
+pub mod origin;
+pub mod remote;
 /// In-memory state and tunnel metadata.
 pub mod state;
+pub mod store;
 pub mod tunnel;
+pub mod update;
 
+pub use logfile::LogFiles;
+pub use login::Login;
 pub use state::{
-    is_loopback_host, SessionInfo, SessionMode, SystemTunnelRole, SystemTunnelSpec, TunnelConfig,
-    TunnelInfo, TunnelKind, TunnelStatus, WebState,
+    is_loopback_host, ImportMode, LogEntry, LogLevel, LogQuery, ProfileBundle, ProfileCheck,
+    ProfileConfig, ProfileImport, RelayProfile, RemoteServer, RemoteServerConfig,
+    RemoteServerStatus, RestartMode, RestartPolicy, SessionInfo, SessionMode, SystemTunnelRole,
+    SystemTunnelSpec, TrafficSample, TunnelConfig, TunnelHealth, TunnelInfo, TunnelKind,
+    TunnelShare, TunnelStats, TunnelStatus, WebEvent, WebState,
 };
+pub use store::Store;
+pub use update::UpdateCheck;
 
 const INDEX_HTML: &str = include_str!("static/index.html");
 const APP_JS: &str = include_str!("static/app.js");
@@ -32,20 +52,18 @@ pub struct ServeConfig {
     pub addr: SocketAddr,
     /// Session metadata exposed through `/api/session`.
     pub session: SessionInfo,
+    /// Serve over HTTPS with these TLS settings, instead of plain HTTP.
+    pub tls: Option<ServerTls>,
 }
 
 struct BoundWebListener {
     listener: TcpListener,
     addr: SocketAddr,
+    tls: Option<ServerTls>,
 }
 
 /// Starts local web console server.
 pub async fn serve(config: ServeConfig) -> Result<()> {
-    if !config.addr.ip().is_loopback() {
-        eprintln!(
-            "WARNING: Web console is exposed on a non-loopback address. This version has no authentication."
-        );
-    }
     let state = WebState::new(config.session.clone());
     serve_with_state(config, state, tokio::signal::ctrl_c()).await
 }
@@ -55,19 +73,27 @@ pub async fn serve_with_state<S>(config: ServeConfig, state: WebState, shutdown:
 where
     S: Future<Output = std::result::Result<(), std::io::Error>> + Send + 'static,
 {
-    let bound = bind_web_listener(config.addr).await?;
+    if !config.addr.ip().is_loopback() && state.login().is_none() {
+        eprintln!("WARNING: Web console is exposed on a non-loopback address without a password.");
+    }
+    let bound = bind_web_listener(config.addr, config.tls).await?;
     serve_bound(bound, state, async move {
         let _ = shutdown.await;
     })
     .await
 }
 
-async fn bind_web_listener(addr: SocketAddr) -> Result<BoundWebListener> {
+async fn bind_web_listener(addr: SocketAddr, tls: Option<ServerTls>) -> Result<BoundWebListener> {
+    let scheme = if tls.is_some() { "https" } else { "http" };
     match TcpListener::bind(addr).await {
         Ok(listener) => {
             let addr = listener.local_addr()?;
-            println!("Bore web console listening on http://{addr}");
-            Ok(BoundWebListener { listener, addr })
+            println!("Bore web console listening on {scheme}://{addr}");
+            Ok(BoundWebListener {
+                listener,
+                addr,
+                tls,
+            })
         }
         Err(primary_err) if addr.port() != 0 => {
             let fallback_addr = SocketAddr::new(addr.ip(), 0);
@@ -75,10 +101,11 @@ async fn bind_web_listener(addr: SocketAddr) -> Result<BoundWebListener> {
                 Ok(listener) => {
                     let actual_addr = listener.local_addr()?;
                     eprintln!("Failed to bind web console on {addr}: {primary_err}");
-                    println!("Bore web console switched to http://{actual_addr}");
+                    println!("Bore web console switched to {scheme}://{actual_addr}");
                     Ok(BoundWebListener {
                         listener,
                         addr: actual_addr,
+                        tls,
                     })
                 }
                 Err(fallback_err) => Err(anyhow!(
@@ -91,16 +118,53 @@ async fn bind_web_listener(addr: SocketAddr) -> Result<BoundWebListener> {
     }
 }
 
-async fn serve_bound<S>(bound: BoundWebListener, state: WebState, shutdown: S) -> Result<()>
+async fn serve_bound<S>(bound: BoundWebListener, mut state: WebState, shutdown: S) -> Result<()>
 where
     S: Future<Output = ()> + Send + 'static,
 {
-    axum::serve(bound.listener, router(state))
-        .with_graceful_shutdown(async move {
-            shutdown.await;
-        })
-        .await?;
-    Ok(())
+    state.set_web_addr(bound.addr);
+    let Some(tls) = bound.tls else {
+        let service = router(state).into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(bound.listener, service)
+            .with_graceful_shutdown(async move {
+                shutdown.await;
+            })
+            .await?;
+        return Ok(());
+    };
+
+    // axum only serves plain TCP, so TLS connections are handed to hyper directly.
+    let router = router(state);
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = bound.listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(%err, "failed to accept web console connection");
+                    continue;
+                }
+            },
+            () = &mut shutdown => return Ok(()),
+        };
+        let tls = tls.clone();
+        let service = TowerToHyperService::new(router.clone().layer(Extension(ConnectInfo(addr))));
+        tokio::spawn(async move {
+            let stream = match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!(%addr, "web console {err:#}");
+                    return;
+                }
+            };
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(%addr, %err, "web console connection failed");
+            }
+        });
+    }
 }
 
 /// Runs web console plus required system tunnels.
@@ -113,7 +177,7 @@ pub async fn run_managed<S>(
 where
     S: Future<Output = ()> + Send + 'static,
 {
-    let bound = bind_web_listener(config.addr).await?;
+    let bound = bind_web_listener(config.addr, config.tls).await?;
     let web_port = bound.addr.port();
     let mut system_ids = Vec::with_capacity(system_tunnels.len());
     for mut spec in system_tunnels {
@@ -181,7 +245,19 @@ pub fn router(state: WebState) -> Router {
         .route("/", get(index))
         .route("/app.js", get(app_js))
         .route("/style.css", get(style_css))
-        .nest("/api", api::router())
+        .nest(
+            "/api",
+            api::router()
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    login::require_login,
+                ))
+                .merge(api::login_router())
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    origin::require_same_origin,
+                )),
+        )
         .with_state(state)
 }
 
@@ -216,7 +292,7 @@ mod tests {
         let occupied = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let occupied_addr = occupied.local_addr().unwrap();
 
-        let bound = bind_web_listener(occupied_addr).await.unwrap();
+        let bound = bind_web_listener(occupied_addr, None).await.unwrap();
 
         assert_eq!(bound.addr.ip(), occupied_addr.ip());
         assert_ne!(bound.addr.port(), occupied_addr.port());
@@ -0,0 +1,150 @@
+//! Protection of the web console API against requests from other sites.
+//!
+//! Browsers send an `Origin` header with every cross-origin request, and with every `POST`,
+//! `PUT` and `DELETE`. A request whose origin is not the console itself, or one of the allowed
+//! origins, is rejected, which also keeps other sites from reading the event stream. Requests
+//! without an `Origin` header, like those from scripts, are let through, unless the browser
+//! marks a state-changing request as cross-site with `Sec-Fetch-Site`.
+//!
+//! Comparing the origin with the `Host` header does not stop DNS rebinding, where a site points
+//! its own name at the console so that its pages become same-origin. So requests must also be
+//! sent to a loopback name or address, the address the console listens on, the console's
+//! remote URL, or the host of an allowed origin. When the console listens on all interfaces, any
+//! IP address is accepted, since a rebinding attack always arrives under the attacker's name.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, uri::Authority, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use super::WebState;
+
+/// Reject API requests made by pages on other sites.
+pub async fn require_same_origin(
+    State(state): State<WebState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut known = state.allowed_origins().to_vec();
+    known.extend(state.session().await.web_remote_url);
+    let headers = request.headers();
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().authority().map(Authority::as_str));
+    // Browsers always send a host, so requests without one are not a rebinding attack.
+    let host_allowed = host.is_none_or(|host| is_allowed_host(host, state.web_addr(), &known));
+    let origin_allowed = match headers.get(header::ORIGIN) {
+        Some(origin) => origin
+            .to_str()
+            .is_ok_and(|origin| is_allowed(origin, headers, state.allowed_origins())),
+        None => request.method().is_safe() || !is_cross_site(headers),
+    };
+    if host_allowed && origin_allowed {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "cross-origin request rejected" })),
+        )
+            .into_response()
+    }
+}
+
+/// Whether `origin` is the host the request was sent to, or one of the allowed origins.
+fn is_allowed(origin: &str, headers: &HeaderMap, allowed: &[String]) -> bool {
+    let origin = normalize_origin(origin);
+    if allowed.contains(&origin) {
+        return true;
+    }
+    let Some((_, authority)) = origin.split_once("://") else {
+        // Sandboxed pages and local files send `null`.
+        return false;
+    };
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
+
+/// Whether `host` names the console, rather than a domain that another site controls.
+fn is_allowed_host(host: &str, web_addr: Option<SocketAddr>, origins: &[String]) -> bool {
+    let name = host_name(host);
+    if name.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    if let Ok(ip) = name.parse::<IpAddr>() {
+        return ip.is_loopback()
+            || web_addr.is_some_and(|addr| addr.ip().is_unspecified() || addr.ip() == ip);
+    }
+    origins
+        .iter()
+        .filter_map(|origin| origin.split_once("://"))
+        .any(|(_, authority)| host_name(authority).eq_ignore_ascii_case(name))
+}
+
+/// The name or address in a `Host` header or origin, without the port or IPv6 brackets.
+fn host_name(authority: &str) -> &str {
+    match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => authority.split(':').next().unwrap_or(authority),
+    }
+}
+
+fn is_cross_site(headers: &HeaderMap) -> bool {
+    headers
+        .get("sec-fetch-site")
+        .is_some_and(|site| site.as_bytes() == b"cross-site")
+}
+
+/// Lowercase an origin and drop a trailing slash, so that allowed origins compare equal.
+pub fn normalize_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::{is_allowed, is_allowed_host, normalize_origin};
+
+    #[test]
+    fn origins_must_match_the_host_or_be_allowed() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("127.0.0.1:7836"));
+        let allowed = vec![normalize_origin("https://Console.example.com/")];
+
+        assert!(is_allowed("http://127.0.0.1:7836", &headers, &allowed));
+        assert!(is_allowed(
+            "https://console.example.com",
+            &headers,
+            &allowed
+        ));
+        assert!(!is_allowed("http://127.0.0.1:9000", &headers, &allowed));
+        assert!(!is_allowed("http://evil.example", &headers, &allowed));
+        assert!(!is_allowed("null", &headers, &allowed));
+        assert!(!is_allowed("http://127.0.0.1:7836", &HeaderMap::new(), &[]));
+    }
+
+    #[test]
+    fn hosts_must_name_the_console() {
+        let allowed = vec![normalize_origin("https://console.example.com")];
+        let lan = Some("192.168.1.5:7836".parse().unwrap());
+        let any = Some("0.0.0.0:7836".parse().unwrap());
+
+        assert!(is_allowed_host("localhost:7836", None, &[]));
+        assert!(is_allowed_host("127.0.0.1:7836", None, &[]));
+        assert!(is_allowed_host("[::1]:7836", None, &[]));
+        assert!(is_allowed_host("192.168.1.5:7836", lan, &[]));
+        assert!(!is_allowed_host("192.168.1.6:7836", lan, &[]));
+        assert!(is_allowed_host("192.168.1.6:7836", any, &[]));
+        assert!(is_allowed_host("Console.example.com", any, &allowed));
+        assert!(!is_allowed_host("evil.example:7836", any, &allowed));
+    }
+}
@@ -0,0 +1,182 @@
+//! Client for the admin API of remote bore servers, watched from the web console.
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    body::Bytes,
+    http::{header, Request, StatusCode, Uri},
+};
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::server::{HealthStatus, TunnelInfo};
+use crate::shared::NETWORK_TIMEOUT;
+use crate::tls::{ClientTls, Transport};
+
+/// Largest admin API response that is read.
+const MAX_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Admin API of one server, at a base URL like `http://relay.example.com:7837`.
+#[derive(Debug, Clone)]
+pub struct AdminClient {
+    https: bool,
+    host: String,
+    port: u16,
+    authority: String,
+    prefix: String,
+    token: Option<String>,
+}
+
+impl AdminClient {
+    /// Use the admin API at `url`, which must be `http` or `https`, sending `token` if set.
+    pub fn new(url: &str, token: Option<String>) -> Result<Self> {
+        let uri: Uri = url.parse().with_context(|| format!("invalid URL {url}"))?;
+        let https = match uri.scheme_str() {
+            Some("http") => false,
+            Some("https") => true,
+            _ => bail!("URL must start with http:// or https://"),
+        };
+        let authority = uri.authority().context("URL must include a host")?;
+        let host = authority.host().trim_matches(['[', ']']).to_string();
+        let port = authority.port_u16().unwrap_or(if https { 443 } else { 80 });
+        Ok(Self {
+            https,
+            host,
+            port,
+            authority: authority.to_string(),
+            prefix: uri.path().trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    /// Health of the server, which is also reported while it is not ready.
+    pub async fn health(&self) -> Result<HealthStatus> {
+        let (status, body) = self.fetch("/healthz").await?;
+        if status != StatusCode::SERVICE_UNAVAILABLE {
+            check_status(status, &body)?;
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Tunnels open on the server.
+    pub async fn tunnels(&self) -> Result<Vec<TunnelInfo>> {
+        self.get("/api/tunnels").await
+    }
+
+    /// Tag of the newest release, if the URL is a repository in GitHub's REST API.
+    pub async fn latest_release(&self) -> Result<String> {
+        let release: Value = self.get("/releases/latest").await?;
+        release["tag_name"]
+            .as_str()
+            .map(str::to_string)
+            .context("release has no tag name")
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let (status, body) = self.fetch(path).await?;
+        check_status(status, &body)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn fetch(&self, path: &str) -> Result<(StatusCode, Bytes)> {
+        timeout(NETWORK_TIMEOUT, self.send(path))
+            .await
+            .with_context(|| format!("timed out waiting for {}", self.authority))?
+    }
+
+    async fn send(&self, path: &str) -> Result<(StatusCode, Bytes)> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("could not connect to {}", self.authority))?;
+        let stream: Transport = if self.https {
+            ClientTls::new(None)?.connect(&self.host, stream).await?
+        } else {
+            Box::new(stream)
+        };
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+
+        let mut request = Request::get(format!("{}{path}", self.prefix))
+            .header(header::HOST, &self.authority)
+            .header(
+                header::USER_AGENT,
+                concat!("bore/", env!("CARGO_PKG_VERSION")),
+            );
+        if let Some(token) = &self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let response = sender
+            .send_request(request.body(Empty::<Bytes>::new())?)
+            .await?;
+        let status = response.status();
+        let body = Limited::new(response.into_body(), MAX_RESPONSE_SIZE)
+            .collect()
+            .await
+            .map_err(|err| anyhow!("could not read response from {}: {err}", self.authority))?
+            .to_bytes();
+        Ok((status, body))
+    }
+}
+
+/// Fail unless `status` is a success, with the error message of the admin API if it has one.
+fn check_status(status: StatusCode, body: &[u8]) -> Result<()> {
+    if status.is_success() {
+        return Ok(());
+    }
+    let message = serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|body| body["error"].as_str().map(str::to_string));
+    match message {
+        Some(message) => bail!("{status}: {message}"),
+        None => bail!("{status}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{AdminClient, MAX_RESPONSE_SIZE};
+
+    #[test]
+    fn base_urls_are_parsed() {
+        let client = AdminClient::new("https://relay.example.com/admin/", None).unwrap();
+        assert!(client.https);
+        assert_eq!(
+            (client.host.as_str(), client.port),
+            ("relay.example.com", 443)
+        );
+        assert_eq!(client.prefix, "/admin");
+
+        let client = AdminClient::new("http://[::1]:7837", None).unwrap();
+        assert_eq!((client.host.as_str(), client.port), ("::1", 7837));
+        assert_eq!(client.authority, "[::1]:7837");
+        assert_eq!(client.prefix, "");
+
+        assert!(AdminClient::new("relay.example.com:7837", None).is_err());
+        assert!(AdminClient::new("ftp://relay.example.com", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).await;
+            let length = MAX_RESPONSE_SIZE + 1;
+            let header = format!("HTTP/1.1 200 OK\r\ncontent-length: {length}\r\n\r\n");
+            let _ = stream.write_all(header.as_bytes()).await;
+            let _ = stream.write_all(&vec![b' '; length]).await;
+        });
+
+        let client = AdminClient::new(&format!("http://{addr}"), None).unwrap();
+        let err = client.tunnels().await.unwrap_err();
+        assert!(err.to_string().contains("length limit exceeded"), "{err}");
+    }
+}
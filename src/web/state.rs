@@ -1,28 +1,55 @@
 #![allow(missing_docs)]
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
     fmt,
-    net::IpAddr,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
     str::FromStr,
-    sync::Arc,
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use axum::http::StatusCode;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::{
-    sync::{mpsc, oneshot, Mutex, RwLock},
+    net::TcpStream,
+    sync::{broadcast, mpsc, oneshot, Mutex, RwLock},
     task::JoinHandle,
-    time::sleep,
+    time::{interval, sleep, timeout, MissedTickBehavior},
 };
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::client::{run_local, LocalArgs, TunnelEvent};
+use super::logfile::LogFiles;
+use super::login::Login;
+use super::origin::normalize_origin;
+use super::remote::AdminClient;
+use super::store::{SavedProfile, SavedServer, SavedState, SavedTunnel, Store};
+use super::update::UpdateCheck;
+use crate::auth::Authenticator;
+use crate::client::{run_local_with_stats, ConnectionInfo, Connections, LocalArgs, TunnelEvent};
+use crate::server::{self, HealthStatus};
+use crate::shared::{ClientMessage, Delimited, ServerMessage, CONTROL_PORT, NETWORK_TIMEOUT};
+use crate::stats::{TrafficSnapshot, TrafficStats};
 
 const MAX_LOG_LINES: usize = 500;
+const MAX_RECENT_ERRORS: usize = 20;
+const SUMMARY_ERRORS: usize = 10;
+const SUMMARY_TOP_TUNNELS: usize = 5;
 const POLL_DELAY: Duration = Duration::from_millis(50);
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Samples kept per tunnel for graphs: five minutes at one per second.
+const MAX_TRAFFIC_SAMPLES: usize = 300;
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+const FLAPPING_FAILURES: u32 = 3;
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
 
 /// Tunnel configuration accepted by the web API.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +60,52 @@ pub struct TunnelConfig {
     pub port: Option<u16>,
     pub local_host: String,
     pub secret: Option<String>,
+    /// IDs of tunnels that must be running before this one starts.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Relay profile supplying the server address and secret.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// When to restart the tunnel after it exits on its own.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+}
+
+/// When a tunnel that exits without being stopped is started again.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartMode {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Restart settings of a tunnel.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RestartPolicy {
+    #[serde(default)]
+    pub mode: RestartMode,
+    /// Restarts allowed before giving up, counted since the tunnel last came up.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Delay before the first restart, doubled for each further attempt.
+    #[serde(default = "default_backoff_secs")]
+    pub backoff_secs: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RestartMode::Never,
+            max_retries: None,
+            backoff_secs: default_backoff_secs(),
+        }
+    }
+}
+
+fn default_backoff_secs() -> u64 {
+    1
 }
 
 /// Public tunnel configuration returned by the web API.
@@ -43,6 +116,9 @@ pub struct PublicTunnelConfig {
     pub to: String,
     pub port: Option<u16>,
     pub local_host: String,
+    pub depends_on: Vec<String>,
+    pub profile: Option<String>,
+    pub restart: RestartPolicy,
 }
 
 /// Tunnel lifecycle state.
@@ -91,6 +167,105 @@ impl SessionInfo {
     }
 }
 
+/// Throughput in bytes per second, sampled while a tunnel runs.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TrafficRate {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Traffic counters of a tunnel returned by the web API.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct TunnelTraffic {
+    #[serde(flatten)]
+    pub totals: TrafficSnapshot,
+    pub rate: TrafficRate,
+}
+
+/// Throughput of a tunnel over one sampling interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficSample {
+    pub at: String,
+    #[serde(flatten)]
+    pub rate: TrafficRate,
+    pub active_connections: u64,
+}
+
+/// Recent throughput of a tunnel returned by the web API, oldest sample first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelStats {
+    pub id: String,
+    pub totals: TrafficSnapshot,
+    pub samples: Vec<TrafficSample>,
+}
+
+/// Public address of a running tunnel, for opening it elsewhere.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelShare {
+    pub id: String,
+    /// Server host and public port, like `bore.pub:4000`.
+    pub address: String,
+    pub url: String,
+}
+
+/// Live change to a tunnel, sent to event subscribers. `id` is the tunnel's.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum WebEvent {
+    /// A new throughput sample was taken.
+    Stats {
+        id: String,
+        #[serde(flatten)]
+        sample: TrafficSample,
+    },
+    /// A visitor connected to the tunnel's public port.
+    ConnectionOpened {
+        id: String,
+        connection: Uuid,
+        peer: Option<SocketAddr>,
+    },
+    /// A visitor connection ended, with the bytes it transferred.
+    ConnectionClosed {
+        id: String,
+        connection: Uuid,
+        error: Option<String>,
+        bytes_in: u64,
+        bytes_out: u64,
+    },
+    /// A line was added to the tunnel's log.
+    Log {
+        id: String,
+        at: String,
+        message: String,
+    },
+    /// The tunnel failed often enough in a row to be considered flapping.
+    TunnelFlapping {
+        id: String,
+        consecutive_failures: u32,
+    },
+}
+
+impl WebEvent {
+    /// Name of the event, as sent in server-sent events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Stats { .. } => "stats",
+            Self::ConnectionOpened { .. } => "connection_opened",
+            Self::ConnectionClosed { .. } => "connection_closed",
+            Self::Log { .. } => "log",
+            Self::TunnelFlapping { .. } => "tunnel_flapping",
+        }
+    }
+}
+
+/// Restart and failure counters of a tunnel.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct TunnelHealth {
+    pub restarts: u32,
+    pub consecutive_failures: u32,
+    pub flapping: bool,
+}
+
 /// Tunnel information returned by the web API.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TunnelInfo {
@@ -105,6 +280,162 @@ pub struct TunnelInfo {
     pub display_url: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    pub traffic: TunnelTraffic,
+    pub health: TunnelHealth,
+}
+
+/// Number of tunnels in each lifecycle state.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StatusCounts {
+    pub stopped: usize,
+    pub starting: usize,
+    pub running: usize,
+    pub failed: usize,
+}
+
+/// A tunnel failure recorded for the dashboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentError {
+    pub id: String,
+    pub name: String,
+    pub error: String,
+    pub at: String,
+}
+
+/// Traffic totals of one tunnel, for ranking the busiest tunnels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopTunnel {
+    pub id: String,
+    pub name: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Aggregated dashboard overview returned by the web API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub total: usize,
+    pub by_status: StatusCounts,
+    pub rate: TrafficRate,
+    pub recent_errors: Vec<RecentError>,
+    pub top_tunnels: Vec<TopTunnel>,
+}
+
+/// Named relay server settings that tunnels can share.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// Relay profiles exported from one console, to import into another.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileBundle {
+    pub profiles: Vec<ProfileConfig>,
+}
+
+/// What importing a relay profile does when one with the same name is saved.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keep the saved profile and skip the imported one.
+    #[default]
+    Merge,
+    /// Replace the saved profile with the imported one.
+    Overwrite,
+}
+
+/// How many relay profiles an import created, replaced and skipped.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProfileImport {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+/// Relay profile returned by the web API, without its secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayProfile {
+    pub id: String,
+    pub name: String,
+    pub to: String,
+    pub has_secret: bool,
+}
+
+/// Remote bore server whose admin API the console watches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteServerConfig {
+    pub name: String,
+    /// Base URL of the admin API, like `http://relay.example.com:7837`.
+    pub url: String,
+    /// Admin token, if the admin API requires one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// Remote server returned by the web API, without its admin token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteServer {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    pub has_token: bool,
+}
+
+/// Live state of a remote server, read from its admin API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteServerStatus {
+    pub health: HealthStatus,
+    pub tunnels: Vec<server::TunnelInfo>,
+    /// Public ports taken by tunnels, in ascending order.
+    pub ports: Vec<u16>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub active_connections: usize,
+}
+
+/// Result of checking that a relay profile can reach its server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileCheck {
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Severity of a tunnel log line, derived from its message.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+/// Filters for searching the buffered logs of a tunnel.
+///
+/// `q` matches as a substring, or as a regular expression if `regex` is set.
+/// `level` keeps lines of at least that severity, and `since` and `until` are
+/// RFC 3339 timestamps excluding older and newer lines. `limit` keeps only the
+/// newest matches.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LogQuery {
+    pub q: Option<String>,
+    #[serde(default)]
+    pub regex: bool,
+    pub level: Option<LogLevel>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// A log line matched by a search.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub at: String,
+    pub level: LogLevel,
+    pub message: String,
 }
 
 /// Static spec for system tunnels.
@@ -127,6 +458,17 @@ pub enum SystemTunnelRole {
 pub struct WebState {
     tunnels: Arc<RwLock<HashMap<String, Arc<Mutex<TunnelRuntime>>>>>,
     session: Arc<RwLock<SessionInfo>>,
+    errors: Arc<Mutex<VecDeque<RecentError>>>,
+    profiles: Arc<RwLock<HashMap<String, ProfileConfig>>>,
+    servers: Arc<RwLock<HashMap<String, RemoteServerConfig>>>,
+    max_running: Option<usize>,
+    log_files: Option<LogFiles>,
+    store: Option<Store>,
+    login: Option<Arc<Login>>,
+    allowed_origins: Arc<Vec<String>>,
+    web_addr: Option<SocketAddr>,
+    update_check: Option<Arc<UpdateCheck>>,
+    events: broadcast::Sender<WebEvent>,
 }
 
 impl Default for WebState {
@@ -163,6 +505,8 @@ struct TunnelRuntime {
     config: TunnelConfig,
     status: TunnelStatus,
     remote_port: Option<u16>,
+    /// Server the tunnel was last started on, which may come from its relay profile.
+    server: String,
     error: Option<String>,
     kind: TunnelKind,
     role: TunnelRole,
@@ -172,7 +516,24 @@ struct TunnelRuntime {
     updated_at: String,
     shutdown_tx: Option<oneshot::Sender<()>>,
     handle: Option<JoinHandle<()>>,
-    logs: VecDeque<String>,
+    logs: VecDeque<LogLine>,
+    stats: Arc<TrafficStats>,
+    rate: TrafficRate,
+    samples: VecDeque<TrafficSample>,
+    sampler: Option<JoinHandle<()>>,
+    connections: Connections,
+    starts: u32,
+    consecutive_failures: u32,
+    restart_attempts: u32,
+    restart: Option<JoinHandle<()>>,
+    log_files: Option<LogFiles>,
+    events: broadcast::Sender<WebEvent>,
+}
+
+#[derive(Debug)]
+struct LogLine {
+    at: OffsetDateTime,
+    message: String,
 }
 
 impl WebState {
@@ -180,6 +541,155 @@ impl WebState {
         Self {
             tunnels: Arc::new(RwLock::new(HashMap::new())),
             session: Arc::new(RwLock::new(session)),
+            errors: Arc::new(Mutex::new(VecDeque::new())),
+            profiles: Arc::new(RwLock::new(HashMap::new())),
+            servers: Arc::new(RwLock::new(HashMap::new())),
+            max_running: None,
+            log_files: None,
+            store: None,
+            login: None,
+            allowed_origins: Arc::new(Vec::new()),
+            web_addr: None,
+            update_check: None,
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Limit how many user tunnels may run at the same time.
+    pub fn set_max_running(&mut self, max_running: Option<usize>) {
+        self.max_running = max_running;
+    }
+
+    /// Also write each tunnel's log lines to a file, so they outlive the process.
+    pub fn set_log_files(&mut self, log_files: Option<LogFiles>) {
+        self.log_files = log_files;
+    }
+
+    /// Require a password for API requests.
+    pub fn set_login(&mut self, login: Option<Login>) {
+        self.login = login.map(Arc::new);
+    }
+
+    /// Password login settings, if the API requires one.
+    pub fn login(&self) -> Option<&Login> {
+        self.login.as_deref()
+    }
+
+    /// Also accept API requests from pages on these origins, like `https://console.example.com`.
+    pub fn set_allowed_origins(&mut self, origins: Vec<String>) {
+        let origins = origins.iter().map(|origin| normalize_origin(origin));
+        self.allowed_origins = Arc::new(origins.collect());
+    }
+
+    /// Origins other than the console itself that may make API requests.
+    pub fn allowed_origins(&self) -> &[String] {
+        &self.allowed_origins
+    }
+
+    /// Record the address the console listens on, whose host API requests may be sent to.
+    pub fn set_web_addr(&mut self, addr: SocketAddr) {
+        self.web_addr = Some(addr);
+    }
+
+    /// Address the console listens on, once it is bound.
+    pub fn web_addr(&self) -> Option<SocketAddr> {
+        self.web_addr
+    }
+
+    /// Report whether a newer release exists, which is off unless enabled.
+    pub fn set_update_check(&mut self, check: Option<UpdateCheck>) {
+        self.update_check = check.map(Arc::new);
+    }
+
+    /// Lookup of the newest release, if enabled.
+    pub fn update_check(&self) -> Option<&UpdateCheck> {
+        self.update_check.as_deref()
+    }
+
+    /// Save user tunnels, relay profiles and remote servers to a file whenever they change.
+    pub fn set_store(&mut self, store: Option<Store>) {
+        self.store = store;
+    }
+
+    /// Load tunnels, relay profiles and remote servers from the store, returning how many tunnels were restored.
+    ///
+    /// Restored tunnels keep their IDs and start out stopped.
+    pub async fn restore(&self) -> Result<usize, StateError> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let saved = store
+            .load()
+            .map_err(|err| bad_request(format!("failed to read saved state: {err}")))?;
+        {
+            let mut profiles = self.profiles.write().await;
+            for SavedProfile { id, profile } in saved.profiles {
+                profiles.insert(id, normalize_profile(profile)?);
+            }
+        }
+        {
+            let mut servers = self.servers.write().await;
+            for SavedServer { id, server } in saved.servers {
+                servers.insert(id, normalize_server(server)?);
+            }
+        }
+        let count = saved.tunnels.len();
+        for SavedTunnel {
+            id,
+            created_at,
+            config,
+        } in saved.tunnels
+        {
+            self.insert_tunnel(id, created_at, config, TunnelRole::User, false, None)
+                .await?;
+        }
+        Ok(count)
+    }
+
+    /// Write user tunnels, relay profiles and remote servers to the store, if there is one.
+    async fn persist(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        // Held until the file is replaced, so a slower save cannot overwrite a newer snapshot.
+        let _saving = store.lock().await;
+        let mut saved = SavedState::default();
+        let entries: Vec<_> = self.tunnels.read().await.values().cloned().collect();
+        for entry in entries {
+            let runtime = entry.lock().await;
+            if runtime.role == TunnelRole::User {
+                saved.tunnels.push(SavedTunnel {
+                    id: runtime.id.clone(),
+                    created_at: runtime.created_at.clone(),
+                    config: runtime.config.clone(),
+                });
+            }
+        }
+        saved
+            .tunnels
+            .sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        saved.profiles = self
+            .profiles
+            .read()
+            .await
+            .iter()
+            .map(|(id, profile)| SavedProfile {
+                id: id.clone(),
+                profile: profile.clone(),
+            })
+            .collect();
+        saved.servers = self
+            .servers
+            .read()
+            .await
+            .iter()
+            .map(|(id, server)| SavedServer {
+                id: id.clone(),
+                server: server.clone(),
+            })
+            .collect();
+        if let Err(err) = store.save(&saved) {
+            warn!(%err, "failed to save web console state");
         }
     }
 
@@ -202,9 +712,59 @@ impl WebState {
         views
     }
 
-    pub async fn create_tunnel(&self, config: TunnelConfig) -> Result<String, StateError> {
-        self.create_tunnel_with_meta(config, TunnelRole::User, false, None)
+    pub async fn summary(&self) -> Summary {
+        let tunnels = self.list_tunnels().await;
+        let mut by_status = StatusCounts::default();
+        let mut rate = TrafficRate::default();
+        for tunnel in &tunnels {
+            match tunnel.status {
+                TunnelStatus::Stopped => by_status.stopped += 1,
+                TunnelStatus::Starting => by_status.starting += 1,
+                TunnelStatus::Running => by_status.running += 1,
+                TunnelStatus::Failed => by_status.failed += 1,
+            }
+            rate.bytes_in += tunnel.traffic.rate.bytes_in;
+            rate.bytes_out += tunnel.traffic.rate.bytes_out;
+        }
+
+        let mut top_tunnels = tunnels
+            .iter()
+            .filter(|tunnel| tunnel.traffic.totals.total_connections > 0)
+            .map(|tunnel| TopTunnel {
+                id: tunnel.id.clone(),
+                name: tunnel.config.name.clone(),
+                bytes_in: tunnel.traffic.totals.bytes_in,
+                bytes_out: tunnel.traffic.totals.bytes_out,
+            })
+            .collect::<Vec<_>>();
+        top_tunnels.sort_by_key(|top| std::cmp::Reverse(top.bytes_in + top.bytes_out));
+        top_tunnels.truncate(SUMMARY_TOP_TUNNELS);
+
+        let recent_errors = self
+            .errors
+            .lock()
             .await
+            .iter()
+            .rev()
+            .take(SUMMARY_ERRORS)
+            .cloned()
+            .collect();
+
+        Summary {
+            total: tunnels.len(),
+            by_status,
+            rate,
+            recent_errors,
+            top_tunnels,
+        }
+    }
+
+    pub async fn create_tunnel(&self, config: TunnelConfig) -> Result<String, StateError> {
+        let id = self
+            .create_tunnel_with_meta(config, TunnelRole::User, false, None)
+            .await?;
+        self.persist().await;
+        Ok(id)
     }
 
     pub async fn create_system_tunnel(&self, spec: SystemTunnelSpec) -> Result<String, StateError> {
@@ -239,10 +799,60 @@ impl WebState {
         runtime.error = None;
         runtime.touch();
         runtime.push_log("tunnel config updated".to_string());
+        drop(runtime);
+        self.persist().await;
         Ok(())
     }
 
     pub async fn start_tunnel(&self, id: &str) -> Result<(), StateError> {
+        self.start_dependencies(id, &mut Vec::new()).await?;
+        self.start_single(id).await
+    }
+
+    /// Start the dependencies of a tunnel in order, waiting for each to run.
+    fn start_dependencies<'a>(
+        &'a self,
+        id: &'a str,
+        path: &'a mut Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), StateError>> + Send + 'a>> {
+        Box::pin(async move {
+            let depends_on = {
+                let entry = self.entry(id).await?;
+                let runtime = entry.lock().await;
+                runtime.config.depends_on.clone()
+            };
+            path.push(id.to_string());
+            for dependency in &depends_on {
+                if path.contains(dependency) {
+                    return Err(bad_request(format!(
+                        "tunnel dependencies form a cycle through {dependency}"
+                    )));
+                }
+                let status = match self.entry(dependency).await {
+                    Ok(entry) => entry.lock().await.status,
+                    Err(_) => {
+                        return Err(bad_request(format!(
+                            "dependency {dependency} does not exist"
+                        )))
+                    }
+                };
+                self.start_dependencies(dependency, path).await?;
+                if !matches!(status, TunnelStatus::Starting | TunnelStatus::Running) {
+                    self.start_single(dependency).await?;
+                }
+                self.wait_for_running(dependency).await.map_err(|err| {
+                    conflict(format!(
+                        "dependency {dependency} failed to start: {}",
+                        err.message
+                    ))
+                })?;
+            }
+            path.pop();
+            Ok(())
+        })
+    }
+
+    async fn start_single(&self, id: &str) -> Result<(), StateError> {
         let entries = {
             let tunnels = self.tunnels.read().await;
             let entry = tunnels
@@ -254,7 +864,7 @@ impl WebState {
         };
 
         let (entry, others) = entries;
-        let config_key = {
+        let (config_key, kind) = {
             let runtime = entry.lock().await;
             if matches!(
                 runtime.status,
@@ -262,54 +872,105 @@ impl WebState {
             ) {
                 return Err(conflict("tunnel is already running"));
             }
-            runtime.config.identity_key()
+            (runtime.config.identity_key(), runtime.kind)
         };
+        if let Some(restart) = entry.lock().await.restart.take() {
+            restart.abort();
+        }
+        let profile = self.tunnel_profile(&entry).await?;
 
+        let mut running_user_tunnels = 0;
         for other in others {
             let runtime = other.lock().await;
-            if runtime.id != id
-                && matches!(
+            if runtime.id == id
+                || !matches!(
                     runtime.status,
                     TunnelStatus::Starting | TunnelStatus::Running
                 )
-                && runtime.config.identity_key() == config_key
             {
+                continue;
+            }
+            if runtime.config.identity_key() == config_key {
                 return Err(conflict("an identical tunnel is already running"));
             }
+            if runtime.kind == TunnelKind::User {
+                running_user_tunnels += 1;
+            }
+        }
+        if let Some(max_running) = self.max_running {
+            if kind == TunnelKind::User && running_user_tunnels >= max_running {
+                return Err(conflict(format!(
+                    "cannot start more than {max_running} tunnels at once"
+                )));
+            }
         }
 
         let (event_tx, mut event_rx) = mpsc::unbounded_channel();
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
         let entry_for_events = Arc::clone(&entry);
         let state_for_events = self.clone();
-        tokio::spawn(async move {
+        let events = tokio::spawn(async move {
             while let Some(event) = event_rx.recv().await {
                 let mut runtime = entry_for_events.lock().await;
                 runtime.apply_event(&state_for_events, event).await;
             }
         });
 
-        let local_args = {
+        let (local_args, stats, connections) = {
             let mut runtime = entry.lock().await;
+            if let Some(sampler) = runtime.sampler.take() {
+                sampler.abort();
+            }
+            runtime.rate = TrafficRate::default();
+            runtime.sampler = Some(tokio::spawn(sample_traffic(
+                Arc::clone(&entry),
+                self.events.clone(),
+            )));
             runtime.status = TunnelStatus::Starting;
+            runtime.starts += 1;
             runtime.remote_port = None;
             runtime.error = None;
             runtime.touch();
             let tunnel_name = runtime.config.name.clone();
             runtime.push_log(format!("starting tunnel {}", tunnel_name));
             runtime.shutdown_tx = Some(shutdown_tx);
-            LocalArgs::from(runtime.config.clone())
+            let mut local_args = LocalArgs::from(runtime.config.clone());
+            if let Some(profile) = profile {
+                local_args.to = profile.to;
+                local_args.secret = local_args.secret.or(profile.secret);
+            }
+            runtime.server = local_args.to.clone();
+            (
+                local_args,
+                Arc::clone(&runtime.stats),
+                Some(runtime.connections.clone()),
+            )
         };
 
+        let state = self.clone();
+        let entry_for_restart = Arc::clone(&entry);
         let handle = tokio::spawn(async move {
-            let _ = run_local(
+            let stop_requested = Arc::new(AtomicBool::new(false));
+            let stop_flag = Arc::clone(&stop_requested);
+            let result = run_local_with_stats(
                 local_args,
+                stats,
+                connections,
                 async move {
-                    let _ = shutdown_rx.await;
+                    if shutdown_rx.await.is_ok() {
+                        stop_flag.store(true, Ordering::Relaxed);
+                    }
                 },
                 Some(event_tx),
             )
             .await;
+            // Let the final status be recorded before deciding whether to restart.
+            let _ = events.await;
+            if !stop_requested.load(Ordering::Relaxed) {
+                state
+                    .schedule_restart(&entry_for_restart, result.is_err())
+                    .await;
+            }
         });
 
         let mut runtime = entry.lock().await;
@@ -317,6 +978,58 @@ impl WebState {
         Ok(())
     }
 
+    /// Start a tunnel again after it exited on its own, if its restart policy allows.
+    ///
+    /// Boxed because restarting starts the tunnel, which schedules the next restart.
+    fn schedule_restart<'a>(
+        &'a self,
+        entry: &'a Arc<Mutex<TunnelRuntime>>,
+        failed: bool,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            let mut runtime = entry.lock().await;
+            let policy = runtime.config.restart.clone();
+            let restart = match policy.mode {
+                RestartMode::Never => false,
+                RestartMode::OnFailure => failed,
+                RestartMode::Always => true,
+            };
+            if !restart {
+                return;
+            }
+            if policy
+                .max_retries
+                .is_some_and(|max_retries| runtime.restart_attempts >= max_retries)
+            {
+                let attempts = runtime.restart_attempts;
+                runtime.push_log(format!("giving up after {attempts} restart attempts"));
+                return;
+            }
+
+            let delay = restart_backoff(policy.backoff_secs, runtime.restart_attempts);
+            runtime.restart_attempts += 1;
+            let attempt = runtime.restart_attempts;
+            runtime.push_log(format!(
+                "restarting in {}s (attempt {attempt})",
+                delay.as_secs()
+            ));
+            let state = self.clone();
+            let entry = Arc::clone(entry);
+            let id = runtime.id.clone();
+            runtime.restart = Some(tokio::spawn(async move {
+                sleep(delay).await;
+                // Detach from the runtime first, so starting does not abort this task.
+                entry.lock().await.restart.take();
+                if let Err(err) = state.start_single(&id).await {
+                    entry
+                        .lock()
+                        .await
+                        .push_log(format!("restart failed: {}", err.message));
+                }
+            }));
+        })
+    }
+
     pub async fn stop_tunnel(&self, id: &str) -> Result<(), StateError> {
         let entry = self.entry(id).await?;
         let (shutdown_tx, handle) = {
@@ -324,6 +1037,9 @@ impl WebState {
             if runtime.locked {
                 return Err(conflict("system tunnel is locked"));
             }
+            if let Some(restart) = runtime.restart.take() {
+                restart.abort();
+            }
             (runtime.shutdown_tx.take(), runtime.handle.take())
         };
 
@@ -335,6 +1051,7 @@ impl WebState {
         }
 
         let mut runtime = entry.lock().await;
+        runtime.stop_sampler();
         if matches!(
             runtime.status,
             TunnelStatus::Starting | TunnelStatus::Running
@@ -350,6 +1067,9 @@ impl WebState {
         let entry = self.entry(id).await?;
         let (shutdown_tx, handle) = {
             let mut runtime = entry.lock().await;
+            if let Some(restart) = runtime.restart.take() {
+                restart.abort();
+            }
             (runtime.shutdown_tx.take(), runtime.handle.take())
         };
 
@@ -361,6 +1081,7 @@ impl WebState {
         }
 
         let mut runtime = entry.lock().await;
+        runtime.stop_sampler();
         if matches!(
             runtime.status,
             TunnelStatus::Starting | TunnelStatus::Running
@@ -375,7 +1096,7 @@ impl WebState {
     pub async fn delete_tunnel(&self, id: &str) -> Result<(), StateError> {
         let entry = self.entry(id).await?;
         {
-            let runtime = entry.lock().await;
+            let mut runtime = entry.lock().await;
             if runtime.locked {
                 return Err(conflict("system tunnel is locked"));
             }
@@ -385,15 +1106,142 @@ impl WebState {
             ) {
                 return Err(conflict("cannot delete a running tunnel"));
             }
+            if let Some(restart) = runtime.restart.take() {
+                restart.abort();
+            }
         }
         self.tunnels.write().await.remove(id);
+        self.persist().await;
         Ok(())
     }
 
     pub async fn logs(&self, id: &str) -> Result<Vec<String>, StateError> {
         let entry = self.entry(id).await?;
         let runtime = entry.lock().await;
-        Ok(runtime.logs.iter().cloned().collect())
+        Ok(runtime
+            .logs
+            .iter()
+            .map(|line| line.message.clone())
+            .collect())
+    }
+
+    /// Public address and URL of a running tunnel.
+    pub async fn share(&self, id: &str) -> Result<TunnelShare, StateError> {
+        let entry = self.entry(id).await?;
+        let runtime = entry.lock().await;
+        let Some(remote_port) = runtime.remote_port else {
+            return Err(conflict("tunnel has no public port until it is running"));
+        };
+        let address = format!("{}:{remote_port}", runtime.server);
+        Ok(TunnelShare {
+            id: runtime.id.clone(),
+            url: runtime
+                .display_url
+                .clone()
+                .unwrap_or_else(|| format!("http://{address}")),
+            address,
+        })
+    }
+
+    /// Recent throughput samples of a tunnel, for drawing graphs.
+    pub async fn tunnel_stats(&self, id: &str) -> Result<TunnelStats, StateError> {
+        let entry = self.entry(id).await?;
+        let runtime = entry.lock().await;
+        Ok(TunnelStats {
+            id: runtime.id.clone(),
+            totals: runtime.stats.snapshot(),
+            samples: runtime.samples.iter().cloned().collect(),
+        })
+    }
+
+    /// Visitor connections of a tunnel that are open right now, oldest first.
+    pub async fn connections(&self, id: &str) -> Result<Vec<ConnectionInfo>, StateError> {
+        let entry = self.entry(id).await?;
+        let connections = entry.lock().await.connections.clone();
+        Ok(connections.list())
+    }
+
+    /// Close one visitor connection of a tunnel.
+    pub async fn close_connection(&self, id: &str, connection: Uuid) -> Result<(), StateError> {
+        let entry = self.entry(id).await?;
+        let connections = entry.lock().await.connections.clone();
+        if !connections.close(connection) {
+            return Err(not_found(format!("connection not found: {connection}")));
+        }
+        Ok(())
+    }
+
+    /// Receive throughput samples, connection changes and log lines of every tunnel as they
+    /// happen.
+    pub fn subscribe(&self) -> broadcast::Receiver<WebEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send an event to subscribers, of which there may be none.
+    fn publish(&self, event: WebEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// A tunnel's whole log as text, one `<time> <message>` line each.
+    ///
+    /// Comes from the log files if they are kept, or else from the recent lines in memory.
+    pub async fn download_logs(&self, id: &str) -> Result<String, StateError> {
+        let entry = self.entry(id).await?;
+        let runtime = entry.lock().await;
+        if let Some(log_files) = &runtime.log_files {
+            return log_files.read(id).map_err(|err| StateError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: format!("could not read log files: {err}"),
+            });
+        }
+        Ok(runtime
+            .logs
+            .iter()
+            .map(|line| format!("{} {}\n", format_rfc3339(line.at), line.message))
+            .collect())
+    }
+
+    pub async fn search_logs(
+        &self,
+        id: &str,
+        query: &LogQuery,
+    ) -> Result<Vec<LogEntry>, StateError> {
+        let pattern = match &query.q {
+            Some(q) if query.regex => {
+                Some(Regex::new(q).map_err(|err| bad_request(format!("invalid regex: {err}")))?)
+            }
+            Some(q) => Some(Regex::new(&regex::escape(q)).expect("escaped pattern should compile")),
+            None => None,
+        };
+        let timestamp = |value: &Option<String>, name: &str| match value {
+            Some(value) => OffsetDateTime::parse(value, &Rfc3339)
+                .map(Some)
+                .map_err(|_| bad_request(format!("{name} must be an RFC 3339 timestamp"))),
+            None => Ok(None),
+        };
+        let since = timestamp(&query.since, "since")?;
+        let until = timestamp(&query.until, "until")?;
+
+        let entry = self.entry(id).await?;
+        let runtime = entry.lock().await;
+        let mut found: Vec<_> = runtime
+            .logs
+            .iter()
+            .filter(|line| since.is_none_or(|since| line.at >= since))
+            .filter(|line| until.is_none_or(|until| line.at <= until))
+            .filter(|line| pattern.as_ref().is_none_or(|re| re.is_match(&line.message)))
+            .map(|line| (line, log_level(&line.message)))
+            .filter(|(_, level)| query.level.is_none_or(|min| *level >= min))
+            .map(|(line, level)| LogEntry {
+                at: format_rfc3339(line.at),
+                level,
+                message: line.message.clone(),
+            })
+            .collect();
+        if let Some(limit) = query.limit {
+            found.drain(..found.len().saturating_sub(limit));
+        }
+        Ok(found)
     }
 
     pub async fn get_tunnel(&self, id: &str) -> Result<TunnelInfo, StateError> {
@@ -463,14 +1311,28 @@ impl WebState {
         locked: bool,
         display_url: Option<String>,
     ) -> Result<String, StateError> {
+        let id = Uuid::new_v4().to_string();
+        self.insert_tunnel(id.clone(), now_rfc3339(), config, role, locked, display_url)
+            .await?;
+        Ok(id)
+    }
+
+    async fn insert_tunnel(
+        &self,
+        id: String,
+        created_at: String,
+        config: TunnelConfig,
+        role: TunnelRole,
+        locked: bool,
+        display_url: Option<String>,
+    ) -> Result<(), StateError> {
         let session = self.session().await;
         let config = normalize_config(config, session.loopback_only)?;
-        self.ensure_unique_config("", &config).await?;
+        self.ensure_unique_config(&id, &config).await?;
 
-        let now = now_rfc3339();
-        let id = Uuid::new_v4().to_string();
         let runtime = TunnelRuntime {
             id: id.clone(),
+            server: config.to.clone(),
             config,
             status: TunnelStatus::Stopped,
             remote_port: None,
@@ -483,19 +1345,254 @@ impl WebState {
             role,
             locked,
             display_url,
-            created_at: now.clone(),
-            updated_at: now,
+            updated_at: created_at.clone(),
+            created_at,
             shutdown_tx: None,
             handle: None,
             logs: VecDeque::new(),
+            stats: Arc::default(),
+            rate: TrafficRate::default(),
+            samples: VecDeque::new(),
+            sampler: None,
+            connections: Connections::default(),
+            starts: 0,
+            consecutive_failures: 0,
+            restart_attempts: 0,
+            restart: None,
+            log_files: self.log_files.clone(),
+            events: self.events.clone(),
         };
         self.tunnels
             .write()
             .await
-            .insert(id.clone(), Arc::new(Mutex::new(runtime)));
+            .insert(id, Arc::new(Mutex::new(runtime)));
+        Ok(())
+    }
+
+    pub async fn list_profiles(&self) -> Vec<RelayProfile> {
+        let profiles = self.profiles.read().await;
+        let mut list: Vec<_> = profiles
+            .iter()
+            .map(|(id, profile)| profile.view(id))
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    pub async fn create_profile(&self, profile: ProfileConfig) -> Result<String, StateError> {
+        let profile = normalize_profile(profile)?;
+        let id = Uuid::new_v4().to_string();
+        self.profiles.write().await.insert(id.clone(), profile);
+        self.persist().await;
         Ok(id)
     }
 
+    pub async fn update_profile(&self, id: &str, profile: ProfileConfig) -> Result<(), StateError> {
+        let profile = normalize_profile(profile)?;
+        let mut profiles = self.profiles.write().await;
+        let existing = profiles
+            .get_mut(id)
+            .ok_or_else(|| not_found("relay profile not found"))?;
+        let secret = profile.secret.or_else(|| existing.secret.take());
+        *existing = ProfileConfig { secret, ..profile };
+        drop(profiles);
+        self.persist().await;
+        Ok(())
+    }
+
+    pub async fn delete_profile(&self, id: &str) -> Result<(), StateError> {
+        let entries = {
+            let tunnels = self.tunnels.read().await;
+            tunnels.values().cloned().collect::<Vec<_>>()
+        };
+        for entry in entries {
+            let runtime = entry.lock().await;
+            if runtime.config.profile.as_deref() == Some(id) {
+                return Err(conflict(format!(
+                    "relay profile is used by tunnel {}",
+                    runtime.config.name
+                )));
+            }
+        }
+        self.profiles
+            .write()
+            .await
+            .remove(id)
+            .ok_or_else(|| not_found("relay profile not found"))?;
+        self.persist().await;
+        Ok(())
+    }
+
+    /// Saved relay profiles sorted by name, with their secrets only if `include_secrets` is set.
+    pub async fn export_profiles(&self, include_secrets: bool) -> ProfileBundle {
+        let profiles = self.profiles.read().await;
+        let mut list: Vec<_> = profiles
+            .values()
+            .map(|profile| ProfileConfig {
+                secret: profile.secret.clone().filter(|_| include_secrets),
+                ..profile.clone()
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        ProfileBundle { profiles: list }
+    }
+
+    /// Save the relay profiles of a bundle, matched to saved profiles by name.
+    ///
+    /// Nothing is saved unless every profile is valid. A replaced profile keeps its ID, so
+    /// tunnels using it pick up the new settings, and keeps its secret if the import has none.
+    pub async fn import_profiles(
+        &self,
+        bundle: ProfileBundle,
+        mode: ImportMode,
+    ) -> Result<ProfileImport, StateError> {
+        let imported = bundle
+            .profiles
+            .into_iter()
+            .map(normalize_profile)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut names = HashSet::new();
+        if let Some(profile) = imported.iter().find(|profile| !names.insert(&profile.name)) {
+            return Err(bad_request(format!(
+                "relay profile {} is imported more than once",
+                profile.name
+            )));
+        }
+
+        let mut result = ProfileImport::default();
+        let mut profiles = self.profiles.write().await;
+        for profile in imported {
+            match profiles
+                .values_mut()
+                .find(|saved| saved.name == profile.name)
+            {
+                None => {
+                    profiles.insert(Uuid::new_v4().to_string(), profile);
+                    result.created += 1;
+                }
+                Some(_) if mode == ImportMode::Merge => result.skipped += 1,
+                Some(saved) => {
+                    let secret = profile.secret.or_else(|| saved.secret.take());
+                    *saved = ProfileConfig { secret, ..profile };
+                    result.updated += 1;
+                }
+            }
+        }
+        drop(profiles);
+        if result.created + result.updated > 0 {
+            self.persist().await;
+        }
+        Ok(result)
+    }
+
+    /// Check that the relay of a profile accepts connections, and the secret if one is set.
+    pub async fn check_profile(&self, id: &str) -> Result<ProfileCheck, StateError> {
+        let profile = self
+            .profiles
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found("relay profile not found"))?;
+        let started = Instant::now();
+        let result = timeout(NETWORK_TIMEOUT, async {
+            let stream = TcpStream::connect((profile.to.as_str(), CONTROL_PORT)).await?;
+            if let Some(secret) = &profile.secret {
+                let mut stream = Delimited::new(stream);
+                Authenticator::new(secret)
+                    .client_handshake(&mut stream)
+                    .await?;
+                // The server only answers a bad secret, so probe with a harmless message.
+                stream.send(ClientMessage::Accept(Uuid::nil())).await?;
+                if let Some(ServerMessage::Error(err)) = stream.recv().await? {
+                    anyhow::bail!(err);
+                }
+            }
+            anyhow::Ok(())
+        })
+        .await;
+        Ok(match result {
+            Ok(Ok(())) => ProfileCheck {
+                ok: true,
+                latency_ms: Some(started.elapsed().as_millis() as u64),
+                error: None,
+            },
+            Ok(Err(err)) => ProfileCheck {
+                ok: false,
+                latency_ms: None,
+                error: Some(err.to_string()),
+            },
+            Err(_) => ProfileCheck {
+                ok: false,
+                latency_ms: None,
+                error: Some("timed out connecting to relay".to_string()),
+            },
+        })
+    }
+
+    pub async fn list_servers(&self) -> Vec<RemoteServer> {
+        let servers = self.servers.read().await;
+        let mut list: Vec<_> = servers.iter().map(|(id, server)| server.view(id)).collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    pub async fn create_server(&self, server: RemoteServerConfig) -> Result<String, StateError> {
+        let server = normalize_server(server)?;
+        let id = Uuid::new_v4().to_string();
+        self.servers.write().await.insert(id.clone(), server);
+        self.persist().await;
+        Ok(id)
+    }
+
+    pub async fn delete_server(&self, id: &str) -> Result<(), StateError> {
+        self.servers
+            .write()
+            .await
+            .remove(id)
+            .ok_or_else(|| not_found("remote server not found"))?;
+        self.persist().await;
+        Ok(())
+    }
+
+    /// Read the health and open tunnels of a remote server from its admin API.
+    pub async fn server_status(&self, id: &str) -> Result<RemoteServerStatus, StateError> {
+        let server = self
+            .servers
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found("remote server not found"))?;
+        let client = AdminClient::new(&server.url, server.token)
+            .map_err(|err| bad_request(format!("{err:#}")))?;
+        let (health, tunnels) = tokio::try_join!(client.health(), client.tunnels())
+            .map_err(|err| bad_gateway(format!("{}: {err:#}", server.name)))?;
+        // Named tunnels share the HTTP port, which is listed once.
+        let ports: BTreeSet<_> = tunnels.iter().map(|tunnel| tunnel.port).collect();
+        Ok(RemoteServerStatus {
+            health,
+            ports: ports.into_iter().collect(),
+            bytes_in: tunnels.iter().map(|tunnel| tunnel.bytes_in).sum(),
+            bytes_out: tunnels.iter().map(|tunnel| tunnel.bytes_out).sum(),
+            active_connections: tunnels.iter().map(|tunnel| tunnel.active_connections).sum(),
+            tunnels,
+        })
+    }
+
+    async fn tunnel_profile(
+        &self,
+        entry: &Mutex<TunnelRuntime>,
+    ) -> Result<Option<ProfileConfig>, StateError> {
+        let Some(id) = entry.lock().await.config.profile.clone() else {
+            return Ok(None);
+        };
+        match self.profiles.read().await.get(&id) {
+            Some(profile) => Ok(Some(profile.clone())),
+            None => Err(bad_request("relay profile not found")),
+        }
+    }
+
     async fn ensure_unique_config(
         &self,
         id: &str,
@@ -515,6 +1612,19 @@ impl WebState {
         Ok(())
     }
 
+    async fn record_error(&self, runtime: &TunnelRuntime, error: String) {
+        let mut errors = self.errors.lock().await;
+        errors.push_back(RecentError {
+            id: runtime.id.clone(),
+            name: runtime.config.name.clone(),
+            error,
+            at: now_rfc3339(),
+        });
+        while errors.len() > MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+    }
+
     async fn entry(&self, id: &str) -> Result<Arc<Mutex<TunnelRuntime>>, StateError> {
         self.tunnels
             .read()
@@ -539,25 +1649,74 @@ impl TunnelRuntime {
             display_url: self.display_url.clone(),
             created_at: self.created_at.clone(),
             updated_at: self.updated_at.clone(),
+            traffic: TunnelTraffic {
+                totals: self.stats.snapshot(),
+                rate: self.rate,
+            },
+            health: TunnelHealth {
+                restarts: self.starts.saturating_sub(1),
+                consecutive_failures: self.consecutive_failures,
+                flapping: self.consecutive_failures >= FLAPPING_FAILURES,
+            },
         }
     }
 
     async fn apply_event(&mut self, state: &WebState, event: TunnelEvent) {
         match event {
             TunnelEvent::Log(message) => self.push_log(message),
+            TunnelEvent::ConnectionOpened { id, peer } => {
+                self.push_log(format!("accepted remote connection {id}"));
+                state.publish(WebEvent::ConnectionOpened {
+                    id: self.id.clone(),
+                    connection: id,
+                    peer,
+                });
+            }
+            TunnelEvent::ConnectionClosed {
+                id,
+                error,
+                bytes_in,
+                bytes_out,
+            } => {
+                if let Some(error) = &error {
+                    self.push_log(format!("connection {id} exited with error: {error}"));
+                }
+                state.publish(WebEvent::ConnectionClosed {
+                    id: self.id.clone(),
+                    connection: id,
+                    error,
+                    bytes_in,
+                    bytes_out,
+                });
+            }
             TunnelEvent::Started { remote_port } => {
                 self.status = TunnelStatus::Running;
                 self.remote_port = remote_port;
                 self.error = None;
+                self.consecutive_failures = 0;
+                self.restart_attempts = 0;
                 self.touch();
                 if let Some(remote_port) = remote_port {
                     self.push_log(format!("remote port assigned: {remote_port}"));
                 }
                 self.update_session_remote(state).await;
             }
+            TunnelEvent::Reconnecting {
+                attempt,
+                delay_ms,
+                error,
+            } => {
+                self.status = TunnelStatus::Starting;
+                self.remote_port = None;
+                self.touch();
+                self.push_log(format!(
+                    "connection lost ({error}), reconnect attempt {attempt} in {delay_ms}ms"
+                ));
+            }
             TunnelEvent::Stopped => {
                 self.status = TunnelStatus::Stopped;
                 self.shutdown_tx = None;
+                self.stop_sampler();
                 self.touch();
                 self.push_log("tunnel stopped".to_string());
             }
@@ -566,8 +1725,20 @@ impl TunnelRuntime {
                 self.remote_port = None;
                 self.error = Some(message.clone());
                 self.shutdown_tx = None;
+                self.stop_sampler();
                 self.touch();
                 self.push_log(format!("error: {message}"));
+                self.consecutive_failures += 1;
+                if self.consecutive_failures == FLAPPING_FAILURES {
+                    self.push_log(format!(
+                        "tunnel is flapping after {FLAPPING_FAILURES} consecutive failures"
+                    ));
+                    state.publish(WebEvent::TunnelFlapping {
+                        id: self.id.clone(),
+                        consecutive_failures: self.consecutive_failures,
+                    });
+                }
+                state.record_error(self, message).await;
             }
         }
     }
@@ -590,7 +1761,19 @@ impl TunnelRuntime {
     }
 
     fn push_log(&mut self, message: String) {
-        self.logs.push_back(message);
+        let at = OffsetDateTime::now_utc();
+        if let Some(log_files) = &self.log_files {
+            let line = format!("{} {message}", format_rfc3339(at));
+            if let Err(err) = log_files.append(&self.id, &line) {
+                warn!(id = %self.id, %err, "failed to write tunnel log file");
+            }
+        }
+        let _ = self.events.send(WebEvent::Log {
+            id: self.id.clone(),
+            at: format_rfc3339(at),
+            message: message.clone(),
+        });
+        self.logs.push_back(LogLine { at, message });
         while self.logs.len() > MAX_LOG_LINES {
             self.logs.pop_front();
         }
@@ -600,6 +1783,35 @@ impl TunnelRuntime {
     fn touch(&mut self) {
         self.updated_at = now_rfc3339();
     }
+
+    fn stop_sampler(&mut self) {
+        if let Some(sampler) = self.sampler.take() {
+            sampler.abort();
+        }
+        self.rate = TrafficRate::default();
+    }
+}
+
+impl ProfileConfig {
+    fn view(&self, id: &str) -> RelayProfile {
+        RelayProfile {
+            id: id.to_string(),
+            name: self.name.clone(),
+            to: self.to.clone(),
+            has_secret: self.secret.is_some(),
+        }
+    }
+}
+
+impl RemoteServerConfig {
+    fn view(&self, id: &str) -> RemoteServer {
+        RemoteServer {
+            id: id.to_string(),
+            name: self.name.clone(),
+            url: self.url.clone(),
+            has_token: self.token.is_some(),
+        }
+    }
 }
 
 impl TunnelConfig {
@@ -610,6 +1822,9 @@ impl TunnelConfig {
             to: self.to.clone(),
             port: self.port,
             local_host: self.local_host.clone(),
+            depends_on: self.depends_on.clone(),
+            profile: self.profile.clone(),
+            restart: self.restart.clone(),
         }
     }
 
@@ -631,6 +1846,7 @@ impl From<TunnelConfig> for LocalArgs {
             to: value.to,
             port: value.port.unwrap_or(0),
             secret: value.secret,
+            ..Default::default()
         }
     }
 }
@@ -663,7 +1879,7 @@ fn normalize_config(
     if config.local_port == 0 {
         return Err(bad_request("local_port must be a valid port"));
     }
-    if config.to.is_empty() {
+    if config.to.is_empty() && config.profile.is_none() {
         return Err(bad_request("to cannot be empty"));
     }
     if config.local_host.is_empty() {
@@ -681,6 +1897,38 @@ fn normalize_config(
     Ok(config)
 }
 
+fn normalize_profile(mut profile: ProfileConfig) -> Result<ProfileConfig, StateError> {
+    profile.name = profile.name.trim().to_string();
+    profile.to = profile.to.trim().to_string();
+    profile.secret = profile
+        .secret
+        .as_ref()
+        .map(|secret| secret.trim().to_string())
+        .filter(|secret| !secret.is_empty());
+    if profile.name.is_empty() {
+        return Err(bad_request("name cannot be empty"));
+    }
+    if profile.to.is_empty() {
+        return Err(bad_request("to cannot be empty"));
+    }
+    Ok(profile)
+}
+
+fn normalize_server(mut server: RemoteServerConfig) -> Result<RemoteServerConfig, StateError> {
+    server.name = server.name.trim().to_string();
+    server.url = server.url.trim().to_string();
+    server.token = server
+        .token
+        .as_ref()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty());
+    if server.name.is_empty() {
+        return Err(bad_request("name cannot be empty"));
+    }
+    AdminClient::new(&server.url, None).map_err(|err| bad_request(format!("{err:#}")))?;
+    Ok(server)
+}
+
 pub fn is_loopback_host(host: &str) -> bool {
     if matches!(host, "localhost" | "127.0.0.1" | "::1") {
         return true;
@@ -691,9 +1939,73 @@ pub fn is_loopback_host(host: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Update the throughput of a tunnel once per interval, until aborted.
+async fn sample_traffic(entry: Arc<Mutex<TunnelRuntime>>, events: broadcast::Sender<WebEvent>) {
+    let (id, stats) = {
+        let runtime = entry.lock().await;
+        (runtime.id.clone(), Arc::clone(&runtime.stats))
+    };
+    let mut last = stats.snapshot();
+    let mut ticker = interval(SAMPLE_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let now = stats.snapshot();
+        let seconds = SAMPLE_INTERVAL.as_secs_f64();
+        let rate = TrafficRate {
+            bytes_in: ((now.bytes_in - last.bytes_in) as f64 / seconds) as u64,
+            bytes_out: ((now.bytes_out - last.bytes_out) as f64 / seconds) as u64,
+        };
+        let sample = TrafficSample {
+            at: now_rfc3339(),
+            rate,
+            active_connections: now.active_connections,
+        };
+        {
+            let mut runtime = entry.lock().await;
+            runtime.rate = rate;
+            if runtime.samples.len() == MAX_TRAFFIC_SAMPLES {
+                runtime.samples.pop_front();
+            }
+            runtime.samples.push_back(sample.clone());
+        }
+        // Nobody may be subscribed, which is not an error.
+        let _ = events.send(WebEvent::Stats {
+            id: id.clone(),
+            sample,
+        });
+        last = now;
+    }
+}
+
+/// Classify a log line by the wording the client and web console use.
+fn log_level(message: &str) -> LogLevel {
+    let message = message.to_ascii_lowercase();
+    if message.contains("error") || message.contains("failed") {
+        LogLevel::Error
+    } else if message.contains("not supported")
+        || message.contains("reconnecting")
+        || message.contains("flapping")
+    {
+        LogLevel::Warn
+    } else {
+        LogLevel::Info
+    }
+}
+
+/// Delay before a restart attempt, doubling from `backoff_secs` up to a limit.
+fn restart_backoff(backoff_secs: u64, attempt: u32) -> Duration {
+    let factor = 1u64 << attempt.min(16);
+    Duration::from_secs(backoff_secs.saturating_mul(factor)).min(MAX_RESTART_BACKOFF)
+}
+
 fn now_rfc3339() -> String {
-    OffsetDateTime::now_utc()
-        .format(&Rfc3339)
+    format_rfc3339(OffsetDateTime::now_utc())
+}
+
+fn format_rfc3339(at: OffsetDateTime) -> String {
+    at.format(&Rfc3339)
         .unwrap_or_else(|_| "1970-01-01T00:00:00Z".to_string())
 }
 
@@ -718,13 +2030,21 @@ fn conflict(message: impl Into<String>) -> StateError {
     }
 }
 
+fn bad_gateway(message: impl Into<String>) -> StateError {
+    StateError {
+        status: StatusCode::BAD_GATEWAY,
+        message: message.into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use axum::http::StatusCode;
 
     use super::{
-        is_loopback_host, SessionInfo, SessionMode, SystemTunnelRole, TunnelConfig, TunnelKind,
-        TunnelStatus, WebState, MAX_LOG_LINES,
+        is_loopback_host, LogLevel, LogQuery, ProfileConfig, RestartPolicy, SessionInfo,
+        SessionMode, Store, SystemTunnelRole, TunnelConfig, TunnelKind, TunnelStatus, WebState,
+        MAX_LOG_LINES,
     };
 
     fn config(name: &str) -> TunnelConfig {
@@ -735,6 +2055,9 @@ mod tests {
             port: Some(9000),
             local_host: "127.0.0.1".to_string(),
             secret: None,
+            depends_on: Vec::new(),
+            profile: None,
+            restart: RestartPolicy::default(),
         }
     }
 
@@ -752,6 +2075,73 @@ mod tests {
         assert_eq!(tunnels[0].kind, TunnelKind::User);
     }
 
+    #[tokio::test]
+    async fn saved_tunnels_and_profiles_are_restored() {
+        let path = std::env::temp_dir().join(format!("bore-state-{}.json", uuid::Uuid::new_v4()));
+        let mut state = WebState::default();
+        state.set_store(Some(Store::new(&path)));
+        let profile = state
+            .create_profile(ProfileConfig {
+                name: "relay".to_string(),
+                to: "bore.pub".to_string(),
+                secret: Some("hunter2".to_string()),
+            })
+            .await
+            .unwrap();
+        let kept = state.create_tunnel(config("kept")).await.unwrap();
+        let removed = state
+            .create_tunnel(TunnelConfig {
+                local_port: 3001,
+                ..config("removed")
+            })
+            .await
+            .unwrap();
+        state.delete_tunnel(&removed).await.unwrap();
+
+        let mut restarted = WebState::default();
+        restarted.set_store(Some(Store::new(&path)));
+        assert_eq!(restarted.restore().await.unwrap(), 1);
+        let tunnels = restarted.list_tunnels().await;
+        assert_eq!(tunnels.len(), 1);
+        assert_eq!(tunnels[0].id, kept);
+        assert_eq!(tunnels[0].status, TunnelStatus::Stopped);
+        let profiles = restarted.list_profiles().await;
+        assert_eq!(profiles[0].id, profile);
+        assert!(profiles[0].has_secret);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_saves_keep_every_tunnel() {
+        let dir = std::env::temp_dir().join(format!("bore-state-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("state.json");
+        let mut state = WebState::default();
+        state.set_store(Some(Store::new(&path)));
+        let creates: Vec<_> = (0..16)
+            .map(|i| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let config = TunnelConfig {
+                        local_port: 3000 + i,
+                        port: Some(9000 + i),
+                        ..config(&format!("tunnel-{i}"))
+                    };
+                    state.create_tunnel(config).await.unwrap();
+                })
+            })
+            .collect();
+        for create in creates {
+            create.await.unwrap();
+        }
+
+        let mut restarted = WebState::default();
+        restarted.set_store(Some(Store::new(&path)));
+        assert_eq!(restarted.restore().await.unwrap(), 16);
+        // No temporary files are left behind.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[tokio::test]
     async fn remote_mode_rejects_non_loopback_local_host() {
         let state = WebState::new(SessionInfo {
@@ -860,6 +2250,84 @@ mod tests {
         assert_eq!(err.status, StatusCode::CONFLICT);
     }
 
+    #[tokio::test]
+    async fn starting_beyond_max_running_returns_error() {
+        let mut state = WebState::default();
+        state.set_max_running(Some(1));
+        let running = state
+            .create_tunnel(config("running"))
+            .await
+            .expect("create should work");
+        let id = state
+            .create_tunnel(TunnelConfig {
+                local_port: 3001,
+                ..config("extra")
+            })
+            .await
+            .expect("create should work");
+        {
+            let entry = state.entry(&running).await.expect("entry should exist");
+            entry.lock().await.status = TunnelStatus::Running;
+        }
+
+        let err = state
+            .start_tunnel(&id)
+            .await
+            .expect_err("start should fail");
+        assert_eq!(err.status, StatusCode::CONFLICT);
+        assert!(err.message.contains("more than 1"));
+    }
+
+    #[tokio::test]
+    async fn dependency_cycles_and_missing_dependencies_are_rejected() {
+        let state = WebState::default();
+        let first = state
+            .create_tunnel(config("first"))
+            .await
+            .expect("create should work");
+        let second = state
+            .create_tunnel(TunnelConfig {
+                local_port: 3001,
+                depends_on: vec![first.clone()],
+                ..config("second")
+            })
+            .await
+            .expect("create should work");
+        state
+            .update_tunnel(
+                &first,
+                TunnelConfig {
+                    depends_on: vec![second.clone()],
+                    ..config("first")
+                },
+            )
+            .await
+            .expect("update should work");
+
+        let err = state
+            .start_tunnel(&second)
+            .await
+            .expect_err("start should fail");
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert!(err.message.contains("cycle"));
+
+        state
+            .update_tunnel(
+                &first,
+                TunnelConfig {
+                    depends_on: vec!["missing".to_string()],
+                    ..config("first")
+                },
+            )
+            .await
+            .expect("update should work");
+        let err = state
+            .start_tunnel(&first)
+            .await
+            .expect_err("start should fail");
+        assert!(err.message.contains("missing does not exist"));
+    }
+
     #[tokio::test]
     async fn update_running_tunnel_returns_error() {
         let state = WebState::default();
@@ -900,6 +2368,88 @@ mod tests {
         assert_eq!(logs.last().expect("last log"), "line-519");
     }
 
+    #[tokio::test]
+    async fn search_logs_filters_by_text_and_level() {
+        let state = WebState::default();
+        let id = state
+            .create_tunnel(config("dev"))
+            .await
+            .expect("create should work");
+        let entry = state.entry(&id).await.expect("entry should exist");
+        {
+            let mut runtime = entry.lock().await;
+            runtime.push_log("connected to localhost:7835".to_string());
+            runtime.push_log("connection 1 exited with error: reset".to_string());
+            runtime.push_log("server is restarting, reconnecting to localhost in 1s".to_string());
+        }
+
+        let search = |query: LogQuery| {
+            let state = state.clone();
+            let id = id.clone();
+            async move { state.search_logs(&id, &query).await }
+        };
+        let found = search(LogQuery {
+            q: Some("connect".to_string()),
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        assert_eq!(found.len(), 3);
+
+        let found = search(LogQuery {
+            level: Some(LogLevel::Warn),
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        let levels: Vec<_> = found.iter().map(|entry| entry.level).collect();
+        assert_eq!(levels, [LogLevel::Error, LogLevel::Warn]);
+
+        let found = search(LogQuery {
+            q: Some(r"^connect(ed|ion \d)".to_string()),
+            regex: true,
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        assert_eq!(found.len(), 2);
+
+        let found = search(LogQuery {
+            since: Some("2999-01-01T00:00:00Z".to_string()),
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        assert!(found.is_empty());
+
+        let found = search(LogQuery {
+            until: Some("2000-01-01T00:00:00Z".to_string()),
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        assert!(found.is_empty());
+
+        let found = search(LogQuery {
+            q: Some("connect".to_string()),
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await
+        .expect("search should work");
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.starts_with("server is restarting"));
+
+        let err = search(LogQuery {
+            q: Some("(".to_string()),
+            regex: true,
+            ..Default::default()
+        })
+        .await
+        .expect_err("invalid regex should fail");
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
     #[test]
     fn loopback_host_parser_accepts_known_values() {
         assert!(is_loopback_host("localhost"));
@@ -1,6 +1,8 @@
 const DEFAULT_HOST = "127.0.0.1";
 const DEFAULT_REMOTE = "bore.pub";
 const POLL_INTERVAL_MS = 2000;
+const SERVER_POLL_INTERVAL_MS = 10000;
+const MAX_TRAFFIC_SAMPLES = 300;
 const LOCALE = document.documentElement.lang || navigator.language || "en-US";
 const STATUS_LABELS = {
   Starting: "Starting",
@@ -13,6 +15,7 @@ const COPY = {
   noSecret: "Not set",
   hasSecret: "Configured",
   noLogs: "No logs yet.",
+  noMatchingLogs: "No matching log lines.",
   loadingLogs: "Loading logs…",
   loadingTunnels: "Loading tunnels…",
   listUpdated: "Tunnel list updated.",
@@ -25,6 +28,13 @@ const COPY = {
   createBusy: "Saving tunnel…",
   createDone: "Tunnel created.",
   updateDone: "Tunnel updated.",
+  profileSaved: "Profile saved.",
+  profileDeleted: "Profile deleted.",
+  serverAdded: "Server added.",
+  serverRemoved: "Server removed.",
+  loadingServer: "Loading status…",
+  noRemoteTunnels: "No open tunnels.",
+  fromProfile: "From profile",
   deletePromptTitle: "Delete this tunnel?",
   deletePromptBody(name) {
     return `Delete "${name}"? This permanently removes the saved tunnel configuration.`;
@@ -46,6 +56,11 @@ const state = {
   syncing: false,
   pendingSync: false,
   busyTunnelActions: new Map(),
+  samples: new Map(),
+  connections: new Map(),
+  profiles: [],
+  servers: [],
+  serverStatus: new Map(),
 };
 
 const renderedTunnels = new Map();
@@ -68,6 +83,17 @@ const sessionWarnings = document.getElementById("session-warnings");
 const deleteDialog = document.getElementById("delete-dialog");
 const deleteDialogTitle = document.getElementById("delete-dialog-title");
 const deleteDialogBody = document.getElementById("delete-dialog-body");
+const profileForm = document.getElementById("profile-form");
+const profileSubmitBtn = document.getElementById("profile-submit-btn");
+const profileMessage = document.getElementById("profile-message");
+const profileList = document.getElementById("profile-list");
+const profileImport = document.getElementById("profile-import");
+const profileOverwrite = document.getElementById("profile-overwrite");
+const serverForm = document.getElementById("server-form");
+const serverSubmitBtn = document.getElementById("server-submit-btn");
+const serverMessage = document.getElementById("server-message");
+const serverList = document.getElementById("server-list");
+const serversRefreshBtn = document.getElementById("servers-refresh-btn");
 
 form.addEventListener("submit", async (event) => {
   event.preventDefault();
@@ -98,11 +124,120 @@ form.addEventListener("submit", async (event) => {
   }
 });
 
+form.elements.profile.addEventListener("change", applyProfileChoice);
+
+profileForm.addEventListener("submit", async (event) => {
+  event.preventDefault();
+
+  if (!profileForm.reportValidity() || profileSubmitBtn.disabled) {
+    return;
+  }
+
+  const data = new FormData(profileForm);
+  const payload = {
+    name: data.get("name")?.toString().trim() || "",
+    to: data.get("to")?.toString().trim() || "",
+    secret: data.get("secret")?.toString() || null,
+  };
+  profileSubmitBtn.disabled = true;
+  try {
+    await api("/api/profiles", { method: "POST", body: JSON.stringify(payload) });
+    profileForm.reset();
+    await syncProfiles();
+    setProfileMessage(COPY.profileSaved, "success");
+  } catch (error) {
+    setProfileMessage(error.message, "error");
+  } finally {
+    profileSubmitBtn.disabled = false;
+  }
+});
+
+profileImport.addEventListener("change", () => {
+  const [file] = profileImport.files;
+  if (!file) {
+    return;
+  }
+
+  profileImport.disabled = true;
+  importProfiles(file)
+    .catch((error) => setProfileMessage(error.message, "error"))
+    .finally(() => {
+      profileImport.disabled = false;
+      profileImport.value = "";
+    });
+});
+
+profileList.addEventListener("click", (event) => {
+  const button = event.target.closest("[data-profile-action]");
+  if (!button || button.disabled) {
+    return;
+  }
+
+  const { profileAction, profileId } = button.dataset;
+  const worker = profileAction === "check" ? checkProfile : deleteProfile;
+  button.disabled = true;
+  worker(profileId)
+    .catch((error) => setProfileMessage(error.message, "error"))
+    .finally(() => {
+      button.disabled = false;
+    });
+});
+
+serverForm.addEventListener("submit", async (event) => {
+  event.preventDefault();
+
+  if (!serverForm.reportValidity() || serverSubmitBtn.disabled) {
+    return;
+  }
+
+  const data = new FormData(serverForm);
+  const payload = {
+    name: data.get("name")?.toString().trim() || "",
+    url: data.get("url")?.toString().trim() || "",
+    token: data.get("token")?.toString() || null,
+  };
+  serverSubmitBtn.disabled = true;
+  try {
+    await api("/api/servers", { method: "POST", body: JSON.stringify(payload) });
+    serverForm.reset();
+    setServerMessage(COPY.serverAdded, "success");
+    await syncServers();
+  } catch (error) {
+    setServerMessage(error.message, "error");
+  } finally {
+    serverSubmitBtn.disabled = false;
+  }
+});
+
+serversRefreshBtn.addEventListener("click", () => {
+  syncServers().catch((error) => setServerMessage(error.message, "error"));
+});
+
+serverList.addEventListener("click", (event) => {
+  const button = event.target.closest("[data-server-id]");
+  if (!button || button.disabled) {
+    return;
+  }
+
+  button.disabled = true;
+  removeServer(button.dataset.serverId).catch((error) => {
+    button.disabled = false;
+    setServerMessage(error.message, "error");
+  });
+});
+
 cancelEditBtn.addEventListener("click", () => {
   resetFormState();
   setFormMessage("");
 });
 
+list.addEventListener("input", (event) => {
+  const id = event.target.dataset?.logFilter;
+  if (id) {
+    fetchLogs(id).catch(showListError);
+  }
+});
+
 refreshBtn.addEventListener("click", () => {
   syncState({ announce: COPY.listRefreshed, source: "manual" }).catch(showListError);
 });
@@ -127,6 +262,8 @@ list.addEventListener("click", (event) => {
     },
     delete: () => deleteTunnel(tunnelId),
     logs: () => toggleLogs(tunnelId, button),
+    share: () => shareTunnel(tunnelId),
+    "close-connection": () => closeConnection(tunnelId, button.dataset.connectionId),
   };
 
   const handler = actions[action];
@@ -152,6 +289,7 @@ function readFormPayload() {
     port: data.get("port") ? Number(data.get("port")) : null,
     local_host: data.get("local_host")?.toString().trim() || "",
     secret: data.get("secret")?.toString() || null,
+    profile: data.get("profile")?.toString() || null,
   };
 
   if (!payload.port) {
@@ -172,6 +310,22 @@ function resetFormState() {
   form.elements.to.value = DEFAULT_REMOTE;
   form.elements.local_host.value = DEFAULT_HOST;
   form.elements.local_host.readOnly = Boolean(state.session?.loopback_only);
+  applyProfileChoice();
+}
+
+// A tunnel with a profile always uses its server, and its secret unless one is filled in.
+function applyProfileChoice() {
+  const usesProfile = Boolean(form.elements.profile.value);
+  const to = form.elements.to;
+  to.required = !usesProfile;
+  to.readOnly = usesProfile;
+  to.placeholder = usesProfile ? COPY.fromProfile : "";
+  form.elements.secret.placeholder = usesProfile ? COPY.fromProfile : "Optional";
+  if (usesProfile) {
+    to.value = "";
+  } else if (!to.value) {
+    to.value = DEFAULT_REMOTE;
+  }
 }
 
 function setFormBusy(isBusy) {
@@ -200,6 +354,22 @@ function setFormMessage(message, tone) {
   }
 }
 
+function setProfileMessage(message, tone) {
+  profileMessage.textContent = message;
+  profileMessage.className = "message";
+  if (tone) {
+    profileMessage.classList.add(`is-${tone}`);
+  }
+}
+
+function setServerMessage(message, tone) {
+  serverMessage.textContent = message;
+  serverMessage.className = "message";
+  if (tone) {
+    serverMessage.classList.add(`is-${tone}`);
+  }
+}
+
 function setListFeedback(message, tone) {
   listFeedback.textContent = message;
   listFeedback.className = "list-feedback";
@@ -228,10 +398,12 @@ function beginEditTunnel(id) {
   cancelEditBtn.hidden = false;
   form.elements.name.value = tunnel.config.name;
   form.elements.local_port.value = tunnel.config.local_port;
+  form.elements.profile.value = tunnel.config.profile ?? "";
   form.elements.to.value = tunnel.config.to;
   form.elements.port.value = tunnel.config.port ?? "";
   form.elements.local_host.value = tunnel.config.local_host;
   form.elements.secret.value = "";
+  applyProfileChoice();
   setFormMessage(
     tunnel.has_secret
       ? "Leave Secret empty to keep the current secret."
@@ -279,11 +451,39 @@ function applySession(session) {
   );
 }
 
-async function api(path, options = {}) {
+let pendingLogin = null;
+
+// Share one password prompt between requests that fail at the same time.
+function login() {
+  if (!pendingLogin) {
+    pendingLogin = promptLogin().finally(() => {
+      pendingLogin = null;
+    });
+  }
+  return pendingLogin;
+}
+
+async function promptLogin() {
+  const password = window.prompt("Enter the web console password");
+  if (password === null) {
+    return false;
+  }
+  const response = await fetch("/api/login", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ password }),
+  });
+  return response.ok;
+}
+
+async function api(path, options = {}, retry = true) {
   const response = await fetch(path, {
     headers: { "Content-Type": "application/json" },
     ...options,
   });
+  if (response.status === 401 && retry && (await login())) {
+    return api(path, options, false);
+  }
   if (!response.ok) {
     let message = `Request failed: ${response.status}`;
     try {
@@ -333,6 +533,200 @@ async function syncState({ announce = "", source = "auto" } = {}) {
   }
 }
 
+async function syncProfiles() {
+  state.profiles = await api("/api/profiles");
+  renderProfiles();
+}
+
+async function checkProfile(id) {
+  const profile = findProfile(id);
+  const result = await api(`/api/profiles/${encodeURIComponent(id)}/check`, { method: "POST" });
+  if (result.ok) {
+    setProfileMessage(`${profile?.name || "Profile"} is reachable (${result.latency_ms} ms).`, "success");
+  } else {
+    setProfileMessage(`${profile?.name || "Profile"} is unreachable: ${result.error}`, "error");
+  }
+}
+
+async function deleteProfile(id) {
+  await api(`/api/profiles/${encodeURIComponent(id)}`, { method: "DELETE" });
+  await syncProfiles();
+  setProfileMessage(COPY.profileDeleted, "success");
+}
+
+async function importProfiles(file) {
+  const format = /\.ya?ml$/i.test(file.name) ? "yaml" : "json";
+  const mode = profileOverwrite.checked ? "overwrite" : "merge";
+  const result = await api(`/api/profiles/import?format=${format}&mode=${mode}`, {
+    method: "POST",
+    body: await file.text(),
+  });
+  await syncProfiles();
+  setProfileMessage(
+    `Imported ${result.created} new, replaced ${result.updated}, skipped ${result.skipped}.`,
+    "success",
+  );
+}
+
+function renderProfiles() {
+  profileList.replaceChildren(
+    ...state.profiles.map((profile) => {
+      const item = document.createElement("li");
+      const label = document.createElement("span");
+      label.textContent = [profile.name, profile.to, profile.has_secret ? "secret set" : null]
+        .filter(Boolean)
+        .join(" • ");
+      const actions = document.createElement("div");
+      actions.className = "profile-actions";
+      actions.append(
+        createProfileButton("Check", "check", profile.id),
+        createProfileButton("Delete", "delete", profile.id),
+      );
+      item.append(label, actions);
+      return item;
+    }),
+  );
+
+  const select = form.elements.profile;
+  const selected = select.value;
+  const none = document.createElement("option");
+  none.value = "";
+  none.textContent = "None";
+  select.replaceChildren(
+    none,
+    ...state.profiles.map((profile) => {
+      const option = document.createElement("option");
+      option.value = profile.id;
+      option.textContent = profile.name;
+      return option;
+    }),
+  );
+  select.value = state.profiles.some((profile) => profile.id === selected) ? selected : "";
+  applyProfileChoice();
+}
+
+function createProfileButton(label, action, profileId) {
+  const button = document.createElement("button");
+  button.type = "button";
+  button.className = "ghost";
+  button.textContent = label;
+  button.dataset.profileAction = action;
+  button.dataset.profileId = profileId;
+  return button;
+}
+
+async function syncServers() {
+  state.servers = await api("/api/servers");
+  renderServers();
+  await Promise.all(state.servers.map((server) => loadServerStatus(server.id)));
+}
+
+async function loadServerStatus(id) {
+  let status;
+  try {
+    status = await api(`/api/servers/${encodeURIComponent(id)}/status`);
+  } catch (error) {
+    status = { error: error.message };
+  }
+  if (!state.servers.some((server) => server.id === id)) {
+    return;
+  }
+  state.serverStatus.set(id, status);
+  renderServers();
+}
+
+async function removeServer(id) {
+  await api(`/api/servers/${encodeURIComponent(id)}`, { method: "DELETE" });
+  state.serverStatus.delete(id);
+  setServerMessage(COPY.serverRemoved, "success");
+  await syncServers();
+}
+
+function renderServers() {
+  serverList.replaceChildren(...state.servers.map(createServerCard));
+}
+
+function createServerCard(server) {
+  const status = state.serverStatus.get(server.id);
+  const card = document.createElement("article");
+  card.className = "server-card";
+
+  const head = document.createElement("div");
+  head.className = "server-card-head";
+  const titles = document.createElement("div");
+  const name = document.createElement("h3");
+  name.textContent = server.name;
+  const url = document.createElement("p");
+  url.className = "server-url";
+  url.textContent = server.url;
+  titles.append(name, url);
+  const remove = document.createElement("button");
+  remove.type = "button";
+  remove.className = "ghost";
+  remove.textContent = "Remove";
+  remove.dataset.serverId = server.id;
+  head.append(titles, remove);
+
+  const summary = document.createElement("p");
+  summary.className = "server-summary";
+  card.append(head, summary);
+  if (!status) {
+    summary.textContent = COPY.loadingServer;
+    return card;
+  }
+  if (status.error) {
+    summary.classList.add("is-error");
+    summary.textContent = status.error;
+    return card;
+  }
+
+  const { health } = status;
+  summary.textContent = [
+    health.draining ? "Draining" : health.ports_exhausted ? "Out of ports" : "Ready",
+    `${health.tunnels} ${health.tunnels === 1 ? "tunnel" : "tunnels"}`,
+    status.ports.length ? `ports ${status.ports.join(", ")}` : null,
+    `${status.active_connections} open connections`,
+    `in ${formatBytes(status.bytes_in)}`,
+    `out ${formatBytes(status.bytes_out)}`,
+  ]
+    .filter(Boolean)
+    .join(" • ");
+  if (!status.tunnels.length) {
+    const empty = document.createElement("p");
+    empty.className = "server-summary";
+    empty.textContent = COPY.noRemoteTunnels;
+    card.append(empty);
+    return card;
+  }
+
+  const table = document.createElement("table");
+  table.className = "server-tunnels";
+  const header = table.createTHead().insertRow();
+  ["Port", "Client", "Token", "Uptime", "In", "Out", "Open"].forEach((label) => {
+    const cell = document.createElement("th");
+    cell.scope = "col";
+    cell.textContent = label;
+    header.append(cell);
+  });
+  const body = table.createTBody();
+  status.tunnels.forEach((tunnel) => {
+    const row = body.insertRow();
+    [
+      tunnel.host ? `${tunnel.host} (:${tunnel.port})` : String(tunnel.port),
+      tunnel.client,
+      tunnel.token || "—",
+      formatDuration(tunnel.uptime_secs),
+      formatBytes(tunnel.bytes_in),
+      formatBytes(tunnel.bytes_out),
+      String(tunnel.active_connections),
+    ].forEach((value) => {
+      row.insertCell().textContent = value;
+    });
+  });
+  card.append(table);
+  return card;
+}
+
 async function createTunnel(payload) {
   return api("/api/tunnels", {
     method: "POST",
@@ -386,8 +780,30 @@ async function deleteTunnel(id) {
 }
 
 async function fetchLogs(id) {
-  const data = await api(`/api/tunnels/${id}/logs`);
-  updateLogs(id, data.logs);
+  const filter = readLogFilter(id);
+  if (!filter) {
+    const data = await api(`/api/tunnels/${id}/logs`);
+    updateLogs(id, data.logs);
+    return;
+  }
+  const entries = await api(`/api/tunnels/${id}/logs/search?${filter}`);
+  updateLogs(id, entries.map((entry) => entry.message), COPY.noMatchingLogs);
+}
+
+function readLogFilter(id) {
+  const card = renderedTunnels.get(id);
+  if (!card) {
+    return "";
+  }
+  const { logsQuery, logsLevel } = card._refs;
+  const params = new URLSearchParams();
+  if (logsQuery.value) {
+    params.set("q", logsQuery.value);
+  }
+  if (logsLevel.value) {
+    params.set("level", logsLevel.value);
+  }
+  return params.toString();
 }
 
 async function refreshExpandedLogs() {
@@ -455,6 +871,8 @@ function renderTunnels(tunnels) {
     if (!card) {
       card = createTunnelCard(tunnel.id);
       renderedTunnels.set(tunnel.id, card);
+      loadStats(tunnel.id).catch(() => {});
+      loadConnections(tunnel.id).catch(() => {});
     }
 
     patchTunnelCard(card, tunnel);
@@ -468,6 +886,8 @@ function renderTunnels(tunnels) {
   existingIds.forEach((id) => {
     renderedTunnels.get(id)?.remove();
     renderedTunnels.delete(id);
+    state.samples.delete(id);
+    state.connections.delete(id);
   });
 
   if (focusKey) {
@@ -517,6 +937,26 @@ function createTunnelCard(id) {
   const metaUpdated = createMetaItem("Updated");
   metaList.append(metaRequested.item, metaSecret.item, metaCreated.item, metaUpdated.item);
 
+  const traffic = document.createElement("figure");
+  traffic.className = "traffic";
+  traffic.hidden = true;
+
+  const graph = document.createElement("canvas");
+  graph.width = 600;
+  graph.height = 72;
+  graph.setAttribute("aria-hidden", "true");
+
+  const trafficCaption = document.createElement("figcaption");
+  traffic.append(graph, trafficCaption);
+
+  const connections = document.createElement("section");
+  connections.className = "connections";
+  connections.hidden = true;
+
+  const connectionsTitle = document.createElement("h4");
+  const connectionsList = document.createElement("ul");
+  connections.append(connectionsTitle, connectionsList);
+
   const error = document.createElement("p");
   error.className = "error-text";
   error.hidden = true;
@@ -531,7 +971,8 @@ function createTunnelCard(id) {
   const stopButton = createActionButton("Stop", "stop", id, "secondary");
   const editButton = createActionButton("Edit", "edit", id, "ghost");
   const deleteButton = createActionButton("Delete", "delete", id, "danger");
-  group.append(startButton, stopButton, editButton, deleteButton);
+  const shareButton = createActionButton("Copy link", "share", id, "ghost");
+  group.append(startButton, stopButton, editButton, deleteButton, shareButton);
 
   const logsButton = createActionButton("View logs", "logs", id, "ghost");
   logsButton.type = "button";
@@ -545,10 +986,32 @@ function createTunnelCard(id) {
   logs.id = `logs-${id}`;
   logs.hidden = true;
 
-  const logsPre = document.createElement("pre");
-  logs.append(logsPre);
+  const logsFilter = document.createElement("div");
+  logsFilter.className = "logs-filter";
 
-  article.append(cardHead, metaList, error, cardActions, logs);
+  const logsQuery = document.createElement("input");
+  logsQuery.type = "search";
+  logsQuery.placeholder = "Filter logs";
+  logsQuery.setAttribute("aria-label", "Filter logs");
+  logsQuery.dataset.logFilter = id;
+
+  const logsLevel = document.createElement("select");
+  logsLevel.setAttribute("aria-label", "Minimum log level");
+  logsLevel.dataset.logFilter = id;
+  for (const [value, label] of [["", "All levels"], ["warn", "Warnings"], ["error", "Errors"]]) {
+    logsLevel.append(new Option(label, value));
+  }
+  logsFilter.append(logsQuery, logsLevel);
+
+  const logsPre = document.createElement("pre");
+  const logsDownload = document.createElement("a");
+  logsDownload.className = "logs-download";
+  logsDownload.href = `/api/tunnels/${id}/logs/download`;
+  logsDownload.download = "";
+  logsDownload.textContent = "Download full log";
+  logs.append(logsFilter, logsPre, logsDownload);
+
+  article.append(cardHead, metaList, traffic, connections, error, cardActions, logs);
   article._refs = {
     name,
     badges,
@@ -558,14 +1021,23 @@ function createTunnelCard(id) {
     metaSecret: metaSecret.value,
     metaCreated: metaCreated.value,
     metaUpdated: metaUpdated.value,
+    traffic,
+    graph,
+    trafficCaption,
+    connections,
+    connectionsTitle,
+    connectionsList,
     error,
     startButton,
     stopButton,
     editButton,
     deleteButton,
+    shareButton,
     group,
     logsButton,
     logs,
+    logsQuery,
+    logsLevel,
     logsPre,
   };
 
@@ -655,8 +1127,9 @@ function patchTunnelCard(card, tunnel) {
   const canEdit = !tunnel.locked && (tunnel.status === "Stopped" || tunnel.status === "Failed");
   const logsExpanded = state.expandedLogs.has(tunnel.id);
   const visiblePort = tunnel.remote_port ?? tunnel.config.port ?? COPY.autoPort;
-  const remoteLabel = `${tunnel.config.to}:${visiblePort}`;
-  const remoteUrl = tunnel.display_url || (tunnel.remote_port ? `http://${tunnel.config.to}:${tunnel.remote_port}` : null);
+  const server = tunnel.config.to || findProfile(tunnel.config.profile)?.to || COPY.fromProfile;
+  const remoteLabel = `${server}:${visiblePort}`;
+  const remoteUrl = tunnel.display_url || (tunnel.remote_port ? `http://${server}:${tunnel.remote_port}` : null);
   const busyAction = state.busyTunnelActions.get(tunnel.id) || null;
   const isBusy = Boolean(busyAction);
 
@@ -685,6 +1158,7 @@ function patchTunnelCard(card, tunnel) {
   refs.stopButton.disabled = isBusy || !canStop;
   refs.editButton.disabled = isBusy || !canEdit;
   refs.deleteButton.disabled = isBusy || !canDelete;
+  refs.shareButton.disabled = isBusy || tunnel.status !== "Running";
   refs.logsButton.disabled = isBusy;
   refs.startButton.classList.toggle("button-busy", busyAction === "start");
   refs.stopButton.classList.toggle("button-busy", busyAction === "stop");
@@ -741,14 +1215,14 @@ function patchTunnel(id, patch) {
   updateSummary(state.tunnels);
 }
 
-function updateLogs(id, logs) {
+function updateLogs(id, logs, emptyText = COPY.noLogs) {
   const card = renderedTunnels.get(id);
   if (!card) {
     return;
   }
 
   const { logsPre } = card._refs;
-  const nextText = (logs || []).join("\n") || COPY.noLogs;
+  const nextText = (logs || []).join("\n") || emptyText;
   const wasPinnedToBottom =
     Math.abs(logsPre.scrollHeight - logsPre.clientHeight - logsPre.scrollTop) < 8;
   const previousScrollTop = logsPre.scrollTop;
@@ -809,10 +1283,158 @@ async function confirmDelete(tunnel) {
   });
 }
 
+async function loadStats(id) {
+  const data = await api(`/api/tunnels/${id}/stats`);
+  state.samples.set(id, data.samples);
+  drawTraffic(id);
+}
+
+async function shareTunnel(id) {
+  const share = await api(`/api/tunnels/${id}/share`);
+  await navigator.clipboard.writeText(share.url);
+  setListFeedback(`Copied ${share.url}`, "success");
+}
+
+async function loadConnections(id) {
+  state.connections.set(id, await api(`/api/tunnels/${id}/connections`));
+  renderConnections(id);
+}
+
+async function closeConnection(id, connection) {
+  await api(`/api/tunnels/${id}/connections/${connection}`, { method: "DELETE" });
+  await loadConnections(id);
+}
+
+function watchEvents() {
+  const source = new EventSource("/api/events");
+  source.addEventListener("stats", (event) => {
+    const sample = JSON.parse(event.data);
+    const samples = state.samples.get(sample.id) || [];
+    samples.push(sample);
+    if (samples.length > MAX_TRAFFIC_SAMPLES) {
+      samples.shift();
+    }
+    state.samples.set(sample.id, samples);
+    drawTraffic(sample.id);
+    // Durations and byte counts of open connections change with every sample.
+    if (state.connections.get(sample.id)?.length) {
+      loadConnections(sample.id).catch(() => {});
+    }
+  });
+  for (const name of ["connection_opened", "connection_closed"]) {
+    source.addEventListener(name, (event) => {
+      loadConnections(JSON.parse(event.data).id).catch(() => {});
+    });
+  }
+  source.addEventListener("log", (event) => {
+    const { id } = JSON.parse(event.data);
+    if (state.expandedLogs.has(id)) {
+      fetchLogs(id).catch(() => {});
+    }
+  });
+}
+
+function renderConnections(id) {
+  const card = renderedTunnels.get(id);
+  const connections = state.connections.get(id) || [];
+  if (!card) {
+    return;
+  }
+
+  const { connections: section, connectionsTitle, connectionsList } = card._refs;
+  section.hidden = !connections.length;
+  connectionsTitle.textContent = `Open connections (${connections.length})`;
+  connectionsList.replaceChildren(
+    ...connections.map((connection) => {
+      const item = document.createElement("li");
+      const label = document.createElement("span");
+      label.textContent = [
+        connection.peer || "Unknown visitor",
+        `open ${formatDuration(connection.open_secs)}`,
+        `in ${formatBytes(connection.bytes_in)}`,
+        `out ${formatBytes(connection.bytes_out)}`,
+      ].join(" • ");
+      const close = createActionButton("Close", "close-connection", id, "ghost");
+      close.dataset.connectionId = connection.id;
+      close.dataset.focusKey = `${id}:close:${connection.id}`;
+      item.append(label, close);
+      return item;
+    }),
+  );
+}
+
+function drawTraffic(id) {
+  const card = renderedTunnels.get(id);
+  const samples = state.samples.get(id) || [];
+  if (!card) {
+    return;
+  }
+
+  const { traffic, graph, trafficCaption } = card._refs;
+  traffic.hidden = !samples.length;
+  if (!samples.length) {
+    return;
+  }
+
+  const styles = getComputedStyle(traffic);
+  const context = graph.getContext("2d");
+  const peak = Math.max(1, ...samples.map((sample) => Math.max(sample.bytes_in, sample.bytes_out)));
+  const step = graph.width / (MAX_TRAFFIC_SAMPLES - 1);
+  const offset = MAX_TRAFFIC_SAMPLES - samples.length;
+  context.clearRect(0, 0, graph.width, graph.height);
+  context.lineWidth = 2;
+  for (const [key, color] of [["bytes_in", "--graph-in"], ["bytes_out", "--graph-out"]]) {
+    context.strokeStyle = styles.getPropertyValue(color);
+    context.beginPath();
+    samples.forEach((sample, index) => {
+      const x = (offset + index) * step;
+      const y = graph.height - 1 - (sample[key] / peak) * (graph.height - 2);
+      if (index === 0) {
+        context.moveTo(x, y);
+      } else {
+        context.lineTo(x, y);
+      }
+    });
+    context.stroke();
+  }
+
+  const latest = samples[samples.length - 1];
+  trafficCaption.textContent = `In ${formatRate(latest.bytes_in)} • Out ${formatRate(latest.bytes_out)} • ${latest.active_connections} open`;
+}
+
+function formatRate(bytesPerSecond) {
+  return `${formatBytes(bytesPerSecond)}/s`;
+}
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return unit === 0 ? `${value} ${units[unit]}` : `${value.toFixed(1)} ${units[unit]}`;
+}
+
+function formatDuration(seconds) {
+  if (seconds < 60) {
+    return `${seconds}s`;
+  }
+  if (seconds < 3600) {
+    return `${Math.floor(seconds / 60)}m ${seconds % 60}s`;
+  }
+  return `${Math.floor(seconds / 3600)}h ${Math.floor((seconds % 3600) / 60)}m`;
+}
+
 function findTunnel(id) {
   return state.tunnels.find((tunnel) => tunnel.id === id);
 }
 
+function findProfile(id) {
+  return state.profiles.find((profile) => profile.id === id);
+}
+
 function updateSummary(tunnels) {
   const running = tunnels.filter((tunnel) => tunnel.status === "Running").length;
   const starting = tunnels.filter((tunnel) => tunnel.status === "Starting").length;
@@ -861,5 +1483,10 @@ init().catch(showListError);
 
 async function init() {
   await syncSession();
-  await syncState();
+  await Promise.all([syncState(), syncProfiles()]);
+  syncServers().catch((error) => setServerMessage(error.message, "error"));
+  window.setInterval(() => {
+    state.servers.forEach((server) => loadServerStatus(server.id));
+  }, SERVER_POLL_INTERVAL_MS);
+  watchEvents();
 }
@@ -0,0 +1,115 @@
+//! JSON file that keeps web console tunnels, relay profiles and remote servers across restarts.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::state::{ProfileConfig, RemoteServerConfig, TunnelConfig};
+
+/// Saved user tunnels, relay profiles and remote servers.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SavedState {
+    /// User tunnels, oldest first.
+    #[serde(default)]
+    pub tunnels: Vec<SavedTunnel>,
+    /// Relay profiles referenced by tunnels.
+    #[serde(default)]
+    pub profiles: Vec<SavedProfile>,
+    /// Remote servers watched through their admin API.
+    #[serde(default)]
+    pub servers: Vec<SavedServer>,
+}
+
+/// Saved tunnel, restored with the same ID so dependencies stay valid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedTunnel {
+    /// Tunnel ID.
+    pub id: String,
+    /// When the tunnel was first created, in RFC 3339 format.
+    pub created_at: String,
+    /// Tunnel configuration, including its secret.
+    pub config: TunnelConfig,
+}
+
+/// Saved relay profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedProfile {
+    /// Profile ID.
+    pub id: String,
+    /// Profile configuration, including its secret.
+    pub profile: ProfileConfig,
+}
+
+/// Saved remote server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedServer {
+    /// Server ID.
+    pub id: String,
+    /// Server configuration, including its admin token.
+    pub server: RemoteServerConfig,
+}
+
+/// Suffix for temporary files, so that concurrent saves never write the same one.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Location of the state file.
+#[derive(Debug, Clone)]
+pub struct Store {
+    path: PathBuf,
+    saving: Arc<Mutex<()>>,
+}
+
+impl Store {
+    /// Use the state file at `path`, which is created on the first save.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            saving: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Wait for other saves to finish, so that a snapshot taken while holding the guard is not
+    /// overwritten by an older one.
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.saving.lock().await
+    }
+
+    /// Read the saved state, which is empty if the file does not exist yet.
+    pub fn load(&self) -> io::Result<SavedState> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(SavedState::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replace the saved state, without leaving a partially written file behind.
+    ///
+    /// The file contains tunnel secrets, so on Unix it is only readable by the owner.
+    pub fn save(&self, state: &SavedState) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        let suffix = NEXT_TMP.fetch_add(1, Ordering::Relaxed);
+        tmp.push(format!(".{}.{suffix}.tmp", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&tmp)?;
+        let result = serde_json::to_writer_pretty(file, state)
+            .map_err(io::Error::other)
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+}
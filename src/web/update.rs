@@ -0,0 +1,80 @@
+//! Opt-in check for newer releases, so the console can prompt admins to upgrade.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use super::remote::AdminClient;
+
+/// REST API of the upstream repository, whose releases are checked.
+pub const UPSTREAM_URL: &str = "https://api.github.com/repos/fishandsheep/bore";
+
+/// How long a result is reused, to stay well within the API's rate limit.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Cached lookups of the newest release.
+#[derive(Debug)]
+pub struct UpdateCheck {
+    client: AdminClient,
+    cached: Mutex<Option<(Instant, Option<String>)>>,
+}
+
+impl UpdateCheck {
+    /// Check the releases of the repository at `url`, like [`UPSTREAM_URL`].
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: AdminClient::new(url, None)?,
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// Newest released version, like `0.6.1`, or `None` if it could not be fetched.
+    pub async fn latest(&self) -> Option<String> {
+        // Held while fetching, so concurrent requests share one lookup.
+        let mut cached = self.cached.lock().await;
+        if let Some((checked_at, latest)) = &*cached {
+            if checked_at.elapsed() < CHECK_INTERVAL {
+                return latest.clone();
+            }
+        }
+        let latest = match self.client.latest_release().await {
+            Ok(tag) => Some(tag.trim_start_matches('v').to_string()),
+            Err(err) => {
+                warn!(%err, "could not check for a newer release");
+                None
+            }
+        };
+        *cached = Some((Instant::now(), latest.clone()));
+        latest
+    }
+}
+
+/// Whether `latest` is a higher version than `current`, comparing numeric components.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(latest) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_newer;
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(is_newer("0.10.0", "0.9.3"));
+        assert!(is_newer("1.0.0", "0.6.0"));
+        assert!(!is_newer("0.6.0", "0.6.0"));
+        assert!(!is_newer("0.6.0-rc.1", "0.6.0"));
+        assert!(!is_newer("0.5.9", "0.6.0"));
+    }
+}
@@ -1,13 +1,31 @@
 use std::net::SocketAddr;
-use std::sync::LazyLock;
-use std::time::Duration;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use bore_cli::{client::Client, server::Server, shared::CONTROL_PORT};
+use bore_cli::{
+    access::AccessRules,
+    audit::{AuditLog, AuditTarget},
+    client::{
+        run_local, Client, ClientOptions, LocalArgs, ServiceCheck, TunnelEvent, TunnelEvents,
+    },
+    e2e,
+    limit::{ByteRate, RateLimit},
+    proxy_protocol::ProxyProtocol,
+    server::Server,
+    shared::{
+        ClientMessage, Delimited, Features, HelloRequest, PortRange, ReconnectHint, ServerMessage,
+        WhenDown, CONTROL_PORT,
+    },
+    tls::{ClientTls, ServerTls},
+    tokens::Token,
+    transport,
+};
+use futures_util::StreamExt;
 use rstest::*;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio::task::JoinHandle;
 use tokio::time;
 
@@ -40,9 +58,14 @@ async fn wait_for_control_port_closed() -> Result<()> {
 
 /// Spawn the server and wait until the control port is accepting connections.
 async fn spawn_server(secret: Option<&str>) -> Result<ServerGuard> {
+    spawn_custom_server(Server::new(1024..=65535, secret)).await
+}
+
+/// Spawn a preconfigured server and wait until the control port is accepting connections.
+async fn spawn_custom_server(server: Server) -> Result<ServerGuard> {
     wait_for_control_port_closed().await?;
 
-    let task = tokio::spawn(Server::new(1024..=65535, secret).listen());
+    let task = tokio::spawn(server.listen());
 
     for _ in 0..50 {
         if task.is_finished() {
@@ -123,6 +146,953 @@ async fn mismatched_secret(
     assert!(spawn_client(client_secret).await.is_err());
 }
 
+#[tokio::test]
+async fn extended_hello_negotiates_features() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let requested = Features::COMPRESSION | Features::from_bits(1 << 31);
+    let options = ClientOptions {
+        features: requested,
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", 5000, "localhost", 0, None, options, None).await?;
+    assert_ne!(client.remote_port(), 0);
+
+    // The server never agrees to capabilities that were not requested.
+    assert!(requested.contains(client.features()));
+    assert!(!client.features().contains(Features::from_bits(1 << 31)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn first_free_preferred_port_is_assigned() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let taken = TcpListener::bind("0.0.0.0:0").await?;
+    let taken_port = taken.local_addr()?.port();
+    let free_port = TcpListener::bind("0.0.0.0:0").await?.local_addr()?.port();
+
+    let options = ClientOptions {
+        ports: vec![PortRange::single(taken_port), PortRange::single(free_port)],
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", 5000, "localhost", 0, None, options, None).await?;
+    assert_eq!(client.remote_port(), free_port);
+
+    let options = ClientOptions {
+        ports: vec![PortRange::single(taken_port)],
+        ..Default::default()
+    };
+    let result = Client::with_options("localhost", 5000, "localhost", 0, None, options, None).await;
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn reconnect_hint_keeps_remote_port() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let server = Server::new(1024..=65535, None);
+    let notifier = server.restart_notifier();
+    let _server = spawn_custom_server(server).await?;
+
+    async fn next_port(events: &mut mpsc::UnboundedReceiver<TunnelEvent>) -> Result<u16> {
+        loop {
+            match time::timeout(Duration::from_secs(5), events.recv()).await? {
+                Some(TunnelEvent::Started {
+                    remote_port: Some(port),
+                }) => return Ok(port),
+                Some(_) => continue,
+                None => return Err(anyhow!("tunnel exited")),
+            }
+        }
+    }
+
+    let args = LocalArgs {
+        local_port: 5000,
+        local_host: "localhost".into(),
+        to: "localhost".into(),
+        ..Default::default()
+    };
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    let tunnel = tokio::spawn(run_local(args, std::future::pending(), Some(event_tx)));
+
+    let port = next_port(&mut events).await?;
+    let hint = ReconnectHint { delay: 0, to: None };
+    assert_eq!(notifier.notify(hint), 1);
+    assert_eq!(next_port(&mut events).await?, port);
+
+    tunnel.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn check_mode_releases_reserved_port() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(Some("abc")).await?;
+
+    let mut args = LocalArgs {
+        local_port: 5000,
+        local_host: "localhost".into(),
+        to: "localhost".into(),
+        secret: Some("abc".into()),
+        check: true,
+        ..Default::default()
+    };
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    time::timeout(
+        Duration::from_secs(5),
+        run_local(args.clone(), std::future::pending(), Some(event_tx)),
+    )
+    .await??;
+    let mut remote_port = None;
+    while let Some(event) = events.recv().await {
+        if let TunnelEvent::Started { remote_port: port } = event {
+            remote_port = port;
+        }
+    }
+    let remote_port = remote_port.expect("check should report the reserved port");
+    time::sleep(Duration::from_millis(100)).await;
+    Client::new("localhost", 5000, "localhost", remote_port, Some("abc")).await?;
+
+    args.secret = Some("wrong".into());
+    assert!(run_local(args, std::future::pending(), None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn suspended_tunnel_is_closed_and_port_blocked() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let server = Server::new(1024..=65535, None);
+    let suspender = server.suspender();
+    let _server = spawn_custom_server(server).await?;
+
+    let client = Client::new("localhost", 5000, "localhost", 0, None).await?;
+    let port = client.remote_port();
+    let tunnel = tokio::spawn(client.listen());
+    time::sleep(Duration::from_millis(50)).await;
+
+    assert!(suspender.suspend(port, "abuse report"));
+    time::timeout(Duration::from_secs(5), tunnel).await???;
+
+    let result = Client::new("localhost", 5000, "localhost", port, None).await;
+    assert!(result.is_err());
+
+    assert!(suspender.resume(port));
+    Client::new("localhost", 5000, "localhost", port, None).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn lost_tunnel_reconnects_with_backoff() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let server = Server::new(1024..=65535, None);
+    let suspender = server.suspender();
+    let _server = spawn_custom_server(server).await?;
+
+    let args = LocalArgs {
+        local_port: 5000,
+        local_host: "localhost".into(),
+        to: "localhost".into(),
+        reconnect: true,
+        reconnect_delay: Some(1),
+        reconnect_jitter: Some(0.0),
+        ..Default::default()
+    };
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    let tunnel = tokio::spawn(run_local(args, std::future::pending(), Some(event_tx)));
+    let mut next_event = async || loop {
+        match time::timeout(Duration::from_secs(5), events.recv()).await? {
+            Some(TunnelEvent::Log(_)) => continue,
+            Some(event) => return anyhow::Ok(event),
+            None => return Err(anyhow!("tunnel exited")),
+        }
+    };
+    let Some(port) = (match next_event().await? {
+        TunnelEvent::Started { remote_port } => remote_port,
+        event => panic!("unexpected event {event:?}"),
+    }) else {
+        panic!("no remote port");
+    };
+
+    // The port stays blocked for the first attempt, so the second one backs off further.
+    assert!(suspender.suspend(port, "maintenance"));
+    for expected in [(1, 1000), (2, 2000)] {
+        match next_event().await? {
+            TunnelEvent::Reconnecting {
+                attempt, delay_ms, ..
+            } => assert_eq!((attempt, delay_ms), expected),
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+    assert!(suspender.resume(port));
+    assert!(matches!(
+        next_event().await?,
+        TunnelEvent::Started { remote_port } if remote_port == Some(port)
+    ));
+
+    tunnel.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn handshakes_are_rate_limited_per_address() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, Some("abc"));
+    server.set_handshake_limit(RateLimit {
+        rate: 0.001,
+        burst: 2.0,
+    });
+    let _server = spawn_custom_server(server).await?;
+
+    spawn_client(Some("abc")).await?;
+    assert!(spawn_client(Some("wrong")).await.is_err());
+    let err = spawn_client(Some("abc")).await.unwrap_err();
+    assert!(err.to_string().contains("too many handshakes"), "{err:#}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn visitor_connections_are_rate_limited() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_visitor_limit(RateLimit {
+        rate: 0.001,
+        burst: 2.0,
+    });
+    let _server = spawn_custom_server(server).await?;
+    let (listener, addr) = spawn_client(None).await?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.write_all(b"ok").await;
+        }
+    });
+
+    let mut buf = [0u8; 2];
+    for _ in 0..2 {
+        let mut visitor = TcpStream::connect(addr).await?;
+        visitor.read_exact(&mut buf).await?;
+    }
+    let mut throttled = TcpStream::connect(addr).await?;
+    assert_eq!(throttled.read(&mut buf).await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_through_intermediate_server() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    // Chain the server through itself, since only one server can own the control port.
+    let mut server = Server::new(1024..=65535, Some("secret"));
+    server.set_allow_relay(true);
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        via: Some("localhost".into()),
+        via_secret: Some("secret".into()),
+        ..Default::default()
+    };
+    let client = Client::with_options(
+        "localhost",
+        local_port,
+        "localhost",
+        0,
+        Some("secret"),
+        options,
+        None,
+    )
+    .await?;
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.write_all(b"relayed").await?;
+        anyhow::Ok(())
+    });
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let mut buf = [0u8; 7];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"relayed");
+    Ok(())
+}
+
+#[tokio::test]
+async fn tls_encrypts_relayed_tunnel() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let dir = std::env::temp_dir().join(format!("bore-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    std::fs::write(&cert, certified.cert.pem())?;
+    std::fs::write(&key, certified.signing_key.serialize_pem())?;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_allow_relay(true);
+    server.set_tls(ServerTls::from_pem_files(&cert, &key)?);
+    let _server = spawn_custom_server(server).await?;
+
+    // Plaintext clients and clients that don't trust the certificate are turned away.
+    assert!(spawn_client(None).await.is_err());
+    let options = ClientOptions {
+        tls: Some(ClientTls::new(None)?),
+        ..Default::default()
+    };
+    let result = Client::with_options("localhost", 5000, "localhost", 0, None, options, None).await;
+    assert!(result.is_err());
+
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        via: Some("localhost".into()),
+        tls: Some(ClientTls::new(Some(&cert))?),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.write_all(b"encrypted").await?;
+        anyhow::Ok(())
+    });
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let mut buf = [0u8; 9];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"encrypted");
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(feature = "prometheus")]
+#[tokio::test]
+async fn server_and_client_metrics_are_served() -> Result<()> {
+    use bore_cli::metrics::{install, MetricsExporter};
+
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let metrics_addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    install(&MetricsExporter::Prometheus(metrics_addr))?;
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        features: Features::PING,
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    assert!(client.features().contains(Features::PING));
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.write_all(b"metrics").await?;
+        anyhow::Ok(())
+    });
+    let mut stream = TcpStream::connect(addr).await?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await?;
+    assert_eq!(buf, b"metrics");
+    drop(stream);
+
+    let mut body = String::new();
+    for _ in 0..50 {
+        let mut http = TcpStream::connect(metrics_addr).await?;
+        http.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").await?;
+        body.clear();
+        http.read_to_string(&mut body).await?;
+        if body.contains("bore_server_tunnel_bytes_out_total")
+            && body.contains("bore_client_heartbeat_rtt_seconds")
+        {
+            break;
+        }
+        time::sleep(Duration::from_millis(20)).await;
+    }
+    let port = addr.port();
+    assert!(body.contains(&format!(
+        "bore_server_tunnel_bytes_out_total{{port=\"{port}\"}} 7"
+    )));
+    assert!(body.contains("bore_server_handshake_duration_seconds_bucket"));
+    assert!(body.contains("bore_server_tunnels_active"));
+    assert!(body.contains("bore_client_heartbeat_rtt_seconds"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn access_rules_refuse_visitors() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    for (allow, permitted) in [("10.0.0.0/8", false), ("127.0.0.0/8", true)] {
+        let listener = TcpListener::bind("localhost:0").await?;
+        let local_port = listener.local_addr()?.port();
+        let options = ClientOptions {
+            access: AccessRules {
+                allow: vec![allow.parse().map_err(|err: String| anyhow!(err))?],
+                deny: Vec::new(),
+            },
+            ..Default::default()
+        };
+        let client =
+            Client::with_options("localhost", local_port, "localhost", 0, None, options, None)
+                .await?;
+        let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+        tokio::spawn(client.listen());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            stream.write_all(b"allowed").await?;
+            anyhow::Ok(())
+        });
+
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        assert_eq!(buf.is_empty(), !permitted, "allow {allow}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_rate_throttles_tunnel_throughput() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_max_rate(ByteRate(100_000));
+    let _server = spawn_custom_server(server).await?;
+    let (listener, addr) = spawn_client(None).await?;
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        stream.write_all(&[0; 250_000]).await?;
+        anyhow::Ok(())
+    });
+
+    // The first second of traffic is a burst, then the rest arrives at the limit.
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(addr).await?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await?;
+    assert_eq!(buf.len(), 250_000);
+    assert!(start.elapsed() >= Duration::from_millis(1200));
+    Ok(())
+}
+
+#[tokio::test]
+async fn connections_over_limit_are_refused() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        max_connections: Some(1),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await?;
+                stream.write_all(&buf).await?;
+                anyhow::Ok(())
+            });
+        }
+    });
+
+    let mut first = TcpStream::connect(addr).await?;
+    first.write_all(b"first").await?;
+    let mut buf = [0u8; 5];
+    first.read_exact(&mut buf).await?;
+
+    // The first connection is still open, so the second one is closed right away.
+    let mut second = TcpStream::connect(addr).await?;
+    assert_eq!(second.read(&mut buf).await?, 0);
+
+    drop(first);
+    time::sleep(Duration::from_millis(100)).await;
+    let mut third = TcpStream::connect(addr).await?;
+    third.write_all(b"third").await?;
+    third.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"third");
+    Ok(())
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed_by_server() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_idle_timeout(Duration::from_millis(300));
+    let _server = spawn_custom_server(server).await?;
+    let (listener, addr) = spawn_client(None).await?;
+    let local = tokio::spawn(async move { listener.accept().await });
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(b"hello").await?;
+    let (mut local_stream, _) = local.await??;
+    let mut buf = [0u8; 5];
+    local_stream.read_exact(&mut buf).await?;
+
+    // Nothing is sent after the greeting, so the server closes the connection.
+    let start = Instant::now();
+    assert_eq!(stream.read(&mut buf).await?, 0);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    Ok(())
+}
+
+#[tokio::test]
+async fn silent_pinging_client_is_disconnected() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_heartbeat_timeout(Duration::from_secs(1));
+    let _server = spawn_custom_server(server).await?;
+
+    let mut conn = Delimited::new(TcpStream::connect(("localhost", CONTROL_PORT)).await?);
+    let request = HelloRequest {
+        features: Features::PING,
+        ..Default::default()
+    };
+    conn.send(ClientMessage::ExtendedHello(request)).await?;
+    let Some(ServerMessage::ExtendedHello(response)) = conn.recv().await? else {
+        panic!("expected extended hello");
+    };
+    assert!(response.features.contains(Features::PING));
+
+    // Only heartbeats arrive until the server gives up on the silent client.
+    let closed = time::timeout(Duration::from_secs(3), async {
+        while let Some(message) = conn.recv::<ServerMessage>().await? {
+            assert!(matches!(message, ServerMessage::Heartbeat));
+        }
+        anyhow::Ok(())
+    });
+    closed.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn proxy_protocol_header_carries_visitor_address() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_addr = listener.local_addr()?;
+    let options = ClientOptions {
+        proxy_protocol: Some(ProxyProtocol::V1),
+        ..Default::default()
+    };
+    let client = Client::with_options(
+        "127.0.0.1",
+        local_addr.port(),
+        "localhost",
+        0,
+        None,
+        options,
+        None,
+    )
+    .await?;
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(b"hello").await?;
+    let (mut local_stream, _) = listener.accept().await?;
+    let mut received = Vec::new();
+    while !received.ends_with(b"hello") {
+        let mut buf = [0u8; 64];
+        let n = local_stream.read(&mut buf).await?;
+        assert_ne!(n, 0, "connection closed early");
+        received.extend(&buf[..n]);
+    }
+    let visitor = stream.local_addr()?;
+    let expected = format!(
+        "PROXY TCP4 {} {} {} {}\r\nhello",
+        visitor.ip(),
+        local_addr.ip(),
+        visitor.port(),
+        local_addr.port()
+    );
+    assert_eq!(String::from_utf8(received)?, expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn http_requests_are_routed_by_subdomain() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let http_port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+    let mut server = Server::new(1024..=65535, None);
+    server.set_http_routing("tunnel.test", http_port);
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let options = ClientOptions {
+        name: Some("myapp".into()),
+        ..Default::default()
+    };
+    let client = Client::with_options(
+        "127.0.0.1",
+        listener.local_addr()?.port(),
+        "localhost",
+        0,
+        None,
+        options,
+        None,
+    )
+    .await?;
+    assert_eq!(client.public_host(), Some("myapp.tunnel.test"));
+    assert_eq!(client.remote_port(), http_port);
+    tokio::spawn(client.listen());
+
+    let request = b"GET / HTTP/1.1\r\nHost: myapp.tunnel.test\r\n\r\n";
+    let mut stream = TcpStream::connect(("127.0.0.1", http_port)).await?;
+    stream.write_all(request).await?;
+    let (mut local_stream, _) = listener.accept().await?;
+    let mut buf = vec![0u8; request.len()];
+    local_stream.read_exact(&mut buf).await?;
+    assert_eq!(buf, request);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", http_port)).await?;
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: other.tunnel.test\r\n\r\n")
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 404 "));
+    Ok(())
+}
+
+#[tokio::test]
+async fn tls_connections_are_routed_by_server_name() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let dir = std::env::temp_dir().join(format!("bore-sni-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let certified = rcgen::generate_simple_self_signed(vec!["myapp.tunnel.test".into()])?;
+    std::fs::write(&cert, certified.cert.pem())?;
+    std::fs::write(&key, certified.signing_key.serialize_pem())?;
+
+    let http_port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+    let tls_port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+    let mut server = Server::new(1024..=65535, None);
+    server.set_http_routing("tunnel.test", http_port);
+    server.set_tls_routing(tls_port);
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let options = ClientOptions {
+        name: Some("myapp".into()),
+        ..Default::default()
+    };
+    let client = Client::with_options(
+        "127.0.0.1",
+        listener.local_addr()?.port(),
+        "localhost",
+        0,
+        None,
+        options,
+        None,
+    )
+    .await?;
+    assert_eq!(client.tls_port(), Some(tls_port));
+    tokio::spawn(client.listen());
+
+    // The local service terminates TLS, so the server only passes encrypted bytes through.
+    let acceptor = ServerTls::from_pem_files(&cert, &key)?;
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = acceptor.accept(stream).await?;
+        stream.write_all(b"end to end").await?;
+        stream.shutdown().await?;
+        anyhow::Ok(())
+    });
+
+    let stream = TcpStream::connect(("127.0.0.1", tls_port)).await?;
+    let mut stream = ClientTls::new(Some(&cert))?
+        .connect("myapp.tunnel.test", stream)
+        .await?;
+    let mut buf = [0u8; 10];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"end to end");
+
+    // Unknown names are refused before any data is forwarded.
+    let stream = TcpStream::connect(("127.0.0.1", tls_port)).await?;
+    let result = ClientTls::new(Some(&cert))?
+        .connect("other.tunnel.test", stream)
+        .await;
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn reserved_ports_are_kept_for_their_secret() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, Some("shared"));
+    server.reserve_ports(PortRange::from((42000, 42001)), "token-a");
+    let _server = spawn_custom_server(server).await?;
+
+    // Clients with the shared secret cannot take reserved ports.
+    let result = Client::new("localhost", 5000, "localhost", 42000, Some("shared")).await;
+    assert!(result.is_err());
+    let client = Client::new("localhost", 5000, "localhost", 0, Some("shared")).await?;
+    assert!(!(42000..=42001).contains(&client.remote_port()));
+
+    // The reserved secret gets its own ports by default, and cannot be guessed.
+    let client = Client::new("localhost", 5000, "localhost", 0, Some("token-a")).await?;
+    assert!((42000..=42001).contains(&client.remote_port()));
+    let client = Client::new("localhost", 5000, "localhost", 42001, Some("token-a")).await?;
+    assert_eq!(client.remote_port(), 42001);
+    assert!(
+        Client::new("localhost", 5000, "localhost", 0, Some("token-b"))
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn revoked_tokens_lose_access() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let mut server = Server::new(1024..=65535, None);
+    server.set_tokens(vec![
+        Token::new("alice", "alice-secret", vec![PortRange::single(42100)]),
+        Token::new("bob", "bob-secret", Vec::new()),
+    ]);
+    let updater = server.token_updater();
+    let _server = spawn_custom_server(server).await?;
+
+    assert!(spawn_client(None).await.is_err());
+    assert!(spawn_client(Some("wrong")).await.is_err());
+    spawn_client(Some("bob-secret")).await?;
+    let alice = Client::new("localhost", 5000, "localhost", 0, Some("alice-secret")).await?;
+    assert_eq!(alice.remote_port(), 42100);
+    let alice = tokio::spawn(alice.listen());
+
+    // Removing a token closes its tunnels and turns it away from now on.
+    updater.update(vec![Token::new("bob", "bob-secret", Vec::new())]);
+    time::timeout(Duration::from_secs(2), alice).await??.ok();
+    assert!(spawn_client(Some("alice-secret")).await.is_err());
+    let bob = Client::new("localhost", 5000, "localhost", 0, Some("bob-secret")).await?;
+    let bob = tokio::spawn(bob.listen());
+
+    // Giving a token a new secret also closes the tunnels opened with the old one.
+    updater.update(vec![Token::new("bob", "new-bob-secret", Vec::new())]);
+    time::timeout(Duration::from_secs(2), bob).await??.ok();
+    assert!(spawn_client(Some("bob-secret")).await.is_err());
+    spawn_client(Some("new-bob-secret")).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn draining_server_lets_open_connections_finish() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let server = Server::new(1024..=65535, None);
+    let drainer = server.drainer();
+    let _server = spawn_custom_server(server).await?;
+
+    let (listener, addr) = spawn_client(None).await?;
+    let mut visitor = TcpStream::connect(addr).await?;
+    let (mut local, _) = listener.accept().await?;
+    visitor.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+
+    let drain = tokio::spawn(async move { drainer.drain(Duration::from_secs(5)).await });
+    time::sleep(Duration::from_millis(100)).await;
+    assert!(!drain.is_finished());
+
+    // New tunnels and visitors are turned away, but the open connection still works.
+    assert!(spawn_client(None).await.is_err());
+    assert!(TcpStream::connect(addr).await.is_err());
+    local.write_all(b"bye").await?;
+    let mut buf = [0u8; 3];
+    visitor.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"bye");
+
+    drop((visitor, local));
+    assert!(time::timeout(Duration::from_secs(2), drain).await??);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_is_local_target() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+
+    let path = std::env::temp_dir().join(format!("bore-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    let options = ClientOptions {
+        local_socket: Some(path.clone()),
+        ..Default::default()
+    };
+    let client = Client::with_options("localhost", 0, "localhost", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    let mut visitor = TcpStream::connect(("localhost", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    local.write_all(b"world").await?;
+    visitor.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"world");
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiplexed_connections_share_one_data_connection() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        multiplex: true,
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    assert!(client.features().contains(Features::MULTIPLEX));
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    // Open several visitors at once, then check that each reaches its own local connection.
+    let mut visitors = Vec::new();
+    for i in 0..5u8 {
+        let mut visitor = TcpStream::connect(("localhost", remote_port)).await?;
+        visitor.write_all(&[i]).await?;
+        visitors.push(visitor);
+    }
+    for _ in 0..5 {
+        let (mut local, _) = listener.accept().await?;
+        let mut buf = [0u8; 1];
+        local.read_exact(&mut buf).await?;
+        local.write_all(&[buf[0] + 100]).await?;
+    }
+    for (i, visitor) in visitors.iter_mut().enumerate() {
+        let mut buf = [0u8; 1];
+        visitor.read_exact(&mut buf).await?;
+        assert_eq!(buf[0], i as u8 + 100);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_disabled_by_default() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let options = ClientOptions {
+        via: Some("localhost".into()),
+        ..Default::default()
+    };
+    let result = Client::with_options("localhost", 5000, "localhost", 0, None, options, None).await;
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn sticky_sessions_pin_visitor_to_backend() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let mut backends = Vec::new();
+    for label in [b'a', b'b', b'c'] {
+        let listener = TcpListener::bind("localhost:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(&[label]).await;
+            }
+        });
+        backends.push(format!("localhost:{port}").parse()?);
+    }
+
+    let first = backends.remove(0);
+    let options = ClientOptions {
+        backends,
+        sticky: true,
+        ..Default::default()
+    };
+    let client =
+        Client::with_options(&first.host, first.port, "localhost", 0, None, options, None).await?;
+    assert!(client.features().contains(Features::PEER_ADDR));
+    let addr: SocketAddr = ([127, 0, 0, 1], client.remote_port()).into();
+    tokio::spawn(client.listen());
+
+    let mut labels = Vec::new();
+    for _ in 0..4 {
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut buf = [0u8; 1];
+        stream.read_exact(&mut buf).await?;
+        labels.push(buf[0]);
+    }
+    assert!(labels.iter().all(|label| *label == labels[0]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn connections_fail_over_to_live_backend() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("localhost:0").await?;
+    let live_port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.write_all(b"up").await;
+        }
+    });
+    // Bind and drop a listener to find a port that refuses connections.
+    let dead_port = TcpListener::bind("localhost:0").await?.local_addr()?.port();
+
+    let options = ClientOptions {
+        backends: vec![format!("localhost:{live_port}").parse()?],
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", dead_port, "localhost", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(("localhost", remote_port)).await?;
+        let mut buf = [0u8; 2];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"up");
+    }
+    Ok(())
+}
+
 #[tokio::test]
 async fn invalid_address() -> Result<()> {
     // We don't need the serial guard for this test because it doesn't create a server.
@@ -205,3 +1175,302 @@ async fn half_closed_tcp_stream() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn client_reaches_server_through_http_proxy() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+
+    let proxy = TcpListener::bind("localhost:0").await?;
+    let proxy_port = proxy.local_addr()?.port();
+    let (tunnels_tx, mut tunnels_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = proxy.accept().await {
+            let tunnels_tx = tunnels_tx.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    request.push(stream.read_u8().await?);
+                }
+                let request = String::from_utf8(request)?;
+                let target = request.split_whitespace().nth(1).unwrap().to_string();
+                let mut upstream = TcpStream::connect(&target).await?;
+                stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await?;
+                tunnels_tx.send(target)?;
+                tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+                anyhow::Ok(())
+            });
+        }
+    });
+
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        proxy: Some(format!("http://127.0.0.1:{proxy_port}").parse().unwrap()),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+    assert_eq!(tunnels_rx.recv().await.unwrap(), "localhost:7835");
+
+    let mut visitor = TcpStream::connect(("localhost", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    assert_eq!(tunnels_rx.recv().await.unwrap(), "localhost:7835");
+    Ok(())
+}
+
+#[tokio::test]
+async fn end_to_end_encrypted_tunnel() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+
+    let listener = TcpListener::bind("localhost:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        e2e_key: Some("correct horse".parse().unwrap()),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("localhost", local_port, "localhost", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    // Visitors without the key only see ciphertext.
+    let mut visitor = TcpStream::connect(("localhost", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    local.write_all(b"plain").await?;
+    let mut buf = [0u8; 5];
+    visitor.read_exact(&mut buf).await?;
+    assert_ne!(&buf, b"plain");
+    drop((visitor, local));
+
+    let decrypted = TcpListener::bind("localhost:0").await?;
+    let decrypted_addr = decrypted.local_addr()?;
+    let remote = format!("localhost:{remote_port}");
+    tokio::spawn(e2e::serve(
+        decrypted,
+        remote,
+        "correct horse".parse().unwrap(),
+    ));
+    let mut visitor = TcpStream::connect(decrypted_addr).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    local.write_all(b"world").await?;
+    visitor.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"world");
+    Ok(())
+}
+
+#[tokio::test]
+async fn tunnel_lifecycle_is_audited() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+
+    let path = std::env::temp_dir().join(format!("bore-audit-e2e-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut server = Server::new(1024..=65535, Some("abc"));
+    server.set_audit_log(AuditLog::open(&AuditTarget::File(path.clone()))?);
+    let _server = spawn_custom_server(server).await?;
+
+    assert!(spawn_client(Some("wrong")).await.is_err());
+    let client = Client::new("localhost", 5000, "localhost", 0, Some("abc")).await?;
+    let port = client.remote_port();
+    drop(client);
+
+    let mut events = Vec::new();
+    for _ in 0..50 {
+        let text = std::fs::read_to_string(&path)?;
+        events = text
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        if events
+            .last()
+            .is_some_and(|event| event["event"] == "tunnel_closed")
+        {
+            break;
+        }
+        time::sleep(Duration::from_millis(10)).await;
+    }
+    // The failed client may try each address of localhost, so it can fail more than once.
+    let [.., opened, closed] = &events[..] else {
+        panic!("expected tunnel events, got {events:?}");
+    };
+    assert_eq!(events[0]["event"], "auth_failed");
+    assert_eq!(opened["event"], "tunnel_opened");
+    assert_eq!(closed["event"], "tunnel_closed");
+    assert_eq!(opened["port"], port);
+    assert_eq!(opened["tunnel"], closed["tunnel"]);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn server_reports_when_ready() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    wait_for_control_port_closed().await?;
+
+    let mut server = Server::new(1024..=65535, None);
+    let ready = server.ready();
+    let _server = ServerGuard {
+        task: tokio::spawn(server.listen()),
+    };
+    time::timeout(Duration::from_secs(1), ready).await??;
+
+    let (listener, addr) = spawn_client(None).await?;
+    let mut conn = TcpStream::connect(addr).await?;
+    let (mut local, _) = listener.accept().await?;
+    conn.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn visitors_get_503_while_local_service_is_down() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(1024..=65535, None);
+    let tunnels = server.tunnels();
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        service_check: Some(ServiceCheck {
+            interval: Duration::from_millis(50),
+            path: None,
+            when_down: WhenDown::Http503,
+        }),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("127.0.0.1", local_port, "localhost", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    let wait_for_service = |up: bool| {
+        let tunnels = tunnels.clone();
+        async move {
+            for _ in 0..100 {
+                if tunnels.list().first().is_some_and(|t| t.service_up == up) {
+                    return Ok(());
+                }
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(anyhow!("server never saw the service as {up}"))
+        }
+    };
+
+    drop(listener);
+    wait_for_service(false).await?;
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    let mut response = String::new();
+    visitor.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    let listener = TcpListener::bind(("127.0.0.1", local_port)).await?;
+    wait_for_service(true).await?;
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    loop {
+        // Checks of the service are connections too, and close without sending anything.
+        let (mut local, _) = listener.accept().await?;
+        if local.read_exact(&mut buf).await.is_ok() {
+            break;
+        }
+    }
+    assert_eq!(&buf, b"hello");
+    Ok(())
+}
+
+#[tokio::test]
+async fn event_stream_reports_connection_traffic() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let args = LocalArgs {
+        local_port: listener.local_addr()?.port(),
+        local_host: "127.0.0.1".into(),
+        to: "localhost".into(),
+        ..Default::default()
+    };
+    let (event_tx, mut events) = TunnelEvents::channel();
+    let tunnel = tokio::spawn(run_local(args, std::future::pending(), Some(event_tx)));
+
+    let remote_port = loop {
+        match events.next().await {
+            Some(TunnelEvent::Started {
+                remote_port: Some(port),
+            }) => break port,
+            Some(_) => continue,
+            None => return Err(anyhow!("tunnel exited")),
+        }
+    };
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    local.write_all(b"hi!").await?;
+    drop(local);
+    visitor.read_exact(&mut buf[..3]).await?;
+    drop(visitor);
+
+    loop {
+        match events.next().await {
+            Some(TunnelEvent::ConnectionClosed {
+                error,
+                bytes_in,
+                bytes_out,
+                ..
+            }) => {
+                assert_eq!(error, None);
+                assert_eq!((bytes_in, bytes_out), (5, 3));
+                break;
+            }
+            Some(_) => continue,
+            None => return Err(anyhow!("tunnel exited")),
+        }
+    }
+    tunnel.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn tunnel_runs_over_in_memory_transport() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let (dialer, acceptor) = transport::memory();
+    let _server = ServerGuard {
+        task: tokio::spawn(Server::new(1024..=65535, None).listen_on(acceptor)),
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let options = ClientOptions {
+        dialer: Some(Arc::new(dialer)),
+        ..Default::default()
+    };
+    let client =
+        Client::with_options("127.0.0.1", local_port, "memory", 0, None, options, None).await?;
+    let remote_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+    Ok(())
+}
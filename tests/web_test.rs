@@ -1,21 +1,30 @@
-use std::sync::LazyLock;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use axum::{
     body::{to_bytes, Body},
+    extract::ConnectInfo,
     http::{Request, StatusCode},
 };
 use bore_cli::{
+    admin,
+    audit::{AuditLog, AuditTarget},
+    client::Client,
     server::Server,
     shared::CONTROL_PORT,
+    tls::{ClientTls, ServerTls},
     web::{
-        router, run_managed, ServeConfig, SessionInfo, SessionMode, SystemTunnelRole,
-        SystemTunnelSpec, TunnelConfig, WebState,
+        router, run_managed, serve_with_state, Login, ProfileConfig, RestartMode, RestartPolicy,
+        ServeConfig, SessionInfo, SessionMode, SystemTunnelRole, SystemTunnelSpec, TunnelConfig,
+        UpdateCheck, WebState,
     },
 };
+use futures_util::{FutureExt, StreamExt};
 use serde_json::{json, Value};
 use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     sync::{oneshot, Mutex},
     task::JoinHandle,
@@ -49,8 +58,12 @@ async fn wait_for_control_port_closed() -> Result<()> {
 }
 
 async fn spawn_server(secret: Option<&str>) -> Result<ServerGuard> {
+    spawn_custom_server(Server::new(1024..=65535, secret)).await
+}
+
+async fn spawn_custom_server(server: Server) -> Result<ServerGuard> {
     wait_for_control_port_closed().await?;
-    let task = tokio::spawn(Server::new(1024..=65535, secret).listen());
+    let task = tokio::spawn(server.listen());
 
     for _ in 0..250 {
         if task.is_finished() {
@@ -99,6 +112,9 @@ fn tunnel_config(name: &str) -> TunnelConfig {
         port: None,
         local_host: "127.0.0.1".to_string(),
         secret: None,
+        depends_on: Vec::new(),
+        profile: None,
+        restart: RestartPolicy::default(),
     }
 }
 
@@ -132,6 +148,91 @@ async fn get_session_returns_remote_metadata() {
     assert_eq!(body["web_remote_url"], "http://localhost:7836");
 }
 
+#[tokio::test]
+async fn cross_origin_requests_are_rejected() {
+    let mut state = WebState::default();
+    state.set_allowed_origins(vec!["https://console.example.com/".to_string()]);
+    let app = router(state);
+    let request = |method: &str, uri: &str, headers: &[(&str, &str)]| {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("host", "127.0.0.1:7836")
+            .header("content-type", "application/json");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let body = json!({ "name": "work", "to": "bore.example.com" }).to_string();
+        app.clone().oneshot(builder.body(Body::from(body)).unwrap())
+    };
+
+    let cases = [
+        (
+            "POST",
+            vec![("origin", "http://127.0.0.1:7836")],
+            StatusCode::CREATED,
+        ),
+        (
+            "POST",
+            vec![("origin", "https://console.example.com")],
+            StatusCode::CREATED,
+        ),
+        ("POST", vec![], StatusCode::CREATED),
+        (
+            "POST",
+            vec![("origin", "http://evil.example")],
+            StatusCode::FORBIDDEN,
+        ),
+        ("POST", vec![("origin", "null")], StatusCode::FORBIDDEN),
+        (
+            "POST",
+            vec![("sec-fetch-site", "cross-site")],
+            StatusCode::FORBIDDEN,
+        ),
+        (
+            "GET",
+            vec![("sec-fetch-site", "cross-site")],
+            StatusCode::OK,
+        ),
+        (
+            "GET",
+            vec![("origin", "http://evil.example")],
+            StatusCode::FORBIDDEN,
+        ),
+    ];
+    for (method, headers, expected) in cases {
+        let response = request(method, "/api/profiles", &headers).await.unwrap();
+        assert_eq!(response.status(), expected, "{method} {headers:?}");
+    }
+    let response = request("GET", "/api/events", &[("origin", "http://evil.example")])
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn rebound_host_names_are_rejected() {
+    let app = router(WebState::default());
+    let request = |host: &str, origin: &str| {
+        let request = Request::get("/api/profiles")
+            .header("host", host)
+            .header("origin", origin)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    // A page on evil.example that resolves its own name to 127.0.0.1 is same-origin.
+    let response = request("evil.example:7836", "http://evil.example:7836")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = request("localhost:7836", "http://localhost:7836")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
 #[tokio::test]
 async fn get_tunnels_returns_empty_array() {
     let app = router(WebState::default());
@@ -149,6 +250,186 @@ async fn get_tunnels_returns_empty_array() {
     assert_eq!(body, json!([]));
 }
 
+#[tokio::test]
+async fn get_version_reports_crate_version() {
+    let app = router(WebState::default());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/version")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .expect("request should succeed");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_response(response).await;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn get_version_reports_newer_releases_when_enabled() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request = [0u8; 1024];
+        let n = stream.read(&mut request).await?;
+        let request = String::from_utf8_lossy(&request[..n]);
+        assert!(request.starts_with("GET /repos/example/bore/releases/latest "));
+        assert!(request.to_ascii_lowercase().contains("user-agent: bore/"));
+        let body = r#"{"tag_name":"v999.0.0"}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await?;
+        anyhow::Ok(())
+    });
+
+    let mut state = WebState::default();
+    let url = format!("http://{addr}/repos/example/bore");
+    state.set_update_check(Some(UpdateCheck::new(&url)?));
+    let app = router(state);
+    let version = || Request::get("/api/version").body(Body::empty());
+    let body = json_response(app.clone().oneshot(version()?).await?).await;
+    assert_eq!(body["latest_version"], "999.0.0");
+    assert_eq!(body["update_available"], true);
+    // The result is cached, so the fake API above only has to answer once.
+    let body = json_response(app.oneshot(version()?).await?).await;
+    assert_eq!(body["latest_version"], "999.0.0");
+    Ok(())
+}
+
+#[tokio::test]
+async fn password_protects_api_routes() {
+    let mut state = WebState::default();
+    state.set_login(Some(Login::new("hunter2")));
+    let app = router(state);
+    let get_tunnels = |cookie: Option<&str>| {
+        let mut request = Request::builder().uri("/api/tunnels");
+        if let Some(cookie) = cookie {
+            request = request.header("cookie", cookie);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    let login = |password: &str| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/login")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "password": password }).to_string()))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(get_tunnels(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.clone().oneshot(login("wrong")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.clone().oneshot(login("hunter2")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookie = response.headers()["set-cookie"].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let response = app
+        .clone()
+        .oneshot(get_tunnels(Some(&cookie)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let index = Request::builder().uri("/").body(Body::empty()).unwrap();
+    assert_eq!(app.oneshot(index).await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn failed_logins_are_throttled_per_address() {
+    let mut state = WebState::default();
+    state.set_login(Some(Login::new("hunter2")));
+    let app = router(state);
+    let login = |password: &str, from: &str| {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/api/login")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "password": password }).to_string()))
+            .unwrap();
+        let addr: SocketAddr = from.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(request)
+    };
+
+    for _ in 0..5 {
+        let response = login("wrong", "203.0.113.7:4000").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = login("hunter2", "203.0.113.7:4001").await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let response = login("hunter2", "198.51.100.1:4000").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn log_level_requires_managed_logging() {
+    let app = router(WebState::default());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/log-level")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .expect("request should succeed");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn usage_export_supports_csv_and_json() -> Result<()> {
+    let state = WebState::default();
+    let id = state.create_tunnel(tunnel_config("api, v2")).await?;
+    let app = router(state);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/metrics/export?format=csv")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX).await?;
+    let csv = String::from_utf8(body.to_vec())?;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("id,name,created_at,bytes_in,bytes_out,connections")
+    );
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with(&format!("{id},\"api, v2\",")));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/metrics/export")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    let body = json_response(response).await;
+    assert_eq!(body[0]["name"], "api, v2");
+    assert_eq!(body[0]["bytes_in"], 0);
+    Ok(())
+}
+
 #[tokio::test]
 async fn remote_mode_rejects_non_loopback_local_host() {
     let app = router(WebState::new(remote_session(SessionMode::RemoteWeb)));
@@ -283,6 +564,26 @@ async fn tunnel_crud_and_logs_api() -> Result<()> {
     let logs_body = json_response(logs_response).await;
     assert!(logs_body["logs"].as_array().expect("logs array").len() >= 2);
 
+    let download_response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/tunnels/{id}/logs/download"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(download_response.status(), StatusCode::OK);
+    assert_eq!(
+        download_response.headers()["content-disposition"],
+        format!("attachment; filename=\"bore-{id}.log\"").as_str()
+    );
+    let body = to_bytes(download_response.into_body(), usize::MAX).await?;
+    let log = String::from_utf8(body.to_vec())?;
+    assert!(log
+        .lines()
+        .any(|line| line.ends_with(" starting tunnel dev")));
+
     let stop_response = app
         .clone()
         .oneshot(
@@ -309,6 +610,286 @@ async fn tunnel_crud_and_logs_api() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn summary_reports_status_counts_and_errors() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    wait_for_control_port_closed().await?;
+    let state = WebState::default();
+    state.create_tunnel(tunnel_config("idle")).await?;
+    let failing = state
+        .create_tunnel(TunnelConfig {
+            local_port: 3001,
+            ..tunnel_config("broken")
+        })
+        .await?;
+    state.start_tunnel(&failing).await?;
+    assert!(state.wait_for_running(&failing).await.is_err());
+
+    let response = router(state)
+        .oneshot(
+            Request::builder()
+                .uri("/api/summary")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_response(response).await;
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["by_status"]["stopped"], 1);
+    assert_eq!(body["by_status"]["failed"], 1);
+    assert_eq!(body["recent_errors"][0]["id"], failing.as_str());
+    assert_eq!(body["recent_errors"][0]["name"], "broken");
+    assert_eq!(body["top_tunnels"], json!([]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn repeated_failures_mark_tunnel_as_flapping() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    wait_for_control_port_closed().await?;
+    let state = WebState::default();
+    let id = state.create_tunnel(tunnel_config("unstable")).await?;
+    let response = router(state.clone())
+        .oneshot(Request::get("/api/events").body(Body::empty())?)
+        .await?;
+    let mut events = response.into_body().into_data_stream();
+    for _ in 0..3 {
+        state.start_tunnel(&id).await?;
+        assert!(state.wait_for_running(&id).await.is_err());
+    }
+
+    let flapping = loop {
+        let chunk = time::timeout(Duration::from_secs(5), events.next())
+            .await?
+            .expect("stream should stay open")?;
+        let text = String::from_utf8(chunk.to_vec())?;
+        if let Some(data) = text.strip_prefix("event: tunnel_flapping\ndata: ") {
+            break serde_json::from_str::<Value>(data.trim_end())?;
+        }
+    };
+    assert_eq!(flapping["id"], id.as_str());
+    assert_eq!(flapping["consecutive_failures"], 3);
+
+    let tunnel = state.get_tunnel(&id).await?;
+    assert_eq!(tunnel.health.restarts, 2);
+    assert_eq!(tunnel.health.consecutive_failures, 3);
+    assert!(tunnel.health.flapping);
+    assert!(state
+        .logs(&id)
+        .await?
+        .iter()
+        .any(|line| line.contains("flapping")));
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_tunnel_restarts_until_retries_run_out() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    wait_for_control_port_closed().await?;
+    let state = WebState::default();
+    let id = state
+        .create_tunnel(TunnelConfig {
+            restart: RestartPolicy {
+                mode: RestartMode::OnFailure,
+                max_retries: Some(2),
+                backoff_secs: 0,
+            },
+            ..tunnel_config("retrying")
+        })
+        .await?;
+    state.start_tunnel(&id).await?;
+
+    for _ in 0..250 {
+        let logs = state.logs(&id).await?;
+        if logs.iter().any(|line| line.contains("giving up")) {
+            break;
+        }
+        time::sleep(Duration::from_millis(20)).await;
+    }
+    let tunnel = state.get_tunnel(&id).await?;
+    assert_eq!(tunnel.health.restarts, 2);
+    assert_eq!(tunnel.config.restart.mode, RestartMode::OnFailure);
+    assert!(state
+        .logs(&id)
+        .await?
+        .iter()
+        .any(|line| line.contains("giving up after 2 restart attempts")));
+    Ok(())
+}
+
+#[tokio::test]
+async fn starting_tunnel_starts_its_dependencies_first() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+    let state = WebState::default();
+    let relay = state.create_tunnel(tunnel_config("relay")).await?;
+    let app = state
+        .create_tunnel(TunnelConfig {
+            local_port: 3001,
+            depends_on: vec![relay.clone()],
+            ..tunnel_config("app")
+        })
+        .await?;
+
+    state.start_tunnel(&app).await?;
+    assert_eq!(
+        state.get_tunnel(&relay).await?.status,
+        bore_cli::web::TunnelStatus::Running
+    );
+    state.wait_for_running(&app).await?;
+    state.shutdown_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tunnels_use_relay_profile_address_and_secret() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(Some("profile-secret")).await?;
+    let state = WebState::default();
+    let profile = state
+        .create_profile(ProfileConfig {
+            name: "home".to_string(),
+            to: "localhost".to_string(),
+            secret: Some("profile-secret".to_string()),
+        })
+        .await?;
+    let wrong = state
+        .create_profile(ProfileConfig {
+            name: "wrong".to_string(),
+            to: "localhost".to_string(),
+            secret: Some("other".to_string()),
+        })
+        .await?;
+    assert!(state.check_profile(&profile).await?.ok);
+    assert!(!state.check_profile(&wrong).await?.ok);
+
+    let id = state
+        .create_tunnel(TunnelConfig {
+            to: String::new(),
+            profile: Some(profile.clone()),
+            ..tunnel_config("via profile")
+        })
+        .await?;
+    state.start_tunnel(&id).await?;
+    state.wait_for_running(&id).await?;
+
+    let err = state
+        .delete_profile(&profile)
+        .await
+        .expect_err("profile in use");
+    assert_eq!(err.status, StatusCode::CONFLICT);
+    assert!(!state.list_profiles().await[0].to.is_empty());
+    state.shutdown_all().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles_are_exported_and_imported() -> Result<()> {
+    let state = WebState::default();
+    state
+        .create_profile(ProfileConfig {
+            name: "work".to_string(),
+            to: "work.example.com".to_string(),
+            secret: Some("work-secret".to_string()),
+        })
+        .await?;
+    let send = |method: &str, uri: &str, body: &str| {
+        router(state.clone()).oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+
+    let response = send("GET", "/api/profiles/export?format=yaml", "").await?;
+    assert_eq!(response.headers()["content-type"], "application/yaml");
+    let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+    let yaml = String::from_utf8(bytes.to_vec())?;
+    assert!(yaml.contains("work.example.com"));
+    assert!(!yaml.contains("work-secret"));
+    let response = send("GET", "/api/profiles/export?secrets=true", "").await?;
+    let body = json_response(response).await;
+    assert_eq!(body["profiles"][0]["secret"], "work-secret");
+
+    let document =
+        "profiles:\n- name: work\n  to: new.example.com\n- name: demo\n  to: demo.example.com\n";
+    let response = send("POST", "/api/profiles/import?format=yaml", document).await?;
+    let body = json_response(response).await;
+    assert_eq!(body, json!({ "created": 1, "updated": 0, "skipped": 1 }));
+    let uri = "/api/profiles/import?format=yaml&mode=overwrite";
+    let response = send("POST", uri, document).await?;
+    let body = json_response(response).await;
+    assert_eq!(body, json!({ "created": 0, "updated": 2, "skipped": 0 }));
+    let bundle = state.export_profiles(true).await;
+    let work = &bundle.profiles[1];
+    assert_eq!(work.to, "new.example.com");
+    assert_eq!(work.secret.as_deref(), Some("work-secret"));
+
+    let response = send("POST", "/api/profiles/import", "{\"profiles\": [{}]}").await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(state.list_profiles().await.len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn web_console_serves_https() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("bore-web-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+    std::fs::write(&cert, certified.cert.pem())?;
+    std::fs::write(&key, certified.signing_key.serialize_pem())?;
+    assert!(ServerTls::self_signed(vec!["localhost".into()]).is_ok());
+
+    let addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let task = tokio::spawn(serve_with_state(
+        ServeConfig {
+            addr,
+            session: SessionInfo::local(),
+            tls: Some(ServerTls::from_pem_files(&cert, &key)?),
+        },
+        WebState::default(),
+        async move { shutdown_rx.await.map_err(std::io::Error::other) },
+    ));
+    let mut stream = None;
+    for _ in 0..250 {
+        if let Ok(connected) = TcpStream::connect(addr).await {
+            stream = Some(connected);
+            break;
+        }
+        time::sleep(Duration::from_millis(20)).await;
+    }
+    let stream = stream.ok_or_else(|| anyhow!("web console did not start"))?;
+
+    let mut stream = ClientTls::new(Some(&cert))?
+        .connect("localhost", stream)
+        .await?;
+    stream
+        .write_all(b"GET /api/version HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    // Plain HTTP gets no answer.
+    let mut plain = TcpStream::connect(addr).await?;
+    plain
+        .write_all(b"GET /api/version HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await?;
+    let mut buf = Vec::new();
+    let _ = plain.read_to_end(&mut buf).await;
+    assert!(!String::from_utf8_lossy(&buf).contains("200 OK"));
+
+    let _ = shutdown_tx.send(());
+    task.await??;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
 #[tokio::test]
 async fn managed_remote_web_starts_and_stops_system_tunnel() -> Result<()> {
     let _guard = SERIAL_GUARD.lock().await;
@@ -320,6 +901,7 @@ async fn managed_remote_web_starts_and_stops_system_tunnel() -> Result<()> {
         ServeConfig {
             addr: "127.0.0.1:0".parse().unwrap(),
             session: state.session().await,
+            tls: None,
         },
         state.clone(),
         vec![SystemTunnelSpec {
@@ -331,6 +913,9 @@ async fn managed_remote_web_starts_and_stops_system_tunnel() -> Result<()> {
                 port: Some(7836),
                 local_host: "127.0.0.1".to_string(),
                 secret: None,
+                depends_on: Vec::new(),
+                profile: None,
+                restart: RestartPolicy::default(),
             },
             display_url: Some("http://localhost:7836".to_string()),
         }],
@@ -376,6 +961,7 @@ async fn managed_remote_web_uses_fallback_web_port_for_system_tunnel() -> Result
         ServeConfig {
             addr: occupied_addr,
             session: state.session().await,
+            tls: None,
         },
         state.clone(),
         vec![SystemTunnelSpec {
@@ -387,6 +973,9 @@ async fn managed_remote_web_uses_fallback_web_port_for_system_tunnel() -> Result
                 port: Some(7836),
                 local_host: "127.0.0.1".to_string(),
                 secret: None,
+                depends_on: Vec::new(),
+                profile: None,
+                restart: RestartPolicy::default(),
             },
             display_url: Some("http://localhost:7836".to_string()),
         }],
@@ -424,6 +1013,7 @@ async fn managed_home_starts_two_locked_system_tunnels() -> Result<()> {
         ServeConfig {
             addr: "127.0.0.1:0".parse().unwrap(),
             session: state.session().await,
+            tls: None,
         },
         state.clone(),
         vec![
@@ -436,6 +1026,9 @@ async fn managed_home_starts_two_locked_system_tunnels() -> Result<()> {
                     port: Some(7836),
                     local_host: "127.0.0.1".to_string(),
                     secret: None,
+                    depends_on: Vec::new(),
+                    profile: None,
+                    restart: RestartPolicy::default(),
                 },
                 display_url: Some("http://localhost:7836".to_string()),
             },
@@ -448,6 +1041,9 @@ async fn managed_home_starts_two_locked_system_tunnels() -> Result<()> {
                     port: Some(2222),
                     local_host: "127.0.0.1".to_string(),
                     secret: None,
+                    depends_on: Vec::new(),
+                    profile: None,
+                    restart: RestartPolicy::default(),
                 },
                 display_url: Some("localhost:2222".to_string()),
             },
@@ -484,3 +1080,582 @@ async fn managed_home_starts_two_locked_system_tunnels() -> Result<()> {
     task.await??;
     Ok(())
 }
+
+#[tokio::test]
+async fn admin_api_lists_and_closes_tunnels() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(1024..=65535, None);
+    let app = admin::router(&server, Some(Login::new("admin-token")), None);
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let client = Client::new(
+        "127.0.0.1",
+        listener.local_addr()?.port(),
+        "localhost",
+        0,
+        None,
+    )
+    .await?;
+    let remote_port = client.remote_port();
+    let client = tokio::spawn(client.listen());
+
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(Request::get("/api/tunnels").body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.clone().oneshot(request("GET", "/api/tunnels")).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let tunnels = json_response(response).await;
+    assert_eq!(tunnels[0]["port"], remote_port);
+    assert_eq!(tunnels[0]["active_connections"], 1);
+    let id = tunnels[0]["id"].as_str().unwrap().to_string();
+
+    let response = app
+        .clone()
+        .oneshot(request("GET", &format!("/api/tunnels/{id}")))
+        .await?;
+    let tunnel = json_response(response).await;
+    let connection = tunnel["connections"][0]["id"].as_str().unwrap().to_string();
+    let response = app
+        .clone()
+        .oneshot(request(
+            "DELETE",
+            &format!("/api/tunnels/{id}/connections/{connection}"),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let n = time::timeout(Duration::from_secs(2), visitor.read(&mut buf)).await??;
+    assert_eq!(n, 0, "visitor connection should be closed");
+
+    let response = app
+        .clone()
+        .oneshot(request("DELETE", &format!("/api/tunnels/{id}")))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    time::timeout(Duration::from_secs(2), client).await??.ok();
+    let response = app.oneshot(request("GET", "/api/tunnels")).await?;
+    assert_eq!(json_response(response).await, json!([]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn remote_servers_report_tunnels_from_admin_api() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(1024..=65535, None);
+    let admin = TcpListener::bind("127.0.0.1:0").await?;
+    let admin_addr = admin.local_addr()?;
+    let app = admin::router(&server, Some(Login::new("admin-token")), None);
+    let _admin = tokio::spawn(async move { axum::serve(admin, app).await });
+    let _server = spawn_custom_server(server).await?;
+
+    let client = Client::new("localhost", 3000, "localhost", 0, None).await?;
+    let remote_port = client.remote_port();
+    let _client = tokio::spawn(client.listen());
+
+    let state = WebState::default();
+    let app = router(state.clone());
+    let register = |body: Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/servers")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+    let url = format!("http://{admin_addr}/");
+    let response = register(json!({ "name": "relay", "url": url, "token": "admin-token" })).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let id = json_response(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let response = register(json!({ "name": "bad", "url": admin_addr.to_string() })).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bad_token = register(json!({ "name": "locked", "url": url, "token": "wrong" })).await?;
+    let bad_token = json_response(bad_token).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let servers = state.list_servers().await;
+    assert_eq!(servers.len(), 2);
+    assert!(servers.iter().all(|server| server.has_token));
+
+    let response = app
+        .clone()
+        .oneshot(Request::get(format!("/api/servers/{id}/status")).body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let status = json_response(response).await;
+    assert_eq!(status["health"]["tunnels"], 1);
+    assert_eq!(status["ports"], json!([remote_port]));
+    assert_eq!(status["tunnels"][0]["port"], remote_port);
+
+    let err = state
+        .server_status(&bad_token)
+        .await
+        .expect_err("wrong token");
+    assert_eq!(err.status, StatusCode::BAD_GATEWAY);
+    assert!(
+        err.message.contains("admin token required"),
+        "{}",
+        err.message
+    );
+    state.delete_server(&bad_token).await?;
+    assert_eq!(state.list_servers().await.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_dashboard_shows_auth_failures() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(1024..=65535, Some("abc"));
+    let app = admin::router(&server, Some(Login::new("admin-token")), None);
+    let _server = spawn_custom_server(server).await?;
+    assert!(
+        Client::new("localhost", 5000, "localhost", 0, Some("wrong"))
+            .await
+            .is_err()
+    );
+
+    // The page itself is public, and signs in to the API from the browser.
+    let response = app
+        .clone()
+        .oneshot(Request::get("/").body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = to_bytes(response.into_body(), usize::MAX).await?;
+    assert!(String::from_utf8(page.to_vec())?.contains("Bore Server Dashboard"));
+
+    let request = Request::get("/api/auth-failures")
+        .header("authorization", "Bearer admin-token")
+        .body(Body::empty())?;
+    let failures = json_response(app.oneshot(request).await?).await;
+    // Startup checks of the control port also count, since they never authenticate.
+    let failures = failures.as_array().unwrap();
+    assert!(failures.iter().any(|f| f["error"] == "invalid secret"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_api_reloads_settings_without_closing_tunnels() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(42200..=42210, None);
+    let reloader = server.reloader();
+    let reload: admin::Reload = Arc::new(move || {
+        reloader.set_port_range(42300..=42300);
+        Ok(())
+    });
+    let app = admin::router(&server, None, Some(reload));
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let client = Client::new("127.0.0.1", local_port, "localhost", 0, None).await?;
+    let open_port = client.remote_port();
+    tokio::spawn(client.listen());
+    assert!(Client::new("127.0.0.1", 5000, "localhost", 42300, None)
+        .await
+        .is_err());
+
+    let response = app
+        .oneshot(Request::post("/api/reload").body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    Client::new("127.0.0.1", 5000, "localhost", 42300, None).await?;
+
+    // The tunnel opened before the reload still takes visitors.
+    let mut visitor = TcpStream::connect(("127.0.0.1", open_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn secondary_secret_is_accepted_until_retired() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let mut server = Server::new(1024..=65535, Some("new secret"));
+    server.set_secondary_secret("old secret");
+    let app = admin::router(&server, None, None);
+    let _server = spawn_custom_server(server).await?;
+
+    let old = Client::new("127.0.0.1", 5000, "localhost", 0, Some("old secret")).await?;
+    tokio::spawn(old.listen());
+    Client::new("127.0.0.1", 5000, "localhost", 0, Some("new secret")).await?;
+
+    let retire = || Request::delete("/api/secondary-secret").body(Body::empty());
+    let response = app.clone().oneshot(retire()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(retire()?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(
+        Client::new("127.0.0.1", 5000, "localhost", 0, Some("old secret"))
+            .await
+            .is_err()
+    );
+    Client::new("127.0.0.1", 5000, "localhost", 0, Some("new secret")).await?;
+
+    // The tunnel opened with the old secret is still listed, and marked as such.
+    let response = app
+        .oneshot(Request::get("/api/tunnels").body(Body::empty())?)
+        .await?;
+    let body = to_bytes(response.into_body(), usize::MAX).await?;
+    let tunnels: serde_json::Value = serde_json::from_slice(&body)?;
+    let secondary = tunnels.as_array().unwrap().iter();
+    assert_eq!(
+        secondary
+            .filter(|tunnel| tunnel["secondary_secret"] == true)
+            .count(),
+        1
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn secrets_rotate_while_tunnel_stays_up() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(1024..=65535, Some("old secret"));
+    let reloader = server.reloader();
+    let reload: admin::Reload = Arc::new(move || {
+        reloader.set_secondary_secret(Some("new secret"));
+        Ok(())
+    });
+    let app = admin::router(&server, None, Some(reload));
+    let _server = spawn_custom_server(server).await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let local_port = listener.local_addr()?.port();
+    let client = Client::new("127.0.0.1", local_port, "localhost", 0, Some("old secret")).await?;
+    let open_port = client.remote_port();
+    tokio::spawn(client.listen());
+
+    let promote = || Request::post("/api/secondary-secret/promote").body(Body::empty());
+    let response = app.clone().oneshot(promote()?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .clone()
+        .oneshot(Request::post("/api/reload").body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.clone().oneshot(promote()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The new secret is now primary, and the old one is still accepted as secondary.
+    Client::new("127.0.0.1", 5000, "localhost", 0, Some("new secret")).await?;
+    let mut visitor = TcpStream::connect(("127.0.0.1", open_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+
+    let response = app
+        .oneshot(Request::delete("/api/secondary-secret").body(Body::empty())?)
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        Client::new("127.0.0.1", 5000, "localhost", 0, Some("old secret"))
+            .await
+            .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_api_suspends_and_resumes_ports() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let path = std::env::temp_dir().join(format!("bore-audit-admin-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut server = Server::new(1024..=65535, None);
+    server.set_audit_log(AuditLog::open(&AuditTarget::File(path.clone()))?);
+    let app = admin::router(&server, None, None);
+    let _server = spawn_custom_server(server).await?;
+
+    let client = Client::new("127.0.0.1", 5000, "localhost", 0, None).await?;
+    let port = client.remote_port();
+    let tunnel = tokio::spawn(client.listen());
+    time::sleep(Duration::from_millis(50)).await;
+
+    let uri = format!("/api/ports/{port}/suspend");
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post(&uri)
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"reason":"abuse report"}"#))?,
+        )
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_response(response).await["closed"], true);
+    time::timeout(Duration::from_secs(5), tunnel).await???;
+    assert!(Client::new("127.0.0.1", 5000, "localhost", port, None)
+        .await
+        .is_err());
+
+    let resume = || Request::delete(&uri).body(Body::empty());
+    let response = app.clone().oneshot(resume()?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.oneshot(resume()?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    Client::new("127.0.0.1", 5000, "localhost", port, None).await?;
+
+    let text = std::fs::read_to_string(&path)?;
+    let events = text
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()?;
+    let suspended = events
+        .iter()
+        .find(|event| event["event"] == "tunnel_suspended")
+        .expect("suspension is audited");
+    assert_eq!(suspended["port"], port);
+    assert_eq!(suspended["reason"], "abuse report");
+    let resumed: Vec<_> = events
+        .iter()
+        .filter(|event| event["event"] == "tunnel_resumed")
+        .collect();
+    assert_eq!(resumed.len(), 1);
+    assert_eq!(resumed[0]["port"], port);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_health_checks_report_readiness() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let server = Server::new(42400..=42400, None);
+    let drainer = server.drainer();
+    let app = admin::router(&server, Some(Login::new("admin-token")), None);
+    let probe = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app.oneshot(Request::get(uri).body(Body::empty())?).await?;
+            let status = response.status();
+            anyhow::Ok((status, json_response(response).await))
+        }
+    };
+
+    let (status, body) = probe("/healthz").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["accepting"], false);
+
+    let _server = spawn_custom_server(server).await?;
+    let (status, body) = probe("/readyz").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tunnels"], 0);
+
+    // The only port is taken, so no more tunnels fit.
+    let client = Client::new("localhost", 5000, "localhost", 0, None).await?;
+    let (status, body) = probe("/readyz").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["ports_exhausted"], true);
+    drop(client);
+
+    drainer.drain(Duration::from_millis(100)).await;
+    let (status, body) = probe("/readyz").await?;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["draining"], true);
+    assert_eq!(probe("/healthz").await?.0, StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+async fn tunnel_stats_are_sampled_and_streamed() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let state = WebState::default();
+    let id = state
+        .create_tunnel(TunnelConfig {
+            local_port: listener.local_addr()?.port(),
+            ..tunnel_config("graphs")
+        })
+        .await?;
+    state.start_tunnel(&id).await?;
+    let remote_port = state
+        .wait_for_running(&id)
+        .await?
+        .remote_port
+        .expect("running tunnel should have a port");
+    let app = router(state);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut events = response.into_body().into_data_stream();
+
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+
+    let update = loop {
+        let chunk = time::timeout(Duration::from_secs(5), events.next())
+            .await?
+            .expect("stream should stay open")?;
+        let text = String::from_utf8(chunk.to_vec())?;
+        let Some(data) = text.strip_prefix("event: stats\ndata: ") else {
+            continue;
+        };
+        let update: Value = serde_json::from_str(data.trim_end())?;
+        if update["bytes_in"] == 5 {
+            break update;
+        }
+    };
+    assert_eq!(update["id"], id.as_str());
+    assert_eq!(update["active_connections"], 1);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/tunnels/{id}/stats"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    let body = json_response(response).await;
+    assert_eq!(body["totals"]["bytes_in"], 5);
+    assert!(body["samples"]
+        .as_array()
+        .expect("samples array")
+        .iter()
+        .any(|sample| sample["bytes_in"] == 5));
+    Ok(())
+}
+
+#[tokio::test]
+async fn open_connections_are_listed_and_closed() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let state = WebState::default();
+    let id = state
+        .create_tunnel(TunnelConfig {
+            local_port: listener.local_addr()?.port(),
+            ..tunnel_config("visitors")
+        })
+        .await?;
+    state.start_tunnel(&id).await?;
+    let remote_port = state
+        .wait_for_running(&id)
+        .await?
+        .remote_port
+        .expect("running tunnel should have a port");
+    let app = router(state.clone());
+
+    let mut visitor = TcpStream::connect(("127.0.0.1", remote_port)).await?;
+    visitor.write_all(b"hello").await?;
+    let (mut local, _) = listener.accept().await?;
+    let mut buf = [0u8; 5];
+    local.read_exact(&mut buf).await?;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/tunnels/{id}/connections"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    let body = json_response(response).await;
+    let connections = body.as_array().expect("connections array");
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0]["bytes_in"], 5);
+    assert!(connections[0]["peer"]
+        .as_str()
+        .expect("server reports the visitor")
+        .starts_with("127.0.0.1:"));
+    let connection = connections[0]["id"].as_str().unwrap().to_string();
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/api/tunnels/{id}/connections/{connection}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let n = time::timeout(Duration::from_secs(1), local.read(&mut buf)).await??;
+    assert_eq!(n, 0);
+    wait_for_condition(|| {
+        state
+            .connections(&id)
+            .now_or_never()
+            .is_some_and(|list| list.is_ok_and(|list| list.is_empty()))
+    })
+    .await?;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/api/tunnels/{id}/connections/{connection}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn running_tunnel_can_be_shared() -> Result<()> {
+    let _guard = SERIAL_GUARD.lock().await;
+    let _server = spawn_server(None).await?;
+    let state = WebState::default();
+    let id = state.create_tunnel(tunnel_config("shared")).await?;
+    let app = router(state.clone());
+    let share = |app: axum::Router| {
+        app.oneshot(
+            Request::builder()
+                .uri(format!("/api/tunnels/{id}/share"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = share(app.clone()).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    state.start_tunnel(&id).await?;
+    let remote_port = state.wait_for_running(&id).await?.remote_port.unwrap();
+    let response = share(app).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_response(response).await;
+    assert_eq!(body["address"], format!("localhost:{remote_port}"));
+    assert_eq!(body["url"], format!("http://localhost:{remote_port}"));
+    Ok(())
+}